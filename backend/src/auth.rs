@@ -4,7 +4,19 @@ use chrono::Utc;
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use sqlx::{SqlitePool, Row};
+use utoipa::ToSchema;
+use sqlx::Row;
+
+use crate::config::Config;
+use crate::crypto::TokenCipher;
+use crate::db::DbPool;
+use crate::errors::{AppError, ErrorBody};
+use crate::logging::LogErr;
+use crate::moderation::{
+    active_ban, current_role, is_banned, redeem_invite, refresh_access, BannedUsers, CurrentAccess, RegistrationMode,
+};
+use crate::profile_images::import_avatar;
+use crate::storage::{SharedStorage, Storage};
 use uuid::Uuid;
 
 // ── Models ──────────────────────────────────────────────
@@ -17,13 +29,16 @@ pub struct Claims {
     pub exp: usize,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct AuthPayload {
     pub username: String,
     pub password: String,
+    /// Required by /api/register when `auth.registration_mode` is `invite`
+    #[serde(default)]
+    pub invite_code: Option<String>,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, ToSchema)]
 pub struct AuthResponse {
     pub token: String,
     pub user_id: String,
@@ -35,7 +50,7 @@ pub struct AuthResponse {
     pub banner_url: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct UpdateProfile {
     pub username: Option<String>,
     pub about: Option<String>,
@@ -45,12 +60,12 @@ pub struct UpdateProfile {
     pub banner_url: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct DiscordUserTokenPayload {
     pub discord_token: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct DiscordProxyPayload {
     pub method: Option<String>,
     pub path: String,
@@ -67,11 +82,7 @@ pub(crate) struct DiscordUser {
 
 // ── JWT helpers ─────────────────────────────────────────
 
-fn jwt_secret() -> String {
-    std::env::var("JWT_SECRET").expect("JWT_SECRET must be set")
-}
-
-pub fn create_token(user_id: &str, username: &str, role: &str) -> String {
+pub fn create_token(config: &Config, user_id: &str, username: &str, role: &str) -> String {
     let expiration = Utc::now()
         .checked_add_signed(chrono::Duration::days(7))
         .expect("valid timestamp")
@@ -87,41 +98,50 @@ pub fn create_token(user_id: &str, username: &str, role: &str) -> String {
     encode(
         &Header::default(),
         &claims,
-        &EncodingKey::from_secret(jwt_secret().as_bytes()),
+        &EncodingKey::from_secret(config.auth.jwt_secret.as_bytes()),
     )
     .expect("token creation failed")
 }
 
-pub fn validate_token(token: &str) -> Option<Claims> {
+pub fn validate_token(config: &Config, token: &str) -> Option<Claims> {
     decode::<Claims>(
         token,
-        &DecodingKey::from_secret(jwt_secret().as_bytes()),
+        &DecodingKey::from_secret(config.auth.jwt_secret.as_bytes()),
         &Validation::default(),
     )
     .map(|data| data.claims)
     .ok()
 }
 
-/// Extract claims from the Authorization header.
+/// Extract claims from the Authorization header, checked against the
+/// app's `Config`. Tokens of banned users are refused, and `role` is the
+/// one the user holds now rather than when the token was issued.
 pub fn extract_claims(req: &HttpRequest) -> Option<Claims> {
+    let config = req.app_data::<web::Data<Config>>()?;
     let auth_header = req.headers().get("Authorization")?.to_str().ok()?;
     let token = auth_header.strip_prefix("Bearer ")?;
-    validate_token(token)
-}
-
-pub(crate) fn discord_api_base_url() -> String {
-    std::env::var("DISCORD_API_BASE_URL").unwrap_or_else(|_| "https://discord.com/api/v10".into())
+    let mut claims = validate_token(config, token)?;
+    if req.app_data::<web::Data<BannedUsers>>().is_some_and(|banned| is_banned(banned, &claims.sub)) {
+        return None;
+    }
+    if let Some(role) = req.app_data::<web::Data<CurrentAccess>>().and_then(|access| current_role(access, &claims.sub)) {
+        claims.role = role;
+    }
+    Some(claims)
 }
 
-pub(crate) fn discord_cdn_base_url() -> String {
-    std::env::var("DISCORD_CDN_BASE_URL").unwrap_or_else(|_| "https://cdn.discordapp.com".into())
+/// The cipher for stored Discord tokens, or the error to return when
+/// `auth.encryption_key` is not set.
+fn discord_token_cipher(config: &Config) -> Result<TokenCipher, AppError> {
+    config.auth.token_cipher().ok_or(AppError::DiscordLoginDisabled)
 }
 
-pub(crate) fn discord_avatar_url(discord_user: &DiscordUser) -> Option<String> {
+/// Where to fetch the Discord account's avatar from, to copy it into upload storage.
+pub(crate) fn discord_avatar_url(config: &Config, discord_user: &DiscordUser) -> Option<String> {
     let avatar_hash = discord_user.avatar.as_ref()?;
     Some(format!(
-        "{}/avatars/{}/{}.png?size=256",
-        discord_cdn_base_url(),
+        "{}/avatars/{}/{}.png?size=512",
+        config.discord.cdn_base_url,
         discord_user.id,
         avatar_hash
     ))
@@ -140,7 +160,8 @@ pub(crate) fn preferred_discord_username(discord_user: &DiscordUser) -> String {
     }
 }
 
-pub(crate) async fn allocate_unique_username(pool: &SqlitePool, preferred: &str) -> String {
+#[tracing::instrument(level = "debug", skip_all, fields(preferred))]
+pub(crate) async fn allocate_unique_username(pool: &DbPool, preferred: &str) -> String {
     let base = if preferred.trim().is_empty() {
         "discord-user"
     } else {
@@ -153,10 +174,11 @@ pub(crate) async fn allocate_unique_username(pool: &SqlitePool, preferred: &str)
         } else {
             format!("{}-{}", base, index)
         };
-        let count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users WHERE username = ?")
+        let count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users WHERE username = $1")
             .bind(&candidate)
             .fetch_one(pool)
             .await
+            .log_err("Reading users")
             .unwrap_or(0);
         if count == 0 {
             return candidate;
@@ -166,48 +188,152 @@ pub(crate) async fn allocate_unique_username(pool: &SqlitePool, preferred: &str)
     format!("discord-{}", Uuid::new_v4().as_simple())
 }
 
+// ── Validation ──────────────────────────────────────────
+// Shared with the `voxium-admin` CLI, which writes to the same tables.
+
+pub const MIN_PASSWORD_LEN: usize = 8;
+pub const PROTECTED_ROLES: [&str; 2] = ["admin", "user"];
+
+pub fn validate_new_user(username: &str, password: &str) -> Result<(), AppError> {
+    if username.trim().is_empty() {
+        return Err(AppError::UsernameRequired);
+    }
+    validate_password(password)
+}
+
+pub fn validate_password(password: &str) -> Result<(), AppError> {
+    if password.len() < MIN_PASSWORD_LEN {
+        return Err(AppError::PasswordTooShort);
+    }
+    Ok(())
+}
+
+/// Lowercased role name, or why it is not acceptable for a new role.
+pub fn normalize_role_name(name: &str) -> Result<String, AppError> {
+    let name = name.trim().to_lowercase();
+    if name.len() < 2 || name.len() > 24 {
+        return Err(AppError::RoleNameLength);
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(AppError::RoleNameCharacters);
+    }
+    Ok(name)
+}
+
+pub fn is_valid_role_color(color: &str) -> bool {
+    color.len() == 7 && color.starts_with('#') && color.chars().skip(1).all(|c| c.is_ascii_hexdigit())
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(name))]
+pub async fn role_exists(pool: &DbPool, name: &str) -> bool {
+    sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM roles WHERE name = $1")
+        .bind(name)
+        .fetch_one(pool)
+        .await
+        .log_err("Reading roles")
+        .unwrap_or(0)
+        > 0
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(username))]
+pub async fn username_taken(pool: &DbPool, username: &str) -> bool {
+    sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users WHERE username = $1")
+        .bind(username)
+        .fetch_one(pool)
+        .await
+        .log_err("Reading users")
+        .unwrap_or(0)
+        > 0
+}
+
+/// Delete a user and their messages. False when the user does not exist.
+#[tracing::instrument(level = "debug", skip_all, fields(user_id))]
+pub async fn remove_user(pool: &DbPool, user_id: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query("DELETE FROM messages WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .log_err("Deleting from messages");
+
+    let result = sqlx::query("DELETE FROM users WHERE id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Delete a role, moving its members back to `user`. False when the role
+/// does not exist.
+#[tracing::instrument(level = "debug", skip_all, fields(name))]
+pub async fn remove_role(pool: &DbPool, name: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query("UPDATE users SET role = 'user' WHERE role = $1")
+        .bind(name)
+        .execute(pool)
+        .await
+        .log_err("Updating users");
+
+    let result = sqlx::query("DELETE FROM roles WHERE name = $1")
+        .bind(name)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
 // ── Handlers ────────────────────────────────────────────
 
+/// POST /api/register — Create an account
+#[utoipa::path(
+    post,
+    path = "/api/register",
+    tag = "auth",
+    request_body = AuthPayload,
+    responses(
+        (status = 200, description = "Account created and signed in", body = AuthResponse),
+        (status = 400, description = "Missing username or password too short", body = ErrorBody),
+        (status = 403, description = "Registration closed, or invite code missing or invalid", body = ErrorBody),
+        (status = 409, description = "Username taken", body = ErrorBody),
+    )
+)]
 pub async fn register(
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
     body: web::Json<AuthPayload>,
-) -> HttpResponse {
+) -> Result<HttpResponse, AppError> {
     let username = body.username.trim();
-    if username.is_empty() || body.password.len() < 8 {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Username must be non-empty and password at least 8 characters"
-        }));
+    validate_new_user(username, &body.password)?;
+
+    let mode = config.auth.registration_mode;
+    if mode == RegistrationMode::Closed {
+        return Err(AppError::RegistrationClosed);
     }
 
     // Check if duplicate
-    let exists = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users WHERE username = ?")
-        .bind(username)
-        .fetch_one(pool.get_ref())
-        .await
-        .unwrap_or(0);
+    if username_taken(pool.get_ref(), username).await {
+        return Err(AppError::UsernameTaken);
+    }
 
-    if exists > 0 {
-        return HttpResponse::Conflict().json(serde_json::json!({
-            "error": "Username already taken"
-        }));
+    if mode == RegistrationMode::Invite {
+        let code = body.invite_code.as_deref().unwrap_or("");
+        if code.trim().is_empty() || !redeem_invite(pool.get_ref(), code).await {
+            return Err(AppError::InviteRequired);
+        }
     }
 
     let id = Uuid::new_v4().to_string();
     let password_hash = hash(&body.password, DEFAULT_COST).expect("hash failed");
     let role = "user"; // Default role
 
-    sqlx::query("INSERT INTO users (id, username, password_hash, role) VALUES (?, ?, ?, ?)")
+    sqlx::query("INSERT INTO users (id, username, password_hash, role) VALUES ($1, $2, $3, $4)")
         .bind(&id)
         .bind(username)
         .bind(&password_hash)
         .bind(role)
         .execute(pool.get_ref())
-        .await
-        .expect("insert user failed");
+        .await?;
 
-    let token = create_token(&id, username, role);
+    let token = create_token(&config, &id, username, role);
 
-    HttpResponse::Ok().json(AuthResponse {
+    Ok(HttpResponse::Ok().json(AuthResponse {
         token,
         user_id: id,
         username: username.to_string(),
@@ -216,19 +342,31 @@ pub async fn register(
         about: "".to_string(),
         avatar_url: None,
         banner_url: None,
-    })
+    }))
 }
 
+/// POST /api/login — Sign in with a username and password
+#[utoipa::path(
+    post,
+    path = "/api/login",
+    tag = "auth",
+    request_body = AuthPayload,
+    responses(
+        (status = 200, description = "Signed in", body = AuthResponse),
+        (status = 401, description = "Wrong username or password", body = ErrorBody),
+        (status = 403, description = "Account banned", body = ErrorBody),
+    )
+)]
 pub async fn login(
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
     body: web::Json<AuthPayload>,
-) -> HttpResponse {
+) -> Result<HttpResponse, AppError> {
     // We select all user fields now
-    let row = sqlx::query("SELECT id, password_hash, role, avatar_color, about, avatar_url, banner_url FROM users WHERE username = ?")
+    let row = sqlx::query("SELECT id, password_hash, role, avatar_color, about, avatar_url, banner_url FROM users WHERE username = $1")
         .bind(&body.username)
         .fetch_optional(pool.get_ref())
-        .await
-        .expect("query failed");
+        .await?;
 
     if let Some(row) = row {
         let id: String = row.get("id");
@@ -240,8 +378,11 @@ pub async fn login(
         let banner_url: Option<String> = row.try_get("banner_url").unwrap_or(None);
 
         if verify(&body.password, &password_hash).unwrap_or(false) {
-            let token = create_token(&id, &body.username, &role);
-            HttpResponse::Ok().json(AuthResponse {
+            if let Some(ban) = active_ban(pool.get_ref(), &id).await {
+                return Err(AppError::Banned(ban));
+            }
+            let token = create_token(&config, &id, &body.username, &role);
+            Ok(HttpResponse::Ok().json(AuthResponse {
                 token,
                 user_id: id,
                 username: body.username.clone(),
@@ -250,50 +391,51 @@ pub async fn login(
                 about,
                 avatar_url,
                 banner_url,
-            })
+            }))
         } else {
-             HttpResponse::Unauthorized().json(serde_json::json!({ "error": "Invalid username or password" }))
+            Err(AppError::InvalidCredentials)
         }
     } else {
-        HttpResponse::Unauthorized().json(serde_json::json!({ "error": "Invalid username or password" }))
+        Err(AppError::InvalidCredentials)
     }
 }
 
 /// Core logic: validate a Discord user token, create/update local user, return AuthResponse.
+#[tracing::instrument(level = "debug", skip_all)]
 pub(crate) async fn do_discord_token_login(
-    pool: &SqlitePool,
+    pool: &DbPool,
+    config: &Config,
+    storage: &dyn Storage,
     discord_token: &str,
-) -> Result<AuthResponse, String> {
+) -> Result<AuthResponse, AppError> {
+    let cipher = discord_token_cipher(config)?;
     let client = Client::new();
     let discord_user_response = client
-        .get(format!("{}/users/@me", discord_api_base_url()))
+        .get(format!("{}/users/@me", config.discord.api_base_url))
         .header("Authorization", discord_token)
         .send()
         .await
-        .map_err(|_| "Discord API indisponible".to_string())?;
+        .map_err(|_| AppError::DiscordUnavailable)?;
 
     if !discord_user_response.status().is_success() {
-        let details = discord_user_response
-            .text()
-            .await
-            .unwrap_or_else(|_| "Token Discord invalide".to_string());
-        return Err(format!("Token Discord invalide ou expiré: {details}"));
+        let details = discord_user_response.text().await.unwrap_or_default();
+        return Err(AppError::DiscordTokenInvalid(details));
     }
 
     let discord_user: DiscordUser = discord_user_response
         .json()
         .await
-        .map_err(|_| "Réponse Discord invalide".to_string())?;
+        .map_err(|_| AppError::DiscordBadResponse)?;
 
-    let discord_avatar = discord_avatar_url(&discord_user);
+    let discord_avatar_source = discord_avatar_url(config, &discord_user);
 
     let existing = sqlx::query(
-        "SELECT id, username, role, avatar_color, about, avatar_url, banner_url FROM users WHERE discord_id = ?",
+        "SELECT id, username, role, avatar_color, about, avatar_url, banner_url FROM users WHERE discord_id = $1",
     )
     .bind(&discord_user.id)
     .fetch_optional(pool)
     .await
-    .ok()
+    .log_err("Reading users")
     .flatten();
 
     let (user_id, username, role, avatar_color, about, avatar_url, banner_url) =
@@ -304,19 +446,50 @@ pub(crate) async fn do_discord_token_login(
             let avatar_color: i32 = row.try_get("avatar_color").unwrap_or(0);
             let about: String = row.try_get("about").unwrap_or_default();
             let old_avatar_url: Option<String> = row.try_get("avatar_url").unwrap_or(None);
+            let old_avatar_url = old_avatar_url.filter(|url| !url.is_empty());
             let banner_url: Option<String> = row.try_get("banner_url").unwrap_or(None);
-            let merged_avatar_url = discord_avatar.clone().or(old_avatar_url);
 
-            let encrypted_token = crate::crypto::encrypt_token(discord_token);
-            let _ = sqlx::query("UPDATE users SET discord_access_token = ?, discord_refresh_token = NULL, discord_token_expires_at = NULL, avatar_url = ? WHERE id = ?")
+            if let Some(ban) = active_ban(pool, &user_id).await {
+                return Err(AppError::Banned(ban));
+            }
+
+            let discord_avatar = match &discord_avatar_source {
+                Some(source) => import_avatar(pool, config, storage, &user_id, source, old_avatar_url.as_deref()).await,
+                None => None,
+            };
+
+            let encrypted_token = cipher.encrypt(discord_token);
+            let updated = sqlx::query("UPDATE users SET discord_access_token = $1, discord_refresh_token = NULL, discord_token_expires_at = NULL, avatar_url = COALESCE($2, avatar_url) WHERE id = $3")
                 .bind(encrypted_token)
-                .bind(&merged_avatar_url)
+                .bind(&discord_avatar)
                 .bind(&user_id)
                 .execute(pool)
-                .await;
-
-            (user_id, username, role, avatar_color, about, merged_avatar_url, banner_url)
+                .await
+                .log_err("Updating users")
+                .is_some();
+
+            // Drop whichever image the profile no longer holds (for an unchanged
+            // avatar, the extra reference the import just took)
+            let avatar_url = match (discord_avatar, updated) {
+                (Some(new), true) => {
+                    if let Some(old) = &old_avatar_url {
+                        crate::uploads::release_upload(pool, storage, old).await;
+                    }
+                    Some(new)
+                }
+                (Some(new), false) => {
+                    crate::uploads::release_upload(pool, storage, &new).await;
+                    old_avatar_url
+                }
+                (None, _) => old_avatar_url,
+            };
+
+            (user_id, username, role, avatar_color, about, avatar_url, banner_url)
         } else {
+            if config.auth.registration_mode != RegistrationMode::Open {
+                return Err(AppError::RegistrationClosed);
+            }
+
             let user_id = Uuid::new_v4().to_string();
             let role = "user".to_string();
             let avatar_color = 0;
@@ -327,8 +500,13 @@ pub(crate) async fn do_discord_token_login(
             let generated_password = Uuid::new_v4().to_string();
             let password_hash = hash(generated_password, DEFAULT_COST).expect("hash failed");
 
-            let encrypted_token = crate::crypto::encrypt_token(discord_token);
-            let insert_result = sqlx::query("INSERT INTO users (id, username, password_hash, role, avatar_color, about, avatar_url, banner_url, discord_id, discord_access_token) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
+            let discord_avatar = match &discord_avatar_source {
+                Some(source) => import_avatar(pool, config, storage, &user_id, source, None).await,
+                None => None,
+            };
+
+            let encrypted_token = cipher.encrypt(discord_token);
+            let inserted = sqlx::query("INSERT INTO users (id, username, password_hash, role, avatar_color, about, avatar_url, banner_url, discord_id, discord_access_token) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)")
                 .bind(&user_id)
                 .bind(&username)
                 .bind(&password_hash)
@@ -341,9 +519,11 @@ pub(crate) async fn do_discord_token_login(
                 .bind(encrypted_token)
                 .execute(pool)
                 .await;
-
-            if insert_result.is_err() {
-                return Err("Impossible de créer l'utilisateur Discord local".to_string());
+            if let Err(e) = inserted {
+                if let Some(url) = &discord_avatar {
+                    crate::uploads::release_upload(pool, storage, url).await;
+                }
+                return Err(e.into());
             }
 
             (
@@ -357,7 +537,7 @@ pub(crate) async fn do_discord_token_login(
             )
         };
 
-    let token = create_token(&user_id, &username, &role);
+    let token = create_token(config, &user_id, &username, &role);
     Ok(AuthResponse {
         token,
         user_id,
@@ -371,90 +551,112 @@ pub(crate) async fn do_discord_token_login(
 }
 
 /// POST /api/auth/discord/token — Login with a Discord user token.
+#[utoipa::path(
+    post,
+    path = "/api/auth/discord/token",
+    tag = "auth",
+    request_body = DiscordUserTokenPayload,
+    responses(
+        (status = 200, description = "Signed in, creating the account on first login", body = AuthResponse),
+        (status = 400, description = "Token missing", body = ErrorBody),
+        (status = 401, description = "Discord rejected the token", body = ErrorBody),
+        (status = 403, description = "Account banned, or registration closed", body = ErrorBody),
+        (status = 502, description = "Discord unreachable or gave an unexpected answer", body = ErrorBody),
+        (status = 503, description = "Discord login disabled (no encryption key)", body = ErrorBody),
+    )
+)]
 pub async fn login_discord_token(
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<SharedStorage>,
     body: web::Json<DiscordUserTokenPayload>,
-) -> HttpResponse {
+) -> Result<HttpResponse, AppError> {
     let discord_token = body.discord_token.trim().to_string();
     if discord_token.is_empty() {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "discord_token manquant"
-        }));
-    }
-    match do_discord_token_login(pool.get_ref(), &discord_token).await {
-        Ok(auth) => HttpResponse::Ok().json(auth),
-        Err(msg) => HttpResponse::Unauthorized().json(serde_json::json!({ "error": msg })),
+        return Err(AppError::DiscordTokenRequired);
     }
+    let auth = do_discord_token_login(pool.get_ref(), &config, storage.get_ref().as_ref(), &discord_token).await?;
+    Ok(HttpResponse::Ok().json(auth))
 }
 
-/// GET /api/discord/me — Fetch the current user's Discord profile using the stored user token.
-pub async fn get_discord_me(req: HttpRequest, pool: web::Data<SqlitePool>) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
-
-    let row = sqlx::query(
-        "SELECT discord_access_token FROM users WHERE id = ?",
-    )
-    .bind(&claims.sub)
-    .fetch_optional(pool.get_ref())
-    .await
-    .unwrap_or(None);
-
-    let Some(row) = row else {
-        return HttpResponse::NotFound().finish();
-    };
+/// The current user's Discord token, decrypted.
+async fn stored_discord_token(pool: &DbPool, config: &Config, user_id: &str) -> Result<String, AppError> {
+    let row = sqlx::query("SELECT discord_access_token FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(AppError::UserNotFound)?;
 
     let access_token: Option<String> = row.try_get("discord_access_token").unwrap_or(None);
+    let encrypted_token = access_token.ok_or(AppError::DiscordNotLinked)?;
+    discord_token_cipher(config)?
+        .decrypt(&encrypted_token)
+        .ok_or(AppError::DiscordTokenUnreadable)
+}
 
-    let Some(encrypted_token) = access_token else {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Aucun token Discord lié"
-        }));
-    };
-
-    let Some(access_token) = crate::crypto::decrypt_token(&encrypted_token) else {
-         return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "Echec du déchiffrement du token"
-        }));
-    };
-
-    let response = match Client::new()
-        .get(format!("{}/users/@me", discord_api_base_url()))
+/// GET /api/discord/me — Fetch the current user's Discord profile using the stored user token.
+#[utoipa::path(
+    get,
+    path = "/api/discord/me",
+    tag = "discord",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Discord's user object, passed through", body = Object),
+        (status = 400, description = "No Discord account linked", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 502, description = "Discord unreachable", body = ErrorBody),
+    )
+)]
+pub async fn get_discord_me(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
+    let access_token = stored_discord_token(pool.get_ref(), &config, &claims.sub).await?;
+
+    let response = Client::new()
+        .get(format!("{}/users/@me", config.discord.api_base_url))
         .header("Authorization", &access_token)
         .send()
         .await
-    {
-        Ok(res) => res,
-        Err(_) => return HttpResponse::BadGateway().json(serde_json::json!({ "error": "Discord API indisponible" })),
-    };
+        .map_err(|_| AppError::DiscordUnavailable)?;
 
     let status = StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
     let payload = response.text().await.unwrap_or_else(|_| "{}".to_string());
     if let Ok(json) = serde_json::from_str::<serde_json::Value>(&payload) {
-        HttpResponse::build(status).json(json)
+        Ok(HttpResponse::build(status).json(json))
     } else {
-        HttpResponse::build(status).body(payload)
+        Ok(HttpResponse::build(status).body(payload))
     }
 }
 
 /// POST /api/discord/proxy — Proxy any Discord API call using the stored user token.
+#[utoipa::path(
+    post,
+    path = "/api/discord/proxy",
+    tag = "discord",
+    security(("bearer" = [])),
+    request_body = DiscordProxyPayload,
+    responses(
+        (status = 200, description = "Discord's answer, passed through with its status code", body = Object),
+        (status = 400, description = "Invalid path or method, or no Discord account linked", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Path not allowed", body = ErrorBody),
+        (status = 502, description = "Discord unreachable", body = ErrorBody),
+    )
+)]
 pub async fn discord_proxy(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
     body: web::Json<DiscordProxyPayload>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     let raw_path = body.path.trim();
     if raw_path.is_empty() || !raw_path.starts_with('/') || raw_path.starts_with("//") {
-         return HttpResponse::BadRequest().json(serde_json::json!({
-             "error": "Discord path invalide"
-         }));
+        return Err(AppError::DiscordPathInvalid);
     }
 
     // Normalize: URL-decode and lowercase to prevent bypass via encoding or case tricks
@@ -465,17 +667,13 @@ pub async fn discord_proxy(
     let allowed_prefixes = ["/users/@me", "/guilds/", "/channels/"];
     let is_allowed = allowed_prefixes.iter().any(|p| path.starts_with(p));
     if !is_allowed {
-        return HttpResponse::Forbidden().json(serde_json::json!({
-             "error": "Path not allowed"
-        }));
+        return Err(AppError::DiscordPathNotAllowed);
     }
 
     // Block sensitive Discord API endpoints even if they match a prefix
     let blocked_keywords = ["billing", "payment", "delete", "disable", "prune", "connections"];
     if blocked_keywords.iter().any(|b| path.contains(b)) {
-        return HttpResponse::Forbidden().json(serde_json::json!({
-             "error": "Path not allowed"
-        }));
+        return Err(AppError::DiscordPathNotAllowed);
     }
 
     // Use the original path (not lowercased) for the actual Discord API call
@@ -489,34 +687,10 @@ pub async fn discord_proxy(
         .to_uppercase();
     let allowed = ["GET", "POST", "PUT", "PATCH", "DELETE"];
     if !allowed.contains(&method.as_str()) {
-        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Method not allowed" }));
+        return Err(AppError::DiscordMethodNotAllowed);
     }
 
-    let row = sqlx::query(
-        "SELECT discord_access_token FROM users WHERE id = ?",
-    )
-    .bind(&claims.sub)
-    .fetch_optional(pool.get_ref())
-    .await
-    .unwrap_or(None);
-
-    let Some(row) = row else {
-        return HttpResponse::NotFound().finish();
-    };
-
-    let access_token: Option<String> = row.try_get("discord_access_token").unwrap_or(None);
-
-    let Some(encrypted_token) = access_token else {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Aucun token Discord lié"
-        }));
-    };
-
-    let Some(access_token) = crate::crypto::decrypt_token(&encrypted_token) else {
-         return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "Echec du déchiffrement du token"
-        }));
-    };
+    let access_token = stored_discord_token(pool.get_ref(), &config, &claims.sub).await?;
 
     let method_obj = match method.as_str() {
         "GET" => reqwest::Method::GET,
@@ -528,79 +702,127 @@ pub async fn discord_proxy(
     };
 
     let mut request_builder = Client::new()
-        .request(method_obj, format!("{}{}", discord_api_base_url(), path))
+        .request(method_obj, format!("{}{}", config.discord.api_base_url, path))
         .header("Authorization", &access_token);
 
     if let Some(json_body) = &body.body {
         request_builder = request_builder.json(json_body);
     }
 
-    let response = match request_builder.send().await {
-        Ok(res) => res,
-        Err(_) => {
-            return HttpResponse::BadGateway().json(serde_json::json!({
-                "error": "Discord API indisponible"
-            }))
-        }
-    };
+    let response = request_builder.send().await.map_err(|_| AppError::DiscordUnavailable)?;
 
     let status = StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
     let payload = response.text().await.unwrap_or_else(|_| "{}".to_string());
 
     if let Ok(json) = serde_json::from_str::<serde_json::Value>(&payload) {
-        HttpResponse::build(status).json(json)
+        Ok(HttpResponse::build(status).json(json))
     } else {
-        HttpResponse::build(status).body(payload)
+        Ok(HttpResponse::build(status).body(payload))
     }
 }
 
+/// GET /api/users/me — Current user's profile
+#[utoipa::path(
+    get,
+    path = "/api/users/me",
+    tag = "users",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Current user's profile", body = Object, example = json!({
+            "user_id": "3f0c…", "username": "alice", "role": "user", "avatar_color": 3,
+            "about": "", "avatar_url": null, "banner_url": null
+        })),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Account deleted", body = ErrorBody),
+    )
+)]
 pub async fn get_me(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
-    let row = sqlx::query("SELECT username, role, avatar_color, about, avatar_url, banner_url FROM users WHERE id = ?")
+    let row = sqlx::query("SELECT username, role, avatar_color, about, avatar_url, banner_url FROM users WHERE id = $1")
         .bind(&claims.sub)
         .fetch_optional(pool.get_ref())
         .await
-        .unwrap_or(None);
+        .log_err("Reading users")
+        .flatten();
+
+    let row = row.ok_or(AppError::UserNotFound)?;
+    let username: String = row.get("username");
+    let role: String = row.get("role");
+    let avatar_color: i32 = row.try_get("avatar_color").unwrap_or(0);
+    let about: String = row.try_get("about").unwrap_or_default();
+    let avatar_url: Option<String> = row.try_get("avatar_url").unwrap_or(None);
+    let banner_url: Option<String> = row.try_get("banner_url").unwrap_or(None);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "user_id": claims.sub,
+        "username": username,
+        "role": role,
+        "avatar_color": avatar_color,
+        "about": about,
+        "avatar_url": avatar_url,
+        "banner_url": banner_url,
+    })))
+}
 
-    if let Some(row) = row {
-         let username: String = row.get("username");
-         let role: String = row.get("role");
-         let avatar_color: i32 = row.try_get("avatar_color").unwrap_or(0);
-         let about: String = row.try_get("about").unwrap_or_default();
-         let avatar_url: Option<String> = row.try_get("avatar_url").unwrap_or(None);
-         let banner_url: Option<String> = row.try_get("banner_url").unwrap_or(None);
-
-         HttpResponse::Ok().json(serde_json::json!({
-             "user_id": claims.sub,
-             "username": username,
-             "role": role,
-             "avatar_color": avatar_color,
-             "about": about,
-             "avatar_url": avatar_url,
-             "banner_url": banner_url,
-         }))
-    } else {
-        HttpResponse::NotFound().finish()
+/// Broadcast a user's current profile (as a `join` upsert) unless they are invisible.
+#[tracing::instrument(level = "debug", skip_all, fields(user_id))]
+pub(crate) async fn broadcast_profile(pool: &DbPool, broadcaster: &crate::ws::Broadcaster, user_id: &str) {
+    let user_row = sqlx::query("SELECT username, role, about, avatar_color, avatar_url, banner_url, presence FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .log_err("Reading users")
+        .flatten();
+
+    let Some(row) = user_row else {
+        return;
+    };
+    let presence: String = row.try_get("presence").unwrap_or_else(|_| "online".to_string());
+    // Invisible users must not be re-announced
+    if presence == "invisible" {
+        return;
     }
+
+    let event = serde_json::json!({
+        "type": "join", // handled as upsert by frontend
+        "user_id": user_id,
+        "username": row.get::<String, _>("username"),
+        "role": row.get::<String, _>("role"),
+        "about": row.get::<String, _>("about"),
+        "avatar_color": row.try_get::<i32, _>("avatar_color").unwrap_or(0),
+        "avatar_url": row.try_get::<Option<String>, _>("avatar_url").unwrap_or(None),
+        "banner_url": row.try_get::<Option<String>, _>("banner_url").unwrap_or(None),
+        "status": presence
+    });
+    let _ = broadcaster.send(event.to_string());
 }
 
+/// PATCH /api/users/me — Update the current user's profile
+#[utoipa::path(
+    patch,
+    path = "/api/users/me",
+    tag = "users",
+    security(("bearer" = [])),
+    request_body = UpdateProfile,
+    responses(
+        (status = 200, description = "Profile updated (`no changes` when the body is empty)", body = Object, example = json!({ "status": "updated" })),
+        (status = 400, description = "Invalid value, or a profile image other than `\"\"` (use the upload endpoints)", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 409, description = "Username taken", body = ErrorBody),
+    )
+)]
 pub async fn update_profile(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
     body: web::Json<UpdateProfile>,
     broadcaster: web::Data<crate::ws::Broadcaster>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+    storage: web::Data<crate::storage::SharedStorage>,
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     // ... existing update logic ...
     // Build UPDATE dynamically — avoid Separated API which can produce broken SQL
@@ -609,36 +831,62 @@ pub async fn update_profile(
 
     if let Some(username) = &body.username {
         if !username.trim().is_empty() {
-            set_clauses.push("username = ?");
+            set_clauses.push("username");
         }
     }
     if body.about.is_some() {
-        set_clauses.push("about = ?");
+        set_clauses.push("about");
     }
     if body.avatar_color.is_some() {
-        set_clauses.push("avatar_color = ?");
+        set_clauses.push("avatar_color");
     }
     if let Some(password) = &body.password {
-        if password.len() < 8 {
-            return HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Password must be at least 8 characters"
-            }));
-        }
+        validate_password(password)?;
         password_hash_val = Some(hash(password, DEFAULT_COST).expect("hash failed"));
-        set_clauses.push("password_hash = ?");
+        set_clauses.push("password_hash");
     }
-    if body.avatar_url.is_some() {
-        set_clauses.push("avatar_url = ?");
+    // Profile images are only set through the avatar/banner upload endpoints;
+    // here they can just be cleared with "" (or sent back unchanged)
+    let previous_images: Option<(Option<String>, Option<String>)> =
+        sqlx::query_as("SELECT avatar_url, banner_url FROM users WHERE id = $1")
+            .bind(&claims.sub)
+            .fetch_optional(pool.get_ref())
+            .await
+            .log_err("Reading users")
+            .flatten();
+    let (current_avatar, current_banner) = previous_images.unwrap_or_default();
+    let cleared = |value: &Option<String>, current: &Option<String>| -> Result<Option<String>, AppError> {
+        let Some(value) = value.as_deref().map(|u| crate::uploads::strip_query(u.trim())) else {
+            return Ok(None);
+        };
+        if current.as_deref().unwrap_or("") == value {
+            Ok(None)
+        } else if value.is_empty() {
+            Ok(current.clone())
+        } else {
+            Err(AppError::ForeignProfileImage)
+        }
+    };
+    // The images being cleared, to release once the update went through
+    let cleared_avatar = cleared(&body.avatar_url, &current_avatar)?;
+    let cleared_banner = cleared(&body.banner_url, &current_banner)?;
+    if cleared_avatar.is_some() {
+        set_clauses.push("avatar_url");
     }
-    if body.banner_url.is_some() {
-        set_clauses.push("banner_url = ?");
+    if cleared_banner.is_some() {
+        set_clauses.push("banner_url");
     }
 
     if set_clauses.is_empty() {
-        return HttpResponse::Ok().json(serde_json::json!({ "status": "no changes" }));
+        return Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "no changes" })));
     }
 
-    let sql = format!("UPDATE users SET {} WHERE id = ?", set_clauses.join(", "));
+    let assignments: Vec<String> = set_clauses
+        .iter()
+        .enumerate()
+        .map(|(idx, column)| format!("{} = ${}", column, idx + 1))
+        .collect();
+    let sql = format!("UPDATE users SET {} WHERE id = ${}", assignments.join(", "), set_clauses.len() + 1);
     let mut query = sqlx::query(&sql);
 
     // Bind values in the same order as set_clauses
@@ -656,73 +904,63 @@ pub async fn update_profile(
     if let Some(ph) = &password_hash_val {
         query = query.bind(ph.clone());
     }
-    if let Some(avatar_url) = &body.avatar_url {
-        query = query.bind(avatar_url.clone());
+    if cleared_avatar.is_some() {
+        query = query.bind("");
     }
-    if let Some(banner_url) = &body.banner_url {
-        query = query.bind(banner_url.clone());
+    if cleared_banner.is_some() {
+        query = query.bind("");
     }
 
     query = query.bind(&claims.sub);
 
-    match query.execute(pool.get_ref()).await {
-        Ok(_) => {
-            // Fetch updated user to broadcast
-            let user_row = sqlx::query("SELECT username, role, about, avatar_color, avatar_url, banner_url FROM users WHERE id = ?")
-                .bind(&claims.sub)
-                .fetch_optional(pool.get_ref())
-                .await
-                .unwrap_or(None);
-
-            if let Some(row) = user_row {
-                 use sqlx::Row;
-                 let username: String = row.get("username");
-                 let role: String = row.get("role");
-                 let about: String = row.get("about");
-                 let avatar_color: i32 = row.try_get("avatar_color").unwrap_or(0);
-                 let avatar_url: Option<String> = row.try_get("avatar_url").unwrap_or(None);
-                 let banner_url: Option<String> = row.try_get("banner_url").unwrap_or(None);
-
-                 let event = serde_json::json!({
-                     "type": "join", // handled as upsert by frontend
-                     "user_id": claims.sub,
-                     "username": username,
-                     "role": role,
-                     "about": about,
-                     "avatar_color": avatar_color,
-                     "avatar_url": avatar_url,
-                     "banner_url": banner_url
-                 });
-                 let _ = broadcaster.send(event.to_string());
-            }
+    if let Err(e) = query.execute(pool.get_ref()).await {
+        let unique_violation = e.as_database_error().is_some_and(|db| db.is_unique_violation());
+        return Err(if unique_violation { AppError::UsernameTaken } else { e.into() });
+    }
 
-            HttpResponse::Ok().json(serde_json::json!({ "status": "updated" }))
-        },
-        Err(e) => {
-            eprintln!("Profile update error: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Update failed (username might be taken)" }))
-        }
+    for old in [cleared_avatar, cleared_banner].into_iter().flatten() {
+        crate::uploads::release_upload(pool.get_ref(), storage.get_ref().as_ref(), &old).await;
     }
+
+    broadcast_profile(pool.get_ref(), broadcaster.get_ref(), &claims.sub).await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "updated" })))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct UpdateRole {
     pub role: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ServerRole {
     pub name: String,
     pub color: String,
+    pub move_members: bool,
+    /// Upload quota in bytes (0 = unlimited); `None` uses the server default
+    pub storage_quota: Option<i64>,
+    pub animated_avatars: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateServerRole {
     pub name: String,
     pub color: Option<String>,
+    pub move_members: Option<bool>,
+    pub storage_quota: Option<i64>,
+    pub animated_avatars: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateServerRole {
+    pub color: Option<String>,
+    pub move_members: Option<bool>,
+    /// Negative resets the role to the server default
+    pub storage_quota: Option<i64>,
+    pub animated_avatars: Option<bool>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ServerUser {
     pub id: String,
     pub username: String,
@@ -730,63 +968,71 @@ pub struct ServerUser {
 }
 
 /// GET /api/server/roles — List roles (Admin only)
+#[utoipa::path(
+    get,
+    path = "/api/server/roles",
+    tag = "server",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Every role", body = [ServerRole]),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Admin only", body = ErrorBody),
+    )
+)]
 pub async fn list_server_roles(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     if claims.role != "admin" {
-        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Admin only" }));
+        return Err(AppError::AdminOnly);
     }
 
-    let rows = sqlx::query("SELECT name, color FROM roles ORDER BY CASE WHEN name='admin' THEN 0 WHEN name='user' THEN 1 ELSE 2 END, name ASC")
+    let rows = sqlx::query("SELECT name, color, move_members, storage_quota, animated_avatars FROM roles ORDER BY CASE WHEN name='admin' THEN 0 WHEN name='user' THEN 1 ELSE 2 END, name ASC")
         .fetch_all(pool.get_ref())
-        .await;
-
-    match rows {
-        Ok(rows) => {
-            let roles: Vec<ServerRole> = rows
-                .into_iter()
-                .map(|row| ServerRole {
-                    name: row.get("name"),
-                    color: row.get("color"),
-                })
-                .collect();
-            HttpResponse::Ok().json(roles)
-        }
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
+        .await?;
+
+    let roles: Vec<ServerRole> = rows
+        .into_iter()
+        .map(|row| ServerRole {
+            name: row.get("name"),
+            color: row.get("color"),
+            move_members: row.try_get::<i64, _>("move_members").unwrap_or(0) != 0,
+            storage_quota: row.try_get("storage_quota").unwrap_or(None),
+            animated_avatars: row.try_get::<i64, _>("animated_avatars").unwrap_or(0) != 0,
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(roles))
 }
 
 /// POST /api/server/roles — Create role (Admin only)
+#[utoipa::path(
+    post,
+    path = "/api/server/roles",
+    tag = "server",
+    security(("bearer" = [])),
+    request_body = CreateServerRole,
+    responses(
+        (status = 200, description = "Role created", body = Object, example = json!({ "status": "role created" })),
+        (status = 400, description = "Invalid name, color or quota", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Admin only", body = ErrorBody),
+        (status = 409, description = "Role already exists", body = ErrorBody),
+    )
+)]
 pub async fn create_server_role(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
     body: web::Json<CreateServerRole>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     if claims.role != "admin" {
-        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Admin only" }));
+        return Err(AppError::AdminOnly);
     }
 
-    let role_name = body.name.trim().to_lowercase();
-    if role_name.len() < 2 || role_name.len() > 24 {
-        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Role name must be 2 to 24 chars" }));
-    }
-    if !role_name
-        .chars()
-        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
-    {
-        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Role name can only contain a-z, 0-9, _ and -" }));
-    }
+    let role_name = normalize_role_name(&body.name)?;
 
     let color = body
         .color
@@ -795,213 +1041,277 @@ pub async fn create_server_role(
         .trim()
         .to_string();
 
-    if color.len() != 7 || !color.starts_with('#') || !color.chars().skip(1).all(|c| c.is_ascii_hexdigit()) {
-        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Invalid role color (expected #RRGGBB)" }));
+    if !is_valid_role_color(&color) {
+        return Err(AppError::InvalidRoleColor);
     }
 
-    let result = sqlx::query("INSERT INTO roles (name, color) VALUES (?, ?)")
+    if body.storage_quota.is_some_and(|q| q < 0) {
+        return Err(AppError::NegativeStorageQuota);
+    }
+
+    sqlx::query("INSERT INTO roles (name, color, move_members, storage_quota, animated_avatars) VALUES ($1, $2, $3, $4, $5)")
         .bind(&role_name)
         .bind(&color)
+        .bind(body.move_members.unwrap_or(false) as i64)
+        .bind(body.storage_quota)
+        .bind(body.animated_avatars.unwrap_or(false) as i64)
         .execute(pool.get_ref())
-        .await;
+        .await
+        .map_err(|_| AppError::RoleExists)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "role created" })))
+}
+
+/// PATCH /api/server/roles/{name} — Update role color / permissions (Admin only)
+#[utoipa::path(
+    patch,
+    path = "/api/server/roles/{name}",
+    tag = "server",
+    security(("bearer" = [])),
+    params(("name" = String, Path, description = "Role name")),
+    request_body = UpdateServerRole,
+    responses(
+        (status = 200, description = "Role updated", body = Object, example = json!({ "status": "role updated" })),
+        (status = 400, description = "Invalid color", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Admin only", body = ErrorBody),
+        (status = 404, description = "No such role", body = ErrorBody),
+    )
+)]
+pub async fn update_server_role(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+    body: web::Json<UpdateServerRole>,
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
-    match result {
-        Ok(_) => HttpResponse::Ok().json(serde_json::json!({ "status": "role created" })),
-        Err(_) => HttpResponse::Conflict().json(serde_json::json!({ "error": "Role already exists" })),
+    if claims.role != "admin" {
+        return Err(AppError::AdminOnly);
     }
+
+    let role_name = path.into_inner().trim().to_lowercase();
+
+    let color = body.color.as_deref().map(|c| c.trim().to_string());
+    if let Some(ref color) = color {
+        if !is_valid_role_color(color) {
+            return Err(AppError::InvalidRoleColor);
+        }
+    }
+
+    let result = sqlx::query(
+        "UPDATE roles SET color = COALESCE($1, color), move_members = COALESCE($2, move_members), \
+         storage_quota = CASE WHEN $3 IS NULL THEN storage_quota WHEN $3 < 0 THEN NULL ELSE $3 END, \
+         animated_avatars = COALESCE($4, animated_avatars) \
+         WHERE name = $5"
+    )
+    .bind(&color)
+    .bind(body.move_members.map(i64::from))
+    .bind(body.storage_quota)
+    .bind(body.animated_avatars.map(i64::from))
+    .bind(&role_name)
+    .execute(pool.get_ref())
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::RoleNotFound);
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "role updated" })))
 }
 
 /// DELETE /api/server/roles/{name} — Delete role (Admin only)
+#[utoipa::path(
+    delete,
+    path = "/api/server/roles/{name}",
+    tag = "server",
+    security(("bearer" = [])),
+    params(("name" = String, Path, description = "Role name")),
+    responses(
+        (status = 200, description = "Role deleted, its members moved back to `user`", body = Object, example = json!({ "status": "role deleted" })),
+        (status = 400, description = "`user` and `admin` cannot be deleted", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Admin only", body = ErrorBody),
+        (status = 404, description = "No such role", body = ErrorBody),
+    )
+)]
 pub async fn delete_server_role(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
     path: web::Path<String>,
+    broadcaster: web::Data<crate::ws::Broadcaster>,
     access_cache: web::Data<crate::ws::AccessCache>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+    current_access: web::Data<CurrentAccess>,
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     if claims.role != "admin" {
-        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Admin only" }));
+        return Err(AppError::AdminOnly);
     }
 
     let role_name = path.into_inner().trim().to_lowercase();
-    if role_name == "admin" || role_name == "user" {
-        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "This role is protected" }));
+    if PROTECTED_ROLES.contains(&role_name.as_str()) {
+        return Err(AppError::ProtectedRole);
     }
 
-    let _ = sqlx::query("UPDATE users SET role = 'user' WHERE role = ?")
-        .bind(&role_name)
-        .execute(pool.get_ref())
-        .await;
-
+    let result = remove_role(pool.get_ref(), &role_name).await;
     crate::ws::cache_clear_user_roles(access_cache.get_ref());
+    refresh_access(pool.get_ref(), current_access.get_ref(), access_cache.get_ref(), broadcaster.get_ref()).await;
 
-    let result = sqlx::query("DELETE FROM roles WHERE name = ?")
-        .bind(&role_name)
-        .execute(pool.get_ref())
-        .await;
-
-    match result {
-        Ok(res) => {
-            if res.rows_affected() > 0 {
-                HttpResponse::Ok().json(serde_json::json!({ "status": "role deleted" }))
-            } else {
-                HttpResponse::NotFound().json(serde_json::json!({ "error": "Role not found" }))
-            }
-        }
-        Err(_) => HttpResponse::InternalServerError().finish(),
+    if !result? {
+        return Err(AppError::RoleNotFound);
     }
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "role deleted" })))
 }
 
 /// GET /api/server/users — List users with role (Admin only)
+#[utoipa::path(
+    get,
+    path = "/api/server/users",
+    tag = "server",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Every user with their role", body = [ServerUser]),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Admin only", body = ErrorBody),
+    )
+)]
 pub async fn list_server_users(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     if claims.role != "admin" {
-        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Admin only" }));
+        return Err(AppError::AdminOnly);
     }
 
     let rows = sqlx::query("SELECT id, username, role FROM users ORDER BY username ASC")
         .fetch_all(pool.get_ref())
-        .await;
-
-    match rows {
-        Ok(rows) => {
-            let users: Vec<ServerUser> = rows
-                .into_iter()
-                .map(|row| ServerUser {
-                    id: row.get("id"),
-                    username: row.get("username"),
-                    role: row.get("role"),
-                })
-                .collect();
-            HttpResponse::Ok().json(users)
-        }
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
+        .await?;
+
+    let users: Vec<ServerUser> = rows
+        .into_iter()
+        .map(|row| ServerUser {
+            id: row.get("id"),
+            username: row.get("username"),
+            role: row.get("role"),
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(users))
 }
 
 /// PATCH /api/users/{id}/role — Promote/Demote user (Admin only)
+#[utoipa::path(
+    patch,
+    path = "/api/users/{id}/role",
+    tag = "server",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "User id")),
+    request_body = UpdateRole,
+    responses(
+        (status = 200, description = "Role changed", body = Object, example = json!({ "status": "role updated" })),
+        (status = 400, description = "No such role", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Admin only", body = ErrorBody),
+    )
+)]
 pub async fn update_user_role(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
     path: web::Path<String>,
     body: web::Json<UpdateRole>,
     broadcaster: web::Data<crate::ws::Broadcaster>,
     access_cache: web::Data<crate::ws::AccessCache>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+    current_access: web::Data<CurrentAccess>,
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     if claims.role != "admin" {
-        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Admin only" }));
+        return Err(AppError::AdminOnly);
     }
 
     let target_id = path.into_inner();
     let new_role = &body.role;
 
-    let role_exists = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM roles WHERE name = ?")
-        .bind(new_role)
-        .fetch_one(pool.get_ref())
-        .await
-        .unwrap_or(0);
-
-    if role_exists <= 0 {
-        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Invalid role" }));
+    if !role_exists(pool.get_ref(), new_role).await {
+        return Err(AppError::UnknownRole);
     }
 
-    let result = sqlx::query("UPDATE users SET role = ? WHERE id = ?")
+    sqlx::query("UPDATE users SET role = $1 WHERE id = $2")
         .bind(new_role)
         .bind(&target_id)
         .execute(pool.get_ref())
-        .await;
-
-    match result {
-        Ok(_) => {
-            // Fetch updated user to broadcast
-            let user_row = sqlx::query("SELECT username, role, about, avatar_color, avatar_url, banner_url FROM users WHERE id = ?")
-                .bind(&target_id)
-                .fetch_optional(pool.get_ref())
-                .await
-                .unwrap_or(None);
-
-            if let Some(row) = user_row {
-                 use sqlx::Row;
-                 let username: String = row.get("username");
-                 let role: String = row.get("role");
-                 let about: String = row.get("about");
-                 let avatar_color: i32 = row.try_get("avatar_color").unwrap_or(0);
-                 let avatar_url: Option<String> = row.try_get("avatar_url").unwrap_or(None);
-                 let banner_url: Option<String> = row.try_get("banner_url").unwrap_or(None);
-
-                  crate::ws::cache_set_user_role(access_cache.get_ref(), &target_id, &role);
-
-                 let event = serde_json::json!({
-                     "type": "join", // handled as upsert by frontend
-                     "user_id": target_id,
-                     "username": username,
-                     "role": role,
-                     "about": about,
-                     "avatar_color": avatar_color,
-                     "avatar_url": avatar_url,
-                     "banner_url": banner_url
-                 });
-                 let _ = broadcaster.send(event.to_string());
-            }
-            HttpResponse::Ok().json(serde_json::json!({ "status": "role updated" }))
-        },
-        Err(_) => HttpResponse::InternalServerError().finish(),
+        .await?;
+    refresh_access(pool.get_ref(), current_access.get_ref(), access_cache.get_ref(), broadcaster.get_ref()).await;
+
+    // Fetch updated user to broadcast
+    let user_row = sqlx::query("SELECT username, role, about, avatar_color, avatar_url, banner_url, presence FROM users WHERE id = $1")
+        .bind(&target_id)
+        .fetch_optional(pool.get_ref())
+        .await
+        .log_err("Reading users")
+        .flatten();
+
+    if let Some(row) = user_row {
+        use sqlx::Row;
+        let username: String = row.get("username");
+        let role: String = row.get("role");
+        let about: String = row.get("about");
+        let avatar_color: i32 = row.try_get("avatar_color").unwrap_or(0);
+        let avatar_url: Option<String> = row.try_get("avatar_url").unwrap_or(None);
+        let banner_url: Option<String> = row.try_get("banner_url").unwrap_or(None);
+
+        let presence: String = row.try_get("presence").unwrap_or_else(|_| "online".to_string());
+
+        crate::ws::cache_set_user_role(access_cache.get_ref(), &target_id, &role);
+
+        if presence != "invisible" {
+            let event = serde_json::json!({
+                "type": "join", // handled as upsert by frontend
+                "user_id": target_id,
+                "username": username,
+                "role": role,
+                "about": about,
+                "avatar_color": avatar_color,
+                "avatar_url": avatar_url,
+                "banner_url": banner_url,
+                "status": presence
+            });
+            let _ = broadcaster.send(event.to_string());
+        }
     }
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "role updated" })))
 }
 
 /// DELETE /api/users/{id} — Delete a user (Admin only)
+#[utoipa::path(
+    delete,
+    path = "/api/users/{id}",
+    tag = "server",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "User id")),
+    responses(
+        (status = 200, description = "User deleted", body = Object, example = json!({ "status": "deleted" })),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Admin only", body = ErrorBody),
+        (status = 404, description = "No such user", body = ErrorBody),
+    )
+)]
 pub async fn delete_user(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
     path: web::Path<String>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     if claims.role != "admin" {
-        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Admin only" }));
+        return Err(AppError::AdminOnly);
     }
 
     let target_id = path.into_inner();
 
-    // Delete messages first
-    let _ = sqlx::query("DELETE FROM messages WHERE user_id = ?")
-        .bind(&target_id)
-        .execute(pool.get_ref())
-        .await;
-
-    // Delete user
-    let result = sqlx::query("DELETE FROM users WHERE id = ?")
-        .bind(&target_id)
-        .execute(pool.get_ref())
-        .await;
-
-    match result {
-        Ok(res) => {
-            if res.rows_affected() > 0 {
-                HttpResponse::Ok().json(serde_json::json!({ "status": "deleted" }))
-            } else {
-                HttpResponse::NotFound().json(serde_json::json!({ "error": "User not found" }))
-            }
-        }
-        Err(_) => HttpResponse::InternalServerError().finish(),
+    if !remove_user(pool.get_ref(), &target_id).await? {
+        return Err(AppError::UserNotFound);
     }
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "deleted" })))
 }
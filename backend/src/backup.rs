@@ -0,0 +1,536 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — Backups
+// ═══════════════════════════════════════════════════════
+//
+// A backup is one `voxium-backup-<timestamp>.tar.gz` archive holding, in
+// this order:
+//
+//   manifest.json   — format, schema version and database checksum
+//   voxium.db       — consistent snapshot of the live database (VACUUM INTO)
+//   uploads/<key>   — every stored upload, whatever the storage backend
+//
+// The snapshot is taken first and the uploads copied after it, so files
+// uploaded meanwhile are included (and swept after a restore if unused).
+// Only SQLite databases can be backed up; use pg_dump for PostgreSQL.
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::BackupConfig;
+use crate::db::{self, Backend, DbPool};
+use crate::storage::{SharedStorage, Storage};
+
+const FORMAT_VERSION: u32 = 1;
+const MANIFEST_ENTRY: &str = "manifest.json";
+const DATABASE_ENTRY: &str = "voxium.db";
+const UPLOADS_PREFIX: &str = "uploads/";
+const ARCHIVE_PREFIX: &str = "voxium-backup-";
+const ARCHIVE_SUFFIX: &str = ".tar.gz";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub format: u32,
+    pub created_at: String,
+    pub app_version: String,
+    pub schema_version: i64,
+    pub database_size: u64,
+    pub database_sha256: String,
+    /// Uploads listed when the backup started
+    pub upload_files: usize,
+    pub upload_bytes: u64,
+}
+
+#[derive(Debug)]
+pub enum BackupError {
+    Unsupported(&'static str),
+    Io(io::Error),
+    Database(sqlx::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackupError::Unsupported(msg) => write!(f, "{}", msg),
+            BackupError::Io(e) => write!(f, "{}", e),
+            BackupError::Database(e) => write!(f, "{}", e),
+            BackupError::Invalid(msg) => write!(f, "invalid backup: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for BackupError {}
+
+impl From<io::Error> for BackupError {
+    fn from(e: io::Error) -> Self {
+        BackupError::Io(e)
+    }
+}
+
+impl From<sqlx::Error> for BackupError {
+    fn from(e: sqlx::Error) -> Self {
+        BackupError::Database(e)
+    }
+}
+
+impl From<tokio::task::JoinError> for BackupError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        BackupError::Io(io::Error::other(e))
+    }
+}
+
+const POSTGRES_UNSUPPORTED: &str = "backups only support SQLite databases; use pg_dump for PostgreSQL";
+
+/// Newest schema version this build can run.
+pub fn latest_schema_version() -> i64 {
+    Backend::Sqlite.migrations().last().map(|m| m.version).unwrap_or(0)
+}
+
+// ── Tar archives ────────────────────────────────────────
+//
+// Plain ustar with regular files only, which is all a backup needs and
+// what `tar -xzf` expects.
+
+const BLOCK: usize = 512;
+
+struct TarWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    fn new(out: W) -> Self {
+        TarWriter { out }
+    }
+
+    fn header(path: &str, size: u64) -> io::Result<[u8; BLOCK]> {
+        let mut header = [0u8; BLOCK];
+        // Long paths are split between the prefix and name fields
+        let (prefix, name) = match path.len() {
+            0..=100 => ("", path),
+            _ => match path.rfind('/') {
+                Some(i) if i <= 155 && path.len() - i - 1 <= 100 => (&path[..i], &path[i + 1..]),
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("path too long for tar: {}", path))),
+            },
+        };
+        let mtime = chrono::Utc::now().timestamp().max(0) as u64;
+
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[108..116].copy_from_slice(b"0000000\0");
+        header[116..124].copy_from_slice(b"0000000\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+        header[136..148].copy_from_slice(format!("{:011o}\0", mtime).as_bytes());
+        header[148..156].copy_from_slice(b"        ");
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+        let checksum: u32 = header.iter().map(|b| *b as u32).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+        Ok(header)
+    }
+
+    fn pad(&mut self, size: u64) -> io::Result<()> {
+        let rem = (size % BLOCK as u64) as usize;
+        if rem != 0 {
+            self.out.write_all(&[0u8; BLOCK][..BLOCK - rem])?;
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+        self.out.write_all(&Self::header(path, data.len() as u64)?)?;
+        self.out.write_all(data)?;
+        self.pad(data.len() as u64)
+    }
+
+    fn append_file(&mut self, path: &str, file: &Path) -> io::Result<()> {
+        let mut file = File::open(file)?;
+        let size = file.metadata()?.len();
+        self.out.write_all(&Self::header(path, size)?)?;
+        let copied = io::copy(&mut (&mut file).take(size), &mut self.out)?;
+        if copied != size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file changed while archiving"));
+        }
+        self.pad(size)
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0u8; BLOCK * 2])?;
+        Ok(self.out)
+    }
+}
+
+struct TarEntry {
+    path: String,
+    size: u64,
+}
+
+struct TarReader<R: Read> {
+    input: R,
+}
+
+fn tar_field(bytes: &[u8]) -> &str {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    std::str::from_utf8(&bytes[..end]).unwrap_or("").trim()
+}
+
+impl<R: Read> TarReader<R> {
+    fn new(input: R) -> Self {
+        TarReader { input }
+    }
+
+    fn next_entry(&mut self) -> Result<Option<TarEntry>, BackupError> {
+        let mut header = [0u8; BLOCK];
+        self.input.read_exact(&mut header)?;
+        if header.iter().all(|b| *b == 0) {
+            return Ok(None);
+        }
+
+        let stored = u32::from_str_radix(tar_field(&header[148..156]), 8)
+            .map_err(|_| BackupError::Invalid("corrupted tar header".into()))?;
+        let computed: u32 = header
+            .iter()
+            .enumerate()
+            .map(|(i, b)| if (148..156).contains(&i) { b' ' as u32 } else { *b as u32 })
+            .sum();
+        if stored != computed {
+            return Err(BackupError::Invalid("corrupted tar header".into()));
+        }
+        if header[156] != b'0' && header[156] != 0 {
+            return Err(BackupError::Invalid("unexpected tar entry type".into()));
+        }
+
+        let size = u64::from_str_radix(tar_field(&header[124..136]), 8)
+            .map_err(|_| BackupError::Invalid("corrupted tar header".into()))?;
+        let name = tar_field(&header[..100]);
+        let prefix = tar_field(&header[345..500]);
+        let path = if prefix.is_empty() { name.to_string() } else { format!("{}/{}", prefix, name) };
+        Ok(Some(TarEntry { path, size }))
+    }
+
+    /// Copy the entry's data to `out`, then skip its padding.
+    fn copy_entry(&mut self, entry: &TarEntry, out: &mut impl Write) -> Result<(), BackupError> {
+        let copied = io::copy(&mut (&mut self.input).take(entry.size), out)?;
+        if copied != entry.size {
+            return Err(BackupError::Invalid(format!("{} is truncated", entry.path)));
+        }
+        let rem = (entry.size % BLOCK as u64) as usize;
+        if rem != 0 {
+            io::copy(&mut (&mut self.input).take((BLOCK - rem) as u64), &mut io::sink())?;
+        }
+        Ok(())
+    }
+
+    /// Read the entry into memory, refusing it if the header claims more
+    /// than `max` bytes. The buffer grows with the data actually read rather
+    /// than the size in the header.
+    fn read_entry(&mut self, entry: &TarEntry, max: u64) -> Result<Vec<u8>, BackupError> {
+        if entry.size > max {
+            return Err(BackupError::Invalid(format!("{} is too large", entry.path)));
+        }
+        let mut data = Vec::new();
+        self.copy_entry(entry, &mut data)?;
+        Ok(data)
+    }
+}
+
+struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hashing = HashingWriter { inner: io::sink(), hasher: Sha256::new() };
+    io::copy(&mut File::open(path)?, &mut hashing)?;
+    Ok(format!("{:x}", hashing.hasher.finalize()))
+}
+
+// ── Backup ──────────────────────────────────────────────
+
+/// Write a backup archive into `dir` and return its path. The archive is
+/// written under a temporary name and only renamed once complete.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn create_backup(pool: &DbPool, storage: &SharedStorage, dir: &Path) -> Result<PathBuf, BackupError> {
+    if Backend::of(pool) != Backend::Sqlite {
+        return Err(BackupError::Unsupported(POSTGRES_UNSUPPORTED));
+    }
+
+    tokio::fs::create_dir_all(dir).await?;
+    let stamp = chrono::Utc::now().format("%Y%m%d-%H%M%S");
+    let archive = dir.join(format!("{}{}{}", ARCHIVE_PREFIX, stamp, ARCHIVE_SUFFIX));
+    let partial = dir.join(format!(".{}{}.partial", ARCHIVE_PREFIX, stamp));
+    let snapshot = dir.join(format!(".{}{}.db", ARCHIVE_PREFIX, stamp));
+
+    let _ = tokio::fs::remove_file(&snapshot).await;
+    sqlx::query("VACUUM INTO $1")
+        .bind(snapshot.to_string_lossy().to_string())
+        .execute(pool)
+        .await?;
+
+    let written = write_archive(pool, storage, &snapshot, &partial).await;
+    let _ = tokio::fs::remove_file(&snapshot).await;
+    if let Err(e) = written {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(e);
+    }
+
+    tokio::fs::rename(&partial, &archive).await?;
+    Ok(archive)
+}
+
+async fn write_archive(pool: &DbPool, storage: &SharedStorage, snapshot: &Path, partial: &Path) -> Result<(), BackupError> {
+    let objects = storage.list().await?;
+    let snapshot_path = snapshot.to_path_buf();
+    let (database_size, database_sha256) = tokio::task::spawn_blocking(move || -> io::Result<(u64, String)> {
+        Ok((std::fs::metadata(&snapshot_path)?.len(), sha256_file(&snapshot_path)?))
+    })
+    .await??;
+
+    let manifest = BackupManifest {
+        format: FORMAT_VERSION,
+        created_at: chrono::Utc::now().to_rfc3339(),
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        schema_version: db::schema_version(pool).await,
+        database_size,
+        database_sha256,
+        upload_files: objects.len(),
+        upload_bytes: objects.iter().map(|o| o.size).sum(),
+    };
+    let manifest_json = serde_json::to_vec_pretty(&manifest).map_err(io::Error::other)?;
+
+    // Archive writes are blocking, so the writer moves in and out of blocking tasks
+    let (partial_path, snapshot_path) = (partial.to_path_buf(), snapshot.to_path_buf());
+    let mut tar = tokio::task::spawn_blocking(move || -> io::Result<_> {
+        let mut tar = TarWriter::new(GzEncoder::new(File::create(&partial_path)?, Compression::default()));
+        tar.append(MANIFEST_ENTRY, &manifest_json)?;
+        tar.append_file(DATABASE_ENTRY, &snapshot_path)?;
+        Ok(tar)
+    })
+    .await??;
+
+    for object in objects {
+        // Removed since listing (by the sweeper, for instance)
+        let Some(data) = storage.get(&object.key).await? else {
+            continue;
+        };
+        tar = tokio::task::spawn_blocking(move || -> io::Result<_> {
+            tar.append(&format!("{}{}", UPLOADS_PREFIX, object.key), &data)?;
+            Ok(tar)
+        })
+        .await??;
+    }
+
+    tokio::task::spawn_blocking(move || -> io::Result<()> { tar.finish()?.finish()?.sync_all() }).await??;
+    Ok(())
+}
+
+/// Delete the oldest archives in `dir` beyond the newest `keep`.
+pub fn rotate_backups(dir: &Path, keep: usize) -> io::Result<Vec<PathBuf>> {
+    if keep == 0 {
+        return Ok(Vec::new());
+    }
+    let mut archives: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(ARCHIVE_PREFIX) && name.ends_with(ARCHIVE_SUFFIX))
+        })
+        .collect();
+    // Timestamped names sort chronologically
+    archives.sort();
+
+    let excess = archives.len().saturating_sub(keep);
+    let removed: Vec<PathBuf> = archives.into_iter().take(excess).collect();
+    for path in &removed {
+        std::fs::remove_file(path)?;
+    }
+    Ok(removed)
+}
+
+/// Back up every `backup.interval_secs` in the background, keeping the
+/// newest `backup.keep` archives in `backup.dir`.
+pub fn spawn_backup_scheduler(pool: DbPool, storage: SharedStorage, config: &BackupConfig) {
+    let interval = config.interval_secs;
+    if interval == 0 {
+        return;
+    }
+    if Backend::of(&pool) != Backend::Sqlite {
+        tracing::warn!("Scheduled backups disabled: {}", POSTGRES_UNSUPPORTED);
+        return;
+    }
+
+    let (dir, keep) = (config.dir.clone(), config.keep);
+    tracing::info!("Scheduled backups every {}s to {} (keeping {})", interval, dir.display(), keep);
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+            match create_backup(&pool, &storage, &dir).await {
+                Ok(archive) => tracing::info!("Backup written to {}", archive.display()),
+                Err(e) => {
+                    tracing::warn!(error = %e, "Scheduled backup failed");
+                    continue;
+                }
+            }
+            match rotate_backups(&dir, keep) {
+                Ok(removed) => {
+                    for path in removed {
+                        tracing::info!("Removed old backup {}", path.display());
+                    }
+                }
+                Err(e) => tracing::warn!(error = %e, "Failed to rotate backups"),
+            }
+        }
+    });
+}
+
+// ── Restore ─────────────────────────────────────────────
+
+#[derive(Debug)]
+pub struct RestoreSummary {
+    pub manifest: BackupManifest,
+    pub uploads_restored: usize,
+    pub uploads_skipped: usize,
+    /// Where the replaced database was moved
+    pub previous_database: Option<PathBuf>,
+}
+
+/// Move a SQLite database and its WAL files aside, keeping them together.
+fn move_database(from: &Path, to: &Path) -> io::Result<()> {
+    for suffix in ["-wal", "-shm"] {
+        let sidecar = PathBuf::from(format!("{}{}", from.display(), suffix));
+        if sidecar.exists() {
+            std::fs::rename(&sidecar, format!("{}{}", to.display(), suffix))?;
+        }
+    }
+    std::fs::rename(from, to)
+}
+
+/// Restore `archive` into the SQLite database at `database_url` and the
+/// upload storage. The server must be stopped. The archive's schema version
+/// is checked before anything is written; the replaced database is kept
+/// next to the new one.
+pub async fn restore_backup(database_url: &str, storage: &dyn Storage, archive: &Path) -> Result<RestoreSummary, BackupError> {
+    let Some(db_path) = database_url.strip_prefix("sqlite:").map(PathBuf::from) else {
+        return Err(BackupError::Unsupported(POSTGRES_UNSUPPORTED));
+    };
+
+    let mut tar = TarReader::new(GzDecoder::new(File::open(archive)?));
+
+    let manifest: BackupManifest = match tar.next_entry()? {
+        Some(entry) if entry.path == MANIFEST_ENTRY => {
+            let data = tar.read_entry(&entry, 1024 * 1024)?;
+            serde_json::from_slice(&data).map_err(|e| BackupError::Invalid(format!("bad manifest: {}", e)))?
+        }
+        _ => return Err(BackupError::Invalid("missing manifest".into())),
+    };
+    if manifest.format != FORMAT_VERSION {
+        return Err(BackupError::Invalid(format!("unsupported backup format {}", manifest.format)));
+    }
+    if manifest.schema_version > latest_schema_version() {
+        return Err(BackupError::Invalid(format!(
+            "backup has schema version {} but this build only knows up to {}; restore it with a newer build",
+            manifest.schema_version,
+            latest_schema_version()
+        )));
+    }
+
+    // Extract the database next to its destination and verify it
+    let staged = PathBuf::from(format!("{}.restore-{}", db_path.display(), uuid::Uuid::new_v4().simple()));
+    let staged_ok = async {
+        match tar.next_entry()? {
+            Some(entry) if entry.path == DATABASE_ENTRY => {
+                if entry.size != manifest.database_size {
+                    return Err(BackupError::Invalid("database size does not match the manifest".into()));
+                }
+                let mut out = HashingWriter { inner: File::create(&staged)?, hasher: Sha256::new() };
+                tar.copy_entry(&entry, &mut out)?;
+                out.inner.sync_all()?;
+                if format!("{:x}", out.hasher.finalize()) != manifest.database_sha256 {
+                    return Err(BackupError::Invalid("database checksum mismatch".into()));
+                }
+            }
+            _ => return Err(BackupError::Invalid("missing database".into())),
+        }
+
+        let pool = db::connect_to(&format!("sqlite:{}", staged.display()), 1).await;
+        let version = db::schema_version(&pool).await;
+        let integrity: String = sqlx::query_scalar("PRAGMA integrity_check").fetch_one(&pool).await?;
+        pool.close().await;
+        if version != manifest.schema_version {
+            return Err(BackupError::Invalid(format!(
+                "database is at schema version {} but the manifest says {}",
+                version, manifest.schema_version
+            )));
+        }
+        if integrity != "ok" {
+            return Err(BackupError::Invalid(format!("database integrity check failed: {}", integrity)));
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(e) = staged_ok {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", staged.display(), suffix));
+        }
+        return Err(e);
+    }
+
+    // Uploads first, so a failure leaves the current database in place. The
+    // archive holds at most the uploads listed in the manifest.
+    let (mut uploads_restored, mut uploads_skipped) = (0, 0);
+    let mut upload_bytes_left = manifest.upload_bytes;
+    while let Some(entry) = tar.next_entry()? {
+        let Some(key) = entry.path.strip_prefix(UPLOADS_PREFIX).filter(|k| crate::storage::is_valid_key(k)) else {
+            return Err(BackupError::Invalid(format!("unexpected entry {}", entry.path)));
+        };
+        if entry.size > upload_bytes_left {
+            return Err(BackupError::Invalid(format!("{} is larger than the manifest allows", entry.path)));
+        }
+        upload_bytes_left -= entry.size;
+        if storage.exists(key).await? {
+            tar.copy_entry(&entry, &mut io::sink())?;
+            uploads_skipped += 1;
+            continue;
+        }
+        let data = tar.read_entry(&entry, entry.size)?;
+        let mime_type = crate::uploads::sniff_mime(&data);
+        storage.put(key, data, mime_type).await?;
+        uploads_restored += 1;
+    }
+
+    let previous_database = if db_path.exists() {
+        let stamp = chrono::Utc::now().format("%Y%m%d-%H%M%S");
+        let previous = PathBuf::from(format!("{}.pre-restore-{}", db_path.display(), stamp));
+        move_database(&db_path, &previous)?;
+        Some(previous)
+    } else {
+        None
+    };
+    move_database(&staged, &db_path)?;
+
+    Ok(RestoreSummary { manifest, uploads_restored, uploads_skipped, previous_database })
+}
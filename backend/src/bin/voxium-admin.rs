@@ -0,0 +1,762 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — Administration CLI
+// ═══════════════════════════════════════════════════════
+//
+// Scriptable counterpart of the admin panel, working directly on the
+// database of the server configuration (`voxium.toml` and environment).
+// It never prompts: everything comes from arguments, and `--json` prints
+// machine-readable output. Checks are the ones the HTTP handlers use.
+//
+// A running server polls bans, roles and room access, so changes made here
+// reach signed-in sessions within a few seconds.
+
+use backend::auth::{self, PROTECTED_ROLES};
+use backend::rooms::{self, Room};
+use backend::config::Config;
+use backend::{db, logging, messages, moderation, uploads};
+use bcrypt::{hash, DEFAULT_COST};
+use rand::distributions::{Alphanumeric, DistString};
+use serde_json::{json, Value};
+use sqlx::Row;
+use std::collections::HashMap;
+use std::io::BufRead;
+
+const USAGE: &str = "\
+Usage: voxium-admin [--json] <command>
+
+Users
+  user list
+  user create NAME [--password PW | --password-stdin] [--role ROLE]
+  user delete NAME
+  user set-role NAME ROLE
+  user reset-password NAME [--password PW | --password-stdin]
+Roles
+  role list
+  role create NAME [--color #RRGGBB] [--quota SIZE] [--move-members] [--animated-avatars]
+  role delete NAME
+Rooms
+  room list
+  room create NAME [--kind text|voice] [--required-role ROLE] [--user-limit N]
+  room delete ROOM
+  room set-required-role ROOM ROLE
+Messages
+  message purge [--user NAME] [--room ROOM]
+Invites
+  invite list
+  invite create [--max-uses N] [--expires-in DURATION] [--note TEXT]
+  invite revoke CODE
+Bans
+  ban list
+  ban add NAME [--reason TEXT] [--for DURATION]
+  ban remove NAME
+
+NAME is a username or user id, ROOM a room name or id. Passwords that are
+not given are generated and printed once. DURATION: 90s, 30m, 12h, 7d, 2w.";
+
+const VALUE_FLAGS: &[&str] = &[
+    "password", "role", "color", "quota", "kind", "required-role", "user-limit",
+    "user", "room", "max-uses", "expires-in", "note", "reason", "for",
+];
+const SWITCH_FLAGS: &[&str] = &["json", "password-stdin", "move-members", "animated-avatars"];
+
+const GENERATED_PASSWORD_LEN: usize = 16;
+
+enum Failure {
+    /// Bad invocation: usage is printed, exit code 2
+    Usage(String),
+    /// The command ran and failed: exit code 1
+    Error(String),
+}
+
+impl From<sqlx::Error> for Failure {
+    fn from(e: sqlx::Error) -> Self {
+        Failure::Error(format!("Database error: {}", e))
+    }
+}
+
+fn error(message: impl ToString) -> Failure {
+    Failure::Error(message.to_string())
+}
+
+fn usage(message: impl Into<String>) -> Failure {
+    Failure::Usage(message.into())
+}
+
+/// What a command prints: `json` with `--json`, `text` otherwise.
+struct Output {
+    json: Value,
+    text: String,
+}
+
+type CommandResult = Result<Output, Failure>;
+
+struct Args {
+    positional: Vec<String>,
+    values: HashMap<String, String>,
+    switches: Vec<String>,
+}
+
+impl Args {
+    fn parse(raw: Vec<String>) -> Result<Args, Failure> {
+        let mut args = Args { positional: Vec::new(), values: HashMap::new(), switches: Vec::new() };
+        let mut iter = raw.into_iter();
+        while let Some(arg) = iter.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                args.positional.push(arg);
+                continue;
+            };
+            let (name, inline) = match flag.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (flag.to_string(), None),
+            };
+            if VALUE_FLAGS.contains(&name.as_str()) {
+                let value = match inline {
+                    Some(value) => value,
+                    None => iter.next().ok_or_else(|| usage(format!("--{} needs a value", name)))?,
+                };
+                args.values.insert(name, value);
+            } else if SWITCH_FLAGS.contains(&name.as_str()) && inline.is_none() {
+                args.switches.push(name);
+            } else {
+                return Err(usage(format!("Unknown option --{}", name)));
+            }
+        }
+        Ok(args)
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    fn switch(&self, name: &str) -> bool {
+        self.switches.iter().any(|s| s == name)
+    }
+
+    /// Positional arguments after the command words, exactly `count` of them.
+    fn operands(&self, count: usize) -> Result<Vec<&str>, Failure> {
+        let operands: Vec<&str> = self.positional.iter().skip(2).map(String::as_str).collect();
+        if operands.len() != count {
+            return Err(usage(format!(
+                "`{}` takes {} argument(s)",
+                self.positional.iter().take(2).cloned().collect::<Vec<_>>().join(" "),
+                count
+            )));
+        }
+        Ok(operands)
+    }
+}
+
+fn main() {
+    dotenvy::dotenv().ok();
+    let args = match Args::parse(std::env::args().skip(1).collect()) {
+        Ok(args) => args,
+        Err(failure) => std::process::exit(report(failure, false)),
+    };
+    let as_json = args.switch("json");
+
+    let code = actix_web::rt::System::new().block_on(async {
+        match run(&args).await {
+            Ok(output) => {
+                if as_json {
+                    println!("{}", serde_json::to_string_pretty(&output.json).unwrap_or_default());
+                } else if !output.text.is_empty() {
+                    println!("{}", output.text);
+                }
+                0
+            }
+            Err(failure) => report(failure, as_json),
+        }
+    });
+    std::process::exit(code);
+}
+
+fn report(failure: Failure, as_json: bool) -> i32 {
+    match failure {
+        Failure::Usage(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            2
+        }
+        Failure::Error(message) => {
+            if as_json {
+                println!("{}", json!({ "error": message }));
+            } else {
+                eprintln!("❌ {}", message);
+            }
+            1
+        }
+    }
+}
+
+async fn run(args: &Args) -> CommandResult {
+    let words: Vec<&str> = args.positional.iter().take(2).map(String::as_str).collect();
+    if words.len() < 2 {
+        return Err(usage("No command given"));
+    }
+
+    let config = Config::load().map_err(|e| error(e.to_string()))?;
+    logging::init(&config.logging);
+    let pool = db::connect(&config.database).await;
+    let latest = db::Backend::of(&pool).migrations().last().map(|m| m.version).unwrap_or(0);
+    let current = db::schema_version(&pool).await;
+    if current < latest {
+        return Err(error(format!(
+            "Database is at schema version {} (expected {}), run `backend migrate` first",
+            current, latest
+        )));
+    }
+
+    match words.as_slice() {
+        ["user", "list"] => user_list(&pool, args).await,
+        ["user", "create"] => user_create(&pool, args).await,
+        ["user", "delete"] => user_delete(&pool, args).await,
+        ["user", "set-role"] => user_set_role(&pool, args).await,
+        ["user", "reset-password"] => user_reset_password(&pool, args).await,
+        ["role", "list"] => role_list(&pool, args).await,
+        ["role", "create"] => role_create(&pool, args).await,
+        ["role", "delete"] => role_delete(&pool, args).await,
+        ["room", "list"] => room_list(&pool, args).await,
+        ["room", "create"] => room_create(&pool, args).await,
+        ["room", "delete"] => room_delete(&pool, args).await,
+        ["room", "set-required-role"] => room_set_required_role(&pool, args).await,
+        ["message", "purge"] => message_purge(&pool, args).await,
+        ["invite", "list"] => invite_list(&pool, &config, args).await,
+        ["invite", "create"] => invite_create(&pool, &config, args).await,
+        ["invite", "revoke"] => invite_revoke(&pool, args).await,
+        ["ban", "list"] => ban_list(&pool, args).await,
+        ["ban", "add"] => ban_add(&pool, args).await,
+        ["ban", "remove"] => ban_remove(&pool, args).await,
+        _ => Err(usage(format!("Unknown command `{}`", words.join(" ")))),
+    }
+}
+
+// ── Lookups and parsing ─────────────────────────────────
+
+struct UserRef {
+    id: String,
+    username: String,
+    role: String,
+}
+
+async fn find_user(pool: &db::DbPool, name_or_id: &str) -> Result<UserRef, Failure> {
+    let row = sqlx::query("SELECT id, username, role FROM users WHERE id = $1 OR username = $1")
+        .bind(name_or_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| error(format!("User '{}' not found", name_or_id)))?;
+    Ok(UserRef { id: row.get("id"), username: row.get("username"), role: row.get("role") })
+}
+
+async fn find_room(pool: &db::DbPool, name_or_id: &str) -> Result<Room, Failure> {
+    sqlx::query_as::<_, Room>(
+        "SELECT id, name, kind, required_role, user_limit, created_at FROM rooms WHERE id = $1 OR name = $1"
+    )
+    .bind(name_or_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| error(format!("Room '{}' not found", name_or_id)))
+}
+
+/// `--password`, `--password-stdin` (first line) or a generated one. The
+/// flag tells whether it was generated and must be shown.
+fn password_from(args: &Args) -> Result<(String, bool), Failure> {
+    if args.switch("password-stdin") {
+        let mut line = String::new();
+        std::io::stdin()
+            .lock()
+            .read_line(&mut line)
+            .map_err(|e| error(format!("Failed to read password: {}", e)))?;
+        return Ok((line.trim_end_matches(['\r', '\n']).to_string(), false));
+    }
+    if let Some(password) = args.value("password") {
+        return Ok((password.to_string(), false));
+    }
+    Ok((Alphanumeric.sample_string(&mut rand::thread_rng(), GENERATED_PASSWORD_LEN), true))
+}
+
+/// `90s`, `30m`, `12h`, `7d`, `2w` or plain seconds.
+fn parse_duration(raw: &str) -> Option<chrono::Duration> {
+    let raw = raw.trim().to_lowercase();
+    let (digits, unit) = match raw.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => (&raw[..i], c),
+        _ => (raw.as_str(), 's'),
+    };
+    let n: i64 = digits.parse().ok().filter(|n| *n > 0)?;
+    match unit {
+        's' => chrono::Duration::try_seconds(n),
+        'm' => chrono::Duration::try_minutes(n),
+        'h' => chrono::Duration::try_hours(n),
+        'd' => chrono::Duration::try_days(n),
+        'w' => chrono::Duration::try_weeks(n),
+        _ => None,
+    }
+}
+
+fn expiry_from(args: &Args, flag: &str) -> Result<Option<chrono::DateTime<chrono::Utc>>, Failure> {
+    match args.value(flag) {
+        None => Ok(None),
+        Some(raw) => parse_duration(raw)
+            .map(|d| Some(chrono::Utc::now() + d))
+            .ok_or_else(|| usage(format!("Invalid duration '{}' for --{}", raw, flag))),
+    }
+}
+
+fn lines<T>(items: &[T], empty: &str, line: impl Fn(&T) -> String) -> String {
+    if items.is_empty() {
+        return empty.to_string();
+    }
+    items.iter().map(line).collect::<Vec<_>>().join("\n")
+}
+
+// ── Users ───────────────────────────────────────────────
+
+async fn user_list(pool: &db::DbPool, args: &Args) -> CommandResult {
+    args.operands(0)?;
+    let rows = sqlx::query("SELECT id, username, role, created_at FROM users ORDER BY username ASC")
+        .fetch_all(pool)
+        .await?;
+    let banned: Vec<String> = moderation::list_bans(pool).await?.into_iter().map(|b| b.user_id).collect();
+
+    let users: Vec<Value> = rows
+        .iter()
+        .map(|row| {
+            let id: String = row.get("id");
+            json!({
+                "id": id,
+                "username": row.get::<String, _>("username"),
+                "role": row.get::<String, _>("role"),
+                "created_at": row.get::<String, _>("created_at"),
+                "banned": banned.contains(&id),
+            })
+        })
+        .collect();
+
+    let text = lines(&users, "No users", |u| {
+        format!(
+            "{:<24} {:<12} {}{}",
+            u["username"].as_str().unwrap_or_default(),
+            u["role"].as_str().unwrap_or_default(),
+            u["id"].as_str().unwrap_or_default(),
+            if u["banned"] == true { "  (banned)" } else { "" }
+        )
+    });
+    Ok(Output { json: Value::Array(users), text })
+}
+
+async fn user_create(pool: &db::DbPool, args: &Args) -> CommandResult {
+    let operands = args.operands(1)?;
+    let username = operands[0].trim();
+    let (password, generated) = password_from(args)?;
+    auth::validate_new_user(username, &password).map_err(error)?;
+
+    let role = args.value("role").unwrap_or("user").trim().to_lowercase();
+    if !auth::role_exists(pool, &role).await {
+        return Err(error("Invalid role"));
+    }
+    if auth::username_taken(pool, username).await {
+        return Err(error("Username already taken"));
+    }
+
+    let id = uuid::Uuid::new_v4().to_string();
+    let password_hash = hash(&password, DEFAULT_COST).map_err(|e| error(e.to_string()))?;
+    sqlx::query("INSERT INTO users (id, username, password_hash, role) VALUES ($1, $2, $3, $4)")
+        .bind(&id)
+        .bind(username)
+        .bind(&password_hash)
+        .bind(&role)
+        .execute(pool)
+        .await?;
+
+    let mut text = format!("✅ Created {} ({}) with role {}", username, id, role);
+    if generated {
+        text.push_str(&format!("\n🔑 Password: {}", password));
+    }
+    Ok(Output {
+        json: json!({
+            "status": "created",
+            "id": id,
+            "username": username,
+            "role": role,
+            "password": generated.then_some(password),
+        }),
+        text,
+    })
+}
+
+async fn user_delete(pool: &db::DbPool, args: &Args) -> CommandResult {
+    let user = find_user(pool, args.operands(1)?[0]).await?;
+    if !auth::remove_user(pool, &user.id).await? {
+        return Err(error("User not found"));
+    }
+    Ok(Output {
+        json: json!({ "status": "deleted", "id": user.id, "username": user.username }),
+        text: format!("🗑️ Deleted {} and their messages", user.username),
+    })
+}
+
+async fn user_set_role(pool: &db::DbPool, args: &Args) -> CommandResult {
+    let operands = args.operands(2)?;
+    let user = find_user(pool, operands[0]).await?;
+    let role = operands[1].trim().to_lowercase();
+    if !auth::role_exists(pool, &role).await {
+        return Err(error("Invalid role"));
+    }
+
+    sqlx::query("UPDATE users SET role = $1 WHERE id = $2")
+        .bind(&role)
+        .bind(&user.id)
+        .execute(pool)
+        .await?;
+
+    Ok(Output {
+        json: json!({ "status": "role updated", "id": user.id, "username": user.username, "previous_role": user.role, "role": role }),
+        text: format!("✅ {} is now {} (was {})", user.username, role, user.role),
+    })
+}
+
+async fn user_reset_password(pool: &db::DbPool, args: &Args) -> CommandResult {
+    let user = find_user(pool, args.operands(1)?[0]).await?;
+    let (password, generated) = password_from(args)?;
+    auth::validate_password(&password).map_err(error)?;
+
+    let password_hash = hash(&password, DEFAULT_COST).map_err(|e| error(e.to_string()))?;
+    sqlx::query("UPDATE users SET password_hash = $1 WHERE id = $2")
+        .bind(&password_hash)
+        .bind(&user.id)
+        .execute(pool)
+        .await?;
+
+    let mut text = format!("✅ Password of {} reset", user.username);
+    if generated {
+        text.push_str(&format!("\n🔑 Password: {}", password));
+    }
+    Ok(Output {
+        json: json!({
+            "status": "password reset",
+            "id": user.id,
+            "username": user.username,
+            "password": generated.then_some(password),
+        }),
+        text,
+    })
+}
+
+// ── Roles ───────────────────────────────────────────────
+
+async fn role_list(pool: &db::DbPool, args: &Args) -> CommandResult {
+    args.operands(0)?;
+    let rows = sqlx::query(
+        "SELECT r.name, r.color, r.move_members, r.storage_quota, r.animated_avatars, \
+                (SELECT COUNT(*) FROM users u WHERE u.role = r.name) AS members \
+         FROM roles r ORDER BY CASE WHEN r.name='admin' THEN 0 WHEN r.name='user' THEN 1 ELSE 2 END, r.name ASC"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let roles: Vec<Value> = rows
+        .iter()
+        .map(|row| {
+            json!({
+                "name": row.get::<String, _>("name"),
+                "color": row.get::<String, _>("color"),
+                "move_members": row.try_get::<i64, _>("move_members").unwrap_or(0) != 0,
+                "storage_quota": row.try_get::<Option<i64>, _>("storage_quota").unwrap_or(None),
+                "animated_avatars": row.try_get::<i64, _>("animated_avatars").unwrap_or(0) != 0,
+                "members": row.get::<i64, _>("members"),
+            })
+        })
+        .collect();
+
+    let text = lines(&roles, "No roles", |r| {
+        let quota = match r["storage_quota"].as_i64() {
+            Some(0) => "unlimited".to_string(),
+            Some(q) => uploads::format_size(q as usize),
+            None => "default".to_string(),
+        };
+        format!(
+            "{:<24} {}  quota {:<10} members {}{}{}",
+            r["name"].as_str().unwrap_or_default(),
+            r["color"].as_str().unwrap_or_default(),
+            quota,
+            r["members"],
+            if r["move_members"] == true { "  move-members" } else { "" },
+            if r["animated_avatars"] == true { "  animated-avatars" } else { "" }
+        )
+    });
+    Ok(Output { json: Value::Array(roles), text })
+}
+
+async fn role_create(pool: &db::DbPool, args: &Args) -> CommandResult {
+    let name = auth::normalize_role_name(args.operands(1)?[0]).map_err(error)?;
+    let color = args.value("color").unwrap_or("#99aab5").trim().to_string();
+    if !auth::is_valid_role_color(&color) {
+        return Err(error("Invalid role color (expected #RRGGBB)"));
+    }
+    let quota = match args.value("quota") {
+        Some(raw) => Some(
+            uploads::parse_size(raw)
+                .map(|q| q as i64)
+                .ok_or_else(|| usage(format!("Invalid size '{}' for --quota", raw)))?,
+        ),
+        None => None,
+    };
+    let move_members = args.switch("move-members");
+    let animated_avatars = args.switch("animated-avatars");
+
+    sqlx::query("INSERT INTO roles (name, color, move_members, storage_quota, animated_avatars) VALUES ($1, $2, $3, $4, $5)")
+        .bind(&name)
+        .bind(&color)
+        .bind(move_members as i64)
+        .bind(quota)
+        .bind(animated_avatars as i64)
+        .execute(pool)
+        .await
+        .map_err(|_| error("Role already exists"))?;
+
+    Ok(Output {
+        json: json!({
+            "status": "role created",
+            "name": name,
+            "color": color,
+            "move_members": move_members,
+            "storage_quota": quota,
+            "animated_avatars": animated_avatars,
+        }),
+        text: format!("✅ Created role {}", name),
+    })
+}
+
+async fn role_delete(pool: &db::DbPool, args: &Args) -> CommandResult {
+    let name = args.operands(1)?[0].trim().to_lowercase();
+    if PROTECTED_ROLES.contains(&name.as_str()) {
+        return Err(error("This role is protected"));
+    }
+    if !auth::remove_role(pool, &name).await? {
+        return Err(error("Role not found"));
+    }
+    Ok(Output {
+        json: json!({ "status": "role deleted", "name": name }),
+        text: format!("🗑️ Deleted role {} (members are back to user)", name),
+    })
+}
+
+// ── Rooms ───────────────────────────────────────────────
+
+async fn room_list(pool: &db::DbPool, args: &Args) -> CommandResult {
+    args.operands(0)?;
+    let rooms = sqlx::query_as::<_, Room>("SELECT id, name, kind, required_role, user_limit, created_at FROM rooms ORDER BY created_at")
+        .fetch_all(pool)
+        .await?;
+
+    let text = lines(&rooms, "No rooms", |r| {
+        let limit = if r.user_limit > 0 { format!("  limit {}", r.user_limit) } else { String::new() };
+        format!("{:<24} {:<6} {:<12} {}{}", r.name, r.kind, r.required_role, r.id, limit)
+    });
+    Ok(Output { json: serde_json::to_value(&rooms).unwrap_or_default(), text })
+}
+
+async fn room_create(pool: &db::DbPool, args: &Args) -> CommandResult {
+    let operands = args.operands(1)?;
+    let name = rooms::validate_room_name(operands[0]).map_err(error)?;
+    let kind = rooms::normalize_room_kind(args.value("kind").unwrap_or("text")).map_err(error)?;
+    let required_role = rooms::validate_required_role(pool, args.value("required-role").unwrap_or("user"))
+        .await
+        .map_err(error)?;
+    let user_limit = match args.value("user-limit") {
+        Some(raw) => raw
+            .trim()
+            .parse::<i64>()
+            .map_err(|_| usage(format!("Invalid number '{}' for --user-limit", raw)))?,
+        None => 0,
+    };
+    let user_limit = rooms::validate_user_limit(user_limit).map_err(error)?;
+
+    let id = uuid::Uuid::new_v4().to_string();
+    sqlx::query("INSERT INTO rooms (id, name, kind, required_role, user_limit) VALUES ($1, $2, $3, $4, $5)")
+        .bind(&id)
+        .bind(name)
+        .bind(&kind)
+        .bind(&required_role)
+        .bind(user_limit)
+        .execute(pool)
+        .await
+        .map_err(|_| error("Room name already exists"))?;
+
+    Ok(Output {
+        json: json!({ "id": id, "name": name, "kind": kind, "required_role": required_role, "user_limit": user_limit }),
+        text: format!("✅ Created {} room {} ({})", kind, name, id),
+    })
+}
+
+async fn room_delete(pool: &db::DbPool, args: &Args) -> CommandResult {
+    let room = find_room(pool, args.operands(1)?[0]).await?;
+    if !rooms::remove_room(pool, &room.id).await? {
+        return Err(error("Room not found"));
+    }
+    Ok(Output {
+        json: json!({ "status": "deleted", "id": room.id, "name": room.name }),
+        text: format!("🗑️ Deleted room {} and its messages", room.name),
+    })
+}
+
+async fn room_set_required_role(pool: &db::DbPool, args: &Args) -> CommandResult {
+    let operands = args.operands(2)?;
+    let room = find_room(pool, operands[0]).await?;
+    let required_role = rooms::validate_required_role(pool, operands[1]).await.map_err(error)?;
+
+    sqlx::query("UPDATE rooms SET required_role = $1 WHERE id = $2")
+        .bind(&required_role)
+        .bind(&room.id)
+        .execute(pool)
+        .await?;
+
+    Ok(Output {
+        json: json!({ "status": "updated", "id": room.id, "name": room.name, "required_role": required_role }),
+        text: format!("✅ {} now requires role {}", room.name, required_role),
+    })
+}
+
+// ── Messages ────────────────────────────────────────────
+
+async fn message_purge(pool: &db::DbPool, args: &Args) -> CommandResult {
+    args.operands(0)?;
+    if args.value("user").is_none() && args.value("room").is_none() {
+        return Err(usage("`message purge` needs --user, --room or both"));
+    }
+    let user = match args.value("user") {
+        Some(name) => Some(find_user(pool, name).await?),
+        None => None,
+    };
+    let room = match args.value("room") {
+        Some(name) => Some(find_room(pool, name).await?),
+        None => None,
+    };
+
+    let count = messages::purge_messages(
+        pool,
+        user.as_ref().map(|u| u.id.as_str()),
+        room.as_ref().map(|r| r.id.as_str()),
+    )
+    .await?;
+
+    let scope = match (&user, &room) {
+        (Some(u), Some(r)) => format!("from {} in {}", u.username, r.name),
+        (Some(u), None) => format!("from {}", u.username),
+        (None, Some(r)) => format!("in {}", r.name),
+        (None, None) => String::new(),
+    };
+    Ok(Output {
+        json: json!({
+            "status": "purged",
+            "count": count,
+            "user_id": user.as_ref().map(|u| &u.id),
+            "room_id": room.as_ref().map(|r| &r.id),
+        }),
+        text: format!("🗑️ Deleted {} message(s) {}", count, scope),
+    })
+}
+
+// ── Invites ─────────────────────────────────────────────
+
+fn describe_invite(invite: &moderation::Invite) -> String {
+    let uses = if invite.max_uses > 0 {
+        format!("{}/{} uses", invite.uses, invite.max_uses)
+    } else {
+        format!("{} uses", invite.uses)
+    };
+    let expires = invite
+        .expires_at
+        .as_deref()
+        .map(|at| format!("  expires {} UTC", at))
+        .unwrap_or_default();
+    let note = if invite.note.is_empty() { String::new() } else { format!("  {}", invite.note) };
+    format!("{}  {}{}{}", invite.code, uses, expires, note)
+}
+
+async fn invite_list(pool: &db::DbPool, config: &Config, args: &Args) -> CommandResult {
+    args.operands(0)?;
+    let invites = moderation::list_invites(pool).await?;
+    let mode = match config.auth.registration_mode {
+        moderation::RegistrationMode::Open => "open",
+        moderation::RegistrationMode::Invite => "invite",
+        moderation::RegistrationMode::Closed => "closed",
+    };
+    let text = format!(
+        "{}\n(registration mode: {})",
+        lines(&invites, "No invite codes", describe_invite),
+        mode
+    );
+    Ok(Output { json: serde_json::to_value(&invites).unwrap_or_default(), text })
+}
+
+async fn invite_create(pool: &db::DbPool, config: &Config, args: &Args) -> CommandResult {
+    args.operands(0)?;
+    let max_uses = match args.value("max-uses") {
+        Some(raw) => raw
+            .trim()
+            .parse::<i64>()
+            .ok()
+            .filter(|n| *n >= 0)
+            .ok_or_else(|| usage(format!("Invalid number '{}' for --max-uses", raw)))?,
+        None => 1,
+    };
+    let expires_at = expiry_from(args, "expires-in")?;
+    let invite = moderation::create_invite(pool, max_uses, expires_at, args.value("note").unwrap_or("").trim()).await?;
+
+    let mut text = format!("🎟️ {}", describe_invite(&invite));
+    if config.auth.registration_mode != moderation::RegistrationMode::Invite {
+        text.push_str("\n⚠️ The registration mode is not `invite`, so codes are not required to register");
+    }
+    Ok(Output { json: serde_json::to_value(&invite).unwrap_or_default(), text })
+}
+
+async fn invite_revoke(pool: &db::DbPool, args: &Args) -> CommandResult {
+    let code = args.operands(1)?[0].trim().to_uppercase();
+    if !moderation::revoke_invite(pool, &code).await? {
+        return Err(error(format!("Invite '{}' not found", code)));
+    }
+    Ok(Output {
+        json: json!({ "status": "revoked", "code": code }),
+        text: format!("🗑️ Revoked invite {}", code),
+    })
+}
+
+// ── Bans ────────────────────────────────────────────────
+
+async fn ban_list(pool: &db::DbPool, args: &Args) -> CommandResult {
+    args.operands(0)?;
+    let bans = moderation::list_bans(pool).await?;
+    let text = lines(&bans, "No active bans", |b| {
+        let until = b.expires_at.as_deref().map(|at| format!("until {} UTC", at)).unwrap_or_else(|| "permanent".into());
+        let reason = if b.reason.is_empty() { String::new() } else { format!("  {}", b.reason) };
+        format!("{:<24} {}{}", b.username, until, reason)
+    });
+    Ok(Output { json: serde_json::to_value(&bans).unwrap_or_default(), text })
+}
+
+async fn ban_add(pool: &db::DbPool, args: &Args) -> CommandResult {
+    let user = find_user(pool, args.operands(1)?[0]).await?;
+    if user.role == "admin" {
+        return Err(error("Admins cannot be banned, change their role first"));
+    }
+    let expires_at = expiry_from(args, "for")?;
+    let reason = args.value("reason").unwrap_or("").trim().to_string();
+    moderation::ban_user(pool, &user.id, &reason, expires_at).await?;
+
+    let expires_at = expires_at.map(moderation::db_timestamp);
+    let until = expires_at.as_deref().map(|at| format!(" until {} UTC", at)).unwrap_or_default();
+    Ok(Output {
+        json: json!({ "status": "banned", "id": user.id, "username": user.username, "reason": reason, "expires_at": expires_at }),
+        text: format!("🔨 Banned {}{} (signed out within a few seconds)", user.username, until),
+    })
+}
+
+async fn ban_remove(pool: &db::DbPool, args: &Args) -> CommandResult {
+    let user = find_user(pool, args.operands(1)?[0]).await?;
+    if !moderation::unban_user(pool, &user.id).await? {
+        return Err(error(format!("{} is not banned", user.username)));
+    }
+    Ok(Output {
+        json: json!({ "status": "unbanned", "id": user.id, "username": user.username }),
+        text: format!("✅ Unbanned {}", user.username),
+    })
+}
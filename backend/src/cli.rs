@@ -0,0 +1,125 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — Backend subcommands
+// ═══════════════════════════════════════════════════════
+//
+// Maintenance commands run by the backend binary instead of the server
+// (`backend migrate status`, ...). Each returns the process exit code.
+
+use crate::config::Config;
+use crate::{backup, db, logging, storage};
+use std::path::Path;
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    actix_web::rt::System::new().block_on(future)
+}
+
+/// Load the configuration, printing what is wrong with it on failure, and
+/// set up logging for the library code the command runs.
+fn load_config() -> Option<Config> {
+    let config = Config::load().map_err(|e| eprintln!("❌ {}", e)).ok()?;
+    logging::init(&config.logging);
+    Some(config)
+}
+
+/// `backend migrate [up]` — Apply pending migrations and exit.
+pub fn migrate_up() -> i32 {
+    let Some(config) = load_config() else { return 1 };
+    block_on(async {
+        let pool = db::connect(&config.database).await;
+        match db::run_migrations(&pool).await {
+            Ok(()) => {
+                println!("✅ Database at schema version {}", db::schema_version(&pool).await);
+                0
+            }
+            Err(e) => {
+                eprintln!("❌ Database migration failed: {}", e);
+                1
+            }
+        }
+    })
+}
+
+/// `backend migrate status` — List applied and pending migrations. Exits
+/// with 1 if an applied migration was modified since.
+pub fn migrate_status() -> i32 {
+    let Some(config) = load_config() else { return 1 };
+    block_on(async {
+        let pool = db::connect(&config.database).await;
+        let statuses = match db::migration_status(&pool).await {
+            Ok(statuses) => statuses,
+            Err(e) => {
+                eprintln!("❌ Failed to read migration status: {}", e);
+                return 1;
+            }
+        };
+
+        let mut modified = false;
+        for status in &statuses {
+            let state = match (&status.applied_at, status.checksum_matches) {
+                (Some(_), false) => {
+                    modified = true;
+                    "MODIFIED".to_string()
+                }
+                (Some(applied_at), true) => format!("applied {}", applied_at),
+                (None, _) => "pending".to_string(),
+            };
+            println!("{:>4}  {:<36} {}", status.version, status.name, state);
+        }
+
+        let pending = statuses.iter().filter(|s| s.applied_at.is_none()).count();
+        println!("{} migration(s), {} pending", statuses.len(), pending);
+        if modified { 1 } else { 0 }
+    })
+}
+
+/// `backend backup [DIR]` — Write a backup archive to `DIR` (default
+/// `backup.dir`). Safe to run while the server is up.
+pub fn backup(dir: Option<&str>) -> i32 {
+    let Some(config) = load_config() else { return 1 };
+    block_on(async {
+        let pool = db::connect(&config.database).await;
+        let dir = dir.map(Into::into).unwrap_or_else(|| config.backup.dir.clone());
+        match backup::create_backup(&pool, &storage::create_storage(&config.storage), &dir).await {
+            Ok(archive) => {
+                println!("💾 Backup written to {}", archive.display());
+                0
+            }
+            Err(e) => {
+                eprintln!("❌ Backup failed: {}", e);
+                1
+            }
+        }
+    })
+}
+
+/// `backend restore ARCHIVE` — Replace the database and restore uploads
+/// from a backup. Stop the server first.
+pub fn restore(archive: &str) -> i32 {
+    let Some(config) = load_config() else { return 1 };
+    block_on(async {
+        let storage = storage::create_storage(&config.storage);
+        match backup::restore_backup(&config.database.url, storage.as_ref(), Path::new(archive)).await {
+            Ok(summary) => {
+                println!(
+                    "✅ Restored backup from {} (schema version {})",
+                    summary.manifest.created_at, summary.manifest.schema_version
+                );
+                println!(
+                    "📦 Uploads: {} restored, {} already present",
+                    summary.uploads_restored, summary.uploads_skipped
+                );
+                if let Some(previous) = summary.previous_database {
+                    println!("🗄️ Previous database moved to {}", previous.display());
+                }
+                if summary.manifest.schema_version < backup::latest_schema_version() {
+                    println!("🗄️ Pending migrations will be applied on the next start");
+                }
+                0
+            }
+            Err(e) => {
+                eprintln!("❌ Restore failed: {}", e);
+                1
+            }
+        }
+    })
+}
@@ -0,0 +1,662 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — Configuration
+// ═══════════════════════════════════════════════════════
+//
+// Settings come from an optional TOML file (`VOXIUM_CONFIG`, or the first
+// `voxium.toml` found in the working directory or its parents), then from
+// environment variables, which keep their historical names and win over
+// the file. Everything is checked once by `Config::load`, which reports
+// every problem at once; the server and the CLI refuse to start on errors.
+//
+// The server shares one `Config` with handlers as `web::Data<Config>`.
+
+use serde::Deserialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::crypto::TokenCipher;
+use crate::logging::LogFormat;
+use crate::moderation::RegistrationMode;
+use crate::uploads::{parse_size, UploadRules};
+
+const CONFIG_FILE_NAME: &str = "voxium.toml";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub auth: AuthConfig,
+    pub discord: DiscordConfig,
+    pub voice: VoiceConfig,
+    pub uploads: UploadsConfig,
+    pub storage: StorageConfig,
+    pub backup: BackupConfig,
+    pub metrics: MetricsConfig,
+    pub logging: LoggingConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub port: u16,
+    pub bind_address: String,
+    /// Origins allowed to call the API from a browser or webview
+    pub cors_origins: Vec<String>,
+    /// Requests per second per client IP, with bursts up to `rate_limit_burst`
+    pub rate_limit_per_second: u64,
+    pub rate_limit_burst: u32,
+    /// Events buffered for each WebSocket before a slow client misses some
+    pub broadcast_capacity: usize,
+    /// Serve a browsable API reference at `/api/docs`
+    pub api_docs: bool,
+    pub tls: TlsConfig,
+}
+
+/// Built-in HTTPS, enabled when both PEM files are set.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// Certificate chain (e.g. certbot's `fullchain.pem`)
+    pub cert_file: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+    /// Plain-HTTP port redirecting to HTTPS; none by default
+    pub redirect_port: Option<u16>,
+}
+
+impl TlsConfig {
+    /// Certificate and key files, when TLS is enabled.
+    pub fn files(&self) -> Option<(&Path, &Path)> {
+        Some((self.cert_file.as_deref()?, self.key_file.as_deref()?))
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            port: 8080,
+            bind_address: "0.0.0.0".to_string(),
+            cors_origins: [
+                "tauri://localhost",
+                "http://localhost:1420",
+                "http://127.0.0.1:1420",
+                "http://localhost:1430",
+                "http://127.0.0.1:1430",
+            ]
+            .map(String::from)
+            .to_vec(),
+            rate_limit_per_second: 10,
+            rate_limit_burst: 20,
+            broadcast_capacity: 256,
+            api_docs: false,
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// `sqlite:<file>` or `postgres://...`
+    pub url: String,
+    pub max_connections: u32,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            url: "sqlite:voxium.db".to_string(),
+            max_connections: 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Signs session tokens; required
+    pub jwt_secret: String,
+    /// Encrypts stored Discord tokens; Discord login is unavailable without it
+    pub encryption_key: Option<String>,
+    pub registration_mode: RegistrationMode,
+}
+
+impl AuthConfig {
+    pub fn token_cipher(&self) -> Option<TokenCipher> {
+        self.encryption_key.as_deref().map(TokenCipher::new)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordConfig {
+    pub api_base_url: String,
+    pub cdn_base_url: String,
+}
+
+impl Default for DiscordConfig {
+    fn default() -> Self {
+        DiscordConfig {
+            api_base_url: "https://discord.com/api/v10".to_string(),
+            cdn_base_url: "https://cdn.discordapp.com".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VoiceConfig {
+    pub stun_urls: Vec<String>,
+    pub turn_urls: Vec<String>,
+    /// Shared with coturn's `static-auth-secret`; required with `turn_urls`
+    pub turn_secret: Option<String>,
+    pub turn_credential_ttl_secs: i64,
+}
+
+impl Default for VoiceConfig {
+    fn default() -> Self {
+        VoiceConfig {
+            stun_urls: vec!["stun:stun.l.google.com:19302".to_string()],
+            turn_urls: Vec::new(),
+            turn_secret: None,
+            turn_credential_ttl_secs: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadsConfig {
+    /// `mime=size` pairs; a trailing `/*` matches a whole family
+    pub allowed_types: UploadRules,
+    /// Largest decoded image (width × height, summed over GIF frames)
+    pub max_pixels: u64,
+    /// Signs upload URLs; defaults to the JWT secret
+    pub signing_secret: Option<String>,
+    pub url_ttl_secs: i64,
+    /// Quota for roles without their own; 0 = unlimited
+    pub user_quota: ByteSize,
+    /// Total size of stored files; 0 = unlimited
+    pub global_quota: ByteSize,
+    /// Orphaned upload sweep interval; 0 disables it
+    pub gc_interval_secs: u64,
+    pub gc_grace_secs: i64,
+}
+
+impl Default for UploadsConfig {
+    fn default() -> Self {
+        UploadsConfig {
+            allowed_types: UploadRules::default(),
+            max_pixels: 40_000_000,
+            signing_secret: None,
+            url_ttl_secs: 3600,
+            user_quota: ByteSize(1024 * 1024 * 1024),
+            global_quota: ByteSize(0),
+            gc_interval_secs: 3600,
+            gc_grace_secs: 24 * 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Local,
+    S3,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "local" => Ok(StorageBackend::Local),
+            "s3" => Ok(StorageBackend::S3),
+            other => Err(format!("unknown backend '{}' (expected local or s3)", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    /// Directory of the local backend
+    pub root: String,
+    pub s3: S3Config,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backend: StorageBackend::Local,
+            root: "uploads".to_string(),
+            s3: S3Config::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Config {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Key prefix inside the bucket
+    pub prefix: String,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        S3Config {
+            endpoint: String::new(),
+            bucket: String::new(),
+            region: "us-east-1".to_string(),
+            access_key_id: String::new(),
+            secret_access_key: String::new(),
+            prefix: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackupConfig {
+    pub dir: PathBuf,
+    /// Scheduled backups; 0 disables them
+    pub interval_secs: u64,
+    /// Archives kept by scheduled backups; 0 keeps all
+    pub keep: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        BackupConfig {
+            dir: PathBuf::from("backups"),
+            interval_secs: 0,
+            keep: 7,
+        }
+    }
+}
+
+/// Prometheus `/metrics` endpoint.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    /// Required as `Authorization: Bearer <token>` when set
+    pub token: Option<String>,
+    /// Serve `/metrics` on this address (e.g. `127.0.0.1:9100`) instead of
+    /// the main port
+    pub bind_address: Option<String>,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            enabled: true,
+            token: None,
+            bind_address: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// `RUST_LOG`-style filter, e.g. `info,backend::ws=debug,sqlx=warn`
+    pub level: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            format: LogFormat::Pretty,
+            level: "info".to_string(),
+        }
+    }
+}
+
+/// A size written as bytes or with a unit (`512KB`, `8MB`, `1GB`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "SizeSpec")]
+pub struct ByteSize(pub u64);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SizeSpec {
+    Bytes(u64),
+    Text(String),
+}
+
+impl TryFrom<SizeSpec> for ByteSize {
+    type Error = String;
+
+    fn try_from(spec: SizeSpec) -> Result<Self, Self::Error> {
+        match spec {
+            SizeSpec::Bytes(bytes) => Ok(ByteSize(bytes)),
+            SizeSpec::Text(text) => text.parse(),
+        }
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_size(s)
+            .map(|bytes| ByteSize(bytes as u64))
+            .ok_or_else(|| format!("invalid size '{}' (expected e.g. 512KB, 8MB, 1GB)", s.trim()))
+    }
+}
+
+impl ByteSize {
+    /// The size as a limit, `None` for 0 (unlimited).
+    pub fn limit(self) -> Option<i64> {
+        Some(self.0 as i64).filter(|v| *v > 0)
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    /// The file that was read, if any
+    pub file: Option<PathBuf>,
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "Invalid configuration ({})", file.display())?,
+            None => write!(f, "Invalid configuration")?,
+        }
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Read the config file and environment, then validate.
+    pub fn load() -> Result<Config, ConfigError> {
+        dotenvy::dotenv().ok();
+
+        let file = match std::env::var("VOXIUM_CONFIG").ok().filter(|v| !v.trim().is_empty()) {
+            Some(path) => Some(PathBuf::from(path)),
+            None => find_config_file(),
+        };
+
+        let mut config = match &file {
+            Some(path) => Config::read_file(path).map_err(|problem| ConfigError {
+                file: file.clone(),
+                problems: vec![problem],
+            })?,
+            None => Config::default(),
+        };
+
+        let mut problems = Vec::new();
+        config.apply_env(&mut problems);
+        config.validate(&mut problems);
+
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError { file, problems })
+        }
+    }
+
+    /// Parse a TOML document (no environment, no validation).
+    pub fn from_toml(text: &str) -> Result<Config, String> {
+        toml::from_str(text).map_err(|e| e.to_string().trim_end().to_string())
+    }
+
+    fn read_file(path: &Path) -> Result<Config, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        Config::from_toml(&text)
+    }
+
+    fn apply_env(&mut self, problems: &mut Vec<String>) {
+        let mut env = EnvOverrides { problems };
+
+        env.parse("PORT", &mut self.server.port);
+        env.string("BIND_ADDRESS", &mut self.server.bind_address);
+        env.list("CORS_ORIGINS", &mut self.server.cors_origins);
+        env.parse("RATE_LIMIT_PER_SECOND", &mut self.server.rate_limit_per_second);
+        env.parse("RATE_LIMIT_BURST", &mut self.server.rate_limit_burst);
+        env.parse("BROADCAST_CAPACITY", &mut self.server.broadcast_capacity);
+        env.parse("API_DOCS", &mut self.server.api_docs);
+        env.optional_parse("TLS_CERT_FILE", &mut self.server.tls.cert_file);
+        env.optional_parse("TLS_KEY_FILE", &mut self.server.tls.key_file);
+        env.optional_parse("TLS_REDIRECT_PORT", &mut self.server.tls.redirect_port);
+
+        env.string("DATABASE_URL", &mut self.database.url);
+        env.parse("DB_MAX_CONNECTIONS", &mut self.database.max_connections);
+
+        env.string("JWT_SECRET", &mut self.auth.jwt_secret);
+        env.optional("ENCRYPTION_KEY", &mut self.auth.encryption_key);
+        env.parse("REGISTRATION_MODE", &mut self.auth.registration_mode);
+
+        env.string("DISCORD_API_BASE_URL", &mut self.discord.api_base_url);
+        env.string("DISCORD_CDN_BASE_URL", &mut self.discord.cdn_base_url);
+
+        env.list("STUN_URLS", &mut self.voice.stun_urls);
+        env.list("TURN_URLS", &mut self.voice.turn_urls);
+        env.optional("TURN_SECRET", &mut self.voice.turn_secret);
+        env.parse("TURN_CREDENTIAL_TTL_SECS", &mut self.voice.turn_credential_ttl_secs);
+
+        env.parse("UPLOAD_ALLOWED_TYPES", &mut self.uploads.allowed_types);
+        env.parse("UPLOAD_MAX_PIXELS", &mut self.uploads.max_pixels);
+        env.optional("UPLOAD_SIGNING_SECRET", &mut self.uploads.signing_secret);
+        env.parse("UPLOAD_URL_TTL_SECS", &mut self.uploads.url_ttl_secs);
+        env.parse("UPLOAD_USER_QUOTA", &mut self.uploads.user_quota);
+        env.parse("UPLOAD_GLOBAL_QUOTA", &mut self.uploads.global_quota);
+        env.parse("UPLOAD_GC_INTERVAL_SECS", &mut self.uploads.gc_interval_secs);
+        env.parse("UPLOAD_GC_GRACE_SECS", &mut self.uploads.gc_grace_secs);
+
+        env.parse("STORAGE_BACKEND", &mut self.storage.backend);
+        env.string("STORAGE_ROOT", &mut self.storage.root);
+        env.string("S3_ENDPOINT", &mut self.storage.s3.endpoint);
+        env.string("S3_BUCKET", &mut self.storage.s3.bucket);
+        env.string("S3_REGION", &mut self.storage.s3.region);
+        env.string("S3_ACCESS_KEY_ID", &mut self.storage.s3.access_key_id);
+        env.string("S3_SECRET_ACCESS_KEY", &mut self.storage.s3.secret_access_key);
+        env.string("S3_PREFIX", &mut self.storage.s3.prefix);
+
+        env.parse("BACKUP_DIR", &mut self.backup.dir);
+        env.parse("BACKUP_INTERVAL_SECS", &mut self.backup.interval_secs);
+        env.parse("BACKUP_KEEP", &mut self.backup.keep);
+
+        env.parse("METRICS_ENABLED", &mut self.metrics.enabled);
+        env.optional("METRICS_TOKEN", &mut self.metrics.token);
+        env.optional("METRICS_BIND_ADDRESS", &mut self.metrics.bind_address);
+
+        env.parse("LOG_FORMAT", &mut self.logging.format);
+        env.string("RUST_LOG", &mut self.logging.level);
+    }
+
+    fn validate(&self, problems: &mut Vec<String>) {
+        let mut check = |ok: bool, problem: &str| {
+            if !ok {
+                problems.push(problem.to_string());
+            }
+        };
+
+        check(self.server.port > 0, "server.port (PORT) must be between 1 and 65535");
+        check(!self.server.bind_address.trim().is_empty(), "server.bind_address (BIND_ADDRESS) must not be empty");
+        check(
+            self.server.cors_origins.iter().all(|o| is_valid_origin(o)),
+            "server.cors_origins (CORS_ORIGINS) must be origins like https://chat.example.com, without a path",
+        );
+        check(self.server.rate_limit_per_second > 0, "server.rate_limit_per_second (RATE_LIMIT_PER_SECOND) must be at least 1");
+        check(self.server.rate_limit_burst > 0, "server.rate_limit_burst (RATE_LIMIT_BURST) must be at least 1");
+        check(self.server.broadcast_capacity > 0, "server.broadcast_capacity (BROADCAST_CAPACITY) must be at least 1");
+
+        let url = self.database.url.trim();
+        check(
+            url.starts_with("sqlite:") || url.starts_with("postgres://") || url.starts_with("postgresql://"),
+            "database.url (DATABASE_URL) must start with sqlite:, postgres:// or postgresql://",
+        );
+        check(self.database.max_connections > 0, "database.max_connections (DB_MAX_CONNECTIONS) must be at least 1");
+
+        check(!self.auth.jwt_secret.trim().is_empty(), "auth.jwt_secret (JWT_SECRET) must be set");
+        check(
+            self.auth.encryption_key.as_deref().is_none_or(|k| !k.trim().is_empty()),
+            "auth.encryption_key (ENCRYPTION_KEY) must not be empty when set",
+        );
+
+        check(is_http_url(&self.discord.api_base_url), "discord.api_base_url (DISCORD_API_BASE_URL) must be an http(s) URL");
+        check(is_http_url(&self.discord.cdn_base_url), "discord.cdn_base_url (DISCORD_CDN_BASE_URL) must be an http(s) URL");
+
+        check(
+            self.voice.turn_urls.is_empty() || self.voice.turn_secret.as_deref().is_some_and(|s| !s.is_empty()),
+            "voice.turn_secret (TURN_SECRET) is required when voice.turn_urls (TURN_URLS) is set",
+        );
+        check(self.voice.turn_credential_ttl_secs > 0, "voice.turn_credential_ttl_secs (TURN_CREDENTIAL_TTL_SECS) must be at least 1");
+
+        check(!self.uploads.allowed_types.is_empty(), "uploads.allowed_types (UPLOAD_ALLOWED_TYPES) must allow at least one type");
+        check(self.uploads.max_pixels > 0, "uploads.max_pixels (UPLOAD_MAX_PIXELS) must be at least 1");
+        check(self.uploads.url_ttl_secs > 0, "uploads.url_ttl_secs (UPLOAD_URL_TTL_SECS) must be at least 1");
+        check(self.uploads.gc_grace_secs >= 0, "uploads.gc_grace_secs (UPLOAD_GC_GRACE_SECS) must not be negative");
+
+        match self.storage.backend {
+            StorageBackend::Local => {
+                check(!self.storage.root.trim().is_empty(), "storage.root (STORAGE_ROOT) must not be empty");
+            }
+            StorageBackend::S3 => {
+                if let Err(e) = crate::storage::S3Storage::from_config(&self.storage.s3) {
+                    problems.push(format!("storage.s3: {}", e));
+                }
+            }
+        }
+
+        if self.backup.dir.as_os_str().is_empty() {
+            problems.push("backup.dir (BACKUP_DIR) must not be empty".to_string());
+        }
+
+        if self.metrics.token.as_deref().is_some_and(|t| t.trim().is_empty()) {
+            problems.push("metrics.token (METRICS_TOKEN) must not be empty when set".to_string());
+        }
+        if let Some(addr) = &self.metrics.bind_address {
+            if addr.parse::<std::net::SocketAddr>().is_err() {
+                problems.push(format!(
+                    "metrics.bind_address (METRICS_BIND_ADDRESS): '{}' is not an address like 127.0.0.1:9100",
+                    addr
+                ));
+            }
+        }
+
+        if let Err(e) = crate::logging::parse_filter(&self.logging.level) {
+            problems.push(format!("logging.level (RUST_LOG): {}", e));
+        }
+
+        let tls = &self.server.tls;
+        match (&tls.cert_file, &tls.key_file) {
+            (Some(cert_file), Some(key_file)) => {
+                if let Err(e) = crate::tls::load_certified_key(cert_file, key_file) {
+                    problems.push(format!("server.tls: {}", e));
+                }
+            }
+            (None, None) => {
+                if tls.redirect_port.is_some() {
+                    problems.push("server.tls.redirect_port (TLS_REDIRECT_PORT) needs server.tls.cert_file and key_file".to_string());
+                }
+            }
+            _ => problems.push(
+                "server.tls.cert_file (TLS_CERT_FILE) and server.tls.key_file (TLS_KEY_FILE) must be set together".to_string(),
+            ),
+        }
+        if tls.redirect_port.is_some_and(|p| p == 0 || p == self.server.port) {
+            problems.push("server.tls.redirect_port (TLS_REDIRECT_PORT) must be a free port other than server.port".to_string());
+        }
+    }
+
+    /// Secret for signed upload URLs.
+    pub fn upload_signing_secret(&self) -> &str {
+        self.uploads
+            .signing_secret
+            .as_deref()
+            .filter(|s| !s.is_empty())
+            .unwrap_or(&self.auth.jwt_secret)
+    }
+}
+
+/// Applies environment variables over the file's values, recording values
+/// that do not parse.
+struct EnvOverrides<'a> {
+    problems: &'a mut Vec<String>,
+}
+
+impl EnvOverrides<'_> {
+    fn get(name: &str) -> Option<String> {
+        std::env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+    }
+
+    fn string(&mut self, name: &str, target: &mut String) {
+        if let Some(value) = Self::get(name) {
+            *target = value;
+        }
+    }
+
+    fn optional(&mut self, name: &str, target: &mut Option<String>) {
+        if let Some(value) = Self::get(name) {
+            *target = Some(value);
+        }
+    }
+
+    /// Comma separated list
+    fn list(&mut self, name: &str, target: &mut Vec<String>) {
+        if let Some(value) = Self::get(name) {
+            *target = value.split(',').map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).collect();
+        }
+    }
+
+    fn parse<T: FromStr>(&mut self, name: &str, target: &mut T)
+    where
+        T::Err: fmt::Display,
+    {
+        if let Some(value) = Self::get(name) {
+            match value.parse() {
+                Ok(parsed) => *target = parsed,
+                Err(e) => self.problems.push(format!("{}: invalid value '{}' ({})", name, value, e)),
+            }
+        }
+    }
+
+    fn optional_parse<T: FromStr>(&mut self, name: &str, target: &mut Option<T>)
+    where
+        T::Err: fmt::Display,
+    {
+        if let Some(value) = Self::get(name) {
+            match value.parse() {
+                Ok(parsed) => *target = Some(parsed),
+                Err(e) => self.problems.push(format!("{}: invalid value '{}' ({})", name, value, e)),
+            }
+        }
+    }
+}
+
+fn find_config_file() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+fn is_http_url(url: &str) -> bool {
+    reqwest::Url::parse(url).is_ok_and(|u| matches!(u.scheme(), "http" | "https") && u.host_str().is_some())
+}
+
+/// `scheme://host[:port]`, as sent in the `Origin` header.
+fn is_valid_origin(origin: &str) -> bool {
+    reqwest::Url::parse(origin).is_ok_and(|u| {
+        u.host_str().is_some() && matches!(u.path(), "" | "/") && !origin.ends_with('/') && u.query().is_none()
+    })
+}
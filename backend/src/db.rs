@@ -1,73 +1,365 @@
-use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use sha2::{Digest, Sha256};
+use sqlx::any::AnyPoolOptions;
+use sqlx::Row;
+use std::fmt;
 use std::path::Path;
 
-/// Create the SQLite connection pool and run migrations.
-pub async fn init_db() -> SqlitePool {
-    dotenvy::dotenv().ok();
-    let database_url = std::env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite:voxium.db".into());
-    let max_connections = std::env::var("DB_MAX_CONNECTIONS")
-        .ok()
-        .and_then(|v| v.parse::<u32>().ok())
-        .filter(|v| *v > 0)
-        .unwrap_or(16);
-
-    // Create the DB file if it doesn't exist
-    let db_path = database_url.trim_start_matches("sqlite:");
-    if !Path::new(db_path).exists() {
-        std::fs::File::create(db_path).expect("Failed to create database file");
+use crate::config::DatabaseConfig;
+use crate::logging::LogErr;
+
+/// Connection pool shared by every module. `database.url` selects the
+/// engine: `sqlite:` (default) or `postgres://`. Queries are written once
+/// for both, with `$N` placeholders.
+pub type DbPool = sqlx::AnyPool;
+pub type DbRow = sqlx::any::AnyRow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+}
+
+impl Backend {
+    pub fn of(pool: &DbPool) -> Backend {
+        match pool.connect_options().database_url.scheme() {
+            "postgres" | "postgresql" => Backend::Postgres,
+            _ => Backend::Sqlite,
+        }
+    }
+
+    /// Migrations for this engine, in version order.
+    pub fn migrations(self) -> &'static [Migration] {
+        match self {
+            Backend::Sqlite => SQLITE_MIGRATIONS,
+            Backend::Postgres => POSTGRES_MIGRATIONS,
+        }
+    }
+}
+
+/// A schema migration from `migrations/` (`migrations/postgres/` for
+/// Postgres), applied once and recorded in `schema_migrations` with the
+/// checksum of its SQL.
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+macro_rules! migration {
+    ($version:expr, $name:literal) => {
+        migration!($version, "", $name)
+    };
+    ($version:expr, $dir:literal, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            sql: include_str!(concat!("../../migrations/", $dir, $name, ".sql")),
+        }
+    };
+}
+
+pub const SQLITE_MIGRATIONS: &[Migration] = &[
+    migration!(1, "001_init"),
+    migration!(2, "002_add_settings"),
+    migration!(3, "003_add_images"),
+    migration!(4, "004_add_avatar_url"),
+    migration!(5, "005_add_room_kind"),
+    migration!(6, "006_add_banner_url"),
+    migration!(7, "007_add_room_required_role"),
+    migration!(8, "008_add_message_reply"),
+    migration!(9, "009_add_message_pins"),
+    migration!(10, "010_add_server_roles"),
+    migration!(11, "011_add_message_reactions"),
+    migration!(12, "012_add_perf_indexes"),
+    migration!(13, "013_add_discord_oauth"),
+    migration!(14, "014_add_voice_user_limit"),
+    migration!(15, "015_add_user_presence"),
+    migration!(16, "016_add_message_attachments"),
+    migration!(17, "017_add_upload_url_indexes"),
+    migration!(18, "018_add_upload_blobs"),
+    migration!(19, "019_add_role_storage_quota"),
+    migration!(20, "020_add_role_animated_avatars"),
+    migration!(21, "021_add_invites_and_bans"),
+    migration!(22, "022_add_upload_blob_retained_at"),
+];
+
+/// Postgres support started at version 20 with the equivalent of the
+/// SQLite history in one file. New migrations go in both lists under the
+/// same version.
+pub const POSTGRES_MIGRATIONS: &[Migration] = &[
+    migration!(20, "postgres/", "020_initial_schema"),
+    migration!(21, "postgres/", "021_add_invites_and_bans"),
+    migration!(22, "postgres/", "022_add_upload_blob_retained_at"),
+];
+
+/// Last migration shipped before `schema_migrations` existed. Databases
+/// without the table but with a schema were migrated up to here by the
+/// old runner, which replayed every file on each boot.
+const LEGACY_BASELINE_VERSION: i64 = 13;
+
+/// Migrations up to here were also replayed by the old runner in
+/// development builds, so their columns may already exist.
+const LAST_REPLAYED_VERSION: i64 = 20;
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Database(sqlx::Error),
+    Failed { name: &'static str, source: sqlx::Error },
+    ChecksumMismatch { version: i64, name: &'static str },
+    UnknownVersion(i64),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Database(e) => write!(f, "{}", e),
+            MigrationError::Failed { name, source } => write!(f, "migration {} failed: {}", name, source),
+            MigrationError::ChecksumMismatch { version, name } => write!(
+                f,
+                "migration {} (version {}) was modified after being applied; add a new migration instead",
+                name, version
+            ),
+            MigrationError::UnknownVersion(version) => write!(
+                f,
+                "database has migration version {} which this build does not know; refusing to run an older build",
+                version
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<sqlx::Error> for MigrationError {
+    fn from(e: sqlx::Error) -> Self {
+        MigrationError::Database(e)
+    }
+}
+
+fn checksum(sql: &str) -> String {
+    format!("{:x}", Sha256::digest(sql.as_bytes()))
+}
+
+/// SQLite settings applied to every new connection.
+const SQLITE_PRAGMAS: &[&str] = &[
+    "PRAGMA journal_mode=WAL",
+    "PRAGMA synchronous=NORMAL",
+    "PRAGMA temp_store=MEMORY",
+    "PRAGMA busy_timeout=5000",
+    "PRAGMA cache_size=-20000",
+];
+
+/// Create the connection pool (without migrating).
+pub async fn connect(config: &DatabaseConfig) -> DbPool {
+    connect_to(&config.url, config.max_connections).await
+}
+
+/// Create a pool for `database_url`, creating the file of a SQLite URL.
+pub async fn connect_to(database_url: &str, max_connections: u32) -> DbPool {
+    // Create the SQLite file if it doesn't exist
+    if let Some(db_path) = database_url.strip_prefix("sqlite:") {
+        if !Path::new(db_path).exists() {
+            std::fs::File::create(db_path).expect("Failed to create database file");
+        }
     }
 
-    let pool = SqlitePoolOptions::new()
+    sqlx::any::install_default_drivers();
+    AnyPoolOptions::new()
         .max_connections(max_connections)
-        .connect(&database_url)
+        .after_connect(|conn, _| {
+            Box::pin(async move {
+                if conn.backend_name() == "SQLite" {
+                    for pragma in SQLITE_PRAGMAS {
+                        sqlx::query(pragma).execute(&mut *conn).await.log_err(pragma);
+                    }
+                }
+                Ok(())
+            })
+        })
+        .connect(database_url)
         .await
-        .expect("Failed to connect to SQLite");
-
-    let _ = sqlx::query("PRAGMA journal_mode=WAL")
-        .execute(&pool)
-        .await;
-    let _ = sqlx::query("PRAGMA synchronous=NORMAL")
-        .execute(&pool)
-        .await;
-    let _ = sqlx::query("PRAGMA temp_store=MEMORY")
-        .execute(&pool)
-        .await;
-    let _ = sqlx::query("PRAGMA busy_timeout=5000")
-        .execute(&pool)
-        .await;
-    let _ = sqlx::query("PRAGMA cache_size=-20000")
-        .execute(&pool)
-        .await;
-
-    let migrations = [
-        include_str!("../../migrations/001_init.sql"),
-        include_str!("../../migrations/002_add_settings.sql"),
-        include_str!("../../migrations/003_add_images.sql"),
-        include_str!("../../migrations/004_add_avatar_url.sql"),
-        include_str!("../../migrations/005_add_room_kind.sql"),
-        include_str!("../../migrations/006_add_banner_url.sql"),
-        include_str!("../../migrations/007_add_room_required_role.sql"),
-        include_str!("../../migrations/008_add_message_reply.sql"),
-        include_str!("../../migrations/009_add_message_pins.sql"),
-        include_str!("../../migrations/010_add_server_roles.sql"),
-        include_str!("../../migrations/011_add_message_reactions.sql"),
-        include_str!("../../migrations/012_add_perf_indexes.sql"),
-        include_str!("../../migrations/013_add_discord_oauth.sql"),
-    ];
-
-    for sql in migrations {
-        run_migration_sql(sql, &pool).await;
+        .expect("Failed to connect to the database")
+}
+
+/// Create the connection pool and run migrations. Exits the process
+/// if a migration fails.
+pub async fn init_db(config: &DatabaseConfig) -> DbPool {
+    let pool = connect(config).await;
+
+    if let Err(e) = run_migrations(&pool).await {
+        tracing::error!(error = %e, "Database migration failed");
+        std::process::exit(1);
     }
 
-    println!("✅ Database initialized");
+    tracing::info!("Database initialized (schema version {})", schema_version(&pool).await);
     pool
 }
 
-async fn run_migration_sql(sql_content: &str, pool: &SqlitePool) {
-        for statement in sql_content.split(';') {
-                let trimmed = statement.trim();
-                if !trimmed.is_empty() {
-                        sqlx::query(trimmed).execute(pool).await.ok();
+async fn ensure_migrations_table(pool: &DbPool) -> Result<(), sqlx::Error> {
+    let now = match Backend::of(pool) {
+        Backend::Sqlite => "datetime('now')",
+        Backend::Postgres => "to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS')",
+    };
+    sqlx::query(&format!(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            checksum TEXT NOT NULL,
+            applied_at TEXT NOT NULL DEFAULT ({})
+        )",
+        now
+    ))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Applied versions and their checksums, in order.
+async fn applied_migrations(pool: &DbPool) -> Result<Vec<(i64, String, String)>, sqlx::Error> {
+    let rows = sqlx::query("SELECT version, checksum, applied_at FROM schema_migrations ORDER BY version")
+        .fetch_all(pool)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.get("version"), row.get("checksum"), row.get("applied_at")))
+        .collect())
+}
+
+/// Highest applied migration version (0 for an empty database).
+pub async fn schema_version(pool: &DbPool) -> i64 {
+    sqlx::query_scalar::<_, Option<i64>>("SELECT MAX(version) FROM schema_migrations")
+        .fetch_one(pool)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or(0)
+}
+
+/// Whether the database has a schema but no `schema_migrations` history.
+/// Only SQLite databases predate the table.
+async fn is_legacy_database(pool: &DbPool) -> Result<bool, sqlx::Error> {
+    if Backend::of(pool) != Backend::Sqlite {
+        return Ok(false);
+    }
+    let has_history: Option<i64> = sqlx::query_scalar(
+        "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations'"
+    )
+    .fetch_optional(pool)
+    .await?;
+    let has_schema: Option<i64> = sqlx::query_scalar(
+        "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'users'"
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(has_history.is_none() && has_schema.is_some())
+}
+
+/// Apply every pending migration, each in its own transaction, and stop
+/// at the first failure.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn run_migrations(pool: &DbPool) -> Result<(), MigrationError> {
+    let backend = Backend::of(pool);
+    let migrations = backend.migrations();
+    let legacy = is_legacy_database(pool).await?;
+    ensure_migrations_table(pool).await?;
+
+    if legacy {
+        for migration in migrations.iter().filter(|m| m.version <= LEGACY_BASELINE_VERSION) {
+            sqlx::query("INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING")
+                .bind(migration.version)
+                .bind(migration.name)
+                .bind(checksum(migration.sql))
+                .execute(pool)
+                .await?;
+        }
+        tracing::info!("Existing database recorded at migration {}", LEGACY_BASELINE_VERSION);
+    }
+
+    let applied = applied_migrations(pool).await?;
+    for (version, applied_checksum, _) in &applied {
+        match migrations.iter().find(|m| m.version == *version) {
+            Some(m) if checksum(m.sql) != *applied_checksum => {
+                return Err(MigrationError::ChecksumMismatch { version: m.version, name: m.name });
+            }
+            Some(_) => {}
+            None => return Err(MigrationError::UnknownVersion(*version)),
+        }
+    }
+
+    for migration in migrations {
+        if applied.iter().any(|(version, _, _)| *version == migration.version) {
+            continue;
+        }
+        apply_migration(pool, backend, migration).await?;
+        tracing::info!("Applied migration {}", migration.name);
+    }
+
+    Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(version = migration.version))]
+async fn apply_migration(pool: &DbPool, backend: Backend, migration: &Migration) -> Result<(), MigrationError> {
+    let failed = |source| MigrationError::Failed { name: migration.name, source };
+    let mut tx = pool.begin().await?;
+
+    if backend == Backend::Sqlite && migration.version <= LAST_REPLAYED_VERSION {
+        // These files predate multi-statement bodies and split safely on `;`
+        for statement in migration.sql.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+            if let Err(e) = sqlx::query(statement).execute(&mut *tx).await {
+                if !e.to_string().contains("duplicate column name") {
+                    return Err(failed(e));
                 }
+            }
         }
+    } else {
+        sqlx::raw_sql(migration.sql).execute(&mut *tx).await.map_err(failed)?;
+    }
+
+    sqlx::query("INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)")
+        .bind(migration.version)
+        .bind(migration.name)
+        .bind(checksum(migration.sql))
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: &'static str,
+    /// `None` while pending
+    pub applied_at: Option<String>,
+    pub checksum_matches: bool,
+}
+
+/// State of every known migration, for `backend migrate status`. Does not
+/// apply or record anything.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn migration_status(pool: &DbPool) -> Result<Vec<MigrationStatus>, sqlx::Error> {
+    let migrations = Backend::of(pool).migrations();
+    let applied = if is_legacy_database(pool).await? {
+        migrations
+            .iter()
+            .filter(|m| m.version <= LEGACY_BASELINE_VERSION)
+            .map(|m| (m.version, checksum(m.sql), "before schema_migrations".to_string()))
+            .collect()
+    } else {
+        ensure_migrations_table(pool).await?;
+        applied_migrations(pool).await?
+    };
+
+    Ok(migrations
+        .iter()
+        .map(|m| {
+            let row = applied.iter().find(|(version, _, _)| *version == m.version);
+            MigrationStatus {
+                version: m.version,
+                name: m.name,
+                applied_at: row.map(|(_, _, applied_at)| applied_at.clone()),
+                checksum_matches: row.is_none_or(|(_, sum, _)| *sum == checksum(m.sql)),
+            }
+        })
+        .collect())
 }
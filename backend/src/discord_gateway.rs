@@ -11,19 +11,24 @@
 use actix_web::{web, HttpRequest, HttpResponse};
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use sqlx::{Row, SqlitePool};
+use utoipa::{IntoParams, ToSchema};
+use sqlx::Row;
+
+use crate::db::DbPool;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::{mpsc, oneshot, Mutex};
 use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
 
 use crate::auth::extract_claims;
+use crate::errors::{AppError, ErrorBody};
 
 const DISCORD_GATEWAY_URL: &str = "wss://gateway.discord.gg/?v=9&encoding=json";
 
 // ── Types ───────────────────────────────────────────────
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct VoiceServerInfo {
     pub token: String,
     pub endpoint: Option<String>,
@@ -32,7 +37,7 @@ pub struct VoiceServerInfo {
     pub user_id: String,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct VoiceParticipant {
     pub user_id: String,
     pub channel_id: Option<String>,
@@ -40,13 +45,13 @@ pub struct VoiceParticipant {
     pub avatar_url: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct VoiceJoinPayload {
     pub guild_id: String,
     pub channel_id: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct VoiceLeavePayload {
     pub guild_id: String,
 }
@@ -65,6 +70,9 @@ enum GatewayCommand {
     },
 }
 
+// (guild_id, channel_id, reply) for a join awaiting VOICE_SERVER_UPDATE
+type PendingVoiceJoin = (String, String, oneshot::Sender<Result<VoiceServerInfo, String>>);
+
 pub struct GatewaySession {
     cmd_tx: mpsc::Sender<GatewayCommand>,
     presence: Arc<Mutex<VoicePresenceState>>,
@@ -76,6 +84,11 @@ pub fn create_discord_gateways() -> DiscordGateways {
     Arc::new(Mutex::new(HashMap::new()))
 }
 
+/// Gateway connections whose task is still running.
+pub async fn live_session_count(gateways: &DiscordGateways) -> usize {
+    gateways.lock().await.values().filter(|s| !s.cmd_tx.is_closed()).count()
+}
+
 #[derive(Default)]
 struct VoicePresenceState {
     // guild_id -> user_id -> participant
@@ -96,7 +109,7 @@ async fn run_gateway(
     let mut request = match DISCORD_GATEWAY_URL.into_client_request() {
         Ok(r) => r,
         Err(e) => {
-            eprintln!("[discord-gw] Failed to build request: {e}");
+            error!(error = %e, "Failed to build gateway request");
             return;
         }
     };
@@ -106,15 +119,15 @@ async fn run_gateway(
         HeaderValue::from_static("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36"),
     );
 
-    eprintln!("[discord-gw] Connecting to Discord Gateway...");
+    debug!("Connecting to Discord Gateway");
     let connect_result = connect_async(request).await;
     let (ws_stream, _) = match connect_result {
         Ok(r) => {
-            eprintln!("[discord-gw] Connected to Discord Gateway");
+            info!("Connected to Discord Gateway");
             r
         }
         Err(e) => {
-            eprintln!("[discord-gw] Connection failed: {e}");
+            warn!(error = %e, "Discord Gateway connection failed");
             // Drain any pending commands
             while let Some(cmd) = cmd_rx.recv().await {
                 match cmd {
@@ -137,11 +150,7 @@ async fn run_gateway(
     let mut sequence: Option<u64> = None;
     let mut session_id: Option<String> = None;
     let mut identified = false;
-    let mut pending_voice_join: Option<(
-        String, // guild_id
-        String, // channel_id
-        oneshot::Sender<Result<VoiceServerInfo, String>>,
-    )> = None;
+    let mut pending_voice_join: Option<PendingVoiceJoin> = None;
     // Queued join command waiting for READY event
     let mut queued_join: Option<GatewayCommand> = None;
     let mut voice_token: Option<String> = None;
@@ -240,7 +249,7 @@ async fn run_gateway(
                                             }
                                         }
                                     });
-                                    eprintln!("[discord-gw] Sending Identify");
+                                    debug!("Sending Identify");
                                     let _ = ws_tx.send(Message::Text(identify.to_string())).await;
                                     identified = true;
                                 }
@@ -267,10 +276,10 @@ async fn run_gateway(
                                                     .and_then(|u| u.get("id"))
                                                     .and_then(|v| v.as_str())
                                                     .map(|s| s.to_string());
-                                                eprintln!("[discord-gw] READY — session_id={:?} user_id={:?}", session_id, discord_user_id);
+                                                info!(session_id = ?session_id, discord_user_id = ?discord_user_id, "Gateway READY");
                                             }
                                         } else {
-                                            eprintln!("[discord-gw] READY_SUPPLEMENTAL received");
+                                            debug!("READY_SUPPLEMENTAL received");
                                         }
 
                                         // Process any queued join command
@@ -280,7 +289,7 @@ async fn run_gateway(
                                             voice_guild_id = None;
                                             pending_voice_join = Some((guild_id.clone(), channel_id.clone(), reply));
 
-                                            eprintln!("[discord-gw] Processing queued join: guild={guild_id} channel={channel_id}");
+                                            debug!(guild_id = %guild_id, channel_id = %channel_id, "Processing queued join");
 
                                             let voice_state = serde_json::json!({
                                                 "op": 4,
@@ -362,9 +371,12 @@ async fn run_gateway(
                                                 .unwrap_or("");
                                             let our_id = discord_user_id.as_deref().unwrap_or("");
 
-                                            eprintln!("[discord-gw] VOICE_STATE_UPDATE — event_user={} our_user={} channel={:?}",
-                                                event_user_id, our_id,
-                                                data.get("channel_id").and_then(|v| v.as_str()));
+                                            debug!(
+                                                event_user_id = %event_user_id,
+                                                our_user_id = %our_id,
+                                                channel_id = ?data.get("channel_id").and_then(|v| v.as_str()),
+                                                "VOICE_STATE_UPDATE"
+                                            );
 
                                             if event_user_id == our_id {
                                                 // If VOICE_SERVER_UPDATE already arrived, reply now
@@ -377,7 +389,7 @@ async fn run_gateway(
                                                             session_id: session_id.clone().unwrap_or_default(),
                                                             user_id: our_id.to_string(),
                                                         };
-                                                        eprintln!("[discord-gw] Sending voice info to frontend (via VSU): endpoint={:?}", info.endpoint);
+                                                        debug!(endpoint = ?info.endpoint, "Sending voice info to frontend (via VOICE_STATE_UPDATE)");
                                                         let _ = reply.send(Ok(info));
                                                     }
                                                 }
@@ -387,9 +399,11 @@ async fn run_gateway(
 
                                     "VOICE_SERVER_UPDATE" => {
                                         if let Some(data) = d {
-                                            eprintln!("[discord-gw] VOICE_SERVER_UPDATE — endpoint={:?} guild={:?}",
-                                                data.get("endpoint").and_then(|v| v.as_str()),
-                                                data.get("guild_id").and_then(|v| v.as_str()));
+                                            debug!(
+                                                endpoint = ?data.get("endpoint").and_then(|v| v.as_str()),
+                                                guild_id = ?data.get("guild_id").and_then(|v| v.as_str()),
+                                                "VOICE_SERVER_UPDATE"
+                                            );
                                             voice_token = data.get("token")
                                                 .and_then(|v| v.as_str())
                                                 .map(|s| s.to_string());
@@ -410,7 +424,7 @@ async fn run_gateway(
                                                     session_id: session_id.clone().unwrap_or_default(),
                                                     user_id: discord_user_id.clone().unwrap_or_default(),
                                                 };
-                                                eprintln!("[discord-gw] Sending voice info to frontend: endpoint={:?}", info.endpoint);
+                                                debug!(endpoint = ?info.endpoint, "Sending voice info to frontend");
                                                 let _ = reply.send(Ok(info));
                                             }
                                         }
@@ -418,20 +432,20 @@ async fn run_gateway(
 
                                     _ => {
                                         // Log unhandled dispatch events for debugging
-                                        eprintln!("[discord-gw] Dispatch event: {} (ignored)", event_name);
+                                        trace!(event = %event_name, "Ignoring dispatch event");
                                     }
                                 }
                             }
 
                             // 7 = Reconnect
                             7 => {
-                                eprintln!("[discord-gw] Received Reconnect (op 7)");
+                                info!("Received Reconnect (op 7)");
                                 running = false;
                             }
 
                             // 9 = Invalid Session
                             9 => {
-                                eprintln!("[discord-gw] Received Invalid Session (op 9)");
+                                warn!("Received Invalid Session (op 9)");
                                 running = false;
                                 if let Some((_, _, reply)) = pending_voice_join.take() {
                                     let _ = reply.send(Err("Discord session invalid".into()));
@@ -443,11 +457,11 @@ async fn run_gateway(
                     }
 
                     Some(Ok(Message::Close(frame))) => {
-                        eprintln!("[discord-gw] WS Closed: {:?}", frame);
+                        info!(frame = ?frame, "Gateway WebSocket closed");
                         running = false;
                     }
                     None => {
-                        eprintln!("[discord-gw] WS stream ended");
+                        info!("Gateway WebSocket stream ended");
                         running = false;
                     }
 
@@ -472,20 +486,20 @@ async fn run_gateway(
                     Some(GatewayCommand::JoinVoice { guild_id, channel_id, reply }) => {
                         if session_id.is_none() {
                             // Gateway not ready yet, queue the command
-                            eprintln!("[discord-gw] Gateway not ready yet, queueing join for guild={guild_id} channel={channel_id}");
+                            debug!(guild_id = %guild_id, channel_id = %channel_id, "Gateway not ready yet, queueing join");
                             queued_join = Some(GatewayCommand::JoinVoice { guild_id, channel_id, reply });
                             continue;
                         }
 
                         // If there's a pending join, cancel it first
                         if let Some((_, _, old_reply)) = pending_voice_join.take() {
-                            eprintln!("[discord-gw] Cancelling previous pending join");
+                            debug!("Cancelling previous pending join");
                             let _ = old_reply.send(Err("Superseded by new join request".into()));
                         }
 
                         // First, leave any current voice channel in this guild
                         // to ensure Discord sends fresh VOICE_SERVER_UPDATE
-                        eprintln!("[discord-gw] Sending leave before join for guild={guild_id}");
+                        debug!(guild_id = %guild_id, "Sending leave before join");
                         let leave_state = serde_json::json!({
                             "op": 4,
                             "d": {
@@ -500,7 +514,7 @@ async fn run_gateway(
                         // Small delay to let Discord process the leave
                         tokio::time::sleep(std::time::Duration::from_millis(200)).await;
 
-                        eprintln!("[discord-gw] Sending Voice State Update (join): guild={guild_id} channel={channel_id}");
+                        debug!(guild_id = %guild_id, channel_id = %channel_id, "Sending Voice State Update (join)");
 
                         // Clear previous voice state
                         voice_token = None;
@@ -599,9 +613,12 @@ async fn ensure_gateway_session(
     let presence: Arc<Mutex<VoicePresenceState>> = Arc::new(Mutex::new(VoicePresenceState::default()));
     let presence_clone = presence.clone();
 
-    tokio::spawn(async move {
-        run_gateway(token, cmd_rx, presence_clone).await;
-    });
+    tokio::spawn(
+        async move {
+            run_gateway(token, cmd_rx, presence_clone).await;
+        }
+        .instrument(info_span!("discord_gateway", user_id = %user_id)),
+    );
 
     map.insert(
         user_id.to_string(),
@@ -614,37 +631,42 @@ async fn ensure_gateway_session(
     (cmd_tx, presence)
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct VoiceParticipantsQuery {
     pub guild_id: String,
     pub channel_id: Option<String>,
 }
 
-/// GET /api/discord/voice/participants?guild_id=...&channel_id=...
+/// GET /api/discord/voice/participants?guild_id=...&channel_id=... — Who is in a guild's voice channels
+#[utoipa::path(
+    get,
+    path = "/api/discord/voice/participants",
+    tag = "discord",
+    security(("bearer" = [])),
+    params(VoiceParticipantsQuery),
+    responses(
+        (status = 200, description = "Users in the guild's voice channels, as seen by your gateway session", body = [VoiceParticipant]),
+        (status = 400, description = "No Discord account linked", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+    )
+)]
 pub async fn voice_participants(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
     gateways: web::Data<DiscordGateways>,
     query: web::Query<VoiceParticipantsQuery>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
-    let discord_token = match get_discord_token(pool.get_ref(), &claims.sub).await {
-        Ok(t) => t,
-        Err(e) => {
-            return HttpResponse::BadRequest().json(serde_json::json!({ "error": e }));
-        }
-    };
+    let discord_token = get_discord_token(pool.get_ref(), &claims.sub).await?;
 
     let (_cmd_tx, presence) = ensure_gateway_session(&claims.sub, &discord_token, gateways.get_ref()).await;
     let p = presence.lock().await;
     let guild_map = match p.by_guild.get(&query.guild_id) {
         Some(m) => m,
         None => {
-            return HttpResponse::Ok().json(Vec::<VoiceParticipant>::new());
+            return Ok(HttpResponse::Ok().json(Vec::<VoiceParticipant>::new()));
         }
     };
 
@@ -653,48 +675,54 @@ pub async fn voice_participants(
         participants.retain(|u| u.channel_id.as_deref() == Some(channel_id));
     }
 
-    HttpResponse::Ok().json(participants)
+    Ok(HttpResponse::Ok().json(participants))
 }
 
 // ── Helper: get Discord token for user ──────────────────
 
-async fn get_discord_token(pool: &SqlitePool, user_id: &str) -> Result<String, String> {
-    let row = sqlx::query("SELECT discord_access_token FROM users WHERE id = ?")
+#[tracing::instrument(level = "debug", skip_all, fields(user_id))]
+async fn get_discord_token(pool: &DbPool, user_id: &str) -> Result<String, AppError> {
+    let row = sqlx::query("SELECT discord_access_token FROM users WHERE id = $1")
         .bind(user_id)
         .fetch_optional(pool)
-        .await
-        .map_err(|_| "Database error".to_string())?;
+        .await?;
 
-    let row = row.ok_or("User not found")?;
+    let row = row.ok_or(AppError::UserNotFound)?;
     let token: Option<String> = row
         .try_get("discord_access_token")
         .unwrap_or(None);
 
-    token.ok_or("No Discord token linked".to_string())
+    token.ok_or(AppError::DiscordNotLinked)
 }
 
 // ── HTTP Handlers ───────────────────────────────────────
 
-/// POST /api/discord/voice/join
+/// POST /api/discord/voice/join — Join a Discord voice channel
 /// Body: { guild_id, channel_id }
 /// Returns: VoiceServerInfo with token, endpoint, session_id, user_id
+#[utoipa::path(
+    post,
+    path = "/api/discord/voice/join",
+    tag = "discord",
+    security(("bearer" = [])),
+    request_body = VoiceJoinPayload,
+    responses(
+        (status = 200, description = "Voice server to connect to", body = VoiceServerInfo),
+        (status = 400, description = "No Discord account linked", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 502, description = "Discord refused the join", body = ErrorBody),
+        (status = 504, description = "No voice server from Discord within 20s", body = ErrorBody),
+    )
+)]
 pub async fn voice_join(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
     gateways: web::Data<DiscordGateways>,
     body: web::Json<VoiceJoinPayload>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
-    let discord_token = match get_discord_token(pool.get_ref(), &claims.sub).await {
-        Ok(t) => t,
-        Err(e) => {
-            return HttpResponse::BadRequest().json(serde_json::json!({ "error": e }));
-        }
-    };
+    let discord_token = get_discord_token(pool.get_ref(), &claims.sub).await?;
 
     let cmd_tx = ensure_gateway(&claims.sub, &discord_token, gateways.get_ref()).await;
 
@@ -712,56 +740,52 @@ pub async fn voice_join(
         // Gateway task died, remove from map
         let mut map = gateways.lock().await;
         map.remove(&claims.sub);
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "Discord Gateway session lost"
-        }));
+        return Err(AppError::DiscordSessionLost);
     }
 
     // Wait for the voice server info with a timeout (20s to allow for gateway identify + voice join)
-    eprintln!("[discord-gw] HTTP handler waiting for voice info (20s timeout)...");
+    debug!("Waiting for voice info (20s timeout)");
     match tokio::time::timeout(std::time::Duration::from_secs(20), reply_rx).await {
         Ok(Ok(Ok(info))) => {
-            eprintln!("[discord-gw] HTTP handler returning voice info OK — endpoint={:?}", info.endpoint);
-            HttpResponse::Ok().json(info)
+            debug!(endpoint = ?info.endpoint, "Voice info received");
+            Ok(HttpResponse::Ok().json(info))
         }
         Ok(Ok(Err(e))) => {
-            eprintln!("[discord-gw] HTTP handler returning error from gateway: {e}");
-            HttpResponse::BadGateway().json(serde_json::json!({ "error": e }))
-        }
-        Ok(Err(_)) => {
-            eprintln!("[discord-gw] HTTP handler: oneshot channel dropped");
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Internal channel error"
-            }))
+            warn!(error = %e, "Gateway failed to join voice");
+            Err(AppError::DiscordVoiceFailed(e))
         }
+        Ok(Err(_)) => Err(AppError::Internal("Gateway dropped the voice join reply".to_string())),
         Err(_) => {
-            eprintln!("[discord-gw] HTTP handler: TIMEOUT — no voice info in 20s");
-            HttpResponse::GatewayTimeout().json(serde_json::json!({
-                "error": "Timeout waiting for Discord voice server info"
-            }))
+            warn!("No voice info from the gateway within 20s");
+            Err(AppError::DiscordTimeout)
         }
     }
 }
 
-/// POST /api/discord/voice/leave
+/// POST /api/discord/voice/leave — Leave the guild's voice channel
 /// Body: { guild_id }
+#[utoipa::path(
+    post,
+    path = "/api/discord/voice/leave",
+    tag = "discord",
+    security(("bearer" = [])),
+    request_body = VoiceLeavePayload,
+    responses(
+        (status = 200, description = "Left the voice channel", body = Object, example = json!({ "ok": true })),
+        (status = 400, description = "No Discord account linked", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 502, description = "Discord refused", body = ErrorBody),
+    )
+)]
 pub async fn voice_leave(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
     gateways: web::Data<DiscordGateways>,
     body: web::Json<VoiceLeavePayload>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
-    let discord_token = match get_discord_token(pool.get_ref(), &claims.sub).await {
-        Ok(t) => t,
-        Err(e) => {
-            return HttpResponse::BadRequest().json(serde_json::json!({ "error": e }));
-        }
-    };
+    let discord_token = get_discord_token(pool.get_ref(), &claims.sub).await?;
 
     let cmd_tx = ensure_gateway(&claims.sub, &discord_token, gateways.get_ref()).await;
 
@@ -777,20 +801,14 @@ pub async fn voice_leave(
     {
         let mut map = gateways.lock().await;
         map.remove(&claims.sub);
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "Discord Gateway session lost"
-        }));
+        return Err(AppError::DiscordSessionLost);
     }
 
     match tokio::time::timeout(std::time::Duration::from_secs(5), reply_rx).await {
         Ok(Ok(Ok(()))) => {
-            HttpResponse::Ok().json(serde_json::json!({ "ok": true }))
-        }
-        Ok(Ok(Err(e))) => {
-            HttpResponse::BadGateway().json(serde_json::json!({ "error": e }))
+            Ok(HttpResponse::Ok().json(serde_json::json!({ "ok": true })))
         }
-        _ => HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "Failed to leave voice"
-        })),
+        Ok(Ok(Err(e))) => Err(AppError::DiscordVoiceFailed(e)),
+        _ => Err(AppError::Internal("No reply from the gateway to leave voice".to_string())),
     }
 }
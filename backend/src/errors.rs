@@ -0,0 +1,497 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — API errors
+// ═══════════════════════════════════════════════════════
+//
+// Handlers return `Result<_, AppError>`. An error goes out as
+//
+//     { "code": "ROOM_NOT_FOUND", "message": "Room not found", "details": null }
+//
+// `code` is stable and is what clients should branch on. `message` is for
+// people: it follows the request's `Accept-Language` (English or French,
+// English by default). `details` carries the values behind the error,
+// such as a size limit, or `null`.
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+use serde_json::json;
+use std::borrow::Cow;
+use std::fmt;
+use utoipa::ToSchema;
+
+use crate::auth::MIN_PASSWORD_LEN;
+use crate::moderation::Ban;
+use crate::uploads::format_size;
+
+#[derive(Debug)]
+pub enum AppError {
+    // General
+    Unauthenticated,
+    AdminOnly,
+    AccessDenied,
+    NotFound,
+    /// Malformed body or query string, with the parser's explanation
+    InvalidRequest(String),
+    /// Unexpected server-side failure. The text is logged, never sent.
+    Internal(String),
+
+    // Accounts
+    InvalidCredentials,
+    UsernameRequired,
+    PasswordTooShort,
+    UsernameTaken,
+    RegistrationClosed,
+    InviteRequired,
+    Banned(Ban),
+    ForeignProfileImage,
+    UserNotFound,
+
+    // Roles
+    RoleNameLength,
+    RoleNameCharacters,
+    InvalidRoleColor,
+    NegativeStorageQuota,
+    RoleExists,
+    RoleNotFound,
+    ProtectedRole,
+    UnknownRole,
+
+    // Rooms
+    RoomNameRequired,
+    InvalidRoomKind,
+    InvalidUserLimit { max: i64 },
+    RestrictedRoomAdminOnly,
+    RoomNameTaken,
+    RoomNotFound,
+    RoomAccessDenied,
+
+    // Messages
+    MessageNotFound,
+    NotMessageAuthor,
+    InvalidEmoji,
+
+    // Voice
+    NothingToUpdate,
+    NotVoiceRoom,
+    NotInVoice,
+    MoveMembersRequired,
+
+    // Uploads
+    NoFileProvided,
+    FileTooLarge { max_bytes: usize, mime_type: Option<&'static str> },
+    FileTypeNotAllowed { mime_type: &'static str },
+    ImageUploadsDisabled,
+    UnsupportedImageFormat,
+    InvalidImage,
+    ImageTooLarge { max_pixels: u64 },
+    AnimationTooLarge { max_pixels: u64 },
+    ImageProcessingFailed,
+    InvalidCrop,
+    CropOutsideImage,
+    UploadFailed,
+    StorageQuotaExceeded { quota: i64 },
+    ServerStorageFull,
+
+    // Discord
+    DiscordLoginDisabled,
+    DiscordTokenRequired,
+    DiscordNotLinked,
+    DiscordTokenUnreadable,
+    DiscordUnavailable,
+    /// Discord refused the token; carries Discord's response body
+    DiscordTokenInvalid(String),
+    DiscordBadResponse,
+    DiscordPathInvalid,
+    DiscordPathNotAllowed,
+    DiscordMethodNotAllowed,
+    DiscordSessionLost,
+    /// The gateway could not join or leave a voice channel
+    DiscordVoiceFailed(String),
+    DiscordTimeout,
+    QrSessionNotFound,
+}
+
+/// Languages error messages are available in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    En,
+    Fr,
+}
+
+impl Lang {
+    /// The preferred supported language of an `Accept-Language` header,
+    /// English when none matches.
+    pub fn from_accept_language(value: &str) -> Lang {
+        let mut best: Option<(Lang, f32)> = None;
+        for entry in value.split(',') {
+            let mut parts = entry.split(';');
+            let tag = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let lang = match tag.split('-').next().unwrap_or_default() {
+                "en" => Lang::En,
+                "fr" => Lang::Fr,
+                _ => continue,
+            };
+            let quality = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if quality > 0.0 && best.is_none_or(|(_, q)| quality > q) {
+                best = Some((lang, quality));
+            }
+        }
+        best.map_or(Lang::En, |(lang, _)| lang)
+    }
+}
+
+/// What an `AppError` response carries.
+#[derive(Serialize, ToSchema)]
+#[schema(example = json!({ "code": "ROOM_NOT_FOUND", "message": "Room not found", "details": null }))]
+pub struct ErrorBody {
+    #[schema(value_type = String)]
+    pub code: &'static str,
+    pub message: String,
+    pub details: Option<serde_json::Value>,
+}
+
+impl AppError {
+    /// Stable identifier, part of the API.
+    pub fn code(&self) -> &'static str {
+        use AppError::*;
+        match self {
+            Unauthenticated => "UNAUTHENTICATED",
+            AdminOnly => "ADMIN_ONLY",
+            AccessDenied => "ACCESS_DENIED",
+            NotFound => "NOT_FOUND",
+            InvalidRequest(_) => "INVALID_REQUEST",
+            Internal(_) => "INTERNAL_ERROR",
+            InvalidCredentials => "INVALID_CREDENTIALS",
+            UsernameRequired => "USERNAME_REQUIRED",
+            PasswordTooShort => "PASSWORD_TOO_SHORT",
+            UsernameTaken => "USERNAME_TAKEN",
+            RegistrationClosed => "REGISTRATION_CLOSED",
+            InviteRequired => "INVITE_REQUIRED",
+            Banned(_) => "ACCOUNT_BANNED",
+            ForeignProfileImage => "FOREIGN_PROFILE_IMAGE",
+            UserNotFound => "USER_NOT_FOUND",
+            RoleNameLength => "ROLE_NAME_LENGTH",
+            RoleNameCharacters => "ROLE_NAME_CHARACTERS",
+            InvalidRoleColor => "INVALID_ROLE_COLOR",
+            NegativeStorageQuota => "NEGATIVE_STORAGE_QUOTA",
+            RoleExists => "ROLE_EXISTS",
+            RoleNotFound => "ROLE_NOT_FOUND",
+            ProtectedRole => "PROTECTED_ROLE",
+            UnknownRole => "UNKNOWN_ROLE",
+            RoomNameRequired => "ROOM_NAME_REQUIRED",
+            InvalidRoomKind => "INVALID_ROOM_KIND",
+            InvalidUserLimit { .. } => "INVALID_USER_LIMIT",
+            RestrictedRoomAdminOnly => "RESTRICTED_ROOM_ADMIN_ONLY",
+            RoomNameTaken => "ROOM_NAME_TAKEN",
+            RoomNotFound => "ROOM_NOT_FOUND",
+            RoomAccessDenied => "ROOM_ACCESS_DENIED",
+            MessageNotFound => "MESSAGE_NOT_FOUND",
+            NotMessageAuthor => "NOT_MESSAGE_AUTHOR",
+            InvalidEmoji => "INVALID_EMOJI",
+            NothingToUpdate => "NOTHING_TO_UPDATE",
+            NotVoiceRoom => "NOT_VOICE_ROOM",
+            NotInVoice => "NOT_IN_VOICE",
+            MoveMembersRequired => "MOVE_MEMBERS_REQUIRED",
+            NoFileProvided => "NO_FILE_PROVIDED",
+            FileTooLarge { .. } => "FILE_TOO_LARGE",
+            FileTypeNotAllowed { .. } => "FILE_TYPE_NOT_ALLOWED",
+            ImageUploadsDisabled => "IMAGE_UPLOADS_DISABLED",
+            UnsupportedImageFormat => "UNSUPPORTED_IMAGE_FORMAT",
+            InvalidImage => "INVALID_IMAGE",
+            ImageTooLarge { .. } => "IMAGE_TOO_LARGE",
+            AnimationTooLarge { .. } => "ANIMATION_TOO_LARGE",
+            ImageProcessingFailed => "IMAGE_PROCESSING_FAILED",
+            InvalidCrop => "INVALID_CROP",
+            CropOutsideImage => "CROP_OUTSIDE_IMAGE",
+            UploadFailed => "UPLOAD_FAILED",
+            StorageQuotaExceeded { .. } => "STORAGE_QUOTA_EXCEEDED",
+            ServerStorageFull => "SERVER_STORAGE_FULL",
+            DiscordLoginDisabled => "DISCORD_LOGIN_DISABLED",
+            DiscordTokenRequired => "DISCORD_TOKEN_REQUIRED",
+            DiscordNotLinked => "DISCORD_NOT_LINKED",
+            DiscordTokenUnreadable => "DISCORD_TOKEN_UNREADABLE",
+            DiscordUnavailable => "DISCORD_UNAVAILABLE",
+            DiscordTokenInvalid(_) => "DISCORD_TOKEN_INVALID",
+            DiscordBadResponse => "DISCORD_BAD_RESPONSE",
+            DiscordPathInvalid => "DISCORD_PATH_INVALID",
+            DiscordPathNotAllowed => "DISCORD_PATH_NOT_ALLOWED",
+            DiscordMethodNotAllowed => "DISCORD_METHOD_NOT_ALLOWED",
+            DiscordSessionLost => "DISCORD_SESSION_LOST",
+            DiscordVoiceFailed(_) => "DISCORD_VOICE_FAILED",
+            DiscordTimeout => "DISCORD_TIMEOUT",
+            QrSessionNotFound => "QR_SESSION_NOT_FOUND",
+        }
+    }
+
+    /// The message in `lang`.
+    pub fn message(&self, lang: Lang) -> String {
+        use AppError::*;
+        let (en, fr): (Cow<str>, Cow<str>) = match self {
+            Unauthenticated => ("Not authenticated".into(), "Non authentifié".into()),
+            AdminOnly => ("Admin only".into(), "Réservé aux administrateurs".into()),
+            AccessDenied => ("Access denied".into(), "Accès refusé".into()),
+            NotFound => ("Not found".into(), "Introuvable".into()),
+            InvalidRequest(_) => ("Invalid request".into(), "Requête invalide".into()),
+            Internal(_) => ("Internal server error".into(), "Erreur interne du serveur".into()),
+            InvalidCredentials => (
+                "Invalid username or password".into(),
+                "Nom d'utilisateur ou mot de passe incorrect".into(),
+            ),
+            UsernameRequired => ("Username is required".into(), "Le nom d'utilisateur est requis".into()),
+            PasswordTooShort => (
+                format!("Password must be at least {} characters", MIN_PASSWORD_LEN).into(),
+                format!("Le mot de passe doit contenir au moins {} caractères", MIN_PASSWORD_LEN).into(),
+            ),
+            UsernameTaken => ("Username already taken".into(), "Ce nom d'utilisateur est déjà pris".into()),
+            RegistrationClosed => (
+                "Registration is closed on this server".into(),
+                "Les inscriptions sont fermées sur ce serveur".into(),
+            ),
+            InviteRequired => ("A valid invite code is required".into(), "Un code d'invitation valide est requis".into()),
+            Banned(ban) => {
+                let (mut en, mut fr) = ("This account is banned".to_string(), "Ce compte est banni".to_string());
+                if let Some(until) = &ban.expires_at {
+                    en.push_str(&format!(" until {} UTC", until));
+                    fr.push_str(&format!(" jusqu'au {} UTC", until));
+                }
+                if !ban.reason.is_empty() {
+                    en.push_str(&format!(": {}", ban.reason));
+                    fr.push_str(&format!(" : {}", ban.reason));
+                }
+                (en.into(), fr.into())
+            }
+            ForeignProfileImage => (
+                "Profile images are set through the avatar and banner uploads".into(),
+                "Les images de profil se changent via l'envoi d'un avatar ou d'une bannière".into(),
+            ),
+            UserNotFound => ("User not found".into(), "Utilisateur introuvable".into()),
+            RoleNameLength => (
+                "Role name must be 2 to 24 chars".into(),
+                "Le nom du rôle doit faire de 2 à 24 caractères".into(),
+            ),
+            RoleNameCharacters => (
+                "Role name can only contain a-z, 0-9, _ and -".into(),
+                "Le nom du rôle ne peut contenir que a-z, 0-9, _ et -".into(),
+            ),
+            InvalidRoleColor => (
+                "Invalid role color (expected #RRGGBB)".into(),
+                "Couleur de rôle invalide (format attendu : #RRGGBB)".into(),
+            ),
+            NegativeStorageQuota => (
+                "Storage quota cannot be negative".into(),
+                "Le quota de stockage ne peut pas être négatif".into(),
+            ),
+            RoleExists => ("Role already exists".into(), "Ce rôle existe déjà".into()),
+            RoleNotFound => ("Role not found".into(), "Rôle introuvable".into()),
+            ProtectedRole => ("This role is protected".into(), "Ce rôle est protégé".into()),
+            UnknownRole => ("Invalid role".into(), "Rôle invalide".into()),
+            RoomNameRequired => ("Room name is required".into(), "Le nom du salon est requis".into()),
+            InvalidRoomKind => (
+                "Room kind must be text or voice".into(),
+                "Le type de salon doit être text ou voice".into(),
+            ),
+            InvalidUserLimit { max } => (
+                format!("User limit must be between 0 and {}", max).into(),
+                format!("La limite d'utilisateurs doit être comprise entre 0 et {}", max).into(),
+            ),
+            RestrictedRoomAdminOnly => (
+                "Only admins can create restricted rooms".into(),
+                "Seuls les administrateurs peuvent créer des salons restreints".into(),
+            ),
+            RoomNameTaken => ("Room name already exists".into(), "Un salon porte déjà ce nom".into()),
+            RoomNotFound => ("Room not found".into(), "Salon introuvable".into()),
+            RoomAccessDenied => ("Access denied for this room".into(), "Accès refusé à ce salon".into()),
+            MessageNotFound => ("Message not found".into(), "Message introuvable".into()),
+            NotMessageAuthor => (
+                "You can only delete your own messages".into(),
+                "Vous ne pouvez supprimer que vos propres messages".into(),
+            ),
+            InvalidEmoji => ("Invalid emoji".into(), "Emoji invalide".into()),
+            NothingToUpdate => ("Nothing to update".into(), "Rien à modifier".into()),
+            NotVoiceRoom => ("This room is not a voice room".into(), "Ce salon n'est pas un salon vocal".into()),
+            NotInVoice => ("User is not in a voice room".into(), "L'utilisateur n'est pas dans un salon vocal".into()),
+            MoveMembersRequired => (
+                "Move members permission required".into(),
+                "La permission de déplacer des membres est requise".into(),
+            ),
+            NoFileProvided => ("No file provided".into(), "Aucun fichier fourni".into()),
+            FileTooLarge { max_bytes, mime_type: None } => (
+                format!("File too large (max {})", format_size(*max_bytes)).into(),
+                format!("Fichier trop volumineux (max {})", format_size(*max_bytes)).into(),
+            ),
+            FileTooLarge { max_bytes, mime_type: Some(mime_type) } => (
+                format!("File too large for {} (max {})", mime_type, format_size(*max_bytes)).into(),
+                format!("Fichier {} trop volumineux (max {})", mime_type, format_size(*max_bytes)).into(),
+            ),
+            FileTypeNotAllowed { mime_type } => (
+                format!("File type not allowed ({})", mime_type).into(),
+                format!("Type de fichier non autorisé ({})", mime_type).into(),
+            ),
+            ImageUploadsDisabled => ("Image uploads are disabled".into(), "L'envoi d'images est désactivé".into()),
+            UnsupportedImageFormat => (
+                "Only image files are allowed (png, jpeg, gif, webp, bmp)".into(),
+                "Seules les images sont acceptées (png, jpeg, gif, webp, bmp)".into(),
+            ),
+            InvalidImage => ("Invalid or corrupted image".into(), "Image invalide ou corrompue".into()),
+            ImageTooLarge { .. } => (
+                "Image dimensions are too large".into(),
+                "Les dimensions de l'image sont trop grandes".into(),
+            ),
+            AnimationTooLarge { .. } => ("Animated image is too large".into(), "L'image animée est trop grande".into()),
+            ImageProcessingFailed => ("Failed to process image".into(), "Échec du traitement de l'image".into()),
+            InvalidCrop => (
+                "Crop needs x, y, width and height (width and height above 0)".into(),
+                "Le recadrage demande x, y, width et height (width et height supérieurs à 0)".into(),
+            ),
+            CropOutsideImage => (
+                "Crop rectangle is outside the image".into(),
+                "La zone de recadrage dépasse de l'image".into(),
+            ),
+            UploadFailed => ("Failed to save file".into(), "Échec de l'enregistrement du fichier".into()),
+            StorageQuotaExceeded { quota } => (
+                format!("Storage quota exceeded ({})", format_size(*quota as usize)).into(),
+                format!("Quota de stockage dépassé ({})", format_size(*quota as usize)).into(),
+            ),
+            ServerStorageFull => ("Server storage is full".into(), "Le stockage du serveur est plein".into()),
+            DiscordLoginDisabled => (
+                "Discord login is disabled on this server".into(),
+                "Connexion Discord désactivée sur ce serveur".into(),
+            ),
+            DiscordTokenRequired => ("discord_token is required".into(), "discord_token manquant".into()),
+            DiscordNotLinked => ("No Discord token linked".into(), "Aucun token Discord lié".into()),
+            DiscordTokenUnreadable => (
+                "Failed to decrypt the Discord token".into(),
+                "Échec du déchiffrement du token Discord".into(),
+            ),
+            DiscordUnavailable => ("Discord API unavailable".into(), "Discord API indisponible".into()),
+            DiscordTokenInvalid(_) => ("Invalid or expired Discord token".into(), "Token Discord invalide ou expiré".into()),
+            DiscordBadResponse => ("Invalid response from Discord".into(), "Réponse Discord invalide".into()),
+            DiscordPathInvalid => ("Invalid Discord path".into(), "Chemin Discord invalide".into()),
+            DiscordPathNotAllowed => ("Path not allowed".into(), "Chemin non autorisé".into()),
+            DiscordMethodNotAllowed => ("Method not allowed".into(), "Méthode non autorisée".into()),
+            DiscordSessionLost => ("Discord Gateway session lost".into(), "Session Discord Gateway perdue".into()),
+            DiscordVoiceFailed(_) => ("Discord voice request failed".into(), "Échec de la requête vocale Discord".into()),
+            DiscordTimeout => (
+                "Timeout waiting for Discord voice server info".into(),
+                "Délai dépassé en attendant le serveur vocal Discord".into(),
+            ),
+            QrSessionNotFound => ("QR login session not found".into(), "Session QR introuvable".into()),
+        };
+        match lang {
+            Lang::En => en.into_owned(),
+            Lang::Fr => fr.into_owned(),
+        }
+    }
+
+    /// Values behind the error, for clients that format their own message.
+    pub fn details(&self) -> Option<serde_json::Value> {
+        use AppError::*;
+        match self {
+            InvalidRequest(reason) => Some(json!({ "reason": reason })),
+            PasswordTooShort => Some(json!({ "min_length": MIN_PASSWORD_LEN })),
+            Banned(ban) => Some(json!({ "expires_at": ban.expires_at, "reason": ban.reason })),
+            RoleNameLength => Some(json!({ "min_length": 2, "max_length": 24 })),
+            InvalidUserLimit { max } => Some(json!({ "min": 0, "max": max })),
+            FileTooLarge { max_bytes, mime_type } => Some(json!({ "max_bytes": max_bytes, "mime_type": mime_type })),
+            FileTypeNotAllowed { mime_type } => Some(json!({ "mime_type": mime_type })),
+            ImageTooLarge { max_pixels } | AnimationTooLarge { max_pixels } => Some(json!({ "max_pixels": max_pixels })),
+            StorageQuotaExceeded { quota } => Some(json!({ "quota_bytes": quota })),
+            DiscordTokenInvalid(response) => Some(json!({ "discord_response": response })),
+            DiscordVoiceFailed(reason) => Some(json!({ "reason": reason })),
+            _ => None,
+        }
+    }
+
+    /// The error response, with the message in `lang`.
+    pub fn render(&self, lang: Lang) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            code: self.code(),
+            message: self.message(lang),
+            details: self.details(),
+        })
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message(Lang::En))
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        use AppError::*;
+        match self {
+            Unauthenticated | InvalidCredentials | DiscordTokenInvalid(_) => StatusCode::UNAUTHORIZED,
+            AdminOnly | AccessDenied | RegistrationClosed | InviteRequired | Banned(_) | RestrictedRoomAdminOnly
+            | RoomAccessDenied | NotMessageAuthor | MoveMembersRequired | DiscordPathNotAllowed => StatusCode::FORBIDDEN,
+            NotFound | UserNotFound | RoleNotFound | RoomNotFound | MessageNotFound | NotInVoice | QrSessionNotFound => {
+                StatusCode::NOT_FOUND
+            }
+            UsernameTaken | RoleExists | RoomNameTaken => StatusCode::CONFLICT,
+            StorageQuotaExceeded { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            ServerStorageFull => StatusCode::INSUFFICIENT_STORAGE,
+            Internal(_) | ImageProcessingFailed | UploadFailed | DiscordTokenUnreadable | DiscordSessionLost => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            DiscordLoginDisabled => StatusCode::SERVICE_UNAVAILABLE,
+            DiscordUnavailable | DiscordBadResponse | DiscordVoiceFailed(_) => StatusCode::BAD_GATEWAY,
+            DiscordTimeout => StatusCode::GATEWAY_TIMEOUT,
+            InvalidRequest(_) | UsernameRequired | PasswordTooShort | ForeignProfileImage | RoleNameLength
+            | RoleNameCharacters | InvalidRoleColor | NegativeStorageQuota | ProtectedRole | UnknownRole
+            | RoomNameRequired | InvalidRoomKind | InvalidUserLimit { .. } | InvalidEmoji | NothingToUpdate
+            | NotVoiceRoom | NoFileProvided
+            | FileTooLarge { .. } | FileTypeNotAllowed { .. } | ImageUploadsDisabled | UnsupportedImageFormat
+            | InvalidImage | ImageTooLarge { .. } | AnimationTooLarge { .. } | InvalidCrop | CropOutsideImage
+            | DiscordTokenRequired | DiscordNotLinked | DiscordPathInvalid | DiscordMethodNotAllowed => {
+                StatusCode::BAD_REQUEST
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if let AppError::Internal(cause) = self {
+            tracing::error!(error = %cause, "Request failed");
+        }
+        self.render(Lang::En)
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        AppError::Internal(e.to_string())
+    }
+}
+
+/// Middleware re-rendering `AppError` responses in the client's
+/// `Accept-Language`. Must run inside CORS, which adds its headers after.
+pub async fn localize(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let lang = req
+        .headers()
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|h| h.to_str().ok())
+        .map_or(Lang::En, Lang::from_accept_language);
+
+    let res = next.call(req).await?;
+    if lang == Lang::En {
+        return Ok(res.map_into_boxed_body());
+    }
+    let localized = res
+        .response()
+        .error()
+        .and_then(|e| e.as_error::<AppError>())
+        .map(|e| e.render(lang));
+    Ok(match localized {
+        Some(localized) => res.into_response(localized),
+        None => res.map_into_boxed_body(),
+    })
+}
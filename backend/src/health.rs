@@ -0,0 +1,247 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — Health checks
+// ═══════════════════════════════════════════════════════
+//
+// `GET /health/live` answers as long as the process serves HTTP: restart
+// the server when it stops answering. `GET /health/ready` checks what
+// requests depend on — a query on the database, a write to upload storage,
+// a fully migrated schema — and answers 503 when any of them fails, so
+// orchestrators (and the desktop shell) only send traffic once it can be
+// served. Both report the version and build.
+//
+// The probes are unauthenticated, so readiness only says which check failed
+// and how long each took; the reasons go to the log. The storage check
+// writes a file (a PUT on S3), so its result is reused for
+// `UPLOADS_CHECK_TTL`.
+
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::config::Config;
+use crate::db::{Backend, DbPool};
+use crate::storage::SharedStorage;
+
+/// Longest a single check may take before it counts as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long a storage check result is reused by later probes.
+const UPLOADS_CHECK_TTL: Duration = Duration::from_secs(5);
+
+/// Last storage check: when it ran, its result and its latency.
+pub type UploadsCheckCache = Arc<Mutex<Option<(Instant, Result<(), String>, u64)>>>;
+
+pub fn create_uploads_check_cache() -> UploadsCheckCache {
+    Arc::new(Mutex::new(None))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BuildInfo {
+    #[schema(value_type = String, example = "0.1.0")]
+    pub version: &'static str,
+    /// `release` or `debug`
+    #[schema(value_type = String)]
+    pub profile: &'static str,
+}
+
+impl BuildInfo {
+    fn current() -> Self {
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            profile: if cfg!(debug_assertions) { "debug" } else { "release" },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Liveness {
+    /// Always `ok`
+    #[schema(value_type = String)]
+    pub status: &'static str,
+    pub build: BuildInfo,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Check {
+    pub ok: bool,
+    pub latency_ms: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SchemaCheck {
+    /// The database has every migration this build knows, and no newer one
+    pub ok: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReadinessChecks {
+    pub database: Check,
+    pub uploads: Check,
+    pub schema: SchemaCheck,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Readiness {
+    /// `ready` or `not_ready`
+    #[schema(value_type = String)]
+    pub status: &'static str,
+    pub build: BuildInfo,
+    pub checks: ReadinessChecks,
+}
+
+/// Run a check under `CHECK_TIMEOUT`.
+async fn timed<T>(check: impl Future<Output = Result<T, String>>) -> (Result<T, String>, u64) {
+    let started = Instant::now();
+    let result = tokio::time::timeout(CHECK_TIMEOUT, check)
+        .await
+        .unwrap_or_else(|_| Err(format!("no answer within {}s", CHECK_TIMEOUT.as_secs())));
+    (result, started.elapsed().as_millis() as u64)
+}
+
+fn check<T>(name: &str, result: &Result<T, String>, latency_ms: u64) -> Check {
+    if let Err(error) = result {
+        tracing::warn!(check = name, error = %error, "Readiness check failed");
+    }
+    Check { ok: result.is_ok(), latency_ms }
+}
+
+/// Storage check, run again once the cached result is `UPLOADS_CHECK_TTL`
+/// old. Concurrent probes wait for the same run.
+async fn check_uploads(storage: &SharedStorage, cache: &UploadsCheckCache) -> (Result<(), String>, u64) {
+    let mut last = cache.lock().await;
+    if let Some((checked_at, result, latency_ms)) = last.as_ref() {
+        if checked_at.elapsed() < UPLOADS_CHECK_TTL {
+            return (result.clone(), *latency_ms);
+        }
+    }
+    let (result, latency_ms) = timed(async { storage.check_writable().await.map_err(|e| e.to_string()) }).await;
+    *last = Some((Instant::now(), result.clone(), latency_ms));
+    (result, latency_ms)
+}
+
+// ── HTTP Handlers ───────────────────────────────────────
+
+/// GET /health/live — Liveness probe
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "system",
+    responses((status = 200, description = "The server is running", body = Liveness))
+)]
+pub async fn live() -> HttpResponse {
+    HttpResponse::Ok().json(Liveness { status: "ok", build: BuildInfo::current() })
+}
+
+/// GET /api/health — Former liveness probe, kept for existing monitors
+#[utoipa::path(
+    get,
+    path = "/api/health",
+    tag = "system",
+    responses((status = 200, description = "The server is running", body = Liveness))
+)]
+#[deprecated(note = "use GET /health/live")]
+pub async fn legacy_live() -> HttpResponse {
+    live().await
+}
+
+/// GET /health/ready — Readiness probe, with the state of each dependency
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "system",
+    responses(
+        (status = 200, description = "Every check passed", body = Readiness),
+        (status = 503, description = "At least one check failed; see `checks`", body = Readiness),
+    )
+)]
+pub async fn ready(
+    pool: web::Data<DbPool>,
+    storage: web::Data<SharedStorage>,
+    uploads_check: web::Data<UploadsCheckCache>,
+) -> HttpResponse {
+    // Reading the schema version is the database check
+    let ((version, database_ms), (uploads, uploads_ms)) = tokio::join!(
+        timed(async {
+            sqlx::query_scalar::<_, Option<i64>>("SELECT MAX(version) FROM schema_migrations")
+                .fetch_one(pool.get_ref())
+                .await
+                .map_err(|e| e.to_string())
+        }),
+        check_uploads(storage.get_ref(), uploads_check.get_ref()),
+    );
+
+    let latest = Backend::of(pool.get_ref()).migrations().last().map(|m| m.version).unwrap_or(0);
+    let version = version.map(|v| v.unwrap_or(0));
+    if let Some(version) = version.as_ref().ok().filter(|v| **v != latest) {
+        tracing::warn!(version, latest, "Readiness check failed: schema is not at this build's migration");
+    }
+    let checks = ReadinessChecks {
+        database: check("database", &version, database_ms),
+        uploads: check("uploads", &uploads, uploads_ms),
+        schema: SchemaCheck { ok: version.is_ok_and(|v| v == latest) },
+    };
+    let is_ready = checks.database.ok && checks.uploads.ok && checks.schema.ok;
+
+    let readiness = Readiness {
+        status: if is_ready { "ready" } else { "not_ready" },
+        build: BuildInfo::current(),
+        checks,
+    };
+    if is_ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+// ── Waiting for readiness ───────────────────────────────
+
+/// Block until the local server answers `/health/ready` with 200, or
+/// `timeout` passes. For the desktop shell, which runs the server on a
+/// background thread of the same process. With built-in TLS the probe goes
+/// over TLS, trusting only the configured certificate.
+pub fn wait_until_ready(timeout: Duration) -> bool {
+    let Ok(config) = Config::load() else {
+        return false;
+    };
+    let host = match config.server.bind_address.as_str() {
+        "0.0.0.0" => "127.0.0.1",
+        "::" => "::1",
+        other => other,
+    };
+    let cert_file = config.server.tls.files().map(|(cert_file, _)| cert_file);
+
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if probe_ready((host, config.server.port), cert_file).unwrap_or(false) {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    false
+}
+
+fn probe_ready(addr: (&str, u16), cert_file: Option<&Path>) -> io::Result<bool> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(CHECK_TIMEOUT * 2))?;
+    let Some(cert_file) = cert_file else {
+        return read_ready_status(&mut stream);
+    };
+    let tls_config = crate::tls::pinned_client_config(cert_file).map_err(io::Error::other)?;
+    let server_name = rustls::pki_types::ServerName::try_from(addr.0.to_string()).map_err(io::Error::other)?;
+    let connection = rustls::ClientConnection::new(Arc::new(tls_config), server_name).map_err(io::Error::other)?;
+    read_ready_status(&mut rustls::StreamOwned::new(connection, stream))
+}
+
+fn read_ready_status<S: Read + Write>(stream: &mut S) -> io::Result<bool> {
+    stream.write_all(b"GET /health/ready HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")?;
+    let mut status_line = [0u8; 12];
+    stream.read_exact(&mut status_line)?;
+    Ok(&status_line == b"HTTP/1.1 200")
+}
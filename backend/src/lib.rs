@@ -1,16 +1,171 @@
 pub mod auth;
+pub mod backup;
+pub mod cli;
+pub mod config;
+pub mod crypto;
 pub mod db;
 pub mod discord_gateway;
+pub mod errors;
+pub mod health;
+pub mod logging;
 pub mod messages;
+pub mod metrics;
+pub mod moderation;
+pub mod openapi;
+pub mod presence;
+pub mod profile_images;
+pub mod quotas;
 pub mod remote_auth;
 pub mod rooms;
+pub mod storage;
+pub mod tls;
+pub mod typing;
+pub mod upload_gc;
 pub mod uploads;
+pub mod voice;
 pub mod ws;
-pub mod crypto;
 
 use actix_cors::Cors;
-use actix_files::Files;
-use actix_web::{web, App, HttpResponse, HttpServer};
+use actix_web::{middleware, web, App, HttpRequest, HttpServer};
+use std::sync::Arc;
+use tracing_actix_web::TracingLogger;
+
+use config::Config;
+
+/// Shared state handed to every handler through `web::Data`.
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: db::DbPool,
+    pub config: Arc<Config>,
+    pub broadcaster: ws::Broadcaster,
+    pub online_users: ws::OnlineUsers,
+    pub access_cache: ws::AccessCache,
+    pub qr_sessions: remote_auth::QrAuthSessions,
+    pub discord_gateways: discord_gateway::DiscordGateways,
+    pub voice_states: voice::VoiceStates,
+    pub typing_tracker: typing::TypingTracker,
+    pub upload_storage: storage::SharedStorage,
+    pub metrics: metrics::SharedMetrics,
+    pub banned_users: moderation::BannedUsers,
+    pub current_access: moderation::CurrentAccess,
+    pub uploads_check: health::UploadsCheckCache,
+}
+
+impl AppState {
+    pub fn new(pool: db::DbPool, config: Config) -> Self {
+        AppState {
+            pool,
+            broadcaster: ws::create_broadcaster(config.server.broadcast_capacity),
+            online_users: ws::create_online_users(),
+            access_cache: ws::create_access_cache(),
+            qr_sessions: remote_auth::create_qr_sessions(),
+            discord_gateways: discord_gateway::create_discord_gateways(),
+            voice_states: voice::create_voice_states(),
+            typing_tracker: typing::create_typing_tracker(),
+            upload_storage: storage::create_storage(&config.storage),
+            metrics: metrics::create_metrics(),
+            banned_users: moderation::create_banned_users(),
+            current_access: moderation::create_current_access(),
+            uploads_check: health::create_uploads_check_cache(),
+            config: Arc::new(config),
+        }
+    }
+}
+
+/// Register the shared state and every route (without CORS or rate
+/// limiting, which `start_server` wraps around them).
+pub fn configure(cfg: &mut web::ServiceConfig, state: &AppState) {
+    cfg.app_data(web::Data::new(state.pool.clone()))
+        .app_data(web::Data::from(state.config.clone()))
+        .app_data(web::Data::new(state.broadcaster.clone()))
+        .app_data(web::Data::new(state.online_users.clone()))
+        .app_data(web::Data::new(state.access_cache.clone()))
+        .app_data(web::Data::new(state.qr_sessions.clone()))
+        .app_data(web::Data::new(state.discord_gateways.clone()))
+        .app_data(web::Data::new(state.voice_states.clone()))
+        .app_data(web::Data::new(state.typing_tracker.clone()))
+        .app_data(web::Data::new(state.upload_storage.clone()))
+        .app_data(web::Data::new(state.metrics.clone()))
+        .app_data(web::Data::new(state.banned_users.clone()))
+        .app_data(web::Data::new(state.current_access.clone()))
+        .app_data(web::Data::new(state.uploads_check.clone()))
+        // Malformed bodies and query strings get the usual error shape
+        .app_data(web::JsonConfig::default().error_handler(|e, _| errors::AppError::InvalidRequest(e.to_string()).into()))
+        .app_data(web::QueryConfig::default().error_handler(|e, _| errors::AppError::InvalidRequest(e.to_string()).into()))
+        .route("/health/live", web::get().to(health::live))
+        .route("/health/ready", web::get().to(health::ready))
+        .route("/api/openapi.json", web::get().to(openapi::get_spec))
+        // Auth
+        .route("/api/register", web::post().to(auth::register))
+        .route("/api/login", web::post().to(auth::login))
+        .route("/api/auth/discord/token", web::post().to(auth::login_discord_token))
+        .route("/api/auth/discord/qr/start", web::post().to(remote_auth::start_qr_session))
+        .route("/api/auth/discord/qr/status", web::get().to(remote_auth::get_qr_status))
+        .route("/api/auth/discord/qr/cancel", web::post().to(remote_auth::cancel_qr_session))
+        .route("/api/users/me", web::get().to(auth::get_me))
+        .route("/api/users/me", web::patch().to(auth::update_profile))
+        .route("/api/users/me/storage", web::get().to(quotas::get_my_storage))
+        .route("/api/users/me/avatar", web::post().to(profile_images::upload_avatar))
+        .route("/api/users/me/banner", web::post().to(profile_images::upload_banner))
+        .route("/api/discord/me", web::get().to(auth::get_discord_me))
+        .route("/api/discord/proxy", web::post().to(auth::discord_proxy))
+        .route("/api/discord/voice/join", web::post().to(discord_gateway::voice_join))
+        .route("/api/discord/voice/leave", web::post().to(discord_gateway::voice_leave))
+        .route(
+            "/api/discord/voice/participants",
+            web::get().to(discord_gateway::voice_participants),
+        )
+        .route("/api/presence", web::get().to(presence::get_presence))
+        .route("/api/users/{id}", web::delete().to(auth::delete_user))
+        .route("/api/users/{id}/role", web::patch().to(auth::update_user_role))
+        .route("/api/server/roles", web::get().to(auth::list_server_roles))
+        .route("/api/server/roles", web::post().to(auth::create_server_role))
+        .route("/api/server/roles/{name}", web::patch().to(auth::update_server_role))
+        .route("/api/server/roles/{name}", web::delete().to(auth::delete_server_role))
+        .route("/api/server/users", web::get().to(auth::list_server_users))
+        .route("/api/server/storage", web::get().to(upload_gc::get_storage_report))
+        .route("/api/server/storage/users", web::get().to(quotas::list_storage_consumers))
+        // Rooms
+        .route("/api/rooms", web::get().to(rooms::list_rooms))
+        .route("/api/rooms", web::post().to(rooms::create_room))
+        .route("/api/rooms/{id}", web::patch().to(rooms::update_room))
+        .route("/api/rooms/{id}", web::delete().to(rooms::delete_room))
+        // Messages
+        .route("/api/messages/{id}", web::delete().to(messages::delete_message))
+        .route("/api/messages/{id}/reactions", web::post().to(messages::add_reaction))
+        .route("/api/messages/{id}/reactions", web::delete().to(messages::remove_reaction))
+        .route("/api/messages/search", web::get().to(messages::search_messages))
+        .route("/api/messages/{id}/pin", web::post().to(messages::pin_message))
+        .route("/api/messages/{id}/pin", web::delete().to(messages::unpin_message))
+        .route("/api/users/{id}/messages", web::delete().to(messages::delete_user_messages))
+        .route("/api/rooms/{room_id}/messages", web::get().to(messages::get_messages))
+        .route("/api/rooms/{room_id}/pins", web::get().to(messages::get_pinned_messages))
+        // Voice
+        .route("/api/voice/ice-servers", web::get().to(voice::get_ice_servers))
+        .route("/api/voice/members/{user_id}", web::patch().to(voice::moderate_voice_member))
+        .route("/api/voice/members/{user_id}", web::delete().to(voice::disconnect_voice_member))
+        .route("/api/voice/members/{user_id}/move", web::post().to(voice::move_voice_member))
+        // Uploads
+        .route("/api/upload", web::post().to(uploads::upload_image))
+        // Serve uploaded files (access-checked, no directory listing)
+        .route("/uploads/{name}", web::get().to(uploads::serve_upload))
+        // WebSocket
+        .route("/ws", web::get().to(ws::ws_handler));
+
+    // Former liveness path, kept for one release
+    #[allow(deprecated)]
+    cfg.route("/api/health", web::get().to(health::legacy_live));
+
+    if state.config.server.api_docs {
+        cfg.route("/api/docs", web::get().to(openapi::get_docs));
+    }
+
+    // Prometheus metrics, unless they have their own listener
+    let metrics_config = &state.config.metrics;
+    if metrics_config.enabled && metrics_config.bind_address.is_none() {
+        metrics::configure_endpoint(cfg, state);
+    }
+}
 
 /// Run the backend HTTP server. This function blocks until the server shuts down.
 /// It creates its own Actix/Tokio runtime via `#[actix_web::main]`.
@@ -22,100 +177,104 @@ pub fn run_server() {
 }
 
 async fn start_server() -> std::io::Result<()> {
-    dotenvy::dotenv().ok();
-
-    let port = std::env::var("PORT").unwrap_or_else(|_| "8080".to_string());
-    let bind_addr = format!("0.0.0.0:{}", port);
-
-    let pool = db::init_db().await;
-    let broadcaster = ws::create_broadcaster();
-    let online_users = ws::create_online_users();
-    let access_cache = ws::create_access_cache();
-    let qr_sessions = remote_auth::create_qr_sessions();
-    let discord_gateways = discord_gateway::create_discord_gateways();
-
-    // Ensure uploads directory exists
-    std::fs::create_dir_all("uploads").ok();
-
-    println!("🚀 Backend running at http://{}", bind_addr);
-
-    HttpServer::new(move || {
-        // CORS: Restrict to Tauri and local dev
-        let cors = Cors::default()
-            .allowed_origin("tauri://localhost")
-            .allowed_origin("http://localhost:1420")
-            .allowed_origin("http://127.0.0.1:1420")
-            .allowed_origin("http://localhost:1430")
-            .allowed_origin("http://127.0.0.1:1430")
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+    logging::init(&config.logging);
+    let bind_addr = format!("{}:{}", config.server.bind_address, config.server.port);
+
+    let state = AppState::new(db::init_db(&config.database).await, config);
+    upload_gc::spawn_upload_sweeper(state.pool.clone(), state.upload_storage.clone(), &state.config.uploads);
+    moderation::spawn_ban_watcher(state.pool.clone(), state.banned_users.clone(), state.broadcaster.clone());
+    moderation::spawn_access_watcher(
+        state.pool.clone(),
+        state.current_access.clone(),
+        state.access_cache.clone(),
+        state.broadcaster.clone(),
+    );
+    backup::spawn_backup_scheduler(state.pool.clone(), state.upload_storage.clone(), &state.config.backup);
+
+    let tls_reloader = match state.config.server.tls.files() {
+        Some((cert_file, key_file)) => {
+            let reloader = Arc::new(tls::CertReloader::new(cert_file, key_file).map_err(std::io::Error::other)?);
+            tls::spawn_reload_on_sighup(reloader.clone());
+            Some(reloader)
+        }
+        None => None,
+    };
+    let redirect_addr = state.config.server.tls.redirect_port.map(|port| (state.config.server.bind_address.clone(), port));
+    let https_port = state.config.server.port;
+    let metrics_server = match &state.config.metrics {
+        metrics_config if !metrics_config.enabled => None,
+        metrics_config => metrics_config.bind_address.clone().map(|addr| (addr, state.clone())),
+    };
+
+    let server = HttpServer::new(move || {
+        let server = &state.config.server;
+
+        // CORS: Restrict to the configured origins (Tauri and local dev by default)
+        let cors = server
+            .cors_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
             .allow_any_method()
             .allow_any_header()
             .max_age(3600);
 
-        // Rate Limiting: 10 req/s with burst of 20
+        // Rate Limiting: per client IP
         let governor_conf = actix_governor::GovernorConfigBuilder::default()
-            .per_second(10)
-            .burst_size(20)
+            .per_second(server.rate_limit_per_second)
+            .burst_size(server.rate_limit_burst)
             .finish()
             .unwrap();
 
         App::new()
+            .wrap(middleware::from_fn(errors::localize))
             .wrap(cors)
             .wrap(actix_governor::Governor::new(&governor_conf))
-            .app_data(web::Data::new(pool.clone()))
-            .app_data(web::Data::new(broadcaster.clone()))
-            .app_data(web::Data::new(online_users.clone()))
-            .app_data(web::Data::new(access_cache.clone()))
-            .app_data(web::Data::new(qr_sessions.clone()))
-            .app_data(web::Data::new(discord_gateways.clone()))
-            .route("/api/health", web::get().to(|| async {
-                HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+            .wrap(middleware::from_fn(metrics::track_requests))
+            .wrap(middleware::from_fn(logging::request_id_header))
+            .wrap(TracingLogger::default())
+            .configure(|cfg| configure(cfg, &state))
+    });
+
+    let mut servers = vec![match tls_reloader {
+        Some(reloader) => {
+            tracing::info!("Backend running at https://{}", bind_addr);
+            server.bind_rustls_0_23(&bind_addr, tls::server_config(reloader))?.run()
+        }
+        None => {
+            tracing::info!("Backend running at http://{}", bind_addr);
+            server.bind(&bind_addr)?.run()
+        }
+    }];
+
+    // Plain HTTP listener sending everything to HTTPS
+    if let Some((bind_address, redirect_port)) = redirect_addr {
+        let redirect = HttpServer::new(move || {
+            App::new().default_service(web::to(move |req: HttpRequest| async move {
+                tls::redirect_to_https(&req, https_port)
             }))
-            // Auth
-            .route("/api/register", web::post().to(auth::register))
-            .route("/api/login", web::post().to(auth::login))
-            .route("/api/auth/discord/token", web::post().to(auth::login_discord_token))
-            .route("/api/auth/discord/qr/start", web::post().to(remote_auth::start_qr_session))
-            .route("/api/auth/discord/qr/status", web::get().to(remote_auth::get_qr_status))
-            .route("/api/auth/discord/qr/cancel", web::post().to(remote_auth::cancel_qr_session))
-            .route("/api/users/me", web::get().to(auth::get_me))
-            .route("/api/users/me", web::patch().to(auth::update_profile))
-            .route("/api/discord/me", web::get().to(auth::get_discord_me))
-            .route("/api/discord/proxy", web::post().to(auth::discord_proxy))
-            .route("/api/discord/voice/join", web::post().to(discord_gateway::voice_join))
-            .route("/api/discord/voice/leave", web::post().to(discord_gateway::voice_leave))
-            .route(
-                "/api/discord/voice/participants",
-                web::get().to(discord_gateway::voice_participants),
-            )
-            .route("/api/users/{id}", web::delete().to(auth::delete_user))
-            .route("/api/users/{id}/role", web::patch().to(auth::update_user_role))
-            .route("/api/server/roles", web::get().to(auth::list_server_roles))
-            .route("/api/server/roles", web::post().to(auth::create_server_role))
-            .route("/api/server/roles/{name}", web::delete().to(auth::delete_server_role))
-            .route("/api/server/users", web::get().to(auth::list_server_users))
-            // Rooms
-            .route("/api/rooms", web::get().to(rooms::list_rooms))
-            .route("/api/rooms", web::post().to(rooms::create_room))
-            .route("/api/rooms/{id}", web::patch().to(rooms::update_room))
-            .route("/api/rooms/{id}", web::delete().to(rooms::delete_room))
-            // Messages
-            .route("/api/messages/{id}", web::delete().to(messages::delete_message))
-            .route("/api/messages/{id}/reactions", web::post().to(messages::add_reaction))
-            .route("/api/messages/{id}/reactions", web::delete().to(messages::remove_reaction))
-            .route("/api/messages/search", web::get().to(messages::search_messages))
-            .route("/api/messages/{id}/pin", web::post().to(messages::pin_message))
-            .route("/api/messages/{id}/pin", web::delete().to(messages::unpin_message))
-            .route("/api/users/{id}/messages", web::delete().to(messages::delete_user_messages))
-            .route("/api/rooms/{room_id}/messages", web::get().to(messages::get_messages))
-            .route("/api/rooms/{room_id}/pins", web::get().to(messages::get_pinned_messages))
-            // Uploads
-            .route("/api/upload", web::post().to(uploads::upload_image))
-            // Serve uploaded files - DISABLE directory listing if enabled by default, but actix-files doesn't by default
-            .service(Files::new("/uploads", "uploads"))
-            // WebSocket
-            .route("/ws", web::get().to(ws::ws_handler))
-    })
-    .bind(&bind_addr)?
-    .run()
-    .await
+        })
+        .bind((bind_address.as_str(), redirect_port))?
+        .run();
+        tracing::info!("Redirecting http://{}:{} to HTTPS", bind_address, redirect_port);
+        servers.push(redirect);
+    }
+
+    // Metrics on their own (usually private) address
+    if let Some((metrics_addr, state)) = metrics_server {
+        let metrics = HttpServer::new(move || App::new().configure(|cfg| metrics::configure_endpoint(cfg, &state)))
+            .workers(1)
+            .bind(&metrics_addr)?
+            .run();
+        tracing::info!("Metrics at http://{}/metrics", metrics_addr);
+        servers.push(metrics);
+    }
+
+    futures_util::future::try_join_all(servers).await.map(|_| ())
 }
@@ -0,0 +1,101 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — Logging
+// ═══════════════════════════════════════════════════════
+//
+// Everything logs through `tracing`. `logging.format` picks human-readable
+// lines (`pretty`) or one JSON object per line (`json`), and
+// `logging.level` is a `RUST_LOG`-style filter such as
+// `info,backend::ws=debug,sqlx=warn`.
+//
+// Each HTTP request runs in a span carrying its request id, which is also
+// returned as `X-Request-Id`; WebSocket sessions and the background tasks
+// a request starts keep that id, so a log line can be traced back to the
+// request behind it.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::HttpMessage;
+use serde::Deserialize;
+use std::fmt;
+use std::panic::Location;
+use std::str::FromStr;
+use tracing_actix_web::RequestId;
+use tracing_subscriber::EnvFilter;
+
+use crate::config::LoggingConfig;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("unknown log format '{}' (expected pretty or json)", other)),
+        }
+    }
+}
+
+/// Check a `logging.level` filter.
+pub fn parse_filter(level: &str) -> Result<EnvFilter, String> {
+    EnvFilter::try_new(level).map_err(|e| e.to_string())
+}
+
+/// Install the global subscriber. Later calls (tests) are no-ops.
+pub fn init(config: &LoggingConfig) {
+    let filter = parse_filter(&config.level).unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let _ = match config.format {
+        LogFormat::Pretty => builder.try_init(),
+        LogFormat::Json => builder.json().flatten_event(true).try_init(),
+    };
+}
+
+/// Middleware returning the request id as `X-Request-Id`. Runs inside
+/// `TracingLogger`, which assigns the id.
+pub async fn request_id_header(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let request_id = req.extensions().get::<RequestId>().copied();
+    let mut res = next.call(req).await?;
+    if let Some(value) = request_id.and_then(|id| HeaderValue::from_str(&id.to_string()).ok()) {
+        res.headers_mut().insert(HeaderName::from_static("x-request-id"), value);
+    }
+    Ok(res)
+}
+
+/// The id of the request being handled, when there is one.
+pub fn request_id(req: &actix_web::HttpRequest) -> Option<String> {
+    req.extensions().get::<RequestId>().map(|id| id.to_string())
+}
+
+/// For results the caller can do without (best-effort cleanup, optional
+/// lookups): log the error instead of dropping it silently.
+pub trait LogErr<T> {
+    /// The value, or `None` after logging "`context` failed".
+    fn log_err(self, context: &str) -> Option<T>;
+}
+
+impl<T, E: fmt::Display> LogErr<T> for Result<T, E> {
+    #[track_caller]
+    fn log_err(self, context: &str) -> Option<T> {
+        match self {
+            Ok(value) => Some(value),
+            Err(e) => {
+                tracing::warn!(error = %e, at = %Location::caller(), "{} failed", context);
+                None
+            }
+        }
+    }
+}
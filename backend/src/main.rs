@@ -1,3 +1,17 @@
 fn main() {
-    backend::run_server();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        [] => backend::run_server(),
+        ["migrate"] | ["migrate", "up"] => std::process::exit(backend::cli::migrate_up()),
+        ["migrate", "status"] => std::process::exit(backend::cli::migrate_status()),
+        ["backup"] => std::process::exit(backend::cli::backup(None)),
+        ["backup", dir] => std::process::exit(backend::cli::backup(Some(dir))),
+        ["restore", archive] => std::process::exit(backend::cli::restore(archive)),
+        _ => {
+            eprintln!("Usage: backend [migrate [up|status] | backup [DIR] | restore ARCHIVE]");
+            std::process::exit(2);
+        }
+    }
 }
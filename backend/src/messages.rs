@@ -1,19 +1,23 @@
 use actix_web::{web, HttpRequest, HttpResponse};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use std::collections::HashMap;
-use sqlx::sqlite::SqliteRow;
-use sqlx::SqlitePool;
+use crate::db::{DbPool, DbRow};
 use sqlx::Row;
 use crate::auth::extract_claims;
+use crate::config::Config;
+use crate::errors::{AppError, ErrorBody};
+use crate::logging::LogErr;
+use crate::uploads::{attachment_from_row, Attachment};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct MessageReaction {
     pub emoji: String,
     pub count: i64,
     pub user_ids: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct Message {
     pub id: String,
     pub room_id: String,
@@ -23,14 +27,18 @@ pub struct Message {
     pub reply_to_id: Option<String>,
     pub created_at: String,
     pub image_url: Option<String>,
+    pub thumbnail_url: Option<String>,
     pub pinned_at: Option<String>,
     pub pinned_by: Option<String>,
     pub avatar_url: Option<String>,
     #[serde(default)]
     pub reactions: Vec<MessageReaction>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
 }
 
-fn message_from_row(row: &SqliteRow) -> Message {
+fn message_from_row(config: &Config, row: &DbRow) -> Message {
+    let image_url: Option<String> = row.try_get("image_url").unwrap_or(None);
     Message {
         id: row.try_get("id").unwrap_or_default(),
         room_id: row.try_get("room_id").unwrap_or_default(),
@@ -39,25 +47,34 @@ fn message_from_row(row: &SqliteRow) -> Message {
         content: row.try_get("content").unwrap_or_default(),
         reply_to_id: row.try_get("reply_to_id").unwrap_or(None),
         created_at: row.try_get("created_at").unwrap_or_default(),
-        image_url: row.try_get("image_url").unwrap_or(None),
+        // Filled in by `enrich_messages_with_attachments`
+        thumbnail_url: None,
+        image_url: image_url.map(|u| crate::uploads::sign_upload_url(config, &u)),
         pinned_at: row.try_get("pinned_at").unwrap_or(None),
         pinned_by: row.try_get("pinned_by").unwrap_or(None),
         avatar_url: row.try_get("avatar_url").unwrap_or(None),
         reactions: Vec::new(),
+        attachments: Vec::new(),
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct SearchQuery {
+    /// Text contained in the message (case-insensitive)
     pub q: Option<String>,
+    /// Part of the author's username
     pub author: Option<String>,
     pub room_id: Option<String>,
+    /// First day included, `YYYY-MM-DD`
     pub from: Option<String>,
+    /// Last day included, `YYYY-MM-DD`
     pub to: Option<String>,
+    /// 1 to 200, 80 by default
     pub limit: Option<i64>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct ReactionInput {
     pub emoji: String,
 }
@@ -77,7 +94,8 @@ fn normalize_emoji(raw: &str) -> Option<String> {
     Some(trimmed.to_string())
 }
 
-async fn enrich_messages_with_reactions(pool: &SqlitePool, messages: &mut [Message]) {
+#[tracing::instrument(level = "debug", skip_all, fields(count = messages.len()))]
+async fn enrich_messages_with_reactions(pool: &DbPool, messages: &mut [Message]) {
     if messages.is_empty() {
         return;
     }
@@ -87,7 +105,7 @@ async fn enrich_messages_with_reactions(pool: &SqlitePool, messages: &mut [Messa
         if idx > 0 {
             query.push(',');
         }
-        query.push('?');
+        query.push_str(&format!("${}", idx + 1));
     }
     query.push(')');
 
@@ -96,7 +114,7 @@ async fn enrich_messages_with_reactions(pool: &SqlitePool, messages: &mut [Messa
         qx = qx.bind(&message.id);
     }
 
-    let rows = qx.fetch_all(pool).await.unwrap_or_default();
+    let rows = qx.fetch_all(pool).await.log_err("Reading message_reactions").unwrap_or_default();
     let mut per_message: HashMap<String, HashMap<String, Vec<String>>> = HashMap::new();
     for row in rows {
         let message_id: String = row.try_get("message_id").unwrap_or_default();
@@ -131,17 +149,98 @@ async fn enrich_messages_with_reactions(pool: &SqlitePool, messages: &mut [Messa
     }
 }
 
-async fn can_access_message_room(pool: &SqlitePool, message_id: &str, role: &str) -> Option<String> {
+#[tracing::instrument(level = "debug", skip_all, fields(count = messages.len()))]
+async fn enrich_messages_with_attachments(pool: &DbPool, config: &Config, messages: &mut [Message]) {
+    if messages.is_empty() {
+        return;
+    }
+
+    let mut query = String::from("SELECT * FROM message_attachments WHERE message_id IN (");
+    for idx in 0..messages.len() {
+        if idx > 0 {
+            query.push(',');
+        }
+        query.push_str(&format!("${}", idx + 1));
+    }
+    query.push_str(") ORDER BY created_at ASC");
+
+    let mut qx = sqlx::query(&query);
+    for message in messages.iter() {
+        qx = qx.bind(&message.id);
+    }
+
+    let rows = qx.fetch_all(pool).await.log_err("Reading message_attachments").unwrap_or_default();
+    // The upload behind `image_url` is claimed by its message too; it is not an extra file
+    let image_urls: HashMap<&str, &str> = messages
+        .iter()
+        .filter_map(|m| Some((m.id.as_str(), crate::uploads::strip_query(m.image_url.as_deref()?))))
+        .collect();
+    let mut per_message: HashMap<String, Vec<Attachment>> = HashMap::new();
+    for row in rows {
+        let message_id: String = row.try_get("message_id").unwrap_or_default();
+        let url: String = row.try_get("url").unwrap_or_default();
+        if image_urls.get(message_id.as_str()) == Some(&url.as_str()) {
+            continue;
+        }
+        per_message.entry(message_id).or_default().push(attachment_from_row(config, &row));
+    }
+
+    for message in messages.iter_mut() {
+        message.attachments = per_message.remove(&message.id).unwrap_or_default();
+    }
+
+    // Thumbnails for `image_url` exist when the image went through the upload
+    // pipeline (see `uploads::has_variants`)
+    let image_urls: Vec<String> = messages
+        .iter()
+        .filter_map(|m| m.image_url.as_deref())
+        .map(|u| crate::uploads::strip_query(u).to_string())
+        .filter(|u| crate::uploads::upload_key(u).is_some())
+        .collect();
+    if image_urls.is_empty() {
+        return;
+    }
+
+    let placeholders = (1..=image_urls.len()).map(|idx| format!("${}", idx)).collect::<Vec<_>>().join(",");
+    let query = format!(
+        "SELECT url FROM message_attachments WHERE width IS NOT NULL AND url IN ({placeholders}) \
+         UNION SELECT '/uploads/' || key FROM upload_blobs WHERE mime_type LIKE 'image/%' \
+           AND '/uploads/' || key IN ({placeholders})"
+    );
+
+    let mut qx = sqlx::query_scalar::<_, String>(&query);
+    for url in &image_urls {
+        qx = qx.bind(url);
+    }
+    let processed: std::collections::HashSet<String> = qx
+        .fetch_all(pool)
+        .await
+        .log_err("Reading message_attachments")
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+
+    for message in messages.iter_mut() {
+        let raw = message.image_url.as_deref().map(crate::uploads::strip_query);
+        if raw.is_some_and(|u| processed.contains(u)) {
+            message.thumbnail_url = crate::uploads::thumbnail_url(raw).map(|u| crate::uploads::sign_upload_url(config, &u));
+        }
+    }
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(message_id))]
+async fn can_access_message_room(pool: &DbPool, message_id: &str, role: &str) -> Option<String> {
     let row = sqlx::query(
         "SELECT m.room_id AS room_id, r.required_role AS required_role \
          FROM messages m \
          LEFT JOIN rooms r ON m.room_id = r.id \
-         WHERE m.id = ?"
+         WHERE m.id = $1"
     )
     .bind(message_id)
     .fetch_optional(pool)
     .await
-    .unwrap_or(None)?;
+    .log_err("Reading messages")
+    .flatten()?;
 
     let room_id: String = row.try_get("room_id").unwrap_or_default();
     let required_role: String = row.try_get("required_role").unwrap_or_else(|_| "user".to_string());
@@ -157,104 +256,144 @@ async fn can_access_message_room(pool: &SqlitePool, message_id: &str, role: &str
 }
 
 /// GET /api/rooms/{room_id}/messages — Fetch message history
+#[utoipa::path(
+    get,
+    path = "/api/rooms/{room_id}/messages",
+    tag = "messages",
+    security(("bearer" = [])),
+    params(("room_id" = String, Path, description = "Room id")),
+    responses(
+        (status = 200, description = "The room's first 200 messages, oldest first", body = [Message]),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Room restricted to another role", body = ErrorBody),
+        (status = 404, description = "No such room", body = ErrorBody),
+    )
+)]
 pub async fn get_messages(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
     path: web::Path<String>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().json(serde_json::json!({ "error": "Not authenticated" })),
-    };
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     let room_id = path.into_inner();
 
-    let room_role: Option<String> = sqlx::query_scalar("SELECT required_role FROM rooms WHERE id = ?")
+    let room_role: Option<String> = sqlx::query_scalar("SELECT required_role FROM rooms WHERE id = $1")
         .bind(&room_id)
         .fetch_optional(pool.get_ref())
         .await
-        .unwrap_or(None);
+        .log_err("Reading rooms")
+        .flatten();
 
     let Some(required_role) = room_role else {
-        return HttpResponse::NotFound().json(serde_json::json!({ "error": "Room not found" }));
+        return Err(AppError::RoomNotFound);
     };
 
     if required_role != "user" && claims.role != "admin" && claims.role != required_role {
-        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Access denied for this room" }));
+        return Err(AppError::RoomAccessDenied);
     }
 
     let rows = sqlx::query(
         "SELECT m.id, m.room_id, m.user_id, m.username, m.content, m.reply_to_id, m.created_at, m.image_url, m.pinned_at, m.pinned_by, u.avatar_url \
          FROM messages m LEFT JOIN users u ON m.user_id = u.id \
-         WHERE m.room_id = ? ORDER BY m.created_at ASC LIMIT 200"
+         WHERE m.room_id = $1 ORDER BY m.created_at ASC LIMIT 200"
     )
     .bind(&room_id)
     .fetch_all(pool.get_ref())
     .await
+    .log_err("Reading messages")
     .unwrap_or_default();
 
-    let mut messages: Vec<Message> = rows.iter().map(message_from_row).collect();
+    let mut messages: Vec<Message> = rows.iter().map(|row| message_from_row(&config, row)).collect();
 
     enrich_messages_with_reactions(pool.get_ref(), &mut messages).await;
+    enrich_messages_with_attachments(pool.get_ref(), &config, &mut messages).await;
 
-    HttpResponse::Ok().json(messages)
+    Ok(HttpResponse::Ok().json(messages))
 }
 
-/// DELETE /api/messages/{id}
+/// DELETE /api/messages/{id} — Delete a message (author or admin)
+#[utoipa::path(
+    delete,
+    path = "/api/messages/{id}",
+    tag = "messages",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "Message id")),
+    responses(
+        (status = 200, description = "Message deleted", body = Object, example = json!({ "status": "deleted" })),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Neither the author nor an admin", body = ErrorBody),
+        (status = 404, description = "No such message", body = ErrorBody),
+    )
+)]
 pub async fn delete_message(
     req: actix_web::HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
     path: web::Path<String>,
     broadcaster: web::Data<crate::ws::Broadcaster>,
-) -> HttpResponse {
+    storage: web::Data<crate::storage::SharedStorage>,
+) -> Result<HttpResponse, AppError> {
     use crate::auth::extract_claims;
 
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     let message_id = path.into_inner();
 
     // 1. Fetch message to check ownership and get room_id
     let msg_row = sqlx::query(
         "SELECT m.id, m.room_id, m.user_id, m.username, m.content, m.reply_to_id, m.created_at, m.image_url, m.pinned_at, m.pinned_by, u.avatar_url \
-         FROM messages m LEFT JOIN users u ON m.user_id = u.id WHERE m.id = ?"
+         FROM messages m LEFT JOIN users u ON m.user_id = u.id WHERE m.id = $1"
     )
         .bind(&message_id)
         .fetch_optional(pool.get_ref())
         .await
-        .unwrap_or(None);
+        .log_err("Reading messages")
+        .flatten();
 
     let msg = match msg_row {
-        Some(row) => message_from_row(&row),
-        None => return HttpResponse::NotFound().json(serde_json::json!({ "error": "Message not found" })),
+        Some(row) => message_from_row(&config, &row),
+        None => return Err(AppError::MessageNotFound),
     };
 
     // 2. Check permissions
     if msg.user_id != claims.sub && claims.role != "admin" {
-        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "You can only delete your own messages" }));
+        return Err(AppError::NotMessageAuthor);
     }
 
-    // 3. Delete uploaded image if any
+    // 3. Release uploaded files (shared blobs go away with their last reference)
     if let Some(ref url) = msg.image_url {
-        // SECURITY: Prevent path traversal
-        let clean_path = url.trim_start_matches('/');
-        if clean_path.starts_with("uploads/") && !clean_path.contains("..") {
-             std::fs::remove_file(clean_path).ok();
-        }
+        crate::uploads::release_or_remove_upload(pool.get_ref(), storage.get_ref().as_ref(), url).await;
+    }
+    let attachment_urls: Vec<String> = sqlx::query_scalar("SELECT url FROM message_attachments WHERE message_id = $1")
+        .bind(&message_id)
+        .fetch_all(pool.get_ref())
+        .await
+        .log_err("Reading message_attachments")
+        .unwrap_or_default();
+    for url in &attachment_urls {
+        crate::uploads::release_or_remove_upload(pool.get_ref(), storage.get_ref().as_ref(), url).await;
     }
 
-    // 4. Delete related reactions + message from DB
-    let _ = sqlx::query("DELETE FROM message_reactions WHERE message_id = ?")
+    // 4. Delete related reactions, attachments + message from DB
+    sqlx::query("DELETE FROM message_reactions WHERE message_id = $1")
         .bind(&message_id)
         .execute(pool.get_ref())
-        .await;
+        .await
+        .log_err("Deleting from message_reactions");
 
-    let _ = sqlx::query("DELETE FROM messages WHERE id = ?")
+    sqlx::query("DELETE FROM message_attachments WHERE message_id = $1")
         .bind(&message_id)
         .execute(pool.get_ref())
-        .await;
+        .await
+        .log_err("Deleting from message_attachments");
+
+    sqlx::query("DELETE FROM messages WHERE id = $1")
+        .bind(&message_id)
+        .execute(pool.get_ref())
+        .await
+        .log_err("Deleting from messages");
 
     // 5. Broadcast
     let event = serde_json::json!({
@@ -264,92 +403,122 @@ pub async fn delete_message(
     });
     let _ = broadcaster.send(event.to_string());
 
-    HttpResponse::Ok().json(serde_json::json!({ "status": "deleted" }))
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "deleted" })))
 }
 
 /// GET /api/rooms/{room_id}/pins — List pinned messages
+#[utoipa::path(
+    get,
+    path = "/api/rooms/{room_id}/pins",
+    tag = "messages",
+    security(("bearer" = [])),
+    params(("room_id" = String, Path, description = "Room id")),
+    responses(
+        (status = 200, description = "Pinned messages, most recently pinned first", body = [Message]),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Room restricted to another role", body = ErrorBody),
+        (status = 404, description = "No such room", body = ErrorBody),
+    )
+)]
 pub async fn get_pinned_messages(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
     path: web::Path<String>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().json(serde_json::json!({ "error": "Not authenticated" })),
-    };
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     let room_id = path.into_inner();
 
-    let room_role: Option<String> = sqlx::query_scalar("SELECT required_role FROM rooms WHERE id = ?")
+    let room_role: Option<String> = sqlx::query_scalar("SELECT required_role FROM rooms WHERE id = $1")
         .bind(&room_id)
         .fetch_optional(pool.get_ref())
         .await
-        .unwrap_or(None);
+        .log_err("Reading rooms")
+        .flatten();
 
     let Some(required_role) = room_role else {
-        return HttpResponse::NotFound().json(serde_json::json!({ "error": "Room not found" }));
+        return Err(AppError::RoomNotFound);
     };
     if required_role != "user" && claims.role != "admin" && claims.role != required_role {
-        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Access denied for this room" }));
+        return Err(AppError::RoomAccessDenied);
     }
 
     let rows = sqlx::query(
         "SELECT m.id, m.room_id, m.user_id, m.username, m.content, m.reply_to_id, m.created_at, m.image_url, m.pinned_at, m.pinned_by, u.avatar_url \
          FROM messages m LEFT JOIN users u ON m.user_id = u.id \
-         WHERE m.room_id = ? AND m.pinned_at IS NOT NULL ORDER BY m.pinned_at DESC LIMIT 50"
+         WHERE m.room_id = $1 AND m.pinned_at IS NOT NULL ORDER BY m.pinned_at DESC LIMIT 50"
     )
     .bind(&room_id)
     .fetch_all(pool.get_ref())
     .await
+    .log_err("Reading messages")
     .unwrap_or_default();
 
-    let mut messages: Vec<Message> = rows.iter().map(message_from_row).collect();
+    let mut messages: Vec<Message> = rows.iter().map(|row| message_from_row(&config, row)).collect();
 
     enrich_messages_with_reactions(pool.get_ref(), &mut messages).await;
+    enrich_messages_with_attachments(pool.get_ref(), &config, &mut messages).await;
 
-    HttpResponse::Ok().json(messages)
+    Ok(HttpResponse::Ok().json(messages))
 }
 
-/// POST /api/messages/{id}/reactions
+/// POST /api/messages/{id}/reactions — React to a message
+#[utoipa::path(
+    post,
+    path = "/api/messages/{id}/reactions",
+    tag = "messages",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "Message id")),
+    request_body = ReactionInput,
+    responses(
+        (status = 200, description = "The `message_reaction_updated` event also sent over the WebSocket", body = Object, example = json!({
+            "type": "message_reaction_updated", "room_id": "9b1e…", "message_id": "5c2d…",
+            "emoji": "👍", "count": 2, "user_ids": ["3f0c…", "a81b…"]
+        })),
+        (status = 400, description = "Invalid emoji", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "No access to the message's room", body = ErrorBody),
+    )
+)]
 pub async fn add_reaction(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
     path: web::Path<String>,
     body: web::Json<ReactionInput>,
     broadcaster: web::Data<crate::ws::Broadcaster>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     let message_id = path.into_inner();
     let Some(emoji) = normalize_emoji(&body.emoji) else {
-        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Invalid emoji" }));
+        return Err(AppError::InvalidEmoji);
     };
 
     let Some(room_id) = can_access_message_room(pool.get_ref(), &message_id, &claims.role).await else {
-        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Access denied" }));
+        return Err(AppError::AccessDenied);
     };
 
     let now = chrono::Utc::now().to_rfc3339();
-    let _ = sqlx::query(
-        "INSERT OR IGNORE INTO message_reactions (message_id, user_id, emoji, created_at) VALUES (?, ?, ?, ?)"
+    sqlx::query(
+        "INSERT INTO message_reactions (message_id, user_id, emoji, created_at) VALUES ($1, $2, $3, $4) ON CONFLICT DO NOTHING"
     )
     .bind(&message_id)
     .bind(&claims.sub)
     .bind(&emoji)
     .bind(&now)
     .execute(pool.get_ref())
-    .await;
+    .await
+    .log_err("Inserting into message_reactions");
 
     let reaction_users = sqlx::query_scalar::<_, String>(
-        "SELECT user_id FROM message_reactions WHERE message_id = ? AND emoji = ? ORDER BY created_at ASC"
+        "SELECT user_id FROM message_reactions WHERE message_id = $1 AND emoji = $2 ORDER BY created_at ASC"
     )
     .bind(&message_id)
     .bind(&emoji)
     .fetch_all(pool.get_ref())
     .await
+    .log_err("Reading message_reactions")
     .unwrap_or_default();
 
     let event = serde_json::json!({
@@ -362,45 +531,58 @@ pub async fn add_reaction(
     });
     let _ = broadcaster.send(event.to_string());
 
-    HttpResponse::Ok().json(event)
+    Ok(HttpResponse::Ok().json(event))
 }
 
-/// DELETE /api/messages/{id}/reactions
+/// DELETE /api/messages/{id}/reactions — Remove your reaction
+#[utoipa::path(
+    delete,
+    path = "/api/messages/{id}/reactions",
+    tag = "messages",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "Message id")),
+    request_body = ReactionInput,
+    responses(
+        (status = 200, description = "The `message_reaction_updated` event also sent over the WebSocket", body = Object),
+        (status = 400, description = "Invalid emoji", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "No access to the message's room", body = ErrorBody),
+    )
+)]
 pub async fn remove_reaction(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
     path: web::Path<String>,
     body: web::Json<ReactionInput>,
     broadcaster: web::Data<crate::ws::Broadcaster>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     let message_id = path.into_inner();
     let Some(emoji) = normalize_emoji(&body.emoji) else {
-        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Invalid emoji" }));
+        return Err(AppError::InvalidEmoji);
     };
 
     let Some(room_id) = can_access_message_room(pool.get_ref(), &message_id, &claims.role).await else {
-        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Access denied" }));
+        return Err(AppError::AccessDenied);
     };
 
-    let _ = sqlx::query("DELETE FROM message_reactions WHERE message_id = ? AND user_id = ? AND emoji = ?")
+    sqlx::query("DELETE FROM message_reactions WHERE message_id = $1 AND user_id = $2 AND emoji = $3")
         .bind(&message_id)
         .bind(&claims.sub)
         .bind(&emoji)
         .execute(pool.get_ref())
-        .await;
+        .await
+        .log_err("Deleting from message_reactions");
 
     let reaction_users = sqlx::query_scalar::<_, String>(
-        "SELECT user_id FROM message_reactions WHERE message_id = ? AND emoji = ? ORDER BY created_at ASC"
+        "SELECT user_id FROM message_reactions WHERE message_id = $1 AND emoji = $2 ORDER BY created_at ASC"
     )
     .bind(&message_id)
     .bind(&emoji)
     .fetch_all(pool.get_ref())
     .await
+    .log_err("Reading message_reactions")
     .unwrap_or_default();
 
     let event = serde_json::json!({
@@ -413,172 +595,228 @@ pub async fn remove_reaction(
     });
     let _ = broadcaster.send(event.to_string());
 
-    HttpResponse::Ok().json(event)
+    Ok(HttpResponse::Ok().json(event))
 }
 
 /// POST /api/messages/{id}/pin — Pin message (admin only)
+#[utoipa::path(
+    post,
+    path = "/api/messages/{id}/pin",
+    tag = "messages",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "Message id")),
+    responses(
+        (status = 200, description = "Message pinned", body = Object, example = json!({ "status": "pinned" })),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Admin only", body = ErrorBody),
+        (status = 404, description = "No such message", body = ErrorBody),
+    )
+)]
 pub async fn pin_message(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
     path: web::Path<String>,
     broadcaster: web::Data<crate::ws::Broadcaster>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     if claims.role != "admin" {
-        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Admin only" }));
+        return Err(AppError::AdminOnly);
     }
 
     let message_id = path.into_inner();
 
-    let msg_room: Option<String> = sqlx::query_scalar("SELECT room_id FROM messages WHERE id = ?")
+    let msg_room: Option<String> = sqlx::query_scalar("SELECT room_id FROM messages WHERE id = $1")
         .bind(&message_id)
         .fetch_optional(pool.get_ref())
         .await
-        .unwrap_or(None);
+        .log_err("Reading messages")
+        .flatten();
 
     let Some(room_id) = msg_room else {
-        return HttpResponse::NotFound().json(serde_json::json!({ "error": "Message not found" }));
+        return Err(AppError::MessageNotFound);
     };
 
     let now = chrono::Utc::now().to_rfc3339();
-    let result = sqlx::query("UPDATE messages SET pinned_at = ?, pinned_by = ? WHERE id = ?")
+    sqlx::query("UPDATE messages SET pinned_at = $1, pinned_by = $2 WHERE id = $3")
         .bind(&now)
         .bind(&claims.sub)
         .bind(&message_id)
         .execute(pool.get_ref())
-        .await;
-
-    match result {
-        Ok(_) => {
-            let event = serde_json::json!({
-                "type": "message_pinned",
-                "id": message_id,
-                "room_id": room_id,
-                "pinned_at": now,
-                "pinned_by": claims.sub,
-            });
-            let _ = broadcaster.send(event.to_string());
-            HttpResponse::Ok().json(serde_json::json!({ "status": "pinned" }))
-        }
-        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Failed to pin message" })),
-    }
+        .await?;
+
+    let event = serde_json::json!({
+        "type": "message_pinned",
+        "id": message_id,
+        "room_id": room_id,
+        "pinned_at": now,
+        "pinned_by": claims.sub,
+    });
+    let _ = broadcaster.send(event.to_string());
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "pinned" })))
 }
 
 /// DELETE /api/messages/{id}/pin — Unpin message (admin only)
+#[utoipa::path(
+    delete,
+    path = "/api/messages/{id}/pin",
+    tag = "messages",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "Message id")),
+    responses(
+        (status = 200, description = "Message unpinned", body = Object, example = json!({ "status": "unpinned" })),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Admin only", body = ErrorBody),
+        (status = 404, description = "No such message", body = ErrorBody),
+    )
+)]
 pub async fn unpin_message(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
     path: web::Path<String>,
     broadcaster: web::Data<crate::ws::Broadcaster>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     if claims.role != "admin" {
-        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Admin only" }));
+        return Err(AppError::AdminOnly);
     }
 
     let message_id = path.into_inner();
 
-    let msg_room: Option<String> = sqlx::query_scalar("SELECT room_id FROM messages WHERE id = ?")
+    let msg_room: Option<String> = sqlx::query_scalar("SELECT room_id FROM messages WHERE id = $1")
         .bind(&message_id)
         .fetch_optional(pool.get_ref())
         .await
-        .unwrap_or(None);
+        .log_err("Reading messages")
+        .flatten();
 
     let Some(room_id) = msg_room else {
-        return HttpResponse::NotFound().json(serde_json::json!({ "error": "Message not found" }));
+        return Err(AppError::MessageNotFound);
     };
 
-    let result = sqlx::query("UPDATE messages SET pinned_at = NULL, pinned_by = NULL WHERE id = ?")
+    sqlx::query("UPDATE messages SET pinned_at = NULL, pinned_by = NULL WHERE id = $1")
         .bind(&message_id)
         .execute(pool.get_ref())
-        .await;
-
-    match result {
-        Ok(_) => {
-            let event = serde_json::json!({
-                "type": "message_unpinned",
-                "id": message_id,
-                "room_id": room_id,
-            });
-            let _ = broadcaster.send(event.to_string());
-            HttpResponse::Ok().json(serde_json::json!({ "status": "unpinned" }))
+        .await?;
+
+    let event = serde_json::json!({
+        "type": "message_unpinned",
+        "id": message_id,
+        "room_id": room_id,
+    });
+    let _ = broadcaster.send(event.to_string());
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "unpinned" })))
+}
+
+/// Delete the messages of a user, of a room, or of a user in one room.
+/// Returns how many were deleted.
+pub async fn purge_messages(
+    pool: &DbPool,
+    user_id: Option<&str>,
+    room_id: Option<&str>,
+) -> Result<u64, sqlx::Error> {
+    let result = match (user_id, room_id) {
+        (Some(user_id), Some(room_id)) => {
+            sqlx::query("DELETE FROM messages WHERE user_id = $1 AND room_id = $2")
+                .bind(user_id)
+                .bind(room_id)
+                .execute(pool)
+                .await?
         }
-        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Failed to unpin message" })),
-    }
+        (Some(user_id), None) => {
+            sqlx::query("DELETE FROM messages WHERE user_id = $1")
+                .bind(user_id)
+                .execute(pool)
+                .await?
+        }
+        (None, Some(room_id)) => {
+            sqlx::query("DELETE FROM messages WHERE room_id = $1")
+                .bind(room_id)
+                .execute(pool)
+                .await?
+        }
+        (None, None) => return Ok(0),
+    };
+    Ok(result.rows_affected())
 }
 
 /// DELETE /api/users/{id}/messages — Admin purge all messages from one user
+#[utoipa::path(
+    delete,
+    path = "/api/users/{id}/messages",
+    tag = "messages",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "User id")),
+    responses(
+        (status = 200, description = "Messages deleted", body = Object, example = json!({ "status": "purged", "count": 42 })),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Admin only", body = ErrorBody),
+    )
+)]
 pub async fn delete_user_messages(
     req: actix_web::HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
     path: web::Path<String>,
     broadcaster: web::Data<crate::ws::Broadcaster>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     if claims.role != "admin" {
-        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Admin only" }));
+        return Err(AppError::AdminOnly);
     }
 
     let target_user_id = path.into_inner();
 
-    let result = sqlx::query("DELETE FROM messages WHERE user_id = ?")
-        .bind(&target_user_id)
-        .execute(pool.get_ref())
-        .await;
-
-    match result {
-        Ok(res) => {
-            let event = serde_json::json!({
-                "type": "messages_purged",
-                "user_id": target_user_id,
-                "count": res.rows_affected()
-            });
-            let _ = broadcaster.send(event.to_string());
-
-            HttpResponse::Ok().json(serde_json::json!({
-                "status": "purged",
-                "count": res.rows_affected()
-            }))
-        }
-        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Failed to purge messages" })),
-    }
+    let count = purge_messages(pool.get_ref(), Some(&target_user_id), None).await?;
+    let event = serde_json::json!({
+        "type": "messages_purged",
+        "user_id": target_user_id,
+        "count": count
+    });
+    let _ = broadcaster.send(event.to_string());
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "purged",
+        "count": count
+    })))
 }
 
 /// GET /api/messages/search — Advanced message search
+#[utoipa::path(
+    get,
+    path = "/api/messages/search",
+    tag = "messages",
+    security(("bearer" = [])),
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "Matching messages in rooms the user can access, newest first", body = [Message]),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "`room_id` is restricted to another role", body = ErrorBody),
+        (status = 404, description = "`room_id` does not exist", body = ErrorBody),
+    )
+)]
 pub async fn search_messages(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
     query: web::Query<SearchQuery>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().json(serde_json::json!({ "error": "Not authenticated" })),
-    };
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     if let Some(room_id) = &query.room_id {
-        let room_role: Option<String> = sqlx::query_scalar("SELECT required_role FROM rooms WHERE id = ?")
+        let room_role: Option<String> = sqlx::query_scalar("SELECT required_role FROM rooms WHERE id = $1")
             .bind(room_id)
             .fetch_optional(pool.get_ref())
             .await
-            .unwrap_or(None);
+            .log_err("Reading rooms")
+            .flatten();
 
         let Some(required_role) = room_role else {
-            return HttpResponse::NotFound().json(serde_json::json!({ "error": "Room not found" }));
+            return Err(AppError::RoomNotFound);
         };
         if required_role != "user" && claims.role != "admin" && claims.role != required_role {
-            return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Access denied for this room" }));
+            return Err(AppError::RoomAccessDenied);
         }
     }
 
@@ -591,27 +829,33 @@ pub async fn search_messages(
          WHERE 1=1"
     );
 
+    let mut params = 0;
+    let mut next_param = || {
+        params += 1;
+        format!("${}", params)
+    };
+
     if claims.role != "admin" {
-        sql.push_str(" AND (r.required_role = 'user' OR r.required_role = ?)");
+        sql.push_str(&format!(" AND (r.required_role = 'user' OR r.required_role = {})", next_param()));
     }
 
     if query.room_id.is_some() {
-        sql.push_str(" AND m.room_id = ?");
+        sql.push_str(&format!(" AND m.room_id = {}", next_param()));
     }
     if query.q.as_ref().map(|v| !v.trim().is_empty()).unwrap_or(false) {
-        sql.push_str(" AND m.content LIKE ?");
+        sql.push_str(&format!(" AND LOWER(m.content) LIKE LOWER({})", next_param()));
     }
     if query.author.as_ref().map(|v| !v.trim().is_empty()).unwrap_or(false) {
-        sql.push_str(" AND m.username LIKE ?");
+        sql.push_str(&format!(" AND LOWER(m.username) LIKE LOWER({})", next_param()));
     }
     if query.from.as_ref().map(|v| !v.trim().is_empty()).unwrap_or(false) {
-        sql.push_str(" AND m.created_at >= ?");
+        sql.push_str(&format!(" AND m.created_at >= {}", next_param()));
     }
     if query.to.as_ref().map(|v| !v.trim().is_empty()).unwrap_or(false) {
-        sql.push_str(" AND m.created_at <= ?");
+        sql.push_str(&format!(" AND m.created_at <= {}", next_param()));
     }
 
-    sql.push_str(" ORDER BY m.created_at DESC LIMIT ?");
+    sql.push_str(&format!(" ORDER BY m.created_at DESC LIMIT {}", next_param()));
 
     let mut qx = sqlx::query(&sql);
     if claims.role != "admin" {
@@ -642,13 +886,14 @@ pub async fn search_messages(
     }
     qx = qx.bind(limit);
 
-    let rows = qx.fetch_all(pool.get_ref()).await.unwrap_or_default();
+    let rows = qx.fetch_all(pool.get_ref()).await?;
     let mut messages: Vec<Message> = Vec::with_capacity(rows.len());
     for row in rows {
-        messages.push(message_from_row(&row));
+        messages.push(message_from_row(&config, &row));
     }
 
     enrich_messages_with_reactions(pool.get_ref(), &mut messages).await;
+    enrich_messages_with_attachments(pool.get_ref(), &config, &mut messages).await;
 
-    HttpResponse::Ok().json(messages)
+    Ok(HttpResponse::Ok().json(messages))
 }
@@ -0,0 +1,200 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — Prometheus metrics
+// ═══════════════════════════════════════════════════════
+//
+// `GET /metrics` in the Prometheus text format. Counters are updated where
+// things happen (the request middleware, the WebSocket loops); gauges for
+// the database pool, Discord gateways and QR sessions are read from the
+// shared state at scrape time. The endpoint is served on the main port
+// unless `metrics.bind_address` gives it its own listener, and requires
+// `Authorization: Bearer <metrics.token>` when a token is set.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::AppState;
+
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    pub ws_connections: IntGauge,
+    /// Broadcast events skipped by sockets that fell too far behind
+    pub ws_broadcast_lagged: IntCounter,
+    pub ws_dropped_frames: IntCounterVec,
+    pub messages_persisted: IntCounter,
+    broadcast_subscribers: IntGauge,
+    db_pool_connections: IntGaugeVec,
+    db_pool_max_connections: IntGauge,
+    discord_gateway_sessions: IntGauge,
+    qr_auth_sessions: IntGauge,
+}
+
+pub type SharedMetrics = Arc<Metrics>;
+
+pub fn create_metrics() -> SharedMetrics {
+    let registry = Registry::new_custom(Some("voxium".to_string()), None).expect("valid metrics prefix");
+
+    let http_requests = IntCounterVec::new(
+        Opts::new("http_requests_total", "HTTP requests by route pattern and status"),
+        &["method", "route", "status"],
+    )
+    .unwrap();
+    let http_request_duration = HistogramVec::new(
+        HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by route pattern"),
+        &["method", "route"],
+    )
+    .unwrap();
+    let ws_connections = IntGauge::new("ws_connections", "Open WebSocket connections").unwrap();
+    let ws_broadcast_lagged = IntCounter::new(
+        "ws_broadcast_lagged_events_total",
+        "Broadcast events skipped by WebSocket connections that fell behind",
+    )
+    .unwrap();
+    let ws_dropped_frames = IntCounterVec::new(
+        Opts::new("ws_dropped_frames_total", "Incoming WebSocket frames dropped by the server"),
+        &["reason"],
+    )
+    .unwrap();
+    let messages_persisted = IntCounter::new("messages_persisted_total", "Chat messages written to the database").unwrap();
+    let broadcast_subscribers = IntGauge::new("broadcast_subscribers", "Receivers of the broadcast channel").unwrap();
+    let db_pool_connections = IntGaugeVec::new(
+        Opts::new("db_pool_connections", "Database pool connections by state"),
+        &["state"],
+    )
+    .unwrap();
+    let db_pool_max_connections = IntGauge::new("db_pool_max_connections", "Database pool size limit").unwrap();
+    let discord_gateway_sessions =
+        IntGauge::new("discord_gateway_sessions", "Live per-user Discord gateway connections").unwrap();
+    let qr_auth_sessions = IntGauge::new("qr_auth_sessions", "Discord QR login sessions in progress").unwrap();
+
+    registry.register(Box::new(http_requests.clone())).unwrap();
+    registry.register(Box::new(http_request_duration.clone())).unwrap();
+    registry.register(Box::new(ws_connections.clone())).unwrap();
+    registry.register(Box::new(ws_broadcast_lagged.clone())).unwrap();
+    registry.register(Box::new(ws_dropped_frames.clone())).unwrap();
+    registry.register(Box::new(messages_persisted.clone())).unwrap();
+    registry.register(Box::new(broadcast_subscribers.clone())).unwrap();
+    registry.register(Box::new(db_pool_connections.clone())).unwrap();
+    registry.register(Box::new(db_pool_max_connections.clone())).unwrap();
+    registry.register(Box::new(discord_gateway_sessions.clone())).unwrap();
+    registry.register(Box::new(qr_auth_sessions.clone())).unwrap();
+
+    Arc::new(Metrics {
+        registry,
+        http_requests,
+        http_request_duration,
+        ws_connections,
+        ws_broadcast_lagged,
+        ws_dropped_frames,
+        messages_persisted,
+        broadcast_subscribers,
+        db_pool_connections,
+        db_pool_max_connections,
+        discord_gateway_sessions,
+        qr_auth_sessions,
+    })
+}
+
+/// Middleware counting requests and their latency. Requests are labelled
+/// with the route pattern (`/api/rooms/{id}`), never the raw path, so ids
+/// do not create new series.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let metrics = req.app_data::<web::Data<SharedMetrics>>().cloned();
+    let method = req.method().to_string();
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+    let started = Instant::now();
+
+    let result = next.call(req).await;
+
+    if let Some(metrics) = metrics {
+        let status = match &result {
+            Ok(res) => res.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+        metrics
+            .http_requests
+            .with_label_values(&[method.as_str(), route.as_str(), status.as_str()])
+            .inc();
+        metrics
+            .http_request_duration
+            .with_label_values(&[method.as_str(), route.as_str()])
+            .observe(started.elapsed().as_secs_f64());
+    }
+    result
+}
+
+/// Register `GET /metrics`, with the state it reads from.
+pub fn configure_endpoint(cfg: &mut web::ServiceConfig, state: &AppState) {
+    cfg.service(
+        web::resource("/metrics")
+            .app_data(web::Data::new(state.clone()))
+            .route(web::get().to(get_metrics)),
+    );
+}
+
+fn token_matches(req: &HttpRequest, expected: &str) -> bool {
+    let Some(token) = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    // Compare digests so the time taken does not depend on the token
+    Sha256::digest(token.as_bytes()) == Sha256::digest(expected.as_bytes())
+}
+
+/// GET /metrics — Prometheus scrape endpoint
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "system",
+    security((), ("metrics_token" = [])),
+    responses(
+        (status = 200, description = "Prometheus text exposition format", content_type = "text/plain; version=0.0.4", body = String),
+        (status = 401, description = "`metrics.token` is set and was not sent"),
+    )
+)]
+async fn get_metrics(req: HttpRequest, state: web::Data<AppState>) -> HttpResponse {
+    if let Some(expected) = state.config.metrics.token.as_deref() {
+        if !token_matches(&req, expected) {
+            return HttpResponse::Unauthorized().json(serde_json::json!({ "error": "Invalid metrics token" }));
+        }
+    }
+
+    let metrics = &state.metrics;
+    let pool_size = state.pool.size() as i64;
+    let pool_idle = state.pool.num_idle() as i64;
+    metrics.db_pool_connections.with_label_values(&["idle"]).set(pool_idle);
+    metrics.db_pool_connections.with_label_values(&["in_use"]).set(pool_size - pool_idle);
+    metrics.db_pool_max_connections.set(state.pool.options().get_max_connections() as i64);
+    metrics.broadcast_subscribers.set(state.broadcaster.receiver_count() as i64);
+    metrics
+        .discord_gateway_sessions
+        .set(crate::discord_gateway::live_session_count(&state.discord_gateways).await as i64);
+    metrics
+        .qr_auth_sessions
+        .set(crate::remote_auth::active_session_count(&state.qr_sessions).await as i64);
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    if let Err(e) = encoder.encode(&metrics.registry.gather(), &mut body) {
+        tracing::warn!(error = %e, "Failed to encode metrics");
+        return HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Failed to encode metrics" }));
+    }
+    HttpResponse::Ok().content_type(encoder.format_type()).body(body)
+}
@@ -0,0 +1,356 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — Invite codes, bans and access changes
+// ═══════════════════════════════════════════════════════
+//
+// Both are managed with the `voxium-admin` CLI. `auth.registration_mode` decides
+// whether /api/register is open, needs an invite code or is closed (accounts
+// are then only created by an admin). A banned account cannot log in or
+// open a WebSocket. Since bans are written by another process, the server
+// polls them into `BannedUsers`: tokens issued earlier stop working and
+// open WebSockets are closed within `POLL_INTERVAL`.
+//
+// Role and room access changes made with the CLI are polled the same way
+// into `CurrentAccess`. When they change, the access cache is cleared and an
+// `access_changed` event makes every WebSocket recompute the rooms it may
+// receive; requests use the current role rather than the one in the token.
+//
+// Expiry times are stored like SQLite's datetime('now') (UTC, second
+// precision) so they compare as text on both engines.
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::db::{DbPool, DbRow};
+use crate::logging::LogErr;
+use crate::ws::{AccessCache, Broadcaster};
+
+const INVITE_CODE_LEN: usize = 10;
+/// No 0/O or 1/I/L, so codes survive being read out loud.
+const INVITE_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+/// How long a ban or access change made with the CLI takes to reach
+/// signed-in sessions
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Users with a ban in effect, as of the last poll.
+pub type BannedUsers = Arc<RwLock<HashSet<String>>>;
+
+pub fn create_banned_users() -> BannedUsers {
+    Arc::new(RwLock::new(HashSet::new()))
+}
+
+pub fn is_banned(banned: &BannedUsers, user_id: &str) -> bool {
+    banned.read().unwrap().contains(user_id)
+}
+
+/// Roles other than `user` and the role each room requires.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct AccessSnapshot {
+    pub user_roles: HashMap<String, String>,
+    pub room_roles: HashMap<String, String>,
+}
+
+/// Access as of the last poll, `None` until the first one.
+pub type CurrentAccess = Arc<RwLock<Option<AccessSnapshot>>>;
+
+pub fn create_current_access() -> CurrentAccess {
+    Arc::new(RwLock::new(None))
+}
+
+/// The role `user_id` holds now, `None` before the first poll.
+pub fn current_role(access: &CurrentAccess, user_id: &str) -> Option<String> {
+    let guard = access.read().unwrap();
+    let snapshot = guard.as_ref()?;
+    Some(snapshot.user_roles.get(user_id).cloned().unwrap_or_else(|| "user".to_string()))
+}
+
+/// Who may use /api/register: `open` (default), `invite` or `closed`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationMode {
+    #[default]
+    Open,
+    Invite,
+    Closed,
+}
+
+impl FromStr for RegistrationMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "open" => Ok(RegistrationMode::Open),
+            "invite" => Ok(RegistrationMode::Invite),
+            "closed" => Ok(RegistrationMode::Closed),
+            other => Err(format!("unknown mode '{}' (expected open, invite or closed)", other)),
+        }
+    }
+}
+
+pub fn db_timestamp(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Invite {
+    pub code: String,
+    /// 0 = unlimited
+    pub max_uses: i64,
+    pub uses: i64,
+    pub expires_at: Option<String>,
+    pub note: String,
+    pub created_at: String,
+}
+
+impl Invite {
+    fn from_row(row: &DbRow) -> Self {
+        Invite {
+            code: row.get("code"),
+            max_uses: row.get("max_uses"),
+            uses: row.get("uses"),
+            expires_at: row.get("expires_at"),
+            note: row.get("note"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Ban {
+    pub user_id: String,
+    pub username: String,
+    pub reason: String,
+    /// `None` = permanent
+    pub expires_at: Option<String>,
+    pub created_at: String,
+}
+
+impl Ban {
+    fn from_row(row: &DbRow) -> Self {
+        Ban {
+            user_id: row.get("user_id"),
+            username: row.get("username"),
+            reason: row.get("reason"),
+            expires_at: row.get("expires_at"),
+            created_at: row.get("created_at"),
+        }
+    }
+
+    /// Error shown to the banned user.
+    pub fn message(&self) -> String {
+        let mut message = "This account is banned".to_string();
+        if let Some(until) = &self.expires_at {
+            message.push_str(&format!(" until {} UTC", until));
+        }
+        if !self.reason.is_empty() {
+            message.push_str(&format!(": {}", self.reason));
+        }
+        message
+    }
+}
+
+// ── Invites ─────────────────────────────────────────────
+
+pub fn generate_invite_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..INVITE_CODE_LEN)
+        .map(|_| INVITE_CODE_ALPHABET[rng.gen_range(0..INVITE_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+pub async fn create_invite(
+    pool: &DbPool,
+    max_uses: i64,
+    expires_at: Option<DateTime<Utc>>,
+    note: &str,
+) -> Result<Invite, sqlx::Error> {
+    let code = generate_invite_code();
+    sqlx::query("INSERT INTO invites (code, max_uses, expires_at, note) VALUES ($1, $2, $3, $4)")
+        .bind(&code)
+        .bind(max_uses)
+        .bind(expires_at.map(db_timestamp))
+        .bind(note)
+        .execute(pool)
+        .await?;
+
+    let row = sqlx::query("SELECT code, max_uses, uses, expires_at, note, created_at FROM invites WHERE code = $1")
+        .bind(&code)
+        .fetch_one(pool)
+        .await?;
+    Ok(Invite::from_row(&row))
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn list_invites(pool: &DbPool) -> Result<Vec<Invite>, sqlx::Error> {
+    let rows = sqlx::query("SELECT code, max_uses, uses, expires_at, note, created_at FROM invites ORDER BY created_at, code")
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(Invite::from_row).collect())
+}
+
+/// Returns false when no invite has this code.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn revoke_invite(pool: &DbPool, code: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM invites WHERE code = $1")
+        .bind(code.trim().to_uppercase())
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Use up one slot of an invite. False when the code is unknown, expired or
+/// used up.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn redeem_invite(pool: &DbPool, code: &str) -> bool {
+    sqlx::query(
+        "UPDATE invites SET uses = uses + 1 \
+         WHERE code = $1 AND (max_uses = 0 OR uses < max_uses) AND (expires_at IS NULL OR expires_at > $2)"
+    )
+    .bind(code.trim().to_uppercase())
+    .bind(db_timestamp(Utc::now()))
+    .execute(pool)
+    .await
+    .log_err("Redeeming invite")
+    .is_some_and(|res| res.rows_affected() > 0)
+}
+
+// ── Bans ────────────────────────────────────────────────
+
+/// Ban a user, replacing any earlier ban.
+#[tracing::instrument(level = "debug", skip_all, fields(user_id))]
+pub async fn ban_user(
+    pool: &DbPool,
+    user_id: &str,
+    reason: &str,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO bans (user_id, reason, expires_at, created_at) VALUES ($1, $2, $3, $4) \
+         ON CONFLICT (user_id) DO UPDATE SET reason = excluded.reason, expires_at = excluded.expires_at, created_at = excluded.created_at"
+    )
+    .bind(user_id)
+    .bind(reason)
+    .bind(expires_at.map(db_timestamp))
+    .bind(db_timestamp(Utc::now()))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns false when the user was not banned.
+#[tracing::instrument(level = "debug", skip_all, fields(user_id))]
+pub async fn unban_user(pool: &DbPool, user_id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM bans WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Bans still in effect.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn list_bans(pool: &DbPool) -> Result<Vec<Ban>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT b.user_id, u.username, b.reason, b.expires_at, b.created_at \
+         FROM bans b JOIN users u ON u.id = b.user_id \
+         WHERE b.expires_at IS NULL OR b.expires_at > $1 \
+         ORDER BY b.created_at, u.username"
+    )
+    .bind(db_timestamp(Utc::now()))
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(Ban::from_row).collect())
+}
+
+/// The user's ban, if one is in effect.
+#[tracing::instrument(level = "debug", skip_all, fields(user_id))]
+pub async fn active_ban(pool: &DbPool, user_id: &str) -> Option<Ban> {
+    sqlx::query(
+        "SELECT b.user_id, u.username, b.reason, b.expires_at, b.created_at \
+         FROM bans b JOIN users u ON u.id = b.user_id \
+         WHERE b.user_id = $1 AND (b.expires_at IS NULL OR b.expires_at > $2)"
+    )
+    .bind(user_id)
+    .bind(db_timestamp(Utc::now()))
+    .fetch_optional(pool)
+    .await
+    .log_err("Reading bans")
+    .flatten()
+    .map(|row| Ban::from_row(&row))
+}
+
+/// Reload `BannedUsers` every `POLL_INTERVAL`. Users banned since the
+/// last poll get a `banned` event, which closes their WebSockets.
+pub fn spawn_ban_watcher(pool: DbPool, banned: BannedUsers, broadcaster: Broadcaster) {
+    actix_web::rt::spawn(async move {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Some(bans) = list_bans(&pool).await.log_err("Reading bans") else {
+                continue;
+            };
+            let previous = std::mem::replace(
+                &mut *banned.write().unwrap(),
+                bans.iter().map(|ban| ban.user_id.clone()).collect(),
+            );
+            for ban in bans.iter().filter(|ban| !previous.contains(&ban.user_id)) {
+                let event = serde_json::json!({
+                    "type": "banned",
+                    "target_user_id": ban.user_id,
+                    "message": ban.message(),
+                });
+                let _ = broadcaster.send(event.to_string());
+            }
+        }
+    });
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn load_access(pool: &DbPool) -> Result<AccessSnapshot, sqlx::Error> {
+    let user_roles = sqlx::query_as::<_, (String, String)>("SELECT id, role FROM users WHERE role <> 'user'")
+        .fetch_all(pool)
+        .await?;
+    let room_roles = sqlx::query_as::<_, (String, String)>("SELECT id, required_role FROM rooms")
+        .fetch_all(pool)
+        .await?;
+    Ok(AccessSnapshot {
+        user_roles: user_roles.into_iter().collect(),
+        room_roles: room_roles.into_iter().collect(),
+    })
+}
+
+/// Reload `CurrentAccess`. On a change, clear the access cache and send
+/// `access_changed` so open WebSockets pick up the rooms they may now (or no
+/// longer) receive.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn refresh_access(pool: &DbPool, access: &CurrentAccess, access_cache: &AccessCache, broadcaster: &Broadcaster) {
+    let Some(snapshot) = load_access(pool).await.log_err("Reading access") else {
+        return;
+    };
+    let changed = {
+        let mut current = access.write().unwrap();
+        let changed = current.as_ref().is_some_and(|previous| *previous != snapshot);
+        *current = Some(snapshot);
+        changed
+    };
+    if changed {
+        crate::ws::cache_clear_access(access_cache);
+        let _ = broadcaster.send(serde_json::json!({ "type": "access_changed" }).to_string());
+    }
+}
+
+/// Run `refresh_access` every `POLL_INTERVAL`, for changes made with the CLI.
+pub fn spawn_access_watcher(pool: DbPool, access: CurrentAccess, access_cache: AccessCache, broadcaster: Broadcaster) {
+    actix_web::rt::spawn(async move {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            refresh_access(&pool, &access, &access_cache, &broadcaster).await;
+        }
+    });
+}
@@ -0,0 +1,195 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — OpenAPI document
+// ═══════════════════════════════════════════════════════
+//
+// The HTTP API as OpenAPI 3, generated from the `#[utoipa::path]`
+// annotations on the handlers and the `ToSchema` types they read and
+// return. It is served at `/api/openapi.json`; `server.api_docs` adds a
+// Redoc page at `/api/docs`. Every route registered in `lib.rs` must have
+// its handler listed in `ApiDoc` (`tests/openapi.rs` checks it).
+
+use actix_web::HttpResponse;
+use std::sync::LazyLock;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::{
+    auth, discord_gateway, health, messages, metrics, presence, profile_images, quotas, remote_auth, rooms, upload_gc,
+    uploads, voice, ws,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Voxium API",
+        license(name = "AGPL-3.0"),
+        description = "HTTP API of a Voxium server. Real-time events go through the `/ws` WebSocket, \
+                       described in PROTOCOL.md. Errors from most endpoints have the `ErrorBody` shape."
+    ),
+    paths(
+        health::live,
+        health::ready,
+        health::legacy_live,
+        get_spec,
+        get_docs,
+        metrics::get_metrics,
+        // Auth
+        auth::register,
+        auth::login,
+        auth::login_discord_token,
+        remote_auth::start_qr_session,
+        remote_auth::get_qr_status,
+        remote_auth::cancel_qr_session,
+        // Users
+        auth::get_me,
+        auth::update_profile,
+        quotas::get_my_storage,
+        profile_images::upload_avatar,
+        profile_images::upload_banner,
+        presence::get_presence,
+        // Discord
+        auth::get_discord_me,
+        auth::discord_proxy,
+        discord_gateway::voice_join,
+        discord_gateway::voice_leave,
+        discord_gateway::voice_participants,
+        // Server administration
+        auth::delete_user,
+        auth::update_user_role,
+        auth::list_server_roles,
+        auth::create_server_role,
+        auth::update_server_role,
+        auth::delete_server_role,
+        auth::list_server_users,
+        upload_gc::get_storage_report,
+        quotas::list_storage_consumers,
+        // Rooms
+        rooms::list_rooms,
+        rooms::create_room,
+        rooms::update_room,
+        rooms::delete_room,
+        // Messages
+        messages::delete_message,
+        messages::add_reaction,
+        messages::remove_reaction,
+        messages::search_messages,
+        messages::pin_message,
+        messages::unpin_message,
+        messages::delete_user_messages,
+        messages::get_messages,
+        messages::get_pinned_messages,
+        // Voice
+        voice::get_ice_servers,
+        voice::moderate_voice_member,
+        voice::disconnect_voice_member,
+        voice::move_voice_member,
+        // Uploads
+        uploads::upload_image,
+        uploads::serve_upload,
+        // WebSocket
+        ws::ws_handler,
+    ),
+    modifiers(&SecuritySchemes, &Summaries),
+    tags(
+        (name = "auth", description = "Creating accounts and signing in"),
+        (name = "users", description = "The signed-in user's profile, storage and presence"),
+        (name = "discord", description = "Calls made to Discord with the user's linked account"),
+        (name = "server", description = "Roles, users and storage administration (admins only)"),
+        (name = "rooms", description = "Text and voice rooms"),
+        (name = "messages", description = "Message history, reactions, pins and search"),
+        (name = "voice", description = "WebRTC configuration and voice moderation"),
+        (name = "uploads", description = "Attachments and uploaded files"),
+        (name = "realtime", description = "WebSocket event stream"),
+        (name = "system", description = "Health, metrics and this document"),
+    )
+)]
+pub struct ApiDoc;
+
+/// `bearer`: the JWT from `/api/login` or `/api/register`.
+/// `metrics_token`: `metrics.token`, for `/metrics`.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).bearer_format("JWT").build()),
+        );
+        components.add_security_scheme(
+            "metrics_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// Handler doc comments read `METHOD /path — Summary. More text`. The
+/// operation already shows the method and path, so keep the first sentence
+/// as the summary and the rest as the description.
+struct Summaries;
+
+impl Modify for Summaries {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for item in openapi.paths.paths.values_mut() {
+            let operations = [&mut item.get, &mut item.post, &mut item.put, &mut item.patch, &mut item.delete];
+            for operation in operations.into_iter().flatten() {
+                let Some(doc) = operation.summary.take() else {
+                    continue;
+                };
+                let text = doc.split_once(" — ").map_or("", |(_, text)| text);
+                let end = [text.find(". "), text.find('\n')].into_iter().flatten().min();
+                let (summary, rest) = match end {
+                    Some(end) => (&text[..end], text[end + 1..].trim()),
+                    None => (text, ""),
+                };
+                let summary = summary.trim_end_matches('.');
+                operation.summary = (!summary.is_empty()).then(|| summary.to_string());
+                if !rest.is_empty() {
+                    operation.description = Some(rest.to_string());
+                }
+            }
+        }
+    }
+}
+
+/// Serialized once, the document never changes while the server runs.
+static SPEC_JSON: LazyLock<String> =
+    LazyLock::new(|| ApiDoc::openapi().to_json().expect("OpenAPI document serializes"));
+
+const REDOC_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <title>Voxium API</title>
+</head>
+<body>
+    <redoc spec-url="/api/openapi.json"></redoc>
+    <script src="https://cdn.jsdelivr.net/npm/redoc@2/bundles/redoc.standalone.js"></script>
+</body>
+</html>
+"#;
+
+// ── HTTP Handlers ───────────────────────────────────────
+
+/// GET /api/openapi.json — This document
+#[utoipa::path(
+    get,
+    path = "/api/openapi.json",
+    tag = "system",
+    responses((status = 200, description = "OpenAPI 3 document of the HTTP API", body = Object))
+)]
+pub async fn get_spec() -> HttpResponse {
+    HttpResponse::Ok().content_type("application/json").body(SPEC_JSON.as_str())
+}
+
+/// GET /api/docs — Browsable API reference (when `server.api_docs` is on)
+#[utoipa::path(
+    get,
+    path = "/api/docs",
+    tag = "system",
+    responses((status = 200, description = "Redoc page rendering `/api/openapi.json`", content_type = "text/html", body = String))
+)]
+pub async fn get_docs() -> HttpResponse {
+    HttpResponse::Ok().content_type("text/html; charset=utf-8").body(REDOC_PAGE)
+}
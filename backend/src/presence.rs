@@ -0,0 +1,348 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — Rich presence
+// ═══════════════════════════════════════════════════════
+//
+// Each user has a persisted status (online / idle / dnd / invisible) and an
+// optional custom status text that may expire. Presence is only ever
+// changed for the identity in the connection's JWT. Invisible users are
+// reported to everyone else as offline.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Serialize;
+use utoipa::ToSchema;
+use sqlx::Row;
+use tracing::Instrument;
+
+use crate::db::DbPool;
+
+use crate::auth::extract_claims;
+use crate::errors::{AppError, ErrorBody};
+use crate::logging::LogErr;
+use crate::ws::{Broadcaster, OnlineUsers};
+
+pub const PRESENCE_STATUSES: [&str; 4] = ["online", "idle", "dnd", "invisible"];
+const MAX_CUSTOM_STATUS_CHARS: usize = 128;
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct UserPresence {
+    pub user_id: String,
+    pub status: String,
+    pub custom_status: Option<String>,
+    pub custom_status_expires_at: Option<String>,
+}
+
+impl UserPresence {
+    pub fn is_invisible(&self) -> bool {
+        self.status == "invisible"
+    }
+
+    /// Clear custom status text whose expiry has already passed.
+    fn drop_expired_custom_status(&mut self) {
+        let expired = self
+            .custom_status_expires_at
+            .as_deref()
+            .and_then(|ts| chrono::DateTime::parse_from_rfc3339(ts).ok())
+            .is_some_and(|ts| ts <= chrono::Utc::now());
+        if expired {
+            self.custom_status = None;
+            self.custom_status_expires_at = None;
+        }
+    }
+
+    pub fn event(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "presence",
+            "user_id": self.user_id,
+            "status": self.status,
+            "custom_status": self.custom_status,
+            "custom_status_expires_at": self.custom_status_expires_at,
+        })
+    }
+}
+
+/// Presence fields a client asked to change. `None` keeps the current value;
+/// an empty `custom_status` clears it.
+#[derive(Debug, Default)]
+pub struct PresenceUpdate {
+    pub status: Option<String>,
+    pub custom_status: Option<String>,
+    pub custom_status_expires_at: Option<String>,
+}
+
+impl PresenceUpdate {
+    /// Validate the update against `current` and return the resulting presence.
+    pub fn apply(&self, current: &UserPresence) -> Result<UserPresence, &'static str> {
+        let mut next = current.clone();
+
+        if let Some(raw) = &self.status {
+            let status = raw.trim().to_lowercase();
+            if !PRESENCE_STATUSES.contains(&status.as_str()) {
+                return Err("Status must be online, idle, dnd or invisible");
+            }
+            next.status = status;
+        }
+
+        if let Some(raw) = &self.custom_status {
+            let text = raw.trim();
+            if text.chars().count() > MAX_CUSTOM_STATUS_CHARS {
+                return Err("Custom status is limited to 128 characters");
+            }
+            if text.chars().any(|c| c.is_control()) {
+                return Err("Custom status contains invalid characters");
+            }
+            if text.is_empty() {
+                next.custom_status = None;
+                next.custom_status_expires_at = None;
+            } else {
+                next.custom_status = Some(text.to_string());
+                next.custom_status_expires_at = None;
+            }
+        }
+
+        if let Some(raw) = &self.custom_status_expires_at {
+            let raw = raw.trim();
+            if raw.is_empty() {
+                next.custom_status_expires_at = None;
+            } else {
+                let ts = chrono::DateTime::parse_from_rfc3339(raw)
+                    .map_err(|_| "custom_status_expires_at must be an RFC 3339 timestamp")?;
+                if ts <= chrono::Utc::now() {
+                    return Err("custom_status_expires_at must be in the future");
+                }
+                next.custom_status_expires_at = Some(ts.with_timezone(&chrono::Utc).to_rfc3339());
+            }
+        }
+
+        if next.custom_status.is_none() {
+            next.custom_status_expires_at = None;
+        }
+
+        Ok(next)
+    }
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(user_id))]
+pub async fn load_presence(pool: &DbPool, user_id: &str) -> UserPresence {
+    let row = sqlx::query("SELECT presence, custom_status, custom_status_expires_at FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .log_err("Reading users")
+        .flatten();
+
+    let mut presence = UserPresence {
+        user_id: user_id.to_string(),
+        status: "online".to_string(),
+        custom_status: None,
+        custom_status_expires_at: None,
+    };
+
+    if let Some(row) = row {
+        let status: String = row.try_get("presence").unwrap_or_default();
+        if PRESENCE_STATUSES.contains(&status.as_str()) {
+            presence.status = status;
+        }
+        presence.custom_status = row.try_get("custom_status").unwrap_or(None);
+        presence.custom_status_expires_at = row.try_get("custom_status_expires_at").unwrap_or(None);
+    }
+
+    presence.drop_expired_custom_status();
+    presence
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(user_id = %presence.user_id))]
+pub async fn save_presence(pool: &DbPool, presence: &UserPresence) {
+    sqlx::query("UPDATE users SET presence = $1, custom_status = $2, custom_status_expires_at = $3 WHERE id = $4")
+        .bind(&presence.status)
+        .bind(&presence.custom_status)
+        .bind(&presence.custom_status_expires_at)
+        .bind(&presence.user_id)
+        .execute(pool)
+        .await
+        .log_err("Updating users");
+}
+
+/// Profile `join` event used to (re-)announce a user who becomes visible.
+#[tracing::instrument(level = "debug", skip_all, fields(user_id = %presence.user_id))]
+pub async fn profile_join_event(pool: &DbPool, presence: &UserPresence) -> Option<serde_json::Value> {
+    let row = sqlx::query("SELECT username, role, about, avatar_color, avatar_url, banner_url FROM users WHERE id = $1")
+        .bind(&presence.user_id)
+        .fetch_optional(pool)
+        .await
+        .log_err("Reading users")
+        .flatten()?;
+
+    let username: String = row.try_get("username").unwrap_or_default();
+    let role: String = row.try_get("role").unwrap_or_else(|_| "user".to_string());
+    let about: Option<String> = row.try_get("about").unwrap_or(None);
+    let avatar_color: i32 = row.try_get("avatar_color").unwrap_or(0);
+    let avatar_url: Option<String> = row.try_get("avatar_url").unwrap_or(None);
+    let banner_url: Option<String> = row.try_get("banner_url").unwrap_or(None);
+
+    Some(serde_json::json!({
+        "type": "join",
+        "user_id": presence.user_id,
+        "username": username,
+        "role": role,
+        "about": about,
+        "avatar_color": avatar_color,
+        "avatar_url": avatar_url,
+        "banner_url": banner_url,
+        "status": presence.status,
+        "custom_status": presence.custom_status,
+        "custom_status_expires_at": presence.custom_status_expires_at,
+    }))
+}
+
+/// Broadcast the transition from `previous` to `next` the way other users
+/// should see it: going invisible looks like a disconnect, coming back looks
+/// like a fresh join.
+pub async fn broadcast_presence_change(
+    pool: &DbPool,
+    broadcaster: &Broadcaster,
+    previous: &UserPresence,
+    next: &UserPresence,
+) {
+    match (previous.is_invisible(), next.is_invisible()) {
+        (false, true) => {
+            let leave = serde_json::json!({ "type": "leave", "user_id": next.user_id });
+            let _ = broadcaster.send(leave.to_string());
+        }
+        (true, false) => {
+            if let Some(event) = profile_join_event(pool, next).await {
+                let _ = broadcaster.send(event.to_string());
+            }
+        }
+        (false, false) => {
+            let _ = broadcaster.send(next.event().to_string());
+        }
+        (true, true) => {}
+    }
+}
+
+/// Validate and persist a presence change for a connected user, update the
+/// online map and tell everyone else. Returns the new presence.
+pub async fn set_presence(
+    pool: &DbPool,
+    online_users: &OnlineUsers,
+    broadcaster: &Broadcaster,
+    user_id: &str,
+    update: &PresenceUpdate,
+) -> Result<UserPresence, &'static str> {
+    let previous = {
+        let guard = online_users.lock().unwrap();
+        guard.get(user_id).map(|online| online.presence.clone())
+    };
+    let previous = match previous {
+        Some(p) => p,
+        None => load_presence(pool, user_id).await,
+    };
+
+    let next = update.apply(&previous)?;
+    if next.status == previous.status
+        && next.custom_status == previous.custom_status
+        && next.custom_status_expires_at == previous.custom_status_expires_at
+    {
+        return Ok(next);
+    }
+
+    save_presence(pool, &next).await;
+    {
+        let mut guard = online_users.lock().unwrap();
+        if let Some(online) = guard.get_mut(user_id) {
+            online.presence = next.clone();
+        }
+    }
+
+    broadcast_presence_change(pool, broadcaster, &previous, &next).await;
+    if next.custom_status_expires_at != previous.custom_status_expires_at {
+        schedule_custom_status_expiry(pool.clone(), online_users.clone(), broadcaster.clone(), &next);
+    }
+
+    Ok(next)
+}
+
+/// Clear the custom status once it expires, unless it was changed meanwhile.
+pub fn schedule_custom_status_expiry(
+    pool: DbPool,
+    online_users: OnlineUsers,
+    broadcaster: Broadcaster,
+    presence: &UserPresence,
+) {
+    let Some(expires_at) = presence.custom_status_expires_at.clone() else {
+        return;
+    };
+    let Ok(deadline) = chrono::DateTime::parse_from_rfc3339(&expires_at) else {
+        return;
+    };
+    let user_id = presence.user_id.clone();
+    let delay = (deadline.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .unwrap_or_default();
+
+    actix_web::rt::spawn(async move {
+        tokio::time::sleep(delay).await;
+
+        let cleared = sqlx::query(
+            "UPDATE users SET custom_status = NULL, custom_status_expires_at = NULL WHERE id = $1 AND custom_status_expires_at = $2"
+        )
+        .bind(&user_id)
+        .bind(&expires_at)
+        .execute(&pool)
+        .await
+        .log_err("Clearing expired custom status")
+        .is_some_and(|res| res.rows_affected() > 0);
+
+        if !cleared {
+            return;
+        }
+
+        let presence = {
+            let mut guard = online_users.lock().unwrap();
+            guard.get_mut(&user_id).map(|online| {
+                online.presence.custom_status = None;
+                online.presence.custom_status_expires_at = None;
+                online.presence.clone()
+            })
+        };
+
+        if let Some(presence) = presence {
+            if !presence.is_invisible() {
+                let _ = broadcaster.send(presence.event().to_string());
+            }
+        }
+    }.in_current_span());
+}
+
+// ── HTTP Handlers ───────────────────────────────────────
+
+/// GET /api/presence — Snapshot of connected users' presence
+#[utoipa::path(
+    get,
+    path = "/api/presence",
+    tag = "users",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Presence of connected users (invisible ones are left out, except yourself)", body = [UserPresence]),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+    )
+)]
+pub async fn get_presence(req: HttpRequest, online_users: web::Data<OnlineUsers>) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
+
+    let mut snapshot: Vec<UserPresence> = {
+        let guard = online_users.lock().unwrap();
+        guard
+            .values()
+            .filter(|online| !online.presence.is_invisible() || online.presence.user_id == claims.sub)
+            .map(|online| online.presence.clone())
+            .collect()
+    };
+
+    for presence in snapshot.iter_mut() {
+        presence.drop_expired_custom_status();
+    }
+    snapshot.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+
+    Ok(HttpResponse::Ok().json(snapshot))
+}
@@ -0,0 +1,354 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — Avatar & banner uploads
+// ═══════════════════════════════════════════════════════
+//
+// Profile images are cropped server-side to fixed sizes (square avatars,
+// 3:1 banners) and stored as regular content-addressed uploads, so they go
+// through the same validation, deduplication and reference counting as
+// message attachments. Animated GIF avatars are a per-role perk; other
+// users get the first frame.
+
+use actix_multipart::Multipart;
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures_util::StreamExt;
+use image::imageops::FilterType;
+use image::{DynamicImage, Frame, ImageFormat};
+use serde::Deserialize;
+use utoipa::IntoParams;
+use sha2::{Digest, Sha256};
+use crate::db::DbPool;
+use std::io::Cursor;
+use std::time::Duration;
+
+use crate::auth::{broadcast_profile, extract_claims};
+use crate::config::Config;
+use crate::errors::{AppError, ErrorBody};
+use crate::logging::LogErr;
+use crate::storage::{SharedStorage, Storage};
+use crate::uploads::{
+    build_variants, check_image_header, decode_gif_frames, decode_image, encode_gif, encode_webp,
+    release_upload, size_limit_for, store_blob, ProcessedImage, UploadForm,
+};
+
+const AVATAR_SIZE: u32 = 512;
+/// Animated avatars keep every frame, so they are stored smaller
+const ANIMATED_AVATAR_SIZE: u32 = 256;
+const BANNER_SIZE: (u32, u32) = (1200, 400);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileImageKind {
+    Avatar,
+    Banner,
+}
+
+impl ProfileImageKind {
+    fn column(self) -> &'static str {
+        match self {
+            ProfileImageKind::Avatar => "avatar_url",
+            ProfileImageKind::Banner => "banner_url",
+        }
+    }
+
+    fn output_size(self, animated: bool) -> (u32, u32) {
+        match self {
+            ProfileImageKind::Avatar if animated => (ANIMATED_AVATAR_SIZE, ANIMATED_AVATAR_SIZE),
+            ProfileImageKind::Avatar => (AVATAR_SIZE, AVATAR_SIZE),
+            ProfileImageKind::Banner => BANNER_SIZE,
+        }
+    }
+}
+
+/// Crop rectangle in source image pixels (after EXIF orientation). The
+/// selection is then center-cropped to the output ratio and resized.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CropQuery {
+    pub x: Option<u32>,
+    pub y: Option<u32>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CropRect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl CropQuery {
+    fn rect(&self) -> Result<Option<CropRect>, AppError> {
+        match (self.x, self.y, self.width, self.height) {
+            (None, None, None, None) => Ok(None),
+            (Some(x), Some(y), Some(width), Some(height)) if width > 0 && height > 0 => {
+                Ok(Some(CropRect { x, y, width, height }))
+            }
+            _ => Err(AppError::InvalidCrop),
+        }
+    }
+}
+
+fn crop_and_resize(img: &DynamicImage, crop: Option<CropRect>, (width, height): (u32, u32)) -> Result<DynamicImage, AppError> {
+    let img = match crop {
+        Some(c) => {
+            let fits = c.x.checked_add(c.width).is_some_and(|right| right <= img.width())
+                && c.y.checked_add(c.height).is_some_and(|bottom| bottom <= img.height());
+            if !fits {
+                return Err(AppError::CropOutsideImage);
+            }
+            img.crop_imm(c.x, c.y, c.width, c.height)
+        }
+        None => img.clone(),
+    };
+    Ok(img.resize_to_fill(width, height, FilterType::Lanczos3))
+}
+
+/// Validate, crop and resize a profile image. Still images are stored as
+/// WebP; animated avatars (when allowed) as GIF.
+fn process_profile_image(
+    data: &[u8],
+    kind: ProfileImageKind,
+    crop: Option<CropRect>,
+    allow_animated: bool,
+    max_pixels: u64,
+) -> Result<ProcessedImage, AppError> {
+    let format = check_image_header(data, max_pixels)?;
+
+    let mut out = Cursor::new(Vec::new());
+    if format == ImageFormat::Gif && allow_animated && kind == ProfileImageKind::Avatar {
+        let frames = decode_gif_frames(data, max_pixels)?;
+        if frames.len() > 1 {
+            let size = kind.output_size(true);
+            let mut resized = Vec::with_capacity(frames.len());
+            for frame in frames {
+                let delay = frame.delay();
+                let img = crop_and_resize(&DynamicImage::ImageRgba8(frame.into_buffer()), crop, size)?;
+                resized.push(Frame::from_parts(img.to_rgba8(), 0, 0, delay));
+            }
+            let variants = build_variants(&DynamicImage::ImageRgba8(resized[0].buffer().clone()))?;
+            encode_gif(resized, &mut out)?;
+            return Ok(ProcessedImage {
+                format: ImageFormat::Gif,
+                data: out.into_inner(),
+                width: size.0,
+                height: size.1,
+                variants,
+            });
+        }
+    }
+
+    let img = crop_and_resize(&decode_image(data, format, max_pixels)?, crop, kind.output_size(false))?;
+    encode_webp(&img, &mut out)?;
+    Ok(ProcessedImage {
+        format: ImageFormat::WebP,
+        data: out.into_inner(),
+        width: img.width(),
+        height: img.height(),
+        variants: build_variants(&img)?,
+    })
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(user_id))]
+async fn role_allows_animated_avatars(pool: &DbPool, user_id: &str) -> bool {
+    sqlx::query_scalar::<_, i64>(
+        "SELECT r.animated_avatars FROM users u JOIN roles r ON r.name = u.role WHERE u.id = $1"
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+    .log_err("Reading users")
+    .flatten()
+    .is_some_and(|allowed| allowed != 0)
+}
+
+/// Copy an avatar from Discord's CDN into upload storage, so clients never
+/// load profile images from a third party. Returns the `/uploads/` URL with
+/// one reference held for the caller to store, or `None` when the image
+/// cannot be fetched, processed or stored.
+#[tracing::instrument(level = "debug", skip_all, fields(user_id))]
+pub(crate) async fn import_avatar(
+    pool: &DbPool,
+    config: &Config,
+    storage: &dyn Storage,
+    user_id: &str,
+    source_url: &str,
+    replaces: Option<&str>,
+) -> Option<String> {
+    let max_bytes = size_limit_for(&config.uploads.allowed_types, "image/png")?;
+    let response = reqwest::Client::new()
+        .get(source_url)
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .log_err("Fetching Discord avatar")?;
+    if !response.status().is_success() {
+        tracing::warn!(status = %response.status(), "Discord avatar unavailable");
+        return None;
+    }
+    let data = response.bytes().await.log_err("Fetching Discord avatar")?;
+    if data.len() > max_bytes {
+        tracing::warn!(size = data.len(), "Discord avatar too large");
+        return None;
+    }
+
+    let allow_animated = role_allows_animated_avatars(pool, user_id).await;
+    let max_pixels = config.uploads.max_pixels;
+    let processed = web::block(move || process_profile_image(&data, ProfileImageKind::Avatar, None, allow_animated, max_pixels))
+        .await
+        .ok()?
+        .log_err("Processing Discord avatar")?;
+
+    let mime_type = processed.mime_type();
+    let sha256 = format!("{:x}", Sha256::digest(&processed.data));
+    let key = format!("{}.{}", sha256, processed.extension());
+    let size = processed.data.len() as i64;
+    crate::quotas::check_upload_quota(pool, config, user_id, &key, size, replaces)
+        .await
+        .log_err("Checking quota for Discord avatar")?;
+    store_blob(pool, storage, &key, &sha256, processed.data, mime_type, processed.variants)
+        .await
+        .log_err("Storing Discord avatar")?;
+    Some(format!("/uploads/{}", key))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn upload_profile_image(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<SharedStorage>,
+    broadcaster: web::Data<crate::ws::Broadcaster>,
+    query: web::Query<CropQuery>,
+    mut payload: Multipart,
+    kind: ProfileImageKind,
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
+    let crop = query.rect()?;
+
+    // Same cap as image attachments
+    let max_bytes = size_limit_for(&config.uploads.allowed_types, "image/png").ok_or(AppError::ImageUploadsDisabled)?;
+
+    let Some(Ok(mut field)) = payload.next().await else {
+        return Err(AppError::NoFileProvided);
+    };
+    let mut data: Vec<u8> = Vec::new();
+    while let Some(Ok(chunk)) = field.next().await {
+        if data.len() + chunk.len() > max_bytes {
+            return Err(AppError::FileTooLarge { max_bytes, mime_type: None });
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    let allow_animated = role_allows_animated_avatars(pool.get_ref(), &claims.sub).await;
+    let max_pixels = config.uploads.max_pixels;
+    let processed = web::block(move || process_profile_image(&data, kind, crop, allow_animated, max_pixels))
+        .await
+        .map_err(|_| AppError::ImageProcessingFailed)??;
+
+    let (width, height, animated) = (processed.width, processed.height, processed.format == ImageFormat::Gif);
+    let mime_type = processed.mime_type();
+    let sha256 = format!("{:x}", Sha256::digest(&processed.data));
+    let key = format!("{}.{}", sha256, processed.extension());
+    let url = format!("/uploads/{}", key);
+
+    let previous: Option<Option<String>> = sqlx::query_scalar(&format!("SELECT {} FROM users WHERE id = $1", kind.column()))
+        .bind(&claims.sub)
+        .fetch_optional(pool.get_ref())
+        .await
+        .log_err("Reading users")
+        .flatten();
+    let previous = previous.flatten().filter(|p| !p.is_empty());
+
+    let size = processed.data.len() as i64;
+    crate::quotas::check_upload_quota(pool.get_ref(), &config, &claims.sub, &key, size, previous.as_deref()).await?;
+
+    // The stored blob's reference is the one held by the profile
+    store_blob(pool.get_ref(), storage.get_ref().as_ref(), &key, &sha256, processed.data, mime_type, processed.variants).await?;
+
+    let updated = sqlx::query(&format!("UPDATE users SET {} = $1 WHERE id = $2", kind.column()))
+        .bind(&url)
+        .bind(&claims.sub)
+        .execute(pool.get_ref())
+        .await;
+    if updated.is_err() {
+        release_upload(pool.get_ref(), storage.get_ref().as_ref(), &url).await;
+        return Err(AppError::UploadFailed);
+    }
+
+    // Drop the old image's reference (for an identical re-upload, the extra one just taken)
+    if let Some(previous) = previous {
+        release_upload(pool.get_ref(), storage.get_ref().as_ref(), &previous).await;
+    }
+
+    broadcast_profile(pool.get_ref(), broadcaster.get_ref(), &claims.sub).await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "url": url,
+        "width": width,
+        "height": height,
+        "animated": animated
+    })))
+}
+
+// ── HTTP Handlers ───────────────────────────────────────
+
+/// POST /api/users/me/avatar — Upload a square avatar (multipart, optional crop)
+#[utoipa::path(
+    post,
+    path = "/api/users/me/avatar",
+    tag = "users",
+    security(("bearer" = [])),
+    params(CropQuery),
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "New avatar stored and set on the profile", body = Object, example = json!({
+            "url": "/uploads/ab12….webp", "width": 512, "height": 512, "animated": false
+        })),
+        (status = 400, description = "No file, too large, not a valid image or crop outside the image", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 413, description = "Role storage quota exceeded", body = ErrorBody),
+        (status = 507, description = "Server storage full", body = ErrorBody),
+    )
+)]
+pub async fn upload_avatar(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<SharedStorage>,
+    broadcaster: web::Data<crate::ws::Broadcaster>,
+    query: web::Query<CropQuery>,
+    payload: Multipart,
+) -> Result<HttpResponse, AppError> {
+    upload_profile_image(req, pool, config, storage, broadcaster, query, payload, ProfileImageKind::Avatar).await
+}
+
+/// POST /api/users/me/banner — Upload a 3:1 banner (multipart, optional crop)
+#[utoipa::path(
+    post,
+    path = "/api/users/me/banner",
+    tag = "users",
+    security(("bearer" = [])),
+    params(CropQuery),
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "New banner stored and set on the profile", body = Object, example = json!({
+            "url": "/uploads/ab12….webp", "width": 1200, "height": 400, "animated": false
+        })),
+        (status = 400, description = "No file, too large, not a valid image or crop outside the image", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 413, description = "Role storage quota exceeded", body = ErrorBody),
+        (status = 507, description = "Server storage full", body = ErrorBody),
+    )
+)]
+pub async fn upload_banner(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<SharedStorage>,
+    broadcaster: web::Data<crate::ws::Broadcaster>,
+    query: web::Query<CropQuery>,
+    payload: Multipart,
+) -> Result<HttpResponse, AppError> {
+    upload_profile_image(req, pool, config, storage, broadcaster, query, payload, ProfileImageKind::Banner).await
+}
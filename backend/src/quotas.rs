@@ -0,0 +1,219 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — Upload storage quotas
+// ═══════════════════════════════════════════════════════
+//
+// A user's usage is the size of every upload they still own: attachment
+// rows disappear with their message, and never-posted ones are expired by
+// the sweeper. Images posted as `image_url` count through the attachment row
+// their message claimed, or for older messages without one, through the
+// message itself. Their avatar and banner count too. Identical files count for
+// each upload, since deduplication is a server-side saving. The server-wide
+// cap counts stored blobs once. When usage cannot be read, uploads are
+// refused rather than let through.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::extract_claims;
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::errors::{AppError, ErrorBody};
+use crate::logging::LogErr;
+
+/// Quota of the user's role (`uploads.user_quota` for roles without their
+/// own), `None` when unlimited.
+#[tracing::instrument(level = "debug", skip_all, fields(user_id))]
+pub async fn user_quota(pool: &DbPool, config: &Config, user_id: &str) -> Option<i64> {
+    let role_quota: Option<i64> = sqlx::query_scalar(
+        "SELECT r.storage_quota FROM users u JOIN roles r ON r.name = u.role WHERE u.id = $1"
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+    .log_err("Reading users")
+    .flatten()
+    .flatten();
+
+    Some(role_quota.unwrap_or(config.uploads.user_quota.0 as i64)).filter(|q| *q > 0)
+}
+
+/// Uploads owned by each user, as `(user_id, size)` rows: attachments,
+/// `image_url` images no attachment row accounts for, avatars and banners.
+const OWNED_UPLOADS: &str = "\
+    SELECT uploader_id AS user_id, size FROM message_attachments \
+    UNION ALL SELECT m.user_id, b.size FROM messages m JOIN upload_blobs b ON m.image_url = '/uploads/' || b.key \
+      WHERE NOT EXISTS (SELECT 1 FROM message_attachments a WHERE a.message_id = m.id AND a.url = m.image_url) \
+    UNION ALL SELECT p.id, b.size FROM users p JOIN upload_blobs b ON p.avatar_url = '/uploads/' || b.key \
+    UNION ALL SELECT p.id, b.size FROM users p JOIN upload_blobs b ON p.banner_url = '/uploads/' || b.key";
+
+/// Bytes and number of files currently owned by the user.
+#[tracing::instrument(level = "debug", skip_all, fields(user_id))]
+pub async fn user_usage(pool: &DbPool, user_id: &str) -> Result<(i64, i64), AppError> {
+    let usage = sqlx::query_as::<_, (i64, i64)>(&format!(
+        "SELECT CAST(COALESCE(SUM(size), 0) AS BIGINT), COUNT(*) FROM ({OWNED_UPLOADS}) owned WHERE user_id = $1"
+    ))
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(usage)
+}
+
+/// Check that `size` more bytes fit in the user's quota and, when `key` is
+/// not stored yet, in the server-wide cap. `replaces` is an upload of the
+/// user's that goes away with this one, such as the previous avatar; its
+/// size is not counted. Concurrent uploads may overshoot by one file.
+#[tracing::instrument(level = "debug", skip_all, fields(user_id, key, size))]
+pub async fn check_upload_quota(
+    pool: &DbPool,
+    config: &Config,
+    user_id: &str,
+    key: &str,
+    size: i64,
+    replaces: Option<&str>,
+) -> Result<(), AppError> {
+    if let Some(quota) = user_quota(pool, config, user_id).await {
+        let (used, _) = user_usage(pool, user_id).await?;
+        let freed = match replaces.and_then(|url| url.strip_prefix("/uploads/")) {
+            Some(replaced_key) => sqlx::query_scalar::<_, i64>("SELECT size FROM upload_blobs WHERE key = $1")
+                .bind(replaced_key)
+                .fetch_optional(pool)
+                .await?
+                .unwrap_or(0),
+            None => 0,
+        };
+        if used - freed + size > quota {
+            return Err(AppError::StorageQuotaExceeded { quota });
+        }
+    }
+
+    if let Some(cap) = config.uploads.global_quota.limit() {
+        let stored: Option<i64> = sqlx::query_scalar("SELECT 1 FROM upload_blobs WHERE key = $1")
+            .bind(key)
+            .fetch_optional(pool)
+            .await?;
+        if stored.is_none() {
+            let total: i64 = sqlx::query_scalar("SELECT CAST(COALESCE(SUM(size), 0) AS BIGINT) FROM upload_blobs")
+                .fetch_one(pool)
+                .await?;
+            if total + size > cap {
+                return Err(AppError::ServerStorageFull);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StorageUsage {
+    pub used: i64,
+    pub files: i64,
+    /// `None` when unlimited
+    pub quota: Option<i64>,
+    pub remaining: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StorageConsumer {
+    pub user_id: String,
+    pub username: String,
+    pub role: String,
+    pub used: i64,
+    pub files: i64,
+    pub quota: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ConsumersQuery {
+    /// 1 to 100, 20 by default
+    pub limit: Option<i64>,
+}
+
+// ── HTTP Handlers ───────────────────────────────────────
+
+/// GET /api/users/me/storage — Current user's storage usage and quota
+#[utoipa::path(
+    get,
+    path = "/api/users/me/storage",
+    tag = "users",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Storage used by the current user's uploads", body = StorageUsage),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+    )
+)]
+pub async fn get_my_storage(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
+
+    let (used, files) = user_usage(pool.get_ref(), &claims.sub).await?;
+    let quota = user_quota(pool.get_ref(), &config, &claims.sub).await;
+
+    Ok(HttpResponse::Ok().json(StorageUsage {
+        used,
+        files,
+        quota,
+        remaining: quota.map(|q| (q - used).max(0)),
+    }))
+}
+
+/// GET /api/server/storage/users — Biggest storage consumers (Admin only)
+#[utoipa::path(
+    get,
+    path = "/api/server/storage/users",
+    tag = "server",
+    security(("bearer" = [])),
+    params(ConsumersQuery),
+    responses(
+        (status = 200, description = "Users storing the most, biggest first", body = [StorageConsumer]),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Admin only", body = ErrorBody),
+    )
+)]
+pub async fn list_storage_consumers(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    query: web::Query<ConsumersQuery>,
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
+
+    if claims.role != "admin" {
+        return Err(AppError::AdminOnly);
+    }
+
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let rows = sqlx::query(&format!(
+        "SELECT u.id, u.username, u.role, r.storage_quota, \
+                CAST(SUM(o.size) AS BIGINT) AS used, COUNT(*) AS files \
+         FROM ({OWNED_UPLOADS}) o \
+         JOIN users u ON u.id = o.user_id \
+         LEFT JOIN roles r ON r.name = u.role \
+         GROUP BY u.id, u.username, u.role, r.storage_quota \
+         ORDER BY used DESC \
+         LIMIT $1"
+    ))
+    .bind(limit)
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let default_quota = config.uploads.user_quota.0 as i64;
+    let consumers: Vec<StorageConsumer> = rows
+        .into_iter()
+        .map(|row| StorageConsumer {
+            user_id: row.get("id"),
+            username: row.get("username"),
+            role: row.get("role"),
+            used: row.get("used"),
+            files: row.get("files"),
+            quota: Some(row.get::<Option<i64>, _>("storage_quota").unwrap_or(default_quota)).filter(|q| *q > 0),
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(consumers))
+}
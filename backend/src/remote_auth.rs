@@ -3,12 +3,17 @@ use base64::{engine::general_purpose, Engine};
 use futures_util::{SinkExt, StreamExt};
 use rsa::{pkcs8::EncodePublicKey, rand_core::OsRng, Oaep, RsaPrivateKey, RsaPublicKey};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use sha2::{Digest, Sha256};
-use sqlx::SqlitePool;
+use crate::config::Config;
+use crate::errors::{AppError, ErrorBody};
+use crate::storage::{SharedStorage, Storage};
+use crate::db::DbPool;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::{mpsc, Mutex};
 use tokio_tungstenite::tungstenite::Message;
+use tracing::Instrument;
 
 const DISCORD_REMOTE_AUTH_GATEWAY: &str = "wss://remote-auth-gateway.discord.gg/?v=2";
 const DISCORD_REMOTE_AUTH_LOGIN_API: &str =
@@ -17,7 +22,7 @@ const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/
 
 // ── Session types ───────────────────────────────────────
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 #[serde(tag = "status")]
 pub enum QrStatus {
     #[serde(rename = "connecting")]
@@ -49,22 +54,44 @@ pub fn create_qr_sessions() -> QrAuthSessions {
     Arc::new(Mutex::new(HashMap::new()))
 }
 
+/// Sessions that have not completed, failed or been cancelled yet.
+pub async fn active_session_count(sessions: &QrAuthSessions) -> usize {
+    sessions
+        .lock()
+        .await
+        .values()
+        .filter(|s| !matches!(s.status, QrStatus::Completed { .. } | QrStatus::Error { .. } | QrStatus::Cancelled))
+        .count()
+}
+
 // ── Request types ───────────────────────────────────────
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct SessionQuery {
     pub session_id: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct CancelPayload {
     pub session_id: String,
 }
 
 // ── Handlers ────────────────────────────────────────────
 
+/// POST /api/auth/discord/qr/start — Start a Discord QR code login
+#[utoipa::path(
+    post,
+    path = "/api/auth/discord/qr/start",
+    tag = "auth",
+    responses(
+        (status = 200, description = "Session started; poll its status", body = Object, example = json!({ "session_id": "e4a9…" })),
+    )
+)]
 pub async fn start_qr_session(
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    storage: web::Data<SharedStorage>,
     sessions: web::Data<QrAuthSessions>,
 ) -> HttpResponse {
     let session_id = uuid::Uuid::new_v4().to_string();
@@ -94,40 +121,62 @@ pub async fn start_qr_session(
 
     let sessions_clone = sessions.get_ref().clone();
     let pool_clone = pool.get_ref().clone();
+    let storage = storage.get_ref().clone();
+    let config = config.into_inner();
     let sid = session_id.clone();
-    tokio::spawn(async move {
-        run_remote_auth_flow(sid, sessions_clone, pool_clone, cancel_rx).await;
-    });
+    let span = tracing::info_span!("qr_auth", session_id = %sid);
+    tokio::spawn(
+        async move {
+            run_remote_auth_flow(sid, sessions_clone, pool_clone, config, storage, cancel_rx).await;
+        }
+        .instrument(span),
+    );
 
     HttpResponse::Ok().json(serde_json::json!({ "session_id": session_id }))
 }
 
+/// GET /api/auth/discord/qr/status — Progress of a QR code login
+#[utoipa::path(
+    get,
+    path = "/api/auth/discord/qr/status",
+    tag = "auth",
+    params(SessionQuery),
+    responses(
+        (status = 200, description = "Current step; `completed` carries the login response in `auth`", body = QrStatus),
+        (status = 404, description = "No such session", body = ErrorBody),
+    )
+)]
 pub async fn get_qr_status(
     sessions: web::Data<QrAuthSessions>,
     query: web::Query<SessionQuery>,
-) -> HttpResponse {
+) -> Result<HttpResponse, AppError> {
     let map = sessions.lock().await;
-    if let Some(session) = map.get(&query.session_id) {
-        HttpResponse::Ok().json(&session.status)
-    } else {
-        HttpResponse::NotFound().json(serde_json::json!({ "error": "Session introuvable" }))
-    }
+    let session = map.get(&query.session_id).ok_or(AppError::QrSessionNotFound)?;
+    Ok(HttpResponse::Ok().json(&session.status))
 }
 
+/// POST /api/auth/discord/qr/cancel — Abandon a QR code login
+#[utoipa::path(
+    post,
+    path = "/api/auth/discord/qr/cancel",
+    tag = "auth",
+    request_body = CancelPayload,
+    responses(
+        (status = 200, description = "Session cancelled", body = Object, example = json!({ "ok": true })),
+        (status = 404, description = "No such session", body = ErrorBody),
+    )
+)]
 pub async fn cancel_qr_session(
     sessions: web::Data<QrAuthSessions>,
     body: web::Json<CancelPayload>,
-) -> HttpResponse {
+) -> Result<HttpResponse, AppError> {
     let mut map = sessions.lock().await;
-    if let Some(session) = map.get_mut(&body.session_id) {
-        if let Some(tx) = session.cancel_tx.take() {
-            let _ = tx.try_send(());
-        }
-        session.status = QrStatus::Cancelled;
-        HttpResponse::Ok().json(serde_json::json!({ "ok": true }))
-    } else {
-        HttpResponse::NotFound().json(serde_json::json!({ "error": "Session introuvable" }))
+    let session = map.get_mut(&body.session_id).ok_or(AppError::QrSessionNotFound)?;
+    if let Some(tx) = session.cancel_tx.take() {
+        let _ = tx.try_send(());
     }
+    session.status = QrStatus::Cancelled;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "ok": true })))
 }
 
 // ── Internal helpers ────────────────────────────────────
@@ -158,7 +207,9 @@ fn generate_qr_data_uri(data: &str) -> Result<String, String> {
 async fn run_remote_auth_flow(
     session_id: String,
     sessions: QrAuthSessions,
-    pool: SqlitePool,
+    pool: DbPool,
+    config: Arc<Config>,
+    storage: SharedStorage,
     mut cancel_rx: mpsc::Receiver<()>,
 ) {
     // Generate RSA-OAEP 2048 key pair
@@ -444,6 +495,8 @@ async fn run_remote_auth_flow(
                                     &ticket,
                                     &private_key,
                                     &pool,
+                                    &config,
+                                    storage.as_ref(),
                                 )
                                 .await
                                 {
@@ -481,6 +534,8 @@ async fn run_remote_auth_flow(
                                         enc_token,
                                         &private_key,
                                         &pool,
+                                        &config,
+                                        storage.as_ref(),
                                     )
                                     .await
                                     {
@@ -570,7 +625,9 @@ async fn run_remote_auth_flow(
 async fn decrypt_and_login(
     encrypted_token_b64: &str,
     private_key: &RsaPrivateKey,
-    pool: &SqlitePool,
+    pool: &DbPool,
+    config: &Config,
+    storage: &dyn Storage,
 ) -> Result<serde_json::Value, String> {
     let encrypted = general_purpose::STANDARD
         .decode(encrypted_token_b64)
@@ -591,7 +648,7 @@ async fn decrypt_and_login(
         return Err("Empty token after decryption".into());
     }
 
-    let auth = crate::auth::do_discord_token_login(pool, &discord_token)
+    let auth = crate::auth::do_discord_token_login(pool, config, storage, &discord_token)
         .await
         .map_err(|e| format!("Login failed: {e}"))?;
 
@@ -601,7 +658,9 @@ async fn decrypt_and_login(
 async fn finalize_with_ticket(
     ticket: &str,
     private_key: &RsaPrivateKey,
-    pool: &SqlitePool,
+    pool: &DbPool,
+    config: &Config,
+    storage: &dyn Storage,
 ) -> Result<serde_json::Value, String> {
     let client = reqwest::Client::new();
     let resp = client
@@ -625,13 +684,13 @@ async fn finalize_with_ticket(
         .map_err(|e| format!("Bad Discord response: {e}"))?;
 
     if let Some(enc) = body.get("encrypted_token").and_then(|v| v.as_str()) {
-        return decrypt_and_login(enc, private_key, pool).await;
+        return decrypt_and_login(enc, private_key, pool, config, storage).await;
     }
 
     if let Some(tok) = body.get("token").and_then(|v| v.as_str()) {
         let t = tok.trim();
         if !t.is_empty() {
-            let auth = crate::auth::do_discord_token_login(pool, t)
+            let auth = crate::auth::do_discord_token_login(pool, config, storage, t)
                 .await
                 .map_err(|e| format!("Login failed: {e}"))?;
             return Ok(serde_json::to_value(auth).unwrap_or_default());
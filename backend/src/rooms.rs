@@ -1,233 +1,301 @@
 use actix_web::{web, HttpRequest, HttpResponse};
 use serde::{Deserialize, Serialize};
-use sqlx::SqlitePool;
+use utoipa::ToSchema;
+use crate::db::DbPool;
 use uuid::Uuid;
-use crate::auth::extract_claims;
+use crate::auth::{extract_claims, role_exists};
+use crate::errors::{AppError, ErrorBody};
+use crate::logging::LogErr;
+use crate::voice::{promote_waiting, set_room_user_limit, VoiceStates};
 use crate::ws::{cache_remove_room, cache_set_room_required_role, AccessCache, Broadcaster};
 
-#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+/// Upper bound for a voice room's `user_limit` (0 = unlimited).
+const MAX_ROOM_USER_LIMIT: i64 = 99;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
 pub struct Room {
     pub id: String,
     pub name: String,
     pub kind: String,
     pub required_role: String,
+    pub user_limit: i64,
     pub created_at: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateRoom {
     pub name: String,
     pub kind: Option<String>,
     pub required_role: Option<String>,
+    pub user_limit: Option<i64>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct UpdateRoomSettings {
     pub name: String,
     pub kind: String,
     pub required_role: String,
+    /// Omitted = keep the current limit.
+    pub user_limit: Option<i64>,
+}
+
+// Room settings checks, shared with the `voxium-admin` CLI
+
+/// Trimmed room name, which must not be empty.
+pub fn validate_room_name(name: &str) -> Result<&str, AppError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError::RoomNameRequired);
+    }
+    Ok(name)
+}
+
+pub fn normalize_room_kind(kind: &str) -> Result<String, AppError> {
+    let kind = kind.trim().to_lowercase();
+    if kind != "text" && kind != "voice" {
+        return Err(AppError::InvalidRoomKind);
+    }
+    Ok(kind)
+}
+
+pub fn validate_user_limit(limit: i64) -> Result<i64, AppError> {
+    if !(0..=MAX_ROOM_USER_LIMIT).contains(&limit) {
+        return Err(AppError::InvalidUserLimit { max: MAX_ROOM_USER_LIMIT });
+    }
+    Ok(limit)
+}
+
+/// Lowercased role name, which must exist.
+#[tracing::instrument(level = "debug", skip_all, fields(role))]
+pub async fn validate_required_role(pool: &DbPool, role: &str) -> Result<String, AppError> {
+    let role = role.trim().to_lowercase();
+    if !role_exists(pool, &role).await {
+        return Err(AppError::UnknownRole);
+    }
+    Ok(role)
+}
+
+/// Delete a room and its messages. False when the room does not exist.
+#[tracing::instrument(level = "debug", skip_all, fields(room_id))]
+pub async fn remove_room(pool: &DbPool, room_id: &str) -> Result<bool, sqlx::Error> {
+    // Delete messages first (cascade typically handles this but we enforce)
+    sqlx::query("DELETE FROM messages WHERE room_id = $1")
+        .bind(room_id)
+        .execute(pool)
+        .await
+        .log_err("Deleting from messages");
+
+    let result = sqlx::query("DELETE FROM rooms WHERE id = $1")
+        .bind(room_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
 }
 
 /// GET /api/rooms — List all rooms
-pub async fn list_rooms(req: HttpRequest, pool: web::Data<SqlitePool>) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().json(serde_json::json!({ "error": "Not authenticated" })),
-    };
+#[utoipa::path(
+    get,
+    path = "/api/rooms",
+    tag = "rooms",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Rooms the user can access, oldest first", body = [Room]),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+    )
+)]
+pub async fn list_rooms(req: HttpRequest, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     let rooms = if claims.role == "admin" {
-        sqlx::query_as::<_, Room>("SELECT id, name, kind, required_role, created_at FROM rooms ORDER BY created_at")
+        sqlx::query_as::<_, Room>("SELECT id, name, kind, required_role, user_limit, created_at FROM rooms ORDER BY created_at")
             .fetch_all(pool.get_ref())
             .await
+            .log_err("Reading rooms")
             .unwrap_or_default()
     } else {
         sqlx::query_as::<_, Room>(
-            "SELECT id, name, kind, required_role, created_at FROM rooms WHERE required_role = 'user' OR required_role = ? ORDER BY created_at"
+            "SELECT id, name, kind, required_role, user_limit, created_at FROM rooms WHERE required_role = 'user' OR required_role = $1 ORDER BY created_at"
         )
         .bind(&claims.role)
         .fetch_all(pool.get_ref())
         .await
+        .log_err("Reading rooms")
         .unwrap_or_default()
     };
 
-    HttpResponse::Ok().json(rooms)
+    Ok(HttpResponse::Ok().json(rooms))
 }
 
 /// POST /api/rooms — Create a new room (requires auth)
+#[utoipa::path(
+    post,
+    path = "/api/rooms",
+    tag = "rooms",
+    security(("bearer" = [])),
+    request_body = CreateRoom,
+    responses(
+        (status = 200, description = "Room created", body = Object, example = json!({
+            "id": "9b1e…", "name": "general", "kind": "text", "required_role": "user", "user_limit": 0
+        })),
+        (status = 400, description = "Invalid name, kind, role or user limit", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Only admins can create rooms restricted to a role", body = ErrorBody),
+        (status = 409, description = "Room name taken", body = ErrorBody),
+    )
+)]
 pub async fn create_room(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
     body: web::Json<CreateRoom>,
     access_cache: web::Data<AccessCache>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().json(serde_json::json!({ "error": "Not authenticated" })),
-    };
-
-    let name = body.name.trim();
-    if name.is_empty() {
-        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Room name is required" }));
-    }
-
-    let kind = body.kind.as_deref().unwrap_or("text").trim().to_lowercase();
-    if kind != "text" && kind != "voice" {
-        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Room kind must be text or voice" }));
-    }
-
-    let required_role = body.required_role.as_deref().unwrap_or("user").trim().to_lowercase();
-
-    let role_exists = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM roles WHERE name = ?")
-        .bind(&required_role)
-        .fetch_one(pool.get_ref())
-        .await
-        .unwrap_or(0);
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
-    if role_exists <= 0 {
-        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Invalid required role" }));
-    }
+    let name = validate_room_name(&body.name)?;
+    let kind = normalize_room_kind(body.kind.as_deref().unwrap_or("text"))?;
+    let required_role = validate_required_role(pool.get_ref(), body.required_role.as_deref().unwrap_or("user")).await?;
 
     if required_role != "user" && claims.role != "admin" {
-        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Only admins can create restricted rooms" }));
+        return Err(AppError::RestrictedRoomAdminOnly);
     }
 
+    let user_limit = validate_user_limit(body.user_limit.unwrap_or(0))?;
+
     let id = Uuid::new_v4().to_string();
 
-    let result = sqlx::query("INSERT INTO rooms (id, name, kind, required_role) VALUES (?, ?, ?, ?)")
+    sqlx::query("INSERT INTO rooms (id, name, kind, required_role, user_limit) VALUES ($1, $2, $3, $4, $5)")
         .bind(&id)
         .bind(name)
         .bind(&kind)
         .bind(&required_role)
+        .bind(user_limit)
         .execute(pool.get_ref())
-        .await;
-
-    match result {
-        Ok(_) => {
-            cache_set_room_required_role(access_cache.get_ref(), &id, &required_role);
-            HttpResponse::Ok().json(serde_json::json!({ "id": id, "name": name, "kind": kind, "required_role": required_role }))
-        }
-        Err(_) => HttpResponse::Conflict().json(serde_json::json!({ "error": "Room name already exists" })),
-    }
+        .await
+        .map_err(|_| AppError::RoomNameTaken)?;
+
+    cache_set_room_required_role(access_cache.get_ref(), &id, &required_role);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "id": id, "name": name, "kind": kind, "required_role": required_role, "user_limit": user_limit })))
 }
 
 /// PATCH /api/rooms/{id} — Update room settings (Admin only)
+#[utoipa::path(
+    patch,
+    path = "/api/rooms/{id}",
+    tag = "rooms",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "Room id")),
+    request_body = UpdateRoomSettings,
+    responses(
+        (status = 200, description = "Room updated", body = Object, example = json!({ "status": "updated" })),
+        (status = 400, description = "Invalid name, kind, role or user limit", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Admin only", body = ErrorBody),
+        (status = 404, description = "No such room", body = ErrorBody),
+        (status = 409, description = "Room name taken", body = ErrorBody),
+    )
+)]
 pub async fn update_room(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
     path: web::Path<String>,
     body: web::Json<UpdateRoomSettings>,
     broadcaster: web::Data<Broadcaster>,
     access_cache: web::Data<AccessCache>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+    voice_states: web::Data<VoiceStates>,
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     if claims.role != "admin" {
-        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Admin only" }));
+        return Err(AppError::AdminOnly);
     }
 
     let room_id = path.into_inner();
-    let room_name = body.name.trim();
-    if room_name.is_empty() {
-        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Room name is required" }));
+    let room_name = validate_room_name(&body.name)?;
+    let kind = normalize_room_kind(&body.kind)?;
+    let required_role = validate_required_role(pool.get_ref(), &body.required_role).await?;
+    if let Some(limit) = body.user_limit {
+        validate_user_limit(limit)?;
     }
 
-    let kind = body.kind.trim().to_lowercase();
-    if kind != "text" && kind != "voice" {
-        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Room kind must be text or voice" }));
+    let result = sqlx::query("UPDATE rooms SET name = $1, kind = $2, required_role = $3, user_limit = COALESCE($4, user_limit) WHERE id = $5")
+        .bind(room_name)
+        .bind(&kind)
+        .bind(&required_role)
+        .bind(body.user_limit)
+        .bind(&room_id)
+        .execute(pool.get_ref())
+        .await
+        .map_err(|_| AppError::RoomNameTaken)?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::RoomNotFound);
     }
 
-    let required_role = body.required_role.trim().to_lowercase();
+    cache_set_room_required_role(access_cache.get_ref(), &room_id, &required_role);
 
-    let role_exists = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM roles WHERE name = ?")
-        .bind(&required_role)
+    let user_limit = sqlx::query_scalar::<_, i64>("SELECT user_limit FROM rooms WHERE id = $1")
+        .bind(&room_id)
         .fetch_one(pool.get_ref())
         .await
+        .log_err("Reading rooms")
         .unwrap_or(0);
 
-    if role_exists <= 0 {
-        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Invalid required role" }));
-    }
-
-    let result = sqlx::query("UPDATE rooms SET name = ?, kind = ?, required_role = ? WHERE id = ?")
-        .bind(room_name)
-        .bind(&kind)
-        .bind(&required_role)
-        .bind(&room_id)
-        .execute(pool.get_ref())
-        .await;
-
-    match result {
-        Ok(res) => {
-            if res.rows_affected() == 0 {
-                return HttpResponse::NotFound().json(serde_json::json!({ "error": "Room not found" }));
-            }
-
-            cache_set_room_required_role(access_cache.get_ref(), &room_id, &required_role);
-
-            let event = serde_json::json!({
-                "type": "room_updated",
-                "room_id": room_id,
-                "name": room_name,
-                "kind": kind,
-                "required_role": required_role,
-            });
-            let _ = broadcaster.send(event.to_string());
-
-            HttpResponse::Ok().json(serde_json::json!({ "status": "updated" }))
-        }
-        Err(_) => HttpResponse::Conflict().json(serde_json::json!({ "error": "Room name already exists" })),
-    }
+    let event = serde_json::json!({
+        "type": "room_updated",
+        "room_id": room_id,
+        "name": room_name,
+        "kind": kind,
+        "required_role": required_role,
+        "user_limit": user_limit,
+    });
+    let _ = broadcaster.send(event.to_string());
+
+    // A raised (or removed) limit frees slots for queued users
+    set_room_user_limit(voice_states.get_ref(), &room_id, user_limit);
+    promote_waiting(voice_states.get_ref(), broadcaster.get_ref(), &room_id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "updated" })))
 }
 
 /// DELETE /api/rooms/{id} — Delete a room (Admin only)
+#[utoipa::path(
+    delete,
+    path = "/api/rooms/{id}",
+    tag = "rooms",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "Room id")),
+    responses(
+        (status = 200, description = "Room and its messages deleted", body = Object, example = json!({ "status": "deleted" })),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Admin only", body = ErrorBody),
+        (status = 404, description = "No such room", body = ErrorBody),
+    )
+)]
 pub async fn delete_room(
     req: HttpRequest,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
     path: web::Path<String>,
     broadcaster: web::Data<Broadcaster>,
     access_cache: web::Data<AccessCache>,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
     if claims.role != "admin" {
-        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Admin only" }));
+        return Err(AppError::AdminOnly);
     }
 
     let room_id = path.into_inner();
 
-    // Delete messages first (cascade typically handles this but we enforce)
-    let _ = sqlx::query("DELETE FROM messages WHERE room_id = ?")
-        .bind(&room_id)
-        .execute(pool.get_ref())
-        .await;
-
-    let result = sqlx::query("DELETE FROM rooms WHERE id = ?")
-        .bind(&room_id)
-        .execute(pool.get_ref())
-        .await;
-
-    match result {
-        Ok(res) => {
-            if res.rows_affected() > 0 {
-                cache_remove_room(access_cache.get_ref(), &room_id);
-
-                // Broadcast room_deleted event
-                let msg = serde_json::json!({
-                    "type": "room_deleted",
-                    "room_id": room_id
-                });
-                let _ = broadcaster.send(msg.to_string());
-                HttpResponse::Ok().json(serde_json::json!({ "status": "deleted" }))
-            } else {
-                HttpResponse::NotFound().json(serde_json::json!({ "error": "Room not found" }))
-            }
-        }
-        Err(_) => HttpResponse::InternalServerError().finish(),
+    if !remove_room(pool.get_ref(), &room_id).await? {
+        return Err(AppError::RoomNotFound);
     }
+    cache_remove_room(access_cache.get_ref(), &room_id);
+
+    // Broadcast room_deleted event
+    let msg = serde_json::json!({
+        "type": "room_deleted",
+        "room_id": room_id
+    });
+    let _ = broadcaster.send(msg.to_string());
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "deleted" })))
 }
@@ -0,0 +1,520 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — Upload storage
+// ═══════════════════════════════════════════════════════
+//
+// Uploaded files are addressed by a flat key, the `<name>` in
+// `/uploads/<name>`. `storage.backend` picks where the bytes live:
+//
+//   local — under `storage.root` (default `uploads`), sharded into two
+//           directory levels by a hash of the key. Files written flat by
+//           older versions are still found.
+//   s3    — an S3-compatible bucket (AWS, MinIO, ...), using path-style
+//           requests signed with AWS Signature Version 4.
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::config::{S3Config, StorageBackend, StorageConfig};
+use crate::logging::LogErr;
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(&self, key: &str, data: Vec<u8>, content_type: &str) -> io::Result<()>;
+
+    /// `Ok(None)` when the key does not exist.
+    async fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Deleting a missing key is not an error.
+    async fn delete(&self, key: &str) -> io::Result<()>;
+
+    async fn exists(&self, key: &str) -> io::Result<bool>;
+
+    /// Every stored object, for the orphan sweeper and storage report.
+    async fn list(&self) -> io::Result<Vec<StoredObject>>;
+
+    /// Write a small probe object, for the readiness check. Its key starts
+    /// with a dot so `list` never reports it.
+    async fn check_writable(&self) -> io::Result<()>;
+
+    /// Path on local disk, for backends that have one, so files can be
+    /// served with range requests and without buffering.
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+pub type SharedStorage = Arc<dyn Storage>;
+
+#[derive(Debug, Clone)]
+pub struct StoredObject {
+    pub key: String,
+    pub size: u64,
+    pub modified: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Build the backend selected by `storage.backend`. The configuration was
+/// validated by `Config::load`.
+pub fn create_storage(config: &StorageConfig) -> SharedStorage {
+    match config.backend {
+        StorageBackend::S3 => {
+            let storage = S3Storage::from_config(&config.s3).unwrap_or_else(|e| panic!("Invalid S3 storage configuration: {}", e));
+            tracing::info!("Upload storage: s3 ({}/{})", storage.endpoint, storage.bucket);
+            Arc::new(storage)
+        }
+        StorageBackend::Local => {
+            tracing::info!("Upload storage: local ({})", config.root);
+            Arc::new(LocalStorage::new(&config.root))
+        }
+    }
+}
+
+/// Keys are single path segments made of safe characters, so they can be
+/// used as file names and URL path segments without escaping.
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= 255
+        && !key.starts_with('.')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+fn invalid_key(key: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("invalid storage key '{}'", key))
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+// ── Local filesystem ────────────────────────────────────
+
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        std::fs::create_dir_all(&root).log_err("Creating the upload directory");
+        Self { root }
+    }
+
+    /// `<root>/ab/cd/<key>` where `abcd…` is the SHA-256 of the key.
+    fn sharded_path(&self, key: &str) -> PathBuf {
+        let hash = sha256_hex(key.as_bytes());
+        self.root.join(&hash[0..2]).join(&hash[2..4]).join(key)
+    }
+
+    /// Where files lived before sharding.
+    fn flat_path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, data: Vec<u8>, _content_type: &str) -> io::Result<()> {
+        if !is_valid_key(key) {
+            return Err(invalid_key(key));
+        }
+        let path = self.sharded_path(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Write then rename so readers never see a partial file
+        let tmp = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+        if let Err(e) = tokio::fs::write(&tmp, &data).await {
+            tokio::fs::remove_file(&tmp).await.ok();
+            return Err(e);
+        }
+        tokio::fs::rename(&tmp, &path).await
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        if !is_valid_key(key) {
+            return Err(invalid_key(key));
+        }
+        for path in [self.sharded_path(key), self.flat_path(key)] {
+            match tokio::fs::read(&path).await {
+                Ok(data) => return Ok(Some(data)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        if !is_valid_key(key) {
+            return Err(invalid_key(key));
+        }
+        for path in [self.sharded_path(key), self.flat_path(key)] {
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        Ok(self.local_path(key).is_some())
+    }
+
+    async fn list(&self) -> io::Result<Vec<StoredObject>> {
+        let root = self.root.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut objects = Vec::new();
+            // Flat files from before sharding, plus `<root>/ab/cd/<key>`
+            let mut dirs = vec![(root, 0)];
+            while let Some((dir, depth)) = dirs.pop() {
+                for entry in std::fs::read_dir(&dir)? {
+                    let entry = entry?;
+                    let meta = entry.metadata()?;
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    if meta.is_dir() {
+                        if depth < 2 {
+                            dirs.push((entry.path(), depth + 1));
+                        }
+                    } else if meta.is_file() && is_valid_key(&name) && !name.contains(".tmp-") {
+                        objects.push(StoredObject {
+                            key: name,
+                            size: meta.len(),
+                            modified: meta.modified().ok().map(chrono::DateTime::<chrono::Utc>::from),
+                        });
+                    }
+                }
+            }
+            Ok(objects)
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+
+    async fn check_writable(&self) -> io::Result<()> {
+        let probe = self.root.join(format!(".write-check-{}", uuid::Uuid::new_v4()));
+        tokio::fs::write(&probe, b"ok").await?;
+        tokio::fs::remove_file(&probe).await
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        if !is_valid_key(key) {
+            return None;
+        }
+        [self.sharded_path(key), self.flat_path(key)]
+            .into_iter()
+            .find(|path| path.is_file())
+    }
+}
+
+// ── S3-compatible ───────────────────────────────────────
+
+pub struct S3Storage {
+    client: reqwest::Client,
+    endpoint: String,
+    host: String,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    prefix: String,
+}
+
+impl S3Storage {
+    /// Checks `storage.s3`: endpoint, bucket and both keys are required.
+    pub fn from_config(config: &S3Config) -> Result<Self, String> {
+        let require = |value: &str, name: &str| {
+            Some(value.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .ok_or_else(|| format!("{} must be set", name))
+        };
+
+        let endpoint = require(&config.endpoint, "endpoint (S3_ENDPOINT)")?.trim_end_matches('/').to_string();
+        let parsed = reqwest::Url::parse(&endpoint).map_err(|e| format!("endpoint (S3_ENDPOINT): {}", e))?;
+        let host = match (parsed.host_str(), parsed.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err("endpoint (S3_ENDPOINT) has no host".to_string()),
+        };
+        if parsed.path() != "/" && !parsed.path().is_empty() {
+            return Err("endpoint (S3_ENDPOINT) must not contain a path".to_string());
+        }
+
+        let bucket = require(&config.bucket, "bucket (S3_BUCKET)")?;
+        if !bucket.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '.')) {
+            return Err("bucket (S3_BUCKET) contains invalid characters".to_string());
+        }
+
+        let prefix = config.prefix.trim().trim_matches('/').to_string();
+        if !prefix.split('/').all(|segment| segment.is_empty() || is_valid_key(segment)) {
+            return Err("prefix (S3_PREFIX) contains invalid characters".to_string());
+        }
+
+        Ok(Self {
+            client: reqwest::Client::new(),
+            endpoint,
+            host,
+            bucket,
+            region: require(&config.region, "region (S3_REGION)")?,
+            access_key: require(&config.access_key_id, "access_key_id (S3_ACCESS_KEY_ID)")?,
+            secret_key: require(&config.secret_access_key, "secret_access_key (S3_SECRET_ACCESS_KEY)")?,
+            prefix: if prefix.is_empty() { prefix } else { format!("{}/", prefix) },
+        })
+    }
+
+    /// Path-style object path, e.g. `/bucket/prefix/key`.
+    fn object_path(&self, key: &str) -> String {
+        format!("/{}/{}{}", self.bucket, self.prefix, key)
+    }
+
+    /// Send a signed request for `key`. The body is hashed into the signature.
+    async fn request(
+        &self,
+        method: reqwest::Method,
+        key: &str,
+        body: Vec<u8>,
+        content_type: Option<&str>,
+    ) -> io::Result<reqwest::Response> {
+        if !is_valid_key(key) {
+            return Err(invalid_key(key));
+        }
+        self.signed_request(method, &self.object_path(key), "", body, content_type).await
+    }
+
+    /// Send a signed request. `path` and `query` must already be canonical
+    /// (URI-encoded, query parameters sorted by name).
+    async fn signed_request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        query: &str,
+        body: Vec<u8>,
+        content_type: Option<&str>,
+    ) -> io::Result<reqwest::Response> {
+        let payload_hash = sha256_hex(&body);
+        let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let headers = [
+            ("host", self.host.clone()),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        let authorization = sigv4_authorization(
+            &SigV4Request {
+                method: method.as_str(),
+                path,
+                query,
+                headers: &headers,
+                payload_hash: &payload_hash,
+                amz_date: &amz_date,
+            },
+            &self.region,
+            &self.access_key,
+            &self.secret_key,
+        );
+
+        let mut req = self
+            .client
+            .request(method, if query.is_empty() {
+                format!("{}{}", self.endpoint, path)
+            } else {
+                format!("{}{}?{}", self.endpoint, path, query)
+            })
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date)
+            .header("authorization", authorization);
+        if let Some(content_type) = content_type {
+            req = req.header("content-type", content_type);
+        }
+        if !body.is_empty() {
+            req = req.body(body);
+        }
+        req.send().await.map_err(io::Error::other)
+    }
+}
+
+fn s3_error(op: &str, key: &str, status: reqwest::StatusCode) -> io::Error {
+    io::Error::other(format!("S3 {} {} failed with status {}", op, key, status))
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, data: Vec<u8>, content_type: &str) -> io::Result<()> {
+        let res = self.request(reqwest::Method::PUT, key, data, Some(content_type)).await?;
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(s3_error("PUT", key, res.status()))
+        }
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let res = self.request(reqwest::Method::GET, key, Vec::new(), None).await?;
+        match res.status() {
+            s if s.is_success() => Ok(Some(res.bytes().await.map_err(io::Error::other)?.to_vec())),
+            reqwest::StatusCode::NOT_FOUND => Ok(None),
+            s => Err(s3_error("GET", key, s)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        let res = self.request(reqwest::Method::DELETE, key, Vec::new(), None).await?;
+        match res.status() {
+            s if s.is_success() || s == reqwest::StatusCode::NOT_FOUND => Ok(()),
+            s => Err(s3_error("DELETE", key, s)),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        let res = self.request(reqwest::Method::HEAD, key, Vec::new(), None).await?;
+        match res.status() {
+            s if s.is_success() => Ok(true),
+            reqwest::StatusCode::NOT_FOUND => Ok(false),
+            s => Err(s3_error("HEAD", key, s)),
+        }
+    }
+
+    async fn list(&self) -> io::Result<Vec<StoredObject>> {
+        let bucket_path = format!("/{}", self.bucket);
+        let mut objects = Vec::new();
+        let mut continuation: Option<String> = None;
+
+        loop {
+            // Canonical query: parameters sorted by name
+            let mut query = String::new();
+            if let Some(token) = &continuation {
+                query.push_str(&format!("continuation-token={}&", urlencoding::encode(token)));
+            }
+            query.push_str("list-type=2");
+            if !self.prefix.is_empty() {
+                query.push_str(&format!("&prefix={}", urlencoding::encode(&self.prefix)));
+            }
+
+            let res = self
+                .signed_request(reqwest::Method::GET, &bucket_path, &query, Vec::new(), None)
+                .await?;
+            if !res.status().is_success() {
+                return Err(s3_error("LIST", &bucket_path, res.status()));
+            }
+            let body = res.text().await.map_err(io::Error::other)?;
+
+            for contents in xml_elements(&body, "Contents") {
+                let Some(key) = xml_elements(contents, "Key").next() else {
+                    continue;
+                };
+                let Some(key) = key.strip_prefix(self.prefix.as_str()) else {
+                    continue;
+                };
+                if !is_valid_key(key) {
+                    continue;
+                }
+                objects.push(StoredObject {
+                    key: key.to_string(),
+                    size: xml_elements(contents, "Size").next().and_then(|v| v.parse().ok()).unwrap_or(0),
+                    modified: xml_elements(contents, "LastModified")
+                        .next()
+                        .and_then(|v| chrono::DateTime::parse_from_rfc3339(v).ok())
+                        .map(|v| v.with_timezone(&chrono::Utc)),
+                });
+            }
+
+            let truncated = xml_elements(&body, "IsTruncated").next() == Some("true");
+            continuation = xml_elements(&body, "NextContinuationToken").next().map(str::to_string);
+            if !truncated || continuation.is_none() {
+                break;
+            }
+        }
+
+        Ok(objects)
+    }
+
+    async fn check_writable(&self) -> io::Result<()> {
+        // Overwritten on every check rather than deleted, so one object at most
+        let path = self.object_path(".write-check");
+        let res = self
+            .signed_request(reqwest::Method::PUT, &path, "", b"ok".to_vec(), Some("text/plain"))
+            .await?;
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(s3_error("PUT", ".write-check", res.status()))
+        }
+    }
+}
+
+/// Text content of every `<tag>…</tag>` in `xml`. Enough for the flat
+/// ListObjectsV2 response; our keys never need XML unescaping.
+fn xml_elements<'a>(xml: &'a str, tag: &str) -> impl Iterator<Item = &'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut rest = xml;
+    std::iter::from_fn(move || {
+        let start = rest.find(&open)? + open.len();
+        let len = rest[start..].find(&close)?;
+        let value = &rest[start..start + len];
+        rest = &rest[start + len + close.len()..];
+        Some(value)
+    })
+}
+
+// ── AWS Signature Version 4 ─────────────────────────────
+
+struct SigV4Request<'a> {
+    method: &'a str,
+    /// Already URI-encoded
+    path: &'a str,
+    /// Already canonical (sorted, encoded)
+    query: &'a str,
+    /// Lowercase names, sorted by name
+    headers: &'a [(&'a str, String)],
+    payload_hash: &'a str,
+    /// `YYYYMMDDTHHMMSSZ`
+    amz_date: &'a str,
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// `Authorization` header value for an S3 request.
+fn sigv4_authorization(req: &SigV4Request, region: &str, access_key: &str, secret_key: &str) -> String {
+    let date = &req.amz_date[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+
+    let canonical_headers: String = req
+        .headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = req.headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        req.method, req.path, req.query, canonical_headers, signed_headers, req.payload_hash
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        req.amz_date,
+        scope,
+        sha256_hex(canonical_request.as_bytes())
+    );
+
+    let k_date = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    let k_region = hmac_sha256(&k_date, region.as_bytes());
+    let k_service = hmac_sha256(&k_region, b"s3");
+    let k_signing = hmac_sha256(&k_service, b"aws4_request");
+    let signature: String = hmac_sha256(&k_signing, string_to_sign.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key, scope, signed_headers, signature
+    )
+}
@@ -0,0 +1,199 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — Built-in TLS
+// ═══════════════════════════════════════════════════════
+//
+// With `server.tls.cert_file` and `server.tls.key_file` set, the server
+// speaks HTTPS/WSS itself (rustls) instead of relying on a reverse proxy.
+// The PEM files are read at startup and again on SIGHUP, so a renewed
+// certificate (certbot, acme.sh, ...) is picked up without a restart; a
+// reload that fails keeps the previous certificate. When
+// `server.tls.redirect_port` is set, plain HTTP on that port is answered
+// with a redirect to the HTTPS URL. The desktop shell checks readiness
+// over the same TLS listener, trusting only the configured certificate.
+
+use actix_web::{http::header, HttpRequest, HttpResponse};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::ring::{default_provider, sign::any_supported_type};
+use rustls::crypto::WebPkiSupportedAlgorithms;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// Read a certificate chain and its private key from PEM files.
+pub fn load_certified_key(cert_file: &Path, key_file: &Path) -> Result<CertifiedKey, String> {
+    let chain = CertificateDer::pem_file_iter(cert_file)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("cannot read certificate {}: {}", cert_file.display(), e))?;
+    if chain.is_empty() {
+        return Err(format!("no certificate found in {}", cert_file.display()));
+    }
+
+    let key = PrivateKeyDer::from_pem_file(key_file)
+        .map_err(|e| format!("cannot read private key {}: {}", key_file.display(), e))?;
+    let signing_key = any_supported_type(&key)
+        .map_err(|e| format!("unsupported private key {}: {}", key_file.display(), e))?;
+
+    let certified = CertifiedKey::new(chain, signing_key);
+    certified
+        .keys_match()
+        .map_err(|_| format!("{} does not match the certificate", key_file.display()))?;
+    Ok(certified)
+}
+
+/// Serves the current certificate and swaps it on `reload`.
+#[derive(Debug)]
+pub struct CertReloader {
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertReloader {
+    pub fn new(cert_file: &Path, key_file: &Path) -> Result<Self, String> {
+        let key = load_certified_key(cert_file, key_file)?;
+        Ok(CertReloader {
+            cert_file: cert_file.to_path_buf(),
+            key_file: key_file.to_path_buf(),
+            current: RwLock::new(Arc::new(key)),
+        })
+    }
+
+    /// Read the PEM files again; on error the previous certificate stays.
+    pub fn reload(&self) -> Result<(), String> {
+        let key = load_certified_key(&self.cert_file, &self.key_file)?;
+        *self.current.write().unwrap() = Arc::new(key);
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for CertReloader {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+/// rustls configuration serving `reloader`'s certificate. actix-web adds
+/// the `h2` and `http/1.1` ALPN protocols.
+pub fn server_config(reloader: Arc<CertReloader>) -> rustls::ServerConfig {
+    rustls::ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .with_no_client_auth()
+        .with_cert_resolver(reloader)
+}
+
+/// Accepts exactly one certificate, whatever name it was issued for: the
+/// desktop shell reaches its own server on a loopback address.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    certificate: CertificateDer<'static>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if end_entity.as_ref() == self.certificate.as_ref() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(rustls::CertificateError::UnknownIssuer))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// rustls client configuration that only trusts the first certificate of
+/// `cert_file`, for requests the process sends to its own listener.
+pub fn pinned_client_config(cert_file: &Path) -> Result<rustls::ClientConfig, String> {
+    let certificate = CertificateDer::pem_file_iter(cert_file)
+        .and_then(|mut certs| certs.next().transpose())
+        .map_err(|e| format!("cannot read certificate {}: {}", cert_file.display(), e))?
+        .ok_or_else(|| format!("no certificate found in {}", cert_file.display()))?;
+
+    let provider = Arc::new(default_provider());
+    let verifier = PinnedCertVerifier { certificate, algorithms: provider.signature_verification_algorithms };
+    Ok(rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth())
+}
+
+/// Reload the certificate whenever the process receives SIGHUP.
+#[cfg(unix)]
+pub fn spawn_reload_on_sighup(reloader: Arc<CertReloader>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    actix_web::rt::spawn(async move {
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(e) => {
+                tracing::warn!(error = %e, "Cannot listen for SIGHUP, certificate reload disabled");
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            match reloader.reload() {
+                Ok(()) => tracing::info!("TLS certificate reloaded"),
+                Err(e) => tracing::warn!(error = %e, "TLS certificate reload failed, keeping the previous one"),
+            }
+        }
+    });
+}
+
+/// There is no SIGHUP on Windows; restart the server to load a new certificate.
+#[cfg(not(unix))]
+pub fn spawn_reload_on_sighup(_reloader: Arc<CertReloader>) {}
+
+/// Redirect a plain-HTTP request to the same URL over HTTPS on `https_port`.
+/// 308 keeps the method and body, so API clients follow it too.
+pub fn redirect_to_https(req: &HttpRequest, https_port: u16) -> HttpResponse {
+    let info = req.connection_info();
+    let host = info.host();
+    // Drop the port of the Host header (`[v6]:port` keeps its brackets)
+    let hostname = match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    let authority = if https_port == 443 {
+        hostname.to_string()
+    } else {
+        format!("{}:{}", hostname, https_port)
+    };
+    let path = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, format!("https://{}{}", authority, path)))
+        .finish()
+}
@@ -0,0 +1,142 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — Typing indicators
+// ═══════════════════════════════════════════════════════
+//
+// Typing frames are rebroadcast with the identity from the connection's
+// JWT, at most once per user per room every `TYPING_THROTTLE`. The server
+// emits `typing_stop` when the user sends a message, disconnects, or has
+// not sent a typing frame for `TYPING_TIMEOUT`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::ws::Broadcaster;
+
+const TYPING_THROTTLE: Duration = Duration::from_secs(3);
+const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct TypingEntry {
+    username: String,
+    last_broadcast: Instant,
+    last_seen: Instant,
+}
+
+// (user_id, room_id) -> entry
+pub type TypingTracker = Arc<Mutex<HashMap<(String, String), TypingEntry>>>;
+
+pub fn create_typing_tracker() -> TypingTracker {
+    Arc::new(Mutex::new(HashMap::new()))
+}
+
+fn typing_event(kind: &str, room_id: &str, user_id: &str, username: &str) -> String {
+    serde_json::json!({
+        "type": kind,
+        "room_id": room_id,
+        "user_id": user_id,
+        "username": username,
+    })
+    .to_string()
+}
+
+/// Record a typing frame. Broadcasts `typing` unless one went out for this
+/// user and room less than `TYPING_THROTTLE` ago, and starts the expiry
+/// timer the first time the user starts typing in the room.
+pub fn start_typing(tracker: &TypingTracker, broadcaster: &Broadcaster, room_id: &str, user_id: &str, username: &str) {
+    let key = (user_id.to_string(), room_id.to_string());
+    let now = Instant::now();
+
+    let (should_broadcast, is_new) = {
+        let mut guard = tracker.lock().unwrap();
+        match guard.get_mut(&key) {
+            Some(entry) => {
+                entry.last_seen = now;
+                entry.username = username.to_string();
+                if now.duration_since(entry.last_broadcast) >= TYPING_THROTTLE {
+                    entry.last_broadcast = now;
+                    (true, false)
+                } else {
+                    (false, false)
+                }
+            }
+            None => {
+                guard.insert(
+                    key.clone(),
+                    TypingEntry {
+                        username: username.to_string(),
+                        last_broadcast: now,
+                        last_seen: now,
+                    },
+                );
+                (true, true)
+            }
+        }
+    };
+
+    if should_broadcast {
+        let _ = broadcaster.send(typing_event("typing", room_id, user_id, username));
+    }
+
+    if is_new {
+        let tracker = tracker.clone();
+        let broadcaster = broadcaster.clone();
+        actix_web::rt::spawn(async move {
+            loop {
+                let deadline = {
+                    let guard = tracker.lock().unwrap();
+                    match guard.get(&key) {
+                        Some(entry) => entry.last_seen + TYPING_TIMEOUT,
+                        // Already stopped by a message or disconnect
+                        None => return,
+                    }
+                };
+
+                tokio::time::sleep_until(deadline.into()).await;
+
+                let expired = {
+                    let mut guard = tracker.lock().unwrap();
+                    let is_stale = guard
+                        .get(&key)
+                        .is_some_and(|entry| entry.last_seen + TYPING_TIMEOUT <= Instant::now());
+                    if is_stale {
+                        guard.remove(&key)
+                    } else {
+                        None
+                    }
+                };
+
+                if let Some(entry) = expired {
+                    let _ = broadcaster.send(typing_event("typing_stop", &key.1, &key.0, &entry.username));
+                    return;
+                }
+            }
+        });
+    }
+}
+
+/// Stop typing in one room (e.g. the user just sent a message there).
+pub fn stop_typing(tracker: &TypingTracker, broadcaster: &Broadcaster, room_id: &str, user_id: &str) {
+    let removed = {
+        let mut guard = tracker.lock().unwrap();
+        guard.remove(&(user_id.to_string(), room_id.to_string()))
+    };
+
+    if let Some(entry) = removed {
+        let _ = broadcaster.send(typing_event("typing_stop", room_id, user_id, &entry.username));
+    }
+}
+
+/// Stop typing everywhere (e.g. the user disconnected).
+pub fn stop_all_typing(tracker: &TypingTracker, broadcaster: &Broadcaster, user_id: &str) {
+    let removed: Vec<(String, TypingEntry)> = {
+        let mut guard = tracker.lock().unwrap();
+        let keys: Vec<(String, String)> = guard.keys().filter(|(uid, _)| uid == user_id).cloned().collect();
+        keys.into_iter()
+            .filter_map(|key| guard.remove(&key).map(|entry| (key.1, entry)))
+            .collect()
+    };
+
+    for (room_id, entry) in removed {
+        let _ = broadcaster.send(typing_event("typing_stop", &room_id, user_id, &entry.username));
+    }
+}
@@ -0,0 +1,260 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — Orphaned upload sweeper
+// ═══════════════════════════════════════════════════════
+//
+// Files become unreferenced when an upload is never posted, or when bulk
+// deletes (purging a user's messages, deleting a room or a user) drop the
+// rows that pointed at them. The sweeper periodically compares the upload
+// storage with every URL still used by a message, attachment, avatar or
+// banner, and deletes what is left once it is older than the grace period.
+// It also resyncs `upload_blobs` reference counts, which bulk deletes skip.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::auth::extract_claims;
+use crate::config::{Config, UploadsConfig};
+use crate::db::DbPool;
+use crate::errors::{AppError, ErrorBody};
+use crate::logging::LogErr;
+use crate::storage::{SharedStorage, StoredObject};
+use crate::uploads::{source_stem, source_url_range, upload_key};
+
+/// Cutoff in the format of `datetime('now')` columns.
+fn sqlite_cutoff(grace_secs: i64) -> String {
+    (chrono::Utc::now() - chrono::Duration::seconds(grace_secs))
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OrphanedFile {
+    pub key: String,
+    pub size: u64,
+    pub modified: Option<String>,
+    /// Old enough to be removed by the next sweep
+    pub deletable: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StorageReport {
+    pub total_files: usize,
+    pub total_bytes: u64,
+    pub referenced_files: usize,
+    pub referenced_bytes: u64,
+    pub orphaned_files: usize,
+    pub orphaned_bytes: u64,
+    /// Uploads never attached to a message and older than the grace period
+    pub expired_pending_uploads: i64,
+    pub grace_period_secs: i64,
+    pub orphans: Vec<OrphanedFile>,
+}
+
+/// Stems of every upload still in use. Pending uploads only count while
+/// they are younger than the grace period.
+#[tracing::instrument(level = "debug", skip_all)]
+async fn referenced_stems(pool: &DbPool, cutoff: &str) -> Result<HashSet<String>, sqlx::Error> {
+    let urls: Vec<String> = sqlx::query_scalar(
+        "SELECT image_url FROM messages WHERE image_url IS NOT NULL \
+         UNION SELECT url FROM message_attachments WHERE message_id IS NOT NULL OR created_at > $1 \
+         UNION SELECT avatar_url FROM users WHERE avatar_url IS NOT NULL \
+         UNION SELECT banner_url FROM users WHERE banner_url IS NOT NULL"
+    )
+    .bind(cutoff)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(urls
+        .iter()
+        .filter_map(|url| upload_key(url))
+        .map(|key| source_stem(key).to_string())
+        .collect())
+}
+
+/// Re-check a single file right before deleting it, in case it was posted
+/// since the report was built.
+#[tracing::instrument(level = "debug", skip_all, fields(key))]
+async fn is_referenced(pool: &DbPool, key: &str, cutoff: &str) -> bool {
+    let (lower, upper) = source_url_range(key);
+    let found: Result<Option<i64>, sqlx::Error> = sqlx::query_scalar(
+        "SELECT 1 FROM messages WHERE image_url >= $1 AND image_url < $2 \
+         UNION ALL SELECT 1 FROM message_attachments WHERE url >= $1 AND url < $2 \
+           AND (message_id IS NOT NULL OR created_at > $3) \
+         UNION ALL SELECT 1 FROM users WHERE (avatar_url >= $1 AND avatar_url < $2) \
+           OR (banner_url >= $1 AND banner_url < $2) \
+         LIMIT 1"
+    )
+    .bind(&lower)
+    .bind(&upper)
+    .bind(cutoff)
+    .fetch_optional(pool)
+    .await;
+    // When in doubt, keep the file
+    !matches!(found, Ok(None))
+}
+
+fn is_older_than(object: &StoredObject, grace_secs: i64) -> bool {
+    object
+        .modified
+        .is_some_and(|modified| (chrono::Utc::now() - modified).num_seconds() >= grace_secs)
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn build_report(pool: &DbPool, storage: &SharedStorage, grace_secs: i64) -> Result<StorageReport, String> {
+    let cutoff = sqlite_cutoff(grace_secs);
+    let referenced = referenced_stems(pool, &cutoff).await.map_err(|e| e.to_string())?;
+    let objects = storage.list().await.map_err(|e| e.to_string())?;
+    let expired_pending_uploads: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM message_attachments WHERE message_id IS NULL AND created_at <= $1"
+    )
+    .bind(&cutoff)
+    .fetch_one(pool)
+    .await
+    .log_err("Reading message_attachments")
+    .unwrap_or(0);
+
+    let mut report = StorageReport {
+        total_files: objects.len(),
+        total_bytes: objects.iter().map(|o| o.size).sum(),
+        referenced_files: 0,
+        referenced_bytes: 0,
+        orphaned_files: 0,
+        orphaned_bytes: 0,
+        expired_pending_uploads,
+        grace_period_secs: grace_secs,
+        orphans: Vec::new(),
+    };
+
+    for object in objects {
+        if referenced.contains(source_stem(&object.key)) {
+            report.referenced_files += 1;
+            report.referenced_bytes += object.size;
+        } else {
+            report.orphaned_files += 1;
+            report.orphaned_bytes += object.size;
+            report.orphans.push(OrphanedFile {
+                deletable: is_older_than(&object, grace_secs),
+                modified: object.modified.map(|m| m.to_rfc3339()),
+                size: object.size,
+                key: object.key,
+            });
+        }
+    }
+    report.orphans.sort_by_key(|o| std::cmp::Reverse(o.size));
+
+    Ok(report)
+}
+
+/// One sweep: expire never-posted uploads, resync reference counts, then
+/// delete unreferenced files older than the grace period. Blobs referenced
+/// within the grace period keep their count: an upload takes its reference
+/// before the row pointing at the blob is written.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn sweep(pool: &DbPool, storage: &SharedStorage, grace_secs: i64) -> Result<(), String> {
+    let cutoff = sqlite_cutoff(grace_secs);
+
+    let expired = sqlx::query("DELETE FROM message_attachments WHERE message_id IS NULL AND created_at <= $1")
+        .bind(&cutoff)
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .rows_affected();
+    if expired > 0 {
+        tracing::info!("Expired {} upload(s) never attached to a message", expired);
+    }
+
+    sqlx::query(
+        "UPDATE upload_blobs SET ref_count = \
+           (SELECT COUNT(*) FROM messages WHERE image_url = '/uploads/' || upload_blobs.key) \
+         + (SELECT COUNT(*) FROM message_attachments WHERE url = '/uploads/' || upload_blobs.key) \
+         + (SELECT COUNT(*) FROM users WHERE avatar_url = '/uploads/' || upload_blobs.key) \
+         + (SELECT COUNT(*) FROM users WHERE banner_url = '/uploads/' || upload_blobs.key) \
+         WHERE created_at <= $1 AND (retained_at IS NULL OR retained_at <= $1)"
+    )
+    .bind(&cutoff)
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let report = build_report(pool, storage, grace_secs).await?;
+    let mut removed_files = 0;
+    let mut removed_bytes = 0;
+    for orphan in report.orphans.iter().filter(|o| o.deletable) {
+        if is_referenced(pool, &orphan.key, &cutoff).await {
+            continue;
+        }
+        sqlx::query("DELETE FROM upload_blobs WHERE key = $1 AND ref_count <= 0")
+            .bind(&orphan.key)
+            .execute(pool)
+            .await
+            .log_err("Deleting from upload_blobs");
+        match storage.delete(&orphan.key).await {
+            Ok(()) => {
+                tracing::info!("Removed orphaned upload {} ({} bytes)", orphan.key, orphan.size);
+                removed_files += 1;
+                removed_bytes += orphan.size;
+            }
+            Err(e) => tracing::warn!(key = %orphan.key, error = %e, "Failed to remove orphaned upload"),
+        }
+    }
+    if removed_files > 0 {
+        tracing::info!("Upload sweep removed {} file(s), {} bytes", removed_files, removed_bytes);
+    }
+
+    Ok(())
+}
+
+/// Run `sweep` every `uploads.gc_interval_secs` in the background. Files
+/// are removed once unreferenced for `uploads.gc_grace_secs`.
+pub fn spawn_upload_sweeper(pool: DbPool, storage: SharedStorage, config: &UploadsConfig) {
+    let (interval, grace_secs) = (config.gc_interval_secs, config.gc_grace_secs);
+    if interval == 0 {
+        tracing::info!("Upload sweeper disabled");
+        return;
+    }
+
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+            if let Err(e) = sweep(&pool, &storage, grace_secs).await {
+                tracing::warn!(error = %e, "Upload sweep failed");
+            }
+        }
+    });
+}
+
+// ── HTTP Handlers ───────────────────────────────────────
+
+/// GET /api/server/storage — Admin storage report (orphaned files included)
+#[utoipa::path(
+    get,
+    path = "/api/server/storage",
+    tag = "server",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Storage totals and orphaned files", body = StorageReport),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Admin only", body = ErrorBody),
+    )
+)]
+pub async fn get_storage_report(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    storage: web::Data<SharedStorage>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
+
+    if claims.role != "admin" {
+        return Err(AppError::AdminOnly);
+    }
+
+    let report = build_report(pool.get_ref(), storage.get_ref(), config.uploads.gc_grace_secs)
+        .await
+        .map_err(|e| AppError::Internal(format!("Building storage report: {}", e)))?;
+    Ok(HttpResponse::Ok().json(report))
+}
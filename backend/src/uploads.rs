@@ -1,26 +1,927 @@
+use std::io::Cursor;
+
+use actix_files::NamedFile;
 use actix_multipart::Multipart;
-use actix_web::{HttpRequest, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL, Engine as _};
 use futures_util::StreamExt;
-use std::io::Write;
+use hmac::{Hmac, Mac};
+use image::{AnimationDecoder, DynamicImage, Frame, ImageDecoder, ImageFormat, ImageReader, Limits};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::Row;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-use crate::auth::extract_claims;
+use crate::auth::{extract_claims, Claims};
+use crate::config::Config;
+use crate::db::{DbPool, DbRow};
+use crate::errors::{AppError, ErrorBody};
+use crate::logging::LogErr;
+use crate::moderation::db_timestamp;
+use crate::storage::{self, SharedStorage, Storage};
+use crate::ws::{can_user_access_room_cached, AccessCache};
+
+const JPEG_QUALITY: u8 = 85;
+
+/// Longest-edge sizes of the resized copies generated for every upload.
+/// Variants are stored next to the original as `<stem>_<size>.webp`.
+pub const VARIANT_SIZES: [u32; 3] = [64, 256, 1024];
+/// Variant used for chat previews (`thumbnail_url`).
+pub const THUMBNAIL_SIZE: u32 = 256;
+
+/// (longest edge, WebP bytes) for each of `VARIANT_SIZES`
+pub type Variants = Vec<(u32, Vec<u8>)>;
+
+/// Allowed attachment types and their size limits, overridable with
+/// `uploads.allowed_types`. A trailing `/*` matches a whole family.
+const DEFAULT_ALLOWED_TYPES: &str = "image/*=8MB,application/pdf=25MB,text/plain=2MB,\
+application/zip=50MB,application/gzip=50MB,application/x-7z-compressed=50MB,application/x-tar=50MB";
+pub const MAX_ATTACHMENTS_PER_MESSAGE: usize = 10;
+const MAX_FILENAME_CHARS: usize = 255;
+
+/// Formats accepted by the upload pipeline, detected from magic bytes.
+const ALLOWED_FORMATS: [ImageFormat; 5] = [
+    ImageFormat::Png,
+    ImageFormat::Jpeg,
+    ImageFormat::Gif,
+    ImageFormat::WebP,
+    ImageFormat::Bmp,
+];
+
+#[derive(Debug, Clone)]
+pub struct UploadRule {
+    pub mime_type: String,
+    pub max_bytes: usize,
+}
+
+/// Parse `8MB`, `512KB`, `1GB` or a plain byte count.
+pub fn parse_size(raw: &str) -> Option<usize> {
+    let raw = raw.trim().to_uppercase();
+    let (digits, multiplier) = if let Some(n) = raw.strip_suffix("GB") {
+        (n, 1024 * 1024 * 1024)
+    } else if let Some(n) = raw.strip_suffix("MB") {
+        (n, 1024 * 1024)
+    } else if let Some(n) = raw.strip_suffix("KB") {
+        (n, 1024)
+    } else {
+        (raw.strip_suffix('B').unwrap_or(&raw), 1)
+    };
+    digits.trim().parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+pub fn format_size(bytes: usize) -> String {
+    if bytes >= 1024 * 1024 * 1024 && bytes.is_multiple_of(1024 * 1024 * 1024) {
+        format!("{}GB", bytes / (1024 * 1024 * 1024))
+    } else if bytes >= 1024 * 1024 && bytes.is_multiple_of(1024 * 1024) {
+        format!("{}MB", bytes / (1024 * 1024))
+    } else if bytes >= 1024 && bytes.is_multiple_of(1024) {
+        format!("{}KB", bytes / 1024)
+    } else {
+        format!("{}B", bytes)
+    }
+}
+
+/// Allow-list of `mime=size` pairs, comma separated in the environment.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct UploadRules(pub Vec<UploadRule>);
+
+impl std::str::FromStr for UploadRules {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        raw.split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let invalid = || format!("invalid entry '{}' (expected e.g. image/*=8MB)", entry.trim());
+                let (mime_type, size) = entry.split_once('=').ok_or_else(invalid)?;
+                let mime_type = mime_type.trim().to_lowercase();
+                if !mime_type.contains('/') {
+                    return Err(invalid());
+                }
+                Ok(UploadRule {
+                    mime_type,
+                    max_bytes: parse_size(size).ok_or_else(invalid)?,
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map(UploadRules)
+    }
+}
+
+impl TryFrom<String> for UploadRules {
+    type Error = String;
+
+    fn try_from(raw: String) -> Result<Self, Self::Error> {
+        raw.parse()
+    }
+}
+
+impl Default for UploadRules {
+    fn default() -> Self {
+        DEFAULT_ALLOWED_TYPES.parse().expect("default upload rules are valid")
+    }
+}
+
+impl std::ops::Deref for UploadRules {
+    type Target = [UploadRule];
+
+    fn deref(&self) -> &[UploadRule] {
+        &self.0
+    }
+}
+
+/// Size limit for `mime_type`, or `None` if the type is not allowed.
+/// Exact entries win over `family/*` ones.
+pub(crate) fn size_limit_for(rules: &[UploadRule], mime_type: &str) -> Option<usize> {
+    let family = mime_type.split('/').next().unwrap_or_default();
+    rules
+        .iter()
+        .find(|r| r.mime_type == mime_type)
+        .or_else(|| {
+            rules
+                .iter()
+                .find(|r| r.mime_type.strip_suffix("/*").is_some_and(|f| f == family) || r.mime_type == "*/*")
+        })
+        .map(|r| r.max_bytes)
+}
+
+/// Detect the type of an upload from its magic bytes.
+pub(crate) fn sniff_mime(data: &[u8]) -> &'static str {
+    if let Ok(format) = image::guess_format(data) {
+        if ALLOWED_FORMATS.contains(&format) {
+            return format.to_mime_type();
+        }
+    }
+    if data.starts_with(b"%PDF-") {
+        "application/pdf"
+    } else if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+        "application/zip"
+    } else if data.starts_with(&[0x1f, 0x8b]) {
+        "application/gzip"
+    } else if data.starts_with(&[0x37, 0x7a, 0xbc, 0xaf, 0x27, 0x1c]) {
+        "application/x-7z-compressed"
+    } else if data.get(257..262) == Some(b"ustar".as_slice()) {
+        "application/x-tar"
+    } else if !data.is_empty() && !data.contains(&0) && std::str::from_utf8(data).is_ok() {
+        "text/plain"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+fn extension_for_mime(mime_type: &str) -> &'static str {
+    match mime_type {
+        "application/pdf" => "pdf",
+        "application/zip" => "zip",
+        "application/gzip" => "gz",
+        "application/x-7z-compressed" => "7z",
+        "application/x-tar" => "tar",
+        "text/plain" => "txt",
+        _ => "bin",
+    }
+}
+
+/// Keep only the last path component and drop control characters.
+fn sanitize_filename(raw: &str) -> String {
+    let name = raw.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_FILENAME_CHARS)
+        .collect();
+    let name = name.trim();
+    if name.is_empty() || name == "." || name == ".." {
+        "file".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// A file attached to a message.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Attachment {
+    pub id: String,
+    pub filename: String,
+    pub url: String,
+    pub mime_type: String,
+    pub size: i64,
+    pub sha256: String,
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    pub thumbnail_url: Option<String>,
+}
+
+/// Multipart body of the upload endpoints. The first part carrying a file
+/// is used, whatever its name.
+#[derive(ToSchema)]
+pub struct UploadForm {
+    #[schema(value_type = String, format = Binary)]
+    pub file: Vec<u8>,
+}
+
+/// Build an attachment for clients, with signed URLs.
+pub fn attachment_from_row(config: &Config, row: &DbRow) -> Attachment {
+    let url: String = row.try_get("url").unwrap_or_default();
+    // Only processed images have width, and every processed image has variants
+    let width: Option<i64> = row.try_get("width").unwrap_or(None);
+    Attachment {
+        id: row.try_get("id").unwrap_or_default(),
+        filename: row.try_get("filename").unwrap_or_default(),
+        thumbnail_url: width
+            .and_then(|_| thumbnail_url(Some(&url)))
+            .map(|u| sign_upload_url(config, &u)),
+        url: sign_upload_url(config, &url),
+        mime_type: row.try_get("mime_type").unwrap_or_default(),
+        size: row.try_get("size").unwrap_or(0),
+        sha256: row.try_get("sha256").unwrap_or_default(),
+        width,
+        height: row.try_get("height").unwrap_or(None),
+    }
+}
+
+/// Ids from `requested` that `uploader_id` uploaded and that are not yet
+/// attached to a message, in request order, capped per message.
+#[tracing::instrument(level = "debug", skip_all, fields(uploader_id))]
+pub async fn pending_attachment_ids(pool: &DbPool, uploader_id: &str, requested: &[String]) -> Vec<String> {
+    let mut ids: Vec<String> = Vec::new();
+    for id in requested {
+        if ids.len() >= MAX_ATTACHMENTS_PER_MESSAGE {
+            break;
+        }
+        if ids.contains(id) {
+            continue;
+        }
+        let pending: Option<String> = sqlx::query_scalar(
+            "SELECT id FROM message_attachments WHERE id = $1 AND uploader_id = $2 AND message_id IS NULL"
+        )
+        .bind(id)
+        .bind(uploader_id)
+        .fetch_optional(pool)
+        .await
+        .log_err("Reading message_attachments")
+        .flatten();
+        if let Some(id) = pending {
+            ids.push(id);
+        }
+    }
+    ids
+}
+
+/// Attach pending uploads to a freshly stored message and return them.
+#[tracing::instrument(level = "debug", skip_all, fields(uploader_id, message_id))]
+pub async fn claim_attachments(pool: &DbPool, config: &Config, uploader_id: &str, message_id: &str, ids: &[String]) -> Vec<Attachment> {
+    let mut attachments = Vec::with_capacity(ids.len());
+    for id in ids {
+        sqlx::query(
+            "UPDATE message_attachments SET message_id = $1 WHERE id = $2 AND uploader_id = $3 AND message_id IS NULL"
+        )
+        .bind(message_id)
+        .bind(id)
+        .bind(uploader_id)
+        .execute(pool)
+        .await
+        .log_err("Updating message_attachments");
+
+        let row = sqlx::query("SELECT * FROM message_attachments WHERE id = $1 AND message_id = $2")
+            .bind(id)
+            .bind(message_id)
+            .fetch_optional(pool)
+            .await
+            .log_err("Reading message_attachments")
+            .flatten();
+        if let Some(row) = row {
+            attachments.push(attachment_from_row(config, &row));
+        }
+    }
+    attachments
+}
+
+/// Give the sender's oldest pending upload of `url` to a message that shows
+/// it as `image_url`, so the upload stays owned (and keeps its metadata)
+/// instead of expiring with the never-posted ones.
+#[tracing::instrument(level = "debug", skip_all, fields(uploader_id, message_id))]
+pub async fn claim_image_upload(pool: &DbPool, uploader_id: &str, message_id: &str, url: &str) {
+    sqlx::query(
+        "UPDATE message_attachments SET message_id = $1 WHERE id = \
+           (SELECT id FROM message_attachments WHERE uploader_id = $2 AND url = $3 AND message_id IS NULL \
+            ORDER BY created_at LIMIT 1)"
+    )
+    .bind(message_id)
+    .bind(uploader_id)
+    .bind(strip_query(url))
+    .execute(pool)
+    .await
+    .log_err("Updating message_attachments");
+}
+
+/// An uploaded image after validation and re-encoding. The bytes no longer
+/// carry any of the original metadata (EXIF, GPS, comments, ...).
+pub struct ProcessedImage {
+    pub format: ImageFormat,
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub variants: Variants,
+}
+
+impl ProcessedImage {
+    pub fn extension(&self) -> &'static str {
+        self.format.extensions_str().first().copied().unwrap_or("bin")
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        self.format.to_mime_type()
+    }
+}
+
+/// Downscale `img` to each of `VARIANT_SIZES` and encode as WebP. Images
+/// already smaller than a size are re-encoded as-is, so every variant exists.
+pub(crate) fn build_variants(img: &DynamicImage) -> Result<Variants, AppError> {
+    let mut variants = Vec::with_capacity(VARIANT_SIZES.len());
+    for size in VARIANT_SIZES {
+        let resized = if img.width() > size || img.height() > size {
+            img.thumbnail(size, size)
+        } else {
+            img.clone()
+        };
+        let mut out = Cursor::new(Vec::new());
+        encode_webp(&resized, &mut out)?;
+        variants.push((size, out.into_inner()));
+    }
+    Ok(variants)
+}
+
+pub(crate) fn encode_webp(img: &DynamicImage, out: &mut Cursor<Vec<u8>>) -> Result<(), AppError> {
+    // The WebP encoder only takes 8-bit RGB(A)
+    let img = if img.color().has_alpha() {
+        DynamicImage::ImageRgba8(img.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(img.to_rgb8())
+    };
+    img.write_to(out, ImageFormat::WebP).map_err(|_| AppError::ImageProcessingFailed)
+}
+
+pub(crate) fn decode_limits(max_pixels: u64) -> Limits {
+    let mut limits = Limits::default();
+    // 4 bytes per RGBA pixel, plus headroom for the decoder's own buffers
+    limits.max_alloc = Some(max_pixels.saturating_mul(8));
+    limits
+}
+
+/// Sniff the image format and check the declared dimensions before
+/// allocating anything for the pixels. Errors are user-facing.
+pub(crate) fn check_image_header(data: &[u8], max_pixels: u64) -> Result<ImageFormat, AppError> {
+    let format = image::guess_format(data).map_err(|_| AppError::UnsupportedImageFormat)?;
+    if !ALLOWED_FORMATS.contains(&format) {
+        return Err(AppError::UnsupportedImageFormat);
+    }
+
+    let (width, height) = ImageReader::with_format(Cursor::new(data), format)
+        .into_dimensions()
+        .map_err(|_| AppError::InvalidImage)?;
+    if width == 0 || height == 0 {
+        return Err(AppError::InvalidImage);
+    }
+    if u64::from(width) * u64::from(height) > max_pixels {
+        return Err(AppError::ImageTooLarge { max_pixels });
+    }
+    Ok(format)
+}
+
+/// Decode a still image (the first frame of an animation) with its
+/// orientation applied.
+pub(crate) fn decode_image(data: &[u8], format: ImageFormat, max_pixels: u64) -> Result<DynamicImage, AppError> {
+    let mut reader = ImageReader::with_format(Cursor::new(data), format);
+    reader.limits(decode_limits(max_pixels));
+    let mut decoder = reader.into_decoder().map_err(|_| AppError::InvalidImage)?;
+    // The orientation tag is dropped with the rest of the metadata, so bake it in
+    let orientation = decoder.orientation().ok();
+    let mut img = DynamicImage::from_decoder(decoder).map_err(|_| AppError::InvalidImage)?;
+    if let Some(orientation) = orientation {
+        img.apply_orientation(orientation);
+    }
+    Ok(img)
+}
+
+/// Decode every frame of a GIF, capping the pixels summed over all frames.
+pub(crate) fn decode_gif_frames(data: &[u8], max_pixels: u64) -> Result<Vec<Frame>, AppError> {
+    let mut decoder = image::codecs::gif::GifDecoder::new(Cursor::new(data)).map_err(|_| AppError::InvalidImage)?;
+    decoder
+        .set_limits(decode_limits(max_pixels))
+        .map_err(|_| AppError::InvalidImage)?;
+
+    let mut frames = Vec::new();
+    let mut total_pixels: u64 = 0;
+    for frame in decoder.into_frames() {
+        let frame = frame.map_err(|_| AppError::InvalidImage)?;
+        let buffer = frame.buffer();
+        total_pixels += u64::from(buffer.width()) * u64::from(buffer.height());
+        if total_pixels > max_pixels {
+            return Err(AppError::AnimationTooLarge { max_pixels });
+        }
+        frames.push(frame);
+    }
+    if frames.is_empty() {
+        return Err(AppError::InvalidImage);
+    }
+    Ok(frames)
+}
+
+/// Encode frames as an infinitely looping GIF.
+pub(crate) fn encode_gif(frames: Vec<Frame>, out: &mut Cursor<Vec<u8>>) -> Result<(), AppError> {
+    let mut encoder = image::codecs::gif::GifEncoder::new(out);
+    encoder
+        .set_repeat(image::codecs::gif::Repeat::Infinite)
+        .map_err(|_| AppError::ImageProcessingFailed)?;
+    encoder.encode_frames(frames).map_err(|_| AppError::ImageProcessingFailed)
+}
+
+/// Sniff, decode and re-encode an uploaded image. Errors are user-facing.
+pub fn process_image(data: &[u8], max_pixels: u64) -> Result<ProcessedImage, AppError> {
+    let format = check_image_header(data, max_pixels)?;
+
+    let mut out = Cursor::new(Vec::new());
+    let (width, height, variants) = if format == ImageFormat::Gif {
+        // Re-encode every frame so animations survive the metadata strip.
+        // Variants are still images built from the first frame.
+        let frames = decode_gif_frames(data, max_pixels)?;
+        let first = DynamicImage::ImageRgba8(frames[0].buffer().clone());
+        let variants = build_variants(&first)?;
+        encode_gif(frames, &mut out)?;
+        (first.width(), first.height(), variants)
+    } else {
+        let img = decode_image(data, format, max_pixels)?;
+        let encoded = match format {
+            ImageFormat::Jpeg => {
+                let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY);
+                DynamicImage::ImageRgb8(img.to_rgb8())
+                    .write_with_encoder(encoder)
+                    .map_err(|_| AppError::ImageProcessingFailed)
+            }
+            ImageFormat::WebP => encode_webp(&img, &mut out),
+            _ => img.write_to(&mut out, format).map_err(|_| AppError::ImageProcessingFailed),
+        };
+        encoded?;
+        (img.width(), img.height(), build_variants(&img)?)
+    };
+
+    Ok(ProcessedImage {
+        format,
+        data: out.into_inner(),
+        width,
+        height,
+        variants,
+    })
+}
+
+/// Storage key of an `/uploads/<name>` URL, rejecting anything else.
+pub fn upload_key(url: &str) -> Option<&str> {
+    let name = strip_query(url).trim_start_matches('/').strip_prefix("uploads/")?;
+    storage::is_valid_key(name).then_some(name)
+}
+
+pub(crate) fn variant_name(name: &str, size: u32) -> String {
+    let stem = name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(name);
+    format!("{}_{}.webp", stem, size)
+}
+
+/// URL of the `size` variant of an uploaded image. Only images that went
+/// through `process_image` have variants (see `has_variants`).
+pub fn variant_url(image_url: &str, size: u32) -> Option<String> {
+    let key = upload_key(image_url)?;
+    Some(format!("/uploads/{}", variant_name(key, size)))
+}
+
+pub fn thumbnail_url(image_url: Option<&str>) -> Option<String> {
+    variant_url(image_url?, THUMBNAIL_SIZE)
+}
+
+/// Whether resized variants were generated for the upload at `url`:
+/// processed attachments have them, and so does every image blob (all went
+/// through `process_image`). Uploads from before variants existed have none.
+#[tracing::instrument(level = "debug", skip_all, fields(url))]
+pub async fn has_variants(pool: &DbPool, url: &str) -> bool {
+    let Some(key) = upload_key(url) else {
+        return false;
+    };
+    sqlx::query_scalar::<_, i64>(
+        "SELECT 1 FROM message_attachments WHERE url = $1 AND width IS NOT NULL \
+         UNION ALL SELECT 1 FROM upload_blobs WHERE key = $2 AND mime_type LIKE 'image/%' \
+         LIMIT 1"
+    )
+    .bind(format!("/uploads/{}", key))
+    .bind(key)
+    .fetch_optional(pool)
+    .await
+    .log_err("Reading message_attachments")
+    .flatten()
+    .is_some()
+}
+
+/// Delete an uploaded file and its resized variants.
+pub async fn remove_upload(storage: &dyn Storage, url: &str) {
+    let Some(key) = upload_key(url) else {
+        return;
+    };
+    for size in VARIANT_SIZES {
+        storage.delete(&variant_name(key, size)).await.log_err("Deleting upload variant");
+    }
+    storage.delete(key).await.log_err("Deleting upload");
+}
+
+// ── Reference counting ──────────────────────────────────
+//
+// Uploads are stored once under `<sha256>.<ext>`. Each pending upload,
+// message, avatar and banner pointing at a blob holds one reference in
+// `upload_blobs`; the blob and its variants are deleted with the last one.
+// Files from before deduplication have no row and are not counted.
 
-/// POST /api/upload — Upload an image file (authenticated)
+/// Take a reference on a freshly uploaded blob, registering it if new.
+/// Returns the new reference count.
+#[tracing::instrument(level = "debug", skip_all, fields(key))]
+async fn retain_blob(pool: &DbPool, key: &str, sha256: &str, size: i64, mime_type: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "INSERT INTO upload_blobs (key, sha256, size, mime_type, ref_count, retained_at) VALUES ($1, $2, $3, $4, 1, $5) \
+         ON CONFLICT(key) DO UPDATE SET ref_count = upload_blobs.ref_count + 1, retained_at = $5 RETURNING ref_count"
+    )
+    .bind(key)
+    .bind(sha256)
+    .bind(size)
+    .bind(mime_type)
+    .bind(db_timestamp(chrono::Utc::now()))
+    .fetch_one(pool)
+    .await
+}
+
+/// Take a reference on the blob `key` and store it with its variants,
+/// unless an identical blob is already stored. The reference is dropped
+/// again if storing fails.
+#[tracing::instrument(level = "debug", skip_all, fields(key))]
+pub(crate) async fn store_blob(
+    pool: &DbPool,
+    storage: &dyn Storage,
+    key: &str,
+    sha256: &str,
+    data: Vec<u8>,
+    mime_type: &str,
+    variants: Variants,
+) -> Result<(), AppError> {
+    // Reference first, so a concurrent release cannot delete the blob under us
+    let ref_count = retain_blob(pool, key, sha256, data.len() as i64, mime_type).await?;
+
+    if ref_count > 1 && storage.exists(key).await.unwrap_or(false) {
+        return Ok(());
+    }
+    let mut saved = storage.put(key, data, mime_type).await;
+    for (size, data) in variants {
+        if saved.is_ok() {
+            saved = storage.put(&variant_name(key, size), data, "image/webp").await;
+        }
+    }
+    if let Err(e) = saved {
+        tracing::warn!(key, error = %e, "Failed to store upload");
+        release_upload(pool, storage, &format!("/uploads/{}", key)).await;
+        return Err(AppError::UploadFailed);
+    }
+    Ok(())
+}
+
+/// Take a reference on the blob behind `url` (no-op for untracked files).
+#[tracing::instrument(level = "debug", skip_all, fields(url))]
+pub async fn retain_upload(pool: &DbPool, url: &str) {
+    let Some(key) = upload_key(url) else {
+        return;
+    };
+    sqlx::query("UPDATE upload_blobs SET ref_count = ref_count + 1, retained_at = $2 WHERE key = $1")
+        .bind(key)
+        .bind(db_timestamp(chrono::Utc::now()))
+        .execute(pool)
+        .await
+        .log_err("Updating upload_blobs");
+}
+
+/// Drop a reference on the blob behind `url`, deleting it with the last one.
+/// Returns `false` if the file is not reference-counted.
+#[tracing::instrument(level = "debug", skip_all, fields(url))]
+pub async fn release_upload(pool: &DbPool, storage: &dyn Storage, url: &str) -> bool {
+    let Some(key) = upload_key(url) else {
+        return false;
+    };
+    let remaining: Option<i64> = sqlx::query_scalar(
+        "UPDATE upload_blobs SET ref_count = ref_count - 1 WHERE key = $1 RETURNING ref_count"
+    )
+    .bind(key)
+    .fetch_optional(pool)
+    .await
+    .log_err("Updating upload_blobs")
+    .flatten();
+
+    match remaining {
+        None => false,
+        Some(n) if n > 0 => true,
+        Some(_) => {
+            // Re-check the count so an upload racing with us keeps the blob
+            let deleted = sqlx::query("DELETE FROM upload_blobs WHERE key = $1 AND ref_count <= 0")
+                .bind(key)
+                .execute(pool)
+                .await
+                .log_err("Deleting upload_blobs")
+                .is_some_and(|res| res.rows_affected() > 0);
+            if deleted {
+                remove_upload(storage, url).await;
+            }
+            true
+        }
+    }
+}
+
+/// Release a reference, or delete the file outright if it predates
+/// reference counting (the old single-owner behaviour).
+#[tracing::instrument(level = "debug", skip_all, fields(url))]
+pub async fn release_or_remove_upload(pool: &DbPool, storage: &dyn Storage, url: &str) {
+    if !release_upload(pool, storage, url).await {
+        remove_upload(storage, url).await;
+    }
+}
+
+/// Content type for a stored key, from the extension it was stored with.
+fn mime_for_key(key: &str) -> &'static str {
+    let extension = key.rsplit_once('.').map(|(_, ext)| ext).unwrap_or_default();
+    if let Some(format) = ImageFormat::from_extension(extension) {
+        return format.to_mime_type();
+    }
+    match extension {
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "7z" => "application/x-7z-compressed",
+        "tar" => "application/x-tar",
+        "txt" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+// ── Access control ──────────────────────────────────────
+//
+// Avatars and banners are public. Anything else is served to its uploader,
+// to users who can access the room it was posted in (bearer token), or to
+// whoever holds a signed URL: `?expires=<unix>&sig=<HMAC-SHA256(path, expires)>`.
+// Signed URLs let `<img>` tags load attachments without a header.
+
+fn url_signature(config: &Config, path: &str, expires: i64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(config.upload_signing_secret().as_bytes()).expect("HMAC accepts any key length");
+    mac.update(path.as_bytes());
+    mac.update(b"\n");
+    mac.update(expires.to_string().as_bytes());
+    mac
+}
+
+pub fn strip_query(url: &str) -> &str {
+    url.split(['?', '#']).next().unwrap_or(url)
+}
+
+/// Sign an `/uploads/` URL; other URLs are returned unchanged. The expiry is
+/// rounded up to the next TTL window so the URL (and the browser cache
+/// entry) stays stable for between one and two TTLs.
+pub fn sign_upload_url(config: &Config, url: &str) -> String {
+    let path = strip_query(url);
+    if !path.starts_with("/uploads/") {
+        return url.to_string();
+    }
+    let ttl = config.uploads.url_ttl_secs;
+    let expires = (chrono::Utc::now().timestamp() / ttl + 2) * ttl;
+    let sig = BASE64_URL.encode(url_signature(config, path, expires).finalize().into_bytes());
+    format!("{}?expires={}&sig={}", path, expires, sig)
+}
+
+fn verify_signed_url(config: &Config, path: &str, expires: i64, sig: &str) -> bool {
+    if expires < chrono::Utc::now().timestamp() {
+        return false;
+    }
+    let Ok(sig) = BASE64_URL.decode(sig) else {
+        return false;
+    };
+    url_signature(config, path, expires).verify_slice(&sig).is_ok()
+}
+
+/// Whether `user_id` uploaded the file at `url`. Blobs are shared, so this
+/// asks whether any of the user's uploads resolved to it; files from before
+/// deduplication carry the uploader's id in their name.
+#[tracing::instrument(level = "debug", skip_all, fields(url, user_id))]
+pub async fn is_own_upload(pool: &DbPool, url: &str, user_id: &str) -> bool {
+    let Some(key) = upload_key(url) else {
+        return false;
+    };
+    if key.starts_with(&format!("{}_", user_id)) {
+        return true;
+    }
+    sqlx::query_scalar::<_, i64>("SELECT 1 FROM message_attachments WHERE url = $1 AND uploader_id = $2 LIMIT 1")
+        .bind(format!("/uploads/{}", key))
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .log_err("Reading message_attachments")
+        .flatten()
+        .is_some()
+}
+
+/// Name of the original upload without extension, for an original or one
+/// of its variants (`<stem>.<ext>`, `<stem>_<size>.webp`).
+pub fn source_stem(name: &str) -> &str {
+    VARIANT_SIZES
+        .iter()
+        .find_map(|size| {
+            name.strip_suffix(".webp")
+                .and_then(|s| s.strip_suffix(&format!("_{}", size)))
+        })
+        .unwrap_or_else(|| name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(name))
+}
+
+/// `[lower, upper)` bounds matching the original upload and all of its
+/// variants in an indexed range query (`/uploads/<stem>.` .. `/uploads/<stem>/`).
+pub fn source_url_range(name: &str) -> (String, String) {
+    let stem = source_stem(name);
+    (format!("/uploads/{}.", stem), format!("/uploads/{}/", stem))
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(name))]
+async fn can_read_upload(pool: &DbPool, cache: &AccessCache, name: &str, claims: Option<&Claims>) -> bool {
+    let url = format!("/uploads/{}", name);
+    let (lower, upper) = source_url_range(name);
+
+    let public: Option<i64> = sqlx::query_scalar(
+        "SELECT 1 FROM users WHERE avatar_url = $1 OR banner_url = $2 \
+         OR (avatar_url >= $3 AND avatar_url < $4) OR (banner_url >= $5 AND banner_url < $6) LIMIT 1"
+    )
+    .bind(&url)
+    .bind(&url)
+    .bind(&lower)
+    .bind(&upper)
+    .bind(&lower)
+    .bind(&upper)
+    .fetch_optional(pool)
+    .await
+    .log_err("Reading users")
+    .flatten();
+    if public.is_some() {
+        return true;
+    }
+
+    let Some(claims) = claims else {
+        return false;
+    };
+    if name.starts_with(&format!("{}_", claims.sub)) {
+        return true;
+    }
+    let own: Option<i64> = sqlx::query_scalar(
+        "SELECT 1 FROM message_attachments WHERE uploader_id = $1 AND url >= $2 AND url < $3 LIMIT 1"
+    )
+    .bind(&claims.sub)
+    .bind(&lower)
+    .bind(&upper)
+    .fetch_optional(pool)
+    .await
+    .log_err("Reading message_attachments")
+    .flatten();
+    if own.is_some() {
+        return true;
+    }
+
+    let room_ids: Vec<String> = sqlx::query_scalar(
+        "SELECT room_id FROM messages WHERE image_url >= $1 AND image_url < $2 \
+         UNION SELECT m.room_id FROM message_attachments a JOIN messages m ON m.id = a.message_id \
+         WHERE a.url >= $3 AND a.url < $4"
+    )
+    .bind(&lower)
+    .bind(&upper)
+    .bind(&lower)
+    .bind(&upper)
+    .fetch_all(pool)
+    .await
+    .log_err("Reading messages")
+    .unwrap_or_default();
+
+    for room_id in room_ids {
+        if can_user_access_room_cached(pool, cache, &claims.sub, &room_id).await {
+            return true;
+        }
+    }
+    false
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SignedUrlQuery {
+    /// Unix time the signature stops being valid
+    pub expires: Option<String>,
+    pub sig: Option<String>,
+}
+
+/// GET /uploads/{name} — Serve an uploaded file (see access rules above)
+#[utoipa::path(
+    get,
+    path = "/uploads/{name}",
+    tag = "uploads",
+    params(("name" = String, Path, description = "Stored file name"), SignedUrlQuery),
+    responses(
+        (status = 200, description = "The file", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 403, description = "Not signed, and the bearer token (if any) cannot see the file", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+    )
+)]
+pub async fn serve_upload(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    access_cache: web::Data<AccessCache>,
+    storage: web::Data<SharedStorage>,
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    query: web::Query<SignedUrlQuery>,
+) -> Result<HttpResponse, AppError> {
+    let name = path.into_inner();
+    let url = format!("/uploads/{}", name);
+    if !storage::is_valid_key(&name) {
+        return Err(AppError::NotFound);
+    }
+
+    let signed = match (query.expires.as_deref().and_then(|e| e.parse::<i64>().ok()), query.sig.as_deref()) {
+        (Some(expires), Some(sig)) => verify_signed_url(&config, &url, expires, sig),
+        _ => false,
+    };
+    let claims = extract_claims(&req);
+    if !signed && !can_read_upload(pool.get_ref(), access_cache.get_ref(), &name, claims.as_ref()).await {
+        return Err(AppError::AccessDenied);
+    }
+
+    let mut res = if let Some(file_path) = storage.local_path(&name) {
+        match NamedFile::open_async(&file_path).await {
+            Ok(file) => file.into_response(&req),
+            Err(_) => return Err(AppError::NotFound),
+        }
+    } else {
+        match storage.get(&name).await {
+            Ok(Some(data)) => {
+                let mime_type = mime_for_key(&name);
+                let disposition = if mime_type.starts_with("image/") || mime_type.starts_with("text/") {
+                    "inline"
+                } else {
+                    "attachment"
+                };
+                HttpResponse::Ok()
+                    .content_type(mime_type)
+                    .insert_header((actix_web::http::header::CONTENT_DISPOSITION, disposition))
+                    .body(data)
+            }
+            Ok(None) => return Err(AppError::NotFound),
+            Err(e) => return Err(AppError::Internal(format!("Reading upload {}: {}", name, e))),
+        }
+    };
+
+    let headers = res.headers_mut();
+    headers.insert(
+        actix_web::http::header::CACHE_CONTROL,
+        actix_web::http::header::HeaderValue::from_static("private, max-age=3600"),
+    );
+    headers.insert(
+        actix_web::http::header::X_CONTENT_TYPE_OPTIONS,
+        actix_web::http::header::HeaderValue::from_static("nosniff"),
+    );
+    Ok(res)
+}
+
+/// POST /api/upload — Upload an attachment (authenticated). The file is
+/// recorded as a pending attachment until a `message` frame claims it.
+#[utoipa::path(
+    post,
+    path = "/api/upload",
+    tag = "uploads",
+    security(("bearer" = [])),
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "File stored; images also get resized WebP `variants`", body = Object, example = json!({
+            "id": "c7d2…", "url": "/uploads/ab12….png?expires=1700000000&sig=…", "filename": "cat.png",
+            "mime_type": "image/png", "size": 48213, "sha256": "ab12…", "width": 800, "height": 600,
+            "thumbnail_url": "/uploads/ab12…_256.webp?expires=1700000000&sig=…", "variants": {}
+        })),
+        (status = 400, description = "No file, type not allowed, too large or not a valid image", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 413, description = "Role storage quota exceeded", body = ErrorBody),
+        (status = 507, description = "Server storage full", body = ErrorBody),
+    )
+)]
 pub async fn upload_image(
     req: HttpRequest,
+    pool: web::Data<DbPool>,
+    storage: web::Data<SharedStorage>,
+    config: web::Data<Config>,
     mut payload: Multipart,
-) -> HttpResponse {
-    let claims = match extract_claims(&req) {
-        Some(c) => c,
-        None => return HttpResponse::Unauthorized().finish(),
-    };
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
 
-    // Ensure uploads directory exists
-    let upload_dir = std::path::Path::new("uploads");
-    if !upload_dir.exists() {
-        std::fs::create_dir_all(upload_dir).ok();
-    }
+    let rules = &config.uploads.allowed_types;
+    let max_bytes = rules.iter().map(|r| r.max_bytes).max().unwrap_or(0);
 
     while let Some(Ok(mut field)) = payload.next().await {
         let content_disposition = match field.content_disposition() {
@@ -28,69 +929,103 @@ pub async fn upload_image(
             None => continue,
         };
 
-        let original_filename = content_disposition
-            .get_filename()
-            .unwrap_or("file")
-            .to_string();
-
-        // Extract extension
-        let extension = original_filename
-            .rsplit('.')
-            .next()
-            .unwrap_or("png")
-            .to_lowercase();
-
-        // Only allow image types
-        let allowed = ["png", "jpg", "jpeg", "gif", "webp", "bmp"];
-        if !allowed.contains(&extension.as_str()) {
-            return HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Only image files are allowed (png, jpg, jpeg, gif, webp, svg, bmp)"
-            }));
-        }
+        let original_filename = sanitize_filename(content_disposition.get_filename().unwrap_or("file"));
 
-        // Generate unique filename
-        let filename = format!("{}_{}.{}", claims.sub, Uuid::new_v4(), extension);
-        let filepath = upload_dir.join(&filename);
-
-        // Write file
-        let mut file = match std::fs::File::create(&filepath) {
-            Ok(f) => f,
-            Err(_) => {
-                return HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": "Failed to save file"
-                }));
+        let mut data: Vec<u8> = Vec::new();
+        while let Some(Ok(chunk)) = field.next().await {
+            if data.len() + chunk.len() > max_bytes {
+                return Err(AppError::FileTooLarge { max_bytes, mime_type: None });
             }
+            data.extend_from_slice(&chunk);
+        }
+
+        // The type comes from the content, never from the client's filename
+        let mime_type = sniff_mime(&data);
+        let Some(limit) = size_limit_for(rules, mime_type) else {
+            return Err(AppError::FileTypeNotAllowed { mime_type });
         };
+        if data.len() > limit {
+            return Err(AppError::FileTooLarge { max_bytes: limit, mime_type: Some(mime_type) });
+        }
 
-        let mut total_size: usize = 0;
-        let max_size: usize = 8 * 1024 * 1024; // 8MB limit
+        let (stored, extension, width, height, variants) = if mime_type.starts_with("image/") {
+            // Decoding is CPU-bound, keep it off the async workers
+            let max_pixels = config.uploads.max_pixels;
+            let p = web::block(move || process_image(&data, max_pixels))
+                .await
+                .map_err(|_| AppError::ImageProcessingFailed)??;
+            let extension = p.extension();
+            (p.data, extension, Some(p.width), Some(p.height), p.variants)
+        } else {
+            (data, extension_for_mime(mime_type), None, None, Vec::new())
+        };
+        // Content-addressed: identical files share one blob
+        let size = stored.len() as i64;
+        let sha256 = format!("{:x}", Sha256::digest(&stored));
+        let filename = format!("{}.{}", sha256, extension);
+        let url = format!("/uploads/{}", filename);
 
-        while let Some(Ok(chunk)) = field.next().await {
-            total_size += chunk.len();
-            if total_size > max_size {
-                // Clean up partial file
-                drop(file);
-                std::fs::remove_file(&filepath).ok();
-                return HttpResponse::BadRequest().json(serde_json::json!({
-                    "error": "File too large (max 8MB)"
-                }));
-            }
-            if file.write_all(&chunk).is_err() {
-                return HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": "Failed to write file"
-                }));
+        crate::quotas::check_upload_quota(pool.get_ref(), &config, &claims.sub, &filename, size, None).await?;
+        store_blob(pool.get_ref(), storage.get_ref().as_ref(), &filename, &sha256, stored, mime_type, variants).await?;
+
+        let mut variant_urls = serde_json::Map::new();
+        if width.is_some() {
+            for size in VARIANT_SIZES {
+                let variant = format!("/uploads/{}", variant_name(&filename, size));
+                variant_urls.insert(size.to_string(), serde_json::json!(sign_upload_url(&config, &variant)));
             }
         }
 
+        let attachment = Attachment {
+            id: Uuid::new_v4().to_string(),
+            filename: original_filename,
+            url: url.clone(),
+            mime_type: mime_type.to_string(),
+            size,
+            sha256,
+            width: width.map(i64::from),
+            height: height.map(i64::from),
+            thumbnail_url: variant_urls
+                .get(&THUMBNAIL_SIZE.to_string())
+                .and_then(|v| v.as_str())
+                .map(str::to_string),
+        };
+
+        let inserted = sqlx::query(
+            "INSERT INTO message_attachments (id, uploader_id, filename, url, mime_type, size, sha256, width, height) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"
+        )
+        .bind(&attachment.id)
+        .bind(&claims.sub)
+        .bind(&attachment.filename)
+        .bind(&attachment.url)
+        .bind(&attachment.mime_type)
+        .bind(attachment.size)
+        .bind(&attachment.sha256)
+        .bind(attachment.width)
+        .bind(attachment.height)
+        .execute(pool.get_ref())
+        .await;
+
+        if inserted.is_err() {
+            release_upload(pool.get_ref(), storage.get_ref().as_ref(), &url).await;
+            return Err(AppError::UploadFailed);
+        }
+
         // Return the URL to the uploaded file
-        let url = format!("/uploads/{}", filename);
-        return HttpResponse::Ok().json(serde_json::json!({
-            "url": url,
-            "filename": original_filename
-        }));
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "id": attachment.id,
+            "url": sign_upload_url(&config, &attachment.url),
+            "filename": attachment.filename,
+            "mime_type": attachment.mime_type,
+            "size": attachment.size,
+            "sha256": attachment.sha256,
+            "width": attachment.width,
+            "height": attachment.height,
+            "thumbnail_url": attachment.thumbnail_url,
+            "variants": variant_urls
+        })));
     }
 
-    HttpResponse::BadRequest().json(serde_json::json!({
-        "error": "No file provided"
-    }))
+    Err(AppError::NoFileProvided)
 }
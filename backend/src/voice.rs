@@ -0,0 +1,664 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — Voice room state & moderation
+// ═══════════════════════════════════════════════════════
+//
+// The server keeps track of who is connected to which voice room so that
+// moderators can act on participants: server mute / server deafen, move
+// to another voice room, or disconnect. Moderator-imposed flags are
+// authoritative: a client cannot clear them with its own `voice_state`.
+//
+// Rooms may set a `user_limit`. A join over the limit is refused with
+// `voice_full`, or — if the client asked to queue — parked on a waiting
+// list. When a slot frees up, the next queued user gets a short
+// reservation and a `voice_queue_ready` event telling it to join.
+//
+// It also hands out ICE servers with short-lived TURN credentials
+// (TURN REST API / coturn `use-auth-secret`), so no static TURN password
+// has to ship with the client.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use sha1::Sha1;
+use sqlx::Row;
+
+use crate::db::DbPool;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::auth::extract_claims;
+use crate::config::Config;
+use crate::errors::{AppError, ErrorBody};
+use crate::logging::LogErr;
+use crate::ws::{can_user_access_room_cached, AccessCache, Broadcaster};
+
+/// How long a freed slot is held for a promoted queued user.
+const QUEUE_RESERVATION_TTL: Duration = Duration::from_secs(30);
+
+// ── Types ───────────────────────────────────────────────
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VoiceMember {
+    pub user_id: String,
+    pub username: String,
+    pub room_id: String,
+    pub muted: bool,
+    pub deafened: bool,
+    pub server_muted: bool,
+    pub server_deafened: bool,
+    /// WebSocket connection that joined, so a stale socket closing does not
+    /// evict a newer session of the same user.
+    #[serde(skip)]
+    pub conn_id: String,
+}
+
+#[derive(Default)]
+pub struct VoiceStatesState {
+    // user_id -> current voice membership
+    pub members: HashMap<String, VoiceMember>,
+    // Moderator flags survive leave/rejoin until lifted
+    pub server_muted: HashSet<String>,
+    pub server_deafened: HashSet<String>,
+    // room_id -> user_limit (0 = unlimited), refreshed on join and room update
+    pub room_limits: HashMap<String, i64>,
+    // room_id -> users waiting for a free slot, in arrival order
+    waiting: HashMap<String, VecDeque<QueuedUser>>,
+    // user_id -> (room_id, granted_at) slot held for a promoted user
+    reservations: HashMap<String, (String, Instant)>,
+}
+
+#[derive(Debug, Clone)]
+struct QueuedUser {
+    user_id: String,
+    conn_id: String,
+}
+
+impl VoiceStatesState {
+    fn prune_reservations(&mut self) {
+        self.reservations
+            .retain(|_, (_, granted_at)| granted_at.elapsed() < QUEUE_RESERVATION_TTL);
+    }
+
+    /// Participants plus held reservations in `room_id`, not counting `exclude_user`.
+    fn occupancy(&self, room_id: &str, exclude_user: Option<&str>) -> usize {
+        let members = self
+            .members
+            .values()
+            .filter(|m| m.room_id == room_id && Some(m.user_id.as_str()) != exclude_user)
+            .count();
+        let reserved = self
+            .reservations
+            .iter()
+            .filter(|(uid, (rid, _))| rid == room_id && Some(uid.as_str()) != exclude_user)
+            .count();
+        members + reserved
+    }
+
+    fn is_full(&self, room_id: &str, exclude_user: Option<&str>) -> bool {
+        let limit = self.room_limits.get(room_id).copied().unwrap_or(0);
+        limit > 0 && self.occupancy(room_id, exclude_user) >= limit as usize
+    }
+
+    fn dequeue(&mut self, keep: impl Fn(&QueuedUser) -> bool) {
+        for queue in self.waiting.values_mut() {
+            queue.retain(&keep);
+        }
+        self.waiting.retain(|_, queue| !queue.is_empty());
+    }
+}
+
+/// A client's request to enter a voice room.
+pub struct VoiceJoinRequest<'a> {
+    pub conn_id: &'a str,
+    pub user_id: &'a str,
+    pub username: &'a str,
+    pub room_id: &'a str,
+    pub muted: bool,
+    pub deafened: bool,
+    /// Admins and roles with `move_members` ignore the room's user limit.
+    pub bypass_limit: bool,
+    /// Wait for a free slot instead of being refused outright.
+    pub queue: bool,
+}
+
+pub enum VoiceJoinOutcome {
+    Joined {
+        member: VoiceMember,
+        previous_room: Option<String>,
+    },
+    /// The room is at capacity. `position` is the 1-based place in the
+    /// waiting list when the client asked to queue.
+    Full { user_limit: i64, position: Option<usize> },
+}
+
+pub type VoiceStates = Arc<Mutex<VoiceStatesState>>;
+
+pub fn create_voice_states() -> VoiceStates {
+    Arc::new(Mutex::new(VoiceStatesState::default()))
+}
+
+/// Why a client-requested voice state change was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoiceStateRejection {
+    ServerMuted,
+    ServerDeafened,
+}
+
+impl VoiceStateRejection {
+    pub fn code(self) -> &'static str {
+        match self {
+            VoiceStateRejection::ServerMuted => "server_muted",
+            VoiceStateRejection::ServerDeafened => "server_deafened",
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            VoiceStateRejection::ServerMuted => "You have been muted by a moderator",
+            VoiceStateRejection::ServerDeafened => "You have been deafened by a moderator",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VoiceModerationPayload {
+    pub server_muted: Option<bool>,
+    pub server_deafened: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VoiceMovePayload {
+    pub room_id: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct IceServer {
+    pub urls: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+}
+
+// ── ICE / TURN configuration ────────────────────────────
+
+/// TURN REST API credentials: the username is `<expiry unix ts>:<user id>`
+/// and the password is base64(HMAC-SHA1(secret, username)). The TURN server
+/// recomputes the HMAC with the same shared secret and rejects expired names.
+pub fn turn_credentials(secret: &str, user_id: &str, expires_at: i64) -> (String, String) {
+    let username = format!("{}:{}", expires_at, user_id);
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(username.as_bytes());
+    let credential = BASE64.encode(mac.finalize().into_bytes());
+    (username, credential)
+}
+
+// ── State helpers (used by the WS handler) ──────────────
+
+pub fn set_room_user_limit(states: &VoiceStates, room_id: &str, user_limit: i64) {
+    let mut guard = states.lock().unwrap();
+    guard.room_limits.insert(room_id.to_string(), user_limit);
+}
+
+/// Register a user in a voice room, subject to the room's user limit.
+/// Moderator flags are applied on top of the client's own mute/deafen.
+/// Users already in the room, or holding a reservation for it, always get in.
+pub fn join_voice_room(states: &VoiceStates, join: &VoiceJoinRequest) -> VoiceJoinOutcome {
+    let mut guard = states.lock().unwrap();
+    guard.prune_reservations();
+
+    let already_in_room = guard
+        .members
+        .get(join.user_id)
+        .is_some_and(|m| m.room_id == join.room_id);
+    let has_reservation = guard
+        .reservations
+        .get(join.user_id)
+        .is_some_and(|(rid, _)| rid == join.room_id);
+
+    if !already_in_room && !has_reservation && !join.bypass_limit && guard.is_full(join.room_id, Some(join.user_id)) {
+        let user_limit = guard.room_limits.get(join.room_id).copied().unwrap_or(0);
+        if !join.queue {
+            return VoiceJoinOutcome::Full { user_limit, position: None };
+        }
+
+        let queue = guard.waiting.entry(join.room_id.to_string()).or_default();
+        let position = match queue.iter().position(|q| q.user_id == join.user_id) {
+            Some(idx) => idx + 1,
+            None => {
+                queue.push_back(QueuedUser {
+                    user_id: join.user_id.to_string(),
+                    conn_id: join.conn_id.to_string(),
+                });
+                queue.len()
+            }
+        };
+        return VoiceJoinOutcome::Full {
+            user_limit,
+            position: Some(position),
+        };
+    }
+
+    guard.reservations.remove(join.user_id);
+    guard.dequeue(|q| q.user_id != join.user_id);
+
+    let server_muted = guard.server_muted.contains(join.user_id);
+    let server_deafened = guard.server_deafened.contains(join.user_id);
+
+    let member = VoiceMember {
+        user_id: join.user_id.to_string(),
+        username: join.username.to_string(),
+        room_id: join.room_id.to_string(),
+        muted: join.muted || server_muted,
+        deafened: join.deafened || server_deafened,
+        server_muted,
+        server_deafened,
+        conn_id: join.conn_id.to_string(),
+    };
+
+    let previous_room = guard
+        .members
+        .insert(join.user_id.to_string(), member.clone())
+        .map(|m| m.room_id)
+        .filter(|rid| rid != join.room_id);
+
+    VoiceJoinOutcome::Joined { member, previous_room }
+}
+
+/// Hand free slots in `room_id` to queued users, reserving each slot for
+/// `QUEUE_RESERVATION_TTL`, and tell the promoted users (only them) to join.
+pub fn promote_waiting(states: &VoiceStates, broadcaster: &Broadcaster, room_id: &str) {
+    let promoted = {
+        let mut guard = states.lock().unwrap();
+        guard.prune_reservations();
+
+        let mut promoted = Vec::new();
+        while !guard.is_full(room_id, None) {
+            let Some(next) = guard.waiting.get_mut(room_id).and_then(|q| q.pop_front()) else {
+                break;
+            };
+            guard
+                .reservations
+                .insert(next.user_id.clone(), (room_id.to_string(), Instant::now()));
+            promoted.push(next.user_id);
+        }
+        guard.waiting.retain(|_, queue| !queue.is_empty());
+        promoted
+    };
+
+    for user_id in promoted {
+        let event = serde_json::json!({
+            "type": "voice_queue_ready",
+            "room_id": room_id,
+            "user_id": user_id,
+            "target_user_id": user_id,
+            "expires_in": QUEUE_RESERVATION_TTL.as_secs(),
+        });
+        let _ = broadcaster.send(event.to_string());
+    }
+}
+
+/// Apply a client-requested mute/deafen change. Fails when the client tries
+/// to lift a flag a moderator has imposed.
+pub fn update_voice_state(
+    states: &VoiceStates,
+    user_id: &str,
+    muted: bool,
+    deafened: bool,
+) -> Result<Option<VoiceMember>, VoiceStateRejection> {
+    let mut guard = states.lock().unwrap();
+    if guard.server_muted.contains(user_id) && !muted {
+        return Err(VoiceStateRejection::ServerMuted);
+    }
+    if guard.server_deafened.contains(user_id) && !deafened {
+        return Err(VoiceStateRejection::ServerDeafened);
+    }
+
+    Ok(guard.members.get_mut(user_id).map(|member| {
+        member.muted = muted;
+        member.deafened = deafened;
+        member.clone()
+    }))
+}
+
+/// Remove `user_id` from voice and from any waiting list. When `conn_id` is
+/// given, only the membership and queue entries created by that connection
+/// are removed.
+pub fn leave_voice_room(states: &VoiceStates, user_id: &str, conn_id: Option<&str>) -> Option<VoiceMember> {
+    let mut guard = states.lock().unwrap();
+    match conn_id {
+        Some(cid) => {
+            guard.dequeue(|q| q.user_id != user_id || q.conn_id != cid);
+            if guard.members.get(user_id).map(|m| m.conn_id.as_str()) != Some(cid) {
+                return None;
+            }
+        }
+        None => guard.dequeue(|q| q.user_id != user_id),
+    }
+    guard.members.remove(user_id)
+}
+
+/// Room a `voice_signal` from this connection belongs to: the sender must be
+/// in voice from that same connection, and the target must be in the same
+/// room. Mute flags do not matter here: a server-muted member still has to
+/// negotiate to hear the room, and its client keeps the microphone off.
+pub fn signal_room(states: &VoiceStates, user_id: &str, conn_id: &str, target_user_id: &str) -> Option<String> {
+    let guard = states.lock().unwrap();
+    let sender = guard.members.get(user_id).filter(|m| m.conn_id == conn_id)?;
+    let target = guard.members.get(target_user_id)?;
+    (target.room_id == sender.room_id).then(|| sender.room_id.clone())
+}
+
+/// The user limit of `room_id`, or `None` when it is not a voice room.
+#[tracing::instrument(level = "debug", skip_all, fields(room_id))]
+pub async fn fetch_voice_room_limit(pool: &DbPool, room_id: &str) -> Option<i64> {
+    sqlx::query_scalar::<_, i64>("SELECT user_limit FROM rooms WHERE id = $1 AND kind = 'voice'")
+        .bind(room_id)
+        .fetch_optional(pool)
+        .await
+        .log_err("Reading rooms")
+        .flatten()
+}
+
+/// Admins and roles flagged `move_members` may move participants and
+/// ignore voice room limits.
+#[tracing::instrument(level = "debug", skip_all, fields(role))]
+pub async fn can_move_members(pool: &DbPool, role: &str) -> bool {
+    if role == "admin" {
+        return true;
+    }
+
+    sqlx::query_scalar::<_, i64>("SELECT move_members FROM roles WHERE name = $1")
+        .bind(role)
+        .fetch_optional(pool)
+        .await
+        .log_err("Reading roles")
+        .flatten()
+        .unwrap_or(0)
+        != 0
+}
+
+fn voice_state_event(member: &VoiceMember) -> serde_json::Value {
+    serde_json::json!({
+        "type": "voice_state",
+        "room_id": member.room_id,
+        "user_id": member.user_id,
+        "username": member.username,
+        "muted": member.muted,
+        "deafened": member.deafened,
+        "server_muted": member.server_muted,
+        "server_deafened": member.server_deafened,
+    })
+}
+
+// ── HTTP Handlers ───────────────────────────────────────
+
+/// GET /api/voice/ice-servers — STUN/TURN servers with short-lived TURN credentials
+#[utoipa::path(
+    get,
+    path = "/api/voice/ice-servers",
+    tag = "voice",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "ICE servers for `RTCPeerConnection`, and how long the TURN credentials last", body = Object, example = json!({
+            "ice_servers": [{ "urls": ["stun:stun.l.google.com:19302"] }], "ttl": 86400
+        })),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+    )
+)]
+pub async fn get_ice_servers(req: HttpRequest, config: web::Data<Config>) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
+
+    let voice = &config.voice;
+    let ttl = voice.turn_credential_ttl_secs;
+    let mut ice_servers = Vec::new();
+
+    if !voice.stun_urls.is_empty() {
+        ice_servers.push(IceServer {
+            urls: voice.stun_urls.clone(),
+            username: None,
+            credential: None,
+        });
+    }
+
+    // `Config::load` refuses TURN URLs without a secret
+    if let (false, Some(secret)) = (voice.turn_urls.is_empty(), voice.turn_secret.as_deref()) {
+        let expires_at = chrono::Utc::now().timestamp() + ttl;
+        let (username, credential) = turn_credentials(secret, &claims.sub, expires_at);
+        ice_servers.push(IceServer {
+            urls: voice.turn_urls.clone(),
+            username: Some(username),
+            credential: Some(credential),
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "ice_servers": ice_servers,
+        "ttl": ttl,
+    })))
+}
+
+/// PATCH /api/voice/members/{user_id} — Server mute / deafen (Admin only)
+/// Body: { server_muted?, server_deafened? }
+#[utoipa::path(
+    patch,
+    path = "/api/voice/members/{user_id}",
+    tag = "voice",
+    security(("bearer" = [])),
+    params(("user_id" = String, Path, description = "User id of the participant")),
+    request_body = VoiceModerationPayload,
+    responses(
+        (status = 200, description = "New server mute/deafen state", body = Object, example = json!({
+            "user_id": "3f0c…", "server_muted": true, "server_deafened": false, "in_voice": true
+        })),
+        (status = 400, description = "Nothing to update", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Admin only", body = ErrorBody),
+    )
+)]
+pub async fn moderate_voice_member(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<VoiceModerationPayload>,
+    voice_states: web::Data<VoiceStates>,
+    broadcaster: web::Data<Broadcaster>,
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
+
+    if claims.role != "admin" {
+        return Err(AppError::AdminOnly);
+    }
+
+    if body.server_muted.is_none() && body.server_deafened.is_none() {
+        return Err(AppError::NothingToUpdate);
+    }
+
+    let target_id = path.into_inner();
+
+    let (server_muted, server_deafened, member) = {
+        let mut guard = voice_states.lock().unwrap();
+        if let Some(value) = body.server_muted {
+            if value {
+                guard.server_muted.insert(target_id.clone());
+            } else {
+                guard.server_muted.remove(&target_id);
+            }
+        }
+        if let Some(value) = body.server_deafened {
+            if value {
+                guard.server_deafened.insert(target_id.clone());
+            } else {
+                guard.server_deafened.remove(&target_id);
+            }
+        }
+
+        let server_muted = guard.server_muted.contains(&target_id);
+        let server_deafened = guard.server_deafened.contains(&target_id);
+        let member = guard.members.get_mut(&target_id).map(|member| {
+            member.server_muted = server_muted;
+            member.server_deafened = server_deafened;
+            member.muted = member.muted || server_muted;
+            member.deafened = member.deafened || server_deafened;
+            member.clone()
+        });
+        (server_muted, server_deafened, member)
+    };
+
+    if let Some(ref member) = member {
+        let _ = broadcaster.send(voice_state_event(member).to_string());
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "user_id": target_id,
+        "server_muted": server_muted,
+        "server_deafened": server_deafened,
+        "in_voice": member.is_some(),
+    })))
+}
+
+/// POST /api/voice/members/{user_id}/move — Move a participant to another voice room
+/// (Admin or `move_members` role). Moves ignore the destination's user limit.
+/// Body: { room_id }
+#[utoipa::path(
+    post,
+    path = "/api/voice/members/{user_id}/move",
+    tag = "voice",
+    security(("bearer" = [])),
+    params(("user_id" = String, Path, description = "User id of the participant")),
+    request_body = VoiceMovePayload,
+    responses(
+        (status = 200, description = "Participant moved (`unchanged` when already there)", body = Object, example = json!({ "status": "moved", "room_id": "9b1e…" })),
+        (status = 400, description = "Destination is not a voice room", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Missing the `move_members` permission, or the participant cannot access the destination", body = ErrorBody),
+        (status = 404, description = "No such room, or the user is not in a voice room", body = ErrorBody),
+    )
+)]
+pub async fn move_voice_member(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+    body: web::Json<VoiceMovePayload>,
+    voice_states: web::Data<VoiceStates>,
+    broadcaster: web::Data<Broadcaster>,
+    access_cache: web::Data<AccessCache>,
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
+
+    if !can_move_members(pool.get_ref(), &claims.role).await {
+        return Err(AppError::MoveMembersRequired);
+    }
+
+    let target_id = path.into_inner();
+    let dest_room_id = body.room_id.trim().to_string();
+
+    let room_row = sqlx::query("SELECT kind FROM rooms WHERE id = $1")
+        .bind(&dest_room_id)
+        .fetch_optional(pool.get_ref())
+        .await?
+        .ok_or(AppError::RoomNotFound)?;
+    let kind: String = room_row.try_get("kind").unwrap_or_default();
+    if kind != "voice" {
+        return Err(AppError::NotVoiceRoom);
+    }
+
+    if !can_user_access_room_cached(pool.get_ref(), access_cache.get_ref(), &target_id, &dest_room_id).await {
+        return Err(AppError::RoomAccessDenied);
+    }
+
+    let moved = {
+        let mut guard = voice_states.lock().unwrap();
+        guard.members.get_mut(&target_id).map(|member| {
+            let from_room_id = std::mem::replace(&mut member.room_id, dest_room_id.clone());
+            (from_room_id, member.clone())
+        })
+    };
+
+    let (from_room_id, member) = moved.ok_or(AppError::NotInVoice)?;
+
+    if from_room_id == dest_room_id {
+        return Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "unchanged" })));
+    }
+
+    promote_waiting(voice_states.get_ref(), broadcaster.get_ref(), &from_room_id);
+
+    // Peers in the old room drop the connection...
+    let leave_event = serde_json::json!({
+        "type": "voice_leave",
+        "room_id": from_room_id,
+        "user_id": member.user_id,
+        "username": member.username,
+    });
+    let _ = broadcaster.send(leave_event.to_string());
+
+    // ...and the moved client re-joins the destination room.
+    let move_event = serde_json::json!({
+        "type": "voice_move",
+        "room_id": dest_room_id,
+        "from_room_id": from_room_id,
+        "user_id": member.user_id,
+        "username": member.username,
+        "muted": member.muted,
+        "deafened": member.deafened,
+        "server_muted": member.server_muted,
+        "server_deafened": member.server_deafened,
+    });
+    let _ = broadcaster.send(move_event.to_string());
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "moved", "room_id": dest_room_id })))
+}
+
+/// DELETE /api/voice/members/{user_id} — Disconnect a participant from voice (Admin only)
+#[utoipa::path(
+    delete,
+    path = "/api/voice/members/{user_id}",
+    tag = "voice",
+    security(("bearer" = [])),
+    params(("user_id" = String, Path, description = "User id of the participant")),
+    responses(
+        (status = 200, description = "Participant disconnected", body = Object, example = json!({ "status": "disconnected" })),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 403, description = "Admin only", body = ErrorBody),
+        (status = 404, description = "The user is not in a voice room", body = ErrorBody),
+    )
+)]
+pub async fn disconnect_voice_member(
+    req: HttpRequest,
+    path: web::Path<String>,
+    voice_states: web::Data<VoiceStates>,
+    broadcaster: web::Data<Broadcaster>,
+) -> Result<HttpResponse, AppError> {
+    let claims = extract_claims(&req).ok_or(AppError::Unauthenticated)?;
+
+    if claims.role != "admin" {
+        return Err(AppError::AdminOnly);
+    }
+
+    let target_id = path.into_inner();
+
+    let member = leave_voice_room(voice_states.get_ref(), &target_id, None).ok_or(AppError::NotInVoice)?;
+
+    // Tell the affected client to tear down its media...
+    let disconnect_event = serde_json::json!({
+        "type": "voice_disconnect",
+        "room_id": member.room_id,
+        "user_id": member.user_id,
+    });
+    let _ = broadcaster.send(disconnect_event.to_string());
+
+    // ...and everyone else that the participant is gone.
+    let leave_event = serde_json::json!({
+        "type": "voice_leave",
+        "room_id": member.room_id,
+        "user_id": member.user_id,
+        "username": member.username,
+    });
+    let _ = broadcaster.send(leave_event.to_string());
+
+    promote_waiting(voice_states.get_ref(), broadcaster.get_ref(), &member.room_id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "disconnected" })))
+}
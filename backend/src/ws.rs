@@ -1,13 +1,22 @@
 use actix_web::{web, HttpRequest, HttpResponse};
-use actix_ws::Message;
+use actix_ws::{CloseCode, Message};
 use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
-use sqlx::SqlitePool;
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::logging::LogErr;
+use crate::metrics::SharedMetrics;
+use crate::moderation::{is_banned, BannedUsers};
 use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 use tokio::sync::broadcast;
+use tracing::Instrument;
 use uuid::Uuid;
 
+use crate::presence::{self, PresenceUpdate, UserPresence};
+use crate::typing::{self, TypingTracker};
+use crate::voice::{self, VoiceStates};
+
 /// Represents a chat message sent/received over WebSocket.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WsMessage {
@@ -20,14 +29,31 @@ pub struct WsMessage {
     pub reply_to_id: Option<String>,
     pub avatar_color: Option<i32>,
     pub image_url: Option<String>,
+    #[serde(skip_deserializing, default)]
+    pub thumbnail_url: Option<String>,
+    /// On `message`: ids returned by `POST /api/upload`.
+    #[serde(default, skip_serializing)]
+    pub attachment_ids: Option<Vec<String>>,
+    #[serde(skip_deserializing, default)]
+    pub attachments: Option<Vec<crate::uploads::Attachment>>,
     pub avatar_url: Option<String>,
     pub banner_url: Option<String>,
     pub status: Option<String>,
+    pub custom_status: Option<String>,
+    pub custom_status_expires_at: Option<String>,
     pub role: Option<String>,
     pub about: Option<String>,
     pub target_user_id: Option<String>,
     pub muted: Option<bool>,
     pub deafened: Option<bool>,
+    pub screen_sharing: Option<bool>,
+    /// On `voice_join`: wait in line when the room is full.
+    #[serde(default, skip_serializing)]
+    pub queue: Option<bool>,
+    #[serde(skip_deserializing, default)]
+    pub server_muted: Option<bool>,
+    #[serde(skip_deserializing, default)]
+    pub server_deafened: Option<bool>,
     pub sdp: Option<serde_json::Value>,
     pub candidate: Option<serde_json::Value>,
     #[serde(skip_deserializing, default)]
@@ -39,8 +65,16 @@ pub struct WsMessage {
 /// Shared broadcast channel for all WebSocket connections.
 pub type Broadcaster = Arc<broadcast::Sender<String>>;
 
-/// Shared state for online users: user_id -> username
-pub type OnlineUsers = Arc<Mutex<HashMap<String, i32>>>; // user_id -> avatar_color (simplified)
+/// A connected user. Several sockets (tabs, devices) may share one entry.
+#[derive(Debug, Clone)]
+pub struct OnlineUser {
+    pub avatar_color: i32,
+    pub presence: UserPresence,
+    pub connections: usize,
+}
+
+/// Shared state for online users: user_id -> OnlineUser
+pub type OnlineUsers = Arc<Mutex<HashMap<String, OnlineUser>>>;
 
 #[derive(Default)]
 pub struct AccessCacheState {
@@ -50,8 +84,10 @@ pub struct AccessCacheState {
 
 pub type AccessCache = Arc<Mutex<AccessCacheState>>;
 
-pub fn create_broadcaster() -> Broadcaster {
-    let (tx, _) = broadcast::channel::<String>(256);
+/// `capacity` is `server.broadcast_capacity`: events a lagging connection
+/// can fall behind before it starts missing some.
+pub fn create_broadcaster(capacity: usize) -> Broadcaster {
+    let (tx, _) = broadcast::channel::<String>(capacity);
     Arc::new(tx)
 }
 
@@ -73,6 +109,13 @@ pub fn cache_clear_user_roles(cache: &AccessCache) {
     guard.user_roles.clear();
 }
 
+/// Forget every cached role and room requirement.
+pub fn cache_clear_access(cache: &AccessCache) {
+    let mut guard = cache.lock().unwrap();
+    guard.user_roles.clear();
+    guard.room_required_roles.clear();
+}
+
 pub fn cache_set_room_required_role(cache: &AccessCache, room_id: &str, required_role: &str) {
     let mut guard = cache.lock().unwrap();
     guard
@@ -85,7 +128,8 @@ pub fn cache_remove_room(cache: &AccessCache, room_id: &str) {
     guard.room_required_roles.remove(room_id);
 }
 
-async fn get_user_role_cached(pool: &SqlitePool, cache: &AccessCache, user_id: &str) -> Option<String> {
+#[tracing::instrument(level = "debug", skip_all, fields(user_id))]
+async fn get_user_role_cached(pool: &DbPool, cache: &AccessCache, user_id: &str) -> Option<String> {
     {
         let guard = cache.lock().unwrap();
         if let Some(role) = guard.user_roles.get(user_id) {
@@ -93,11 +137,12 @@ async fn get_user_role_cached(pool: &SqlitePool, cache: &AccessCache, user_id: &
         }
     }
 
-    let role: Option<String> = sqlx::query_scalar("SELECT role FROM users WHERE id = ?")
+    let role: Option<String> = sqlx::query_scalar("SELECT role FROM users WHERE id = $1")
         .bind(user_id)
         .fetch_optional(pool)
         .await
-        .unwrap_or(None);
+        .log_err("Reading users")
+        .flatten();
 
     if let Some(ref role_value) = role {
         cache_set_user_role(cache, user_id, role_value);
@@ -106,7 +151,8 @@ async fn get_user_role_cached(pool: &SqlitePool, cache: &AccessCache, user_id: &
     role
 }
 
-async fn get_room_required_role_cached(pool: &SqlitePool, cache: &AccessCache, room_id: &str) -> Option<String> {
+#[tracing::instrument(level = "debug", skip_all, fields(room_id))]
+async fn get_room_required_role_cached(pool: &DbPool, cache: &AccessCache, room_id: &str) -> Option<String> {
     {
         let guard = cache.lock().unwrap();
         if let Some(required_role) = guard.room_required_roles.get(room_id) {
@@ -114,11 +160,12 @@ async fn get_room_required_role_cached(pool: &SqlitePool, cache: &AccessCache, r
         }
     }
 
-    let required_role: Option<String> = sqlx::query_scalar("SELECT required_role FROM rooms WHERE id = ?")
+    let required_role: Option<String> = sqlx::query_scalar("SELECT required_role FROM rooms WHERE id = $1")
         .bind(room_id)
         .fetch_optional(pool)
         .await
-        .unwrap_or(None);
+        .log_err("Reading rooms")
+        .flatten();
 
     if let Some(ref role_value) = required_role {
         cache_set_room_required_role(cache, room_id, role_value);
@@ -128,7 +175,7 @@ async fn get_room_required_role_cached(pool: &SqlitePool, cache: &AccessCache, r
 }
 
 pub async fn can_user_access_room_cached(
-    pool: &SqlitePool,
+    pool: &DbPool,
     cache: &AccessCache,
     user_id: &str,
     room_id: &str,
@@ -144,27 +191,43 @@ pub async fn can_user_access_room_cached(
     }
 }
 
-fn extract_room_id(payload: &str) -> Option<String> {
-    let value = serde_json::from_str::<serde_json::Value>(payload).ok()?;
-    value
-        .get("room_id")
-        .and_then(|v| v.as_str())
-        .map(|v| v.to_string())
+/// Send an error frame to a single WebSocket client.
+async fn send_error(session: &mut actix_ws::Session, code: &str, message: &str, room_id: Option<&str>) {
+    let event = serde_json::json!({
+        "type": "error",
+        "code": code,
+        "message": message,
+        "room_id": room_id,
+    });
+    let _ = session.text(event.to_string()).await;
+}
+
+/// Who a broadcast event is for: `room_id` limits it to users who can
+/// access the room, `target_user_id` to a single user.
+fn event_recipients(payload: &str) -> (Option<String>, Option<String>) {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(payload) else {
+        return (None, None);
+    };
+    let field = |name: &str| value.get(name).and_then(|v| v.as_str()).map(|v| v.to_string());
+    (field("room_id"), field("target_user_id"))
 }
 
-async fn fetch_accessible_rooms(pool: &SqlitePool, role: &str) -> HashSet<String> {
+#[tracing::instrument(level = "debug", skip_all, fields(role))]
+async fn fetch_accessible_rooms(pool: &DbPool, role: &str) -> HashSet<String> {
     let rows = if role == "admin" {
         sqlx::query_scalar::<_, String>("SELECT id FROM rooms")
             .fetch_all(pool)
             .await
+            .log_err("Reading rooms")
             .unwrap_or_default()
     } else {
         sqlx::query_scalar::<_, String>(
-            "SELECT id FROM rooms WHERE required_role = 'user' OR required_role = ?"
+            "SELECT id FROM rooms WHERE required_role = 'user' OR required_role = $1"
         )
         .bind(role)
         .fetch_all(pool)
         .await
+        .log_err("Reading rooms")
         .unwrap_or_default()
     };
 
@@ -172,13 +235,27 @@ async fn fetch_accessible_rooms(pool: &SqlitePool, role: &str) -> HashSet<String
 }
 
 /// GET /ws — WebSocket upgrade
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "realtime",
+    responses(
+        (status = 101, description = "Switched to the WebSocket event stream (see PROTOCOL.md for the frames)"),
+    )
+)]
+#[allow(clippy::too_many_arguments)]
 pub async fn ws_handler(
     req: HttpRequest,
     stream: web::Payload,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
     broadcaster: web::Data<Broadcaster>,
     online_users: web::Data<OnlineUsers>,
     access_cache: web::Data<AccessCache>,
+    voice_states: web::Data<VoiceStates>,
+    typing_tracker: web::Data<TypingTracker>,
+    config: web::Data<Config>,
+    metrics: web::Data<SharedMetrics>,
+    banned_users: web::Data<BannedUsers>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let (response, session, mut msg_stream) = actix_ws::handle(&req, stream)?;
 
@@ -186,10 +263,14 @@ pub async fn ws_handler(
     let tx = broadcaster.get_ref().clone();
     let users = online_users.get_ref().clone();
     let access_cache = access_cache.get_ref().clone();
+    let voice_states = voice_states.get_ref().clone();
+    let typing_tracker = typing_tracker.get_ref().clone();
+    let config = config.into_inner();
+    let metrics = metrics.get_ref().clone();
+    let banned_users = banned_users.get_ref().clone();
     let mut rx = tx.subscribe();
+    let conn_id = Uuid::new_v4().to_string();
 
-    // We'll wait for a "join" message to hydrate user context.
-    let mut my_user_id: Option<String> = None;
     let allowed_rooms: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(HashSet::new()));
     let is_admin = Arc::new(Mutex::new(false));
 
@@ -218,15 +299,20 @@ pub async fn ws_handler(
     }
 
     let claims = match token {
-        Some(t) => match validate_token(&t) {
+        Some(t) => match validate_token(&config, &t) {
             Some(claims) => claims,
             None => return Err(actix_web::error::ErrorUnauthorized("Invalid token")),
         },
         None => return Err(actix_web::error::ErrorUnauthorized("No token provided")),
     };
 
+    if let Some(ban) = crate::moderation::active_ban(&pool, &claims.sub).await {
+        return Err(actix_web::error::ErrorForbidden(ban.message()));
+    }
+
     // Pre-hydrate user session
-    my_user_id = Some(claims.sub.clone());
+    let my_user_id: Option<String> = Some(claims.sub.clone());
+    let my_username = claims.username.clone();
     
     // Fetch initial state
     let role = get_user_role_cached(&pool, &access_cache, &claims.sub)
@@ -242,22 +328,63 @@ pub async fn ws_handler(
         *admin_guard = role == "admin";
     }
     
-    // Add to online users
+    // Add to online users with their persisted presence
+    let initial_presence = presence::load_presence(&pool, &claims.sub).await;
     {
-         let mut guard = users.lock().unwrap();
-         // We don't have avatar_color here without DB query, default to 0 for now. 
-         // Realistically we should fetch user profile or include it in token if needed, 
-         // but for now 0 is safe. 
-         guard.insert(claims.sub.clone(), 0);
+        let mut guard = users.lock().unwrap();
+        let online = guard.entry(claims.sub.clone()).or_insert_with(|| OnlineUser {
+            avatar_color: 0,
+            presence: initial_presence,
+            connections: 0,
+        });
+        online.connections += 1;
     }
+    metrics.ws_connections.inc();
+
+    // Both tasks log under the upgrade request's id
+    let session_span = tracing::info_span!(
+        "ws_session",
+        request_id = %crate::logging::request_id(&req).unwrap_or_default(),
+        user_id = %claims.sub,
+        conn_id = %conn_id,
+    );
+    session_span.in_scope(|| tracing::debug!("WebSocket connected"));
 
     // Spawn task: forward broadcast messages to this client
     let mut send_session = session.clone();
     let send_allowed_rooms = allowed_rooms.clone();
     let send_is_admin = is_admin.clone();
+    let send_metrics = metrics.clone();
+    let send_user_id = claims.sub.clone();
+    let send_pool = pool.clone();
+    let send_access_cache = access_cache.clone();
     actix_web::rt::spawn(async move {
-        while let Ok(text) = rx.recv().await {
-            let room_id = extract_room_id(&text);
+        loop {
+            let text = match rx.recv().await {
+                Ok(text) => text,
+                // Too slow to keep up: skip what was missed and carry on
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    send_metrics.ws_broadcast_lagged.inc_by(skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let (room_id, target_user_id) = event_recipients(&text);
+            if target_user_id.as_ref().is_some_and(|target| *target != send_user_id) {
+                continue;
+            }
+            // Roles or room requirements changed: recompute what this socket receives
+            if room_id.is_none()
+                && target_user_id.is_none()
+                && serde_json::from_str::<WsMessage>(&text).is_ok_and(|event| event.msg_type == "access_changed")
+            {
+                let role = get_user_role_cached(&send_pool, &send_access_cache, &send_user_id)
+                    .await
+                    .unwrap_or_else(|| "user".to_string());
+                let rooms = fetch_accessible_rooms(&send_pool, &role).await;
+                *send_allowed_rooms.lock().unwrap() = rooms;
+                *send_is_admin.lock().unwrap() = role == "admin";
+            }
             if let Some(rid) = room_id {
                 let allowed = {
                     let admin = *send_is_admin.lock().unwrap();
@@ -274,13 +401,21 @@ pub async fn ws_handler(
                 }
             }
 
+            // Banned while connected: pass the reason on, then hang up
+            let banned = target_user_id.is_some()
+                && serde_json::from_str::<WsMessage>(&text).is_ok_and(|event| event.msg_type == "banned");
             if send_session.text(text).await.is_err() {
                 break;
             }
+            if banned {
+                let _ = send_session.close(Some(CloseCode::Policy.into())).await;
+                break;
+            }
         }
-    });
+    }.instrument(session_span.clone()));
 
     // Spawn task: read messages from this client
+    let mut reply_session = session.clone();
     actix_web::rt::spawn(async move {
         // Per-connection message rate limiter: max 10 messages per second
         let mut msg_timestamps: std::collections::VecDeque<std::time::Instant> = std::collections::VecDeque::new();
@@ -288,14 +423,18 @@ pub async fn ws_handler(
         let rate_window = std::time::Duration::from_secs(1);
 
         while let Some(Ok(msg)) = msg_stream.next().await {
+            if my_user_id.as_deref().is_some_and(|uid| is_banned(&banned_users, uid)) {
+                break;
+            }
             match msg {
                 Message::Text(text) => {
                     // Rate limit: drop messages that exceed the threshold
                     let now = std::time::Instant::now();
-                    while msg_timestamps.front().map_or(false, |t| now.duration_since(*t) > rate_window) {
+                    while msg_timestamps.front().is_some_and(|t| now.duration_since(*t) > rate_window) {
                         msg_timestamps.pop_front();
                     }
                     if msg_timestamps.len() >= max_msgs_per_window {
+                        metrics.ws_dropped_frames.with_label_values(&["rate_limited"]).inc();
                         continue; // silently drop — client is flooding
                     }
                     msg_timestamps.push_back(now);
@@ -314,27 +453,64 @@ pub async fn ws_handler(
                                 // Update color in map if provided
                                 if let Some(color) = ws_msg.avatar_color {
                                      let mut guard = users.lock().unwrap();
-                                     guard.insert(uid.clone(), color);
+                                     if let Some(online) = guard.get_mut(uid) {
+                                         online.avatar_color = color;
+                                     }
                                 }
-                                
+
+                                // A status announced on join is a presence change like any other
+                                let update = PresenceUpdate {
+                                    status: ws_msg.status.clone(),
+                                    ..Default::default()
+                                };
+                                let current = match presence::set_presence(&pool, &users, &tx, uid, &update).await {
+                                    Ok(p) => p,
+                                    Err(_) => presence::load_presence(&pool, uid).await,
+                                };
+
+                                // Invisible users are not announced
+                                if current.is_invisible() {
+                                    continue;
+                                }
+
+                                ws_msg.status = Some(current.status);
+                                ws_msg.custom_status = current.custom_status;
+                                ws_msg.custom_status_expires_at = current.custom_status_expires_at;
+
                                 // Broadcast join
                                 let _ = tx.send(serde_json::to_string(&ws_msg).unwrap());
                              }
                         }
-                        // Handle LEAVE (explicit)
+                        // Handle LEAVE (explicit) — cleanup below broadcasts it
                         else if ws_msg.msg_type == "leave" {
-                             if let Some(uid) = &my_user_id {
-                                {
-                                    let mut guard = users.lock().unwrap();
-                                    guard.remove(uid);
-                                }
-                                let _ = tx.send(text.to_string()); // Broadcast leave
-                             }
                              break;
+                        }
+                        // Handle PRESENCE: validated against the token identity and persisted
+                        else if ws_msg.msg_type == "presence" {
+                            let Some(uid) = my_user_id.as_deref() else {
+                                continue;
+                            };
+
+                            let update = PresenceUpdate {
+                                status: ws_msg.status.clone(),
+                                custom_status: ws_msg.custom_status.clone(),
+                                custom_status_expires_at: ws_msg.custom_status_expires_at.clone(),
+                            };
+                            match presence::set_presence(&pool, &users, &tx, uid, &update).await {
+                                Ok(current) => {
+                                    // Invisible users still need their own state echoed back
+                                    if current.is_invisible() {
+                                        let _ = reply_session.text(current.event().to_string()).await;
+                                    }
+                                }
+                                Err(reason) => {
+                                    send_error(&mut reply_session, "invalid_presence", reason, None).await;
+                                }
+                            }
                         }
                                 // Handle MESSAGE
                         else if ws_msg.msg_type == "message" {
-                             if let (Some(content), Some(rid), Some(uid), Some(uname)) = (&ws_msg.content, &ws_msg.room_id, &ws_msg.user_id, &ws_msg.username) {
+                             if let (Some(content), Some(rid), Some(uid)) = (&ws_msg.content, &ws_msg.room_id, &ws_msg.user_id) {
                                 // SECURITY: Force user_id to match token
                                 if Some(uid) != my_user_id.as_ref() {
                                     continue;
@@ -347,47 +523,232 @@ pub async fn ws_handler(
                                 }
 
                                 let has_content = !content.trim().is_empty();
-                                let has_image = ws_msg.image_url.as_ref().map_or(false, |u| !u.is_empty());
-                                if has_content || has_image {
+                                // Store the bare path; only the sender's own uploads may be referenced
+                                let image_url = ws_msg
+                                    .image_url
+                                    .as_deref()
+                                    .map(|u| crate::uploads::strip_query(u).to_string());
+                                ws_msg.image_url = match image_url {
+                                    Some(url) if url.starts_with("/uploads/") => {
+                                        crate::uploads::is_own_upload(&pool, &url, uid).await.then_some(url)
+                                    }
+                                    other => other,
+                                };
+                                let has_image = ws_msg.image_url.as_ref().is_some_and(|u| !u.is_empty());
+                                let attachment_ids = match ws_msg.attachment_ids.as_deref() {
+                                    Some(requested) if !requested.is_empty() => {
+                                        crate::uploads::pending_attachment_ids(&pool, uid, requested).await
+                                    }
+                                    _ => Vec::new(),
+                                };
+                                if has_content || has_image || !attachment_ids.is_empty() {
                                     let msg_id = Uuid::new_v4().to_string();
                                     let now = chrono::Utc::now().to_rfc3339();
 
-                                    let _ = sqlx::query(
-                                        "INSERT INTO messages (id, room_id, user_id, username, content, created_at, image_url, reply_to_id) VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
+                                    let inserted = sqlx::query(
+                                        "INSERT INTO messages (id, room_id, user_id, username, content, created_at, image_url, reply_to_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"
                                     )
                                     .bind(&msg_id)
                                     .bind(rid)
                                     .bind(uid)
-                                    .bind(uname)
+                                    .bind(&my_username)
                                     .bind(content)
                                     .bind(&now)
                                     .bind(&ws_msg.image_url)
                                     .bind(&ws_msg.reply_to_id)
                                     .execute(&pool)
                                     .await;
+                                    if inserted.log_err("Inserting message").is_none() {
+                                        send_error(&mut reply_session, "message_not_saved", "The message could not be saved", Some(rid)).await;
+                                        continue;
+                                    }
+                                    metrics.messages_persisted.inc();
+
+                                    if let Some(url) = ws_msg.image_url.as_deref() {
+                                        crate::uploads::retain_upload(&pool, url).await;
+                                    }
 
                                     ws_msg.id = msg_id;
                                     ws_msg.created_at = now;
-
+                                    // Like user_id, the name comes from the token, not the client
+                                    ws_msg.username = Some(my_username.clone());
+                                    ws_msg.thumbnail_url = match ws_msg.image_url.as_deref() {
+                                        Some(url) if crate::uploads::has_variants(&pool, url).await => {
+                                            crate::uploads::thumbnail_url(Some(url)).map(|u| crate::uploads::sign_upload_url(&config, &u))
+                                        }
+                                        _ => None,
+                                    };
+                                    ws_msg.image_url = ws_msg.image_url.as_deref().map(|u| crate::uploads::sign_upload_url(&config, u));
+                                    ws_msg.attachments = Some(
+                                        crate::uploads::claim_attachments(&pool, &config, uid, &ws_msg.id, &attachment_ids).await,
+                                    );
+                                    if let Some(url) = ws_msg.image_url.as_deref() {
+                                        crate::uploads::claim_image_upload(&pool, uid, &ws_msg.id, url).await;
+                                    }
+
+                                    typing::stop_typing(&typing_tracker, &tx, rid, uid);
                                     let _ = tx.send(serde_json::to_string(&ws_msg).unwrap());
                                 }
                              }
                         }
-                        // Handle TYPING relay
+                        // Handle TYPING: identity from the token, room access required, throttled
                         else if ws_msg.msg_type == "typing" {
-                            let _ = tx.send(text.to_string());
+                            let (Some(uid), Some(rid)) = (my_user_id.as_deref(), ws_msg.room_id.as_deref()) else {
+                                continue;
+                            };
+
+                            if !can_user_access_room_cached(&pool, &access_cache, uid, rid).await {
+                                continue;
+                            }
+
+                            typing::start_typing(&typing_tracker, &tx, rid, uid, &my_username);
                         }
-                        // Handle PRESENCE relay
-                        else if ws_msg.msg_type == "presence" {
-                            let _ = tx.send(text.to_string());
+                        // Handle VOICE join: track membership, apply moderator flags
+                        else if ws_msg.msg_type == "voice_join" {
+                            let (Some(uid), Some(rid)) = (my_user_id.clone(), ws_msg.room_id.clone()) else {
+                                continue;
+                            };
+
+                            if !can_user_access_room_cached(&pool, &access_cache, &uid, &rid).await {
+                                continue;
+                            }
+
+                            // Text rooms have no voice channel to join
+                            let Some(user_limit) = voice::fetch_voice_room_limit(&pool, &rid).await else {
+                                send_error(&mut reply_session, "not_voice_room", "This room is not a voice room", Some(&rid)).await;
+                                continue;
+                            };
+
+                            let username = my_username.clone();
+                            let role = get_user_role_cached(&pool, &access_cache, &uid)
+                                .await
+                                .unwrap_or_else(|| "user".to_string());
+                            let bypass_limit = voice::can_move_members(&pool, &role).await;
+                            voice::set_room_user_limit(&voice_states, &rid, user_limit);
+                            // Slots whose reservation lapsed go to the next in line first
+                            voice::promote_waiting(&voice_states, &tx, &rid);
+
+                            let outcome = voice::join_voice_room(
+                                &voice_states,
+                                &voice::VoiceJoinRequest {
+                                    conn_id: &conn_id,
+                                    user_id: &uid,
+                                    username: &username,
+                                    room_id: &rid,
+                                    muted: ws_msg.muted.unwrap_or(false),
+                                    deafened: ws_msg.deafened.unwrap_or(false),
+                                    bypass_limit,
+                                    queue: ws_msg.queue.unwrap_or(false),
+                                },
+                            );
+
+                            let (member, previous_room) = match outcome {
+                                voice::VoiceJoinOutcome::Joined { member, previous_room } => (member, previous_room),
+                                voice::VoiceJoinOutcome::Full { user_limit, position } => {
+                                    let full_msg = serde_json::json!({
+                                        "type": "error",
+                                        "code": "voice_full",
+                                        "message": "This voice room is full",
+                                        "room_id": rid,
+                                        "user_limit": user_limit,
+                                        "queued": position.is_some(),
+                                        "position": position,
+                                    });
+                                    let _ = reply_session.text(full_msg.to_string()).await;
+                                    continue;
+                                }
+                            };
+
+                            if let Some(prev_rid) = previous_room {
+                                let leave_msg = serde_json::json!({
+                                    "type": "voice_leave",
+                                    "room_id": prev_rid,
+                                    "user_id": uid,
+                                    "username": username,
+                                });
+                                let _ = tx.send(leave_msg.to_string());
+                                voice::promote_waiting(&voice_states, &tx, &prev_rid);
+                            }
+
+                            ws_msg.user_id = Some(uid);
+                            ws_msg.username = Some(username);
+                            ws_msg.muted = Some(member.muted);
+                            ws_msg.deafened = Some(member.deafened);
+                            ws_msg.server_muted = Some(member.server_muted);
+                            ws_msg.server_deafened = Some(member.server_deafened);
+                            let _ = tx.send(serde_json::to_string(&ws_msg).unwrap());
+                        }
+                        // Handle VOICE state: clients cannot lift a server mute/deafen
+                        else if ws_msg.msg_type == "voice_state" {
+                            let Some(uid) = my_user_id.clone() else {
+                                continue;
+                            };
+
+                            match voice::update_voice_state(
+                                &voice_states,
+                                &uid,
+                                ws_msg.muted.unwrap_or(false),
+                                ws_msg.deafened.unwrap_or(false),
+                            ) {
+                                Ok(member) => {
+                                    if let Some(member) = member {
+                                        ws_msg.room_id = Some(member.room_id);
+                                        ws_msg.server_muted = Some(member.server_muted);
+                                        ws_msg.server_deafened = Some(member.server_deafened);
+                                    }
+                                    ws_msg.user_id = Some(uid);
+                                    ws_msg.username = Some(my_username.clone());
+                                    let _ = tx.send(serde_json::to_string(&ws_msg).unwrap());
+                                }
+                                Err(rejection) => {
+                                    send_error(
+                                        &mut reply_session,
+                                        rejection.code(),
+                                        rejection.message(),
+                                        ws_msg.room_id.as_deref(),
+                                    )
+                                    .await;
+                                }
+                            }
                         }
-                        // Handle VOICE events relay
-                        else if ws_msg.msg_type == "voice_join"
-                            || ws_msg.msg_type == "voice_leave"
-                            || ws_msg.msg_type == "voice_state"
-                            || ws_msg.msg_type == "voice_signal"
-                        {
-                            let _ = tx.send(text.to_string());
+                        // Handle VOICE leave
+                        else if ws_msg.msg_type == "voice_leave" {
+                            let Some(uid) = my_user_id.clone() else {
+                                continue;
+                            };
+
+                            // Leaving only the waiting list has nothing to announce
+                            let Some(member) = voice::leave_voice_room(&voice_states, &uid, None) else {
+                                continue;
+                            };
+                            let leave_msg = serde_json::json!({
+                                "type": "voice_leave",
+                                "room_id": member.room_id,
+                                "user_id": uid,
+                                "username": my_username,
+                            });
+                            let _ = tx.send(leave_msg.to_string());
+                            voice::promote_waiting(&voice_states, &tx, &member.room_id);
+                        }
+                        // Handle VOICE signaling relay: between members of the same room only
+                        else if ws_msg.msg_type == "voice_signal" {
+                            let (Some(uid), Some(target)) = (my_user_id.as_deref(), ws_msg.target_user_id.as_deref()) else {
+                                continue;
+                            };
+                            let Some(room_id) = voice::signal_room(&voice_states, uid, &conn_id, target) else {
+                                metrics.ws_dropped_frames.with_label_values(&["not_in_voice"]).inc();
+                                continue;
+                            };
+
+                            let signal = serde_json::json!({
+                                "type": "voice_signal",
+                                "room_id": room_id,
+                                "user_id": uid,
+                                "target_user_id": target,
+                                "sdp": ws_msg.sdp,
+                                "candidate": ws_msg.candidate,
+                            });
+                            let _ = tx.send(signal.to_string());
                         }
                     }
                 }
@@ -397,19 +758,41 @@ pub async fn ws_handler(
         }
 
         // Cleanup on disconnect
+        tracing::debug!("WebSocket disconnected");
+        metrics.ws_connections.dec();
         if let Some(uid) = my_user_id {
-            {
+            typing::stop_all_typing(&typing_tracker, &tx, &uid);
+            let last_connection = {
                 let mut guard = users.lock().unwrap();
-                guard.remove(&uid);
+                let remaining = guard.get_mut(&uid).map(|online| {
+                    online.connections = online.connections.saturating_sub(1);
+                    online.connections
+                });
+                if remaining == Some(0) {
+                    guard.remove(&uid);
+                }
+                remaining.unwrap_or(0) == 0
+            };
+            if let Some(member) = voice::leave_voice_room(&voice_states, &uid, Some(&conn_id)) {
+                let voice_leave_msg = serde_json::json!({
+                    "type": "voice_leave",
+                    "room_id": member.room_id,
+                    "user_id": uid,
+                    "username": member.username,
+                });
+                let _ = tx.send(voice_leave_msg.to_string());
+                voice::promote_waiting(&voice_states, &tx, &member.room_id);
+            }
+            // Broadcast offline once the user's last socket is gone
+            if last_connection {
+                let offline_msg = serde_json::json!({
+                    "type": "leave",
+                    "user_id": uid
+                });
+                let _ = tx.send(offline_msg.to_string());
             }
-            // Broadcast offline
-            let offline_msg = serde_json::json!({
-                "type": "leave",
-                "user_id": uid
-            });
-            let _ = tx.send(offline_msg.to_string());
         }
-    });
+    }.instrument(session_span));
 
     Ok(response)
 }
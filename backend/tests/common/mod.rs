@@ -0,0 +1,203 @@
+// Helpers shared by the feature tests: a SQLite-backed server on a
+// temporary directory, accounts, uploads and WebSocket clients.
+#![allow(dead_code)]
+
+use backend::config::Config;
+use backend::db::{self, DbPool};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// How long a test waits for an expected WebSocket event.
+const EVENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A server listening on a free loopback port, with its state for
+/// inspecting the database and upload storage.
+pub struct TestServer {
+    pub addr: SocketAddr,
+    pub state: backend::AppState,
+    pub dir: PathBuf,
+    http: reqwest::Client,
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        Self::start_with(|_| {}).await
+    }
+
+    /// Start with `adjust` applied to the test configuration.
+    pub async fn start_with(adjust: impl FnOnce(&mut Config)) -> Self {
+        let dir = std::env::temp_dir().join(format!("voxium-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pool = db::connect_to(&format!("sqlite:{}", dir.join("voxium.db").display()), 4).await;
+        db::run_migrations(&pool).await.expect("migrations");
+
+        let mut config = Config::default();
+        config.auth.jwt_secret = "feature-test-secret".to_string();
+        config.storage.root = dir.join("uploads").display().to_string();
+        adjust(&mut config);
+
+        let state = backend::AppState::new(pool, config);
+        let app_state = state.clone();
+        let server = actix_web::HttpServer::new(move || {
+            let state = app_state.clone();
+            actix_web::App::new().configure(move |cfg| backend::configure(cfg, &state))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+
+        // No pooling: a connection idle past the server's keep-alive may already be closed
+        let http = reqwest::Client::builder().pool_max_idle_per_host(0).build().unwrap();
+        TestServer { addr, state, dir, http }
+    }
+
+    pub fn pool(&self) -> &DbPool {
+        &self.state.pool
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// Send a request and return the status with the JSON body (`Null` when empty).
+    pub async fn send(&self, request: reqwest::RequestBuilder, token: Option<&str>) -> (u16, Value) {
+        let request = match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+        let response = request.send().await.expect("request");
+        let status = response.status().as_u16();
+        let body = response.bytes().await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    /// Like `send`, asserting a 2xx answer.
+    pub async fn ok(&self, request: reqwest::RequestBuilder, token: Option<&str>) -> Value {
+        let (status, body) = self.send(request, token).await;
+        assert!((200..300).contains(&status), "{status} -> {body}");
+        body
+    }
+
+    pub fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        self.http.get(self.url(path))
+    }
+
+    pub fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        self.http.post(self.url(path))
+    }
+
+    pub fn patch(&self, path: &str) -> reqwest::RequestBuilder {
+        self.http.patch(self.url(path))
+    }
+
+    /// Register `username` and return `(user_id, token)`.
+    pub async fn register(&self, username: &str) -> (String, String) {
+        let body = self
+            .ok(self.post("/api/register").json(&json!({ "username": username, "password": "correct horse" })), None)
+            .await;
+        (body["user_id"].as_str().unwrap().to_string(), body["token"].as_str().unwrap().to_string())
+    }
+
+    /// Register `username` with the `admin` role and return `(user_id, token)`.
+    pub async fn register_admin(&self, username: &str) -> (String, String) {
+        let (user_id, _) = self.register(username).await;
+        sqlx::query("UPDATE users SET role = 'admin' WHERE id = $1")
+            .bind(&user_id)
+            .execute(self.pool())
+            .await
+            .unwrap();
+        let body = self
+            .ok(self.post("/api/login").json(&json!({ "username": username, "password": "correct horse" })), None)
+            .await;
+        (user_id, body["token"].as_str().unwrap().to_string())
+    }
+
+    /// Upload `data` as `filename` and return the response body.
+    pub async fn upload(&self, token: &str, filename: &str, data: Vec<u8>) -> Value {
+        let boundary = "voxium-test-boundary";
+        let mut body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\nContent-Type: image/png\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(&data);
+        body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+        let request = self
+            .post("/api/upload")
+            .header(reqwest::header::CONTENT_TYPE, format!("multipart/form-data; boundary={boundary}"))
+            .body(body);
+        self.ok(request, Some(token)).await
+    }
+
+    /// Open a WebSocket as the owner of `token`.
+    pub async fn connect(&self, token: &str) -> WsClient {
+        let url = format!("ws://{}/ws?access_token={}", self.addr, token);
+        let (stream, _) = tokio_tungstenite::connect_async(url).await.expect("WebSocket upgrade");
+        WsClient { stream }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub struct WsClient {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl WsClient {
+    pub async fn send(&mut self, event: Value) {
+        self.stream.send(Message::Text(event.to_string())).await.expect("WebSocket send");
+    }
+
+    /// Next event matching `wanted`, skipping the others.
+    pub async fn expect(&mut self, wanted: impl Fn(&Value) -> bool) -> Value {
+        self.next_matching(&wanted, EVENT_TIMEOUT).await.expect("expected WebSocket event")
+    }
+
+    /// Whether an event matching `wanted` arrives within `wait`.
+    pub async fn receives(&mut self, wanted: impl Fn(&Value) -> bool, wait: Duration) -> bool {
+        self.next_matching(&wanted, wait).await.is_some()
+    }
+
+    async fn next_matching(&mut self, wanted: &impl Fn(&Value) -> bool, wait: Duration) -> Option<Value> {
+        let deadline = tokio::time::Instant::now() + wait;
+        loop {
+            let frame = tokio::time::timeout_at(deadline, self.stream.next()).await.ok()??.ok()?;
+            let Message::Text(text) = frame else {
+                continue;
+            };
+            let event: Value = serde_json::from_str(&text).unwrap_or(Value::Null);
+            if wanted(&event) {
+                return Some(event);
+            }
+        }
+    }
+}
+
+/// A small PNG whose pixels depend on `seed`, so each seed is a distinct upload.
+pub fn png_bytes(seed: u8) -> Vec<u8> {
+    let img = image::RgbImage::from_fn(64, 48, |x, y| image::Rgb([x as u8 * 4, y as u8 * 5, seed]));
+    let mut out = Cursor::new(Vec::new());
+    img.write_to(&mut out, image::ImageFormat::Png).unwrap();
+    out.into_inner()
+}
+
+/// `/uploads/<key>` of an upload response, without the signature.
+pub fn bare_url(upload: &Value) -> String {
+    upload["url"].as_str().unwrap().split('?').next().unwrap().to_string()
+}
+
+pub fn event_type(event: &Value) -> &str {
+    event["type"].as_str().unwrap_or_default()
+}
@@ -0,0 +1,272 @@
+// Runs the same HTTP scenario against every supported database engine.
+//
+// SQLite always runs (on a temporary file). Postgres runs when
+// `VOXIUM_TEST_POSTGRES_URL` points at a database the test may create
+// schemas in, e.g. `postgres://postgres@localhost/voxium_test`.
+
+use actix_web::{http::header, test, App};
+use backend::config::Config;
+use backend::db::{self, DbPool};
+use serde_json::{json, Value};
+use std::io::Cursor;
+use std::path::Path;
+
+fn test_config(upload_dir: &Path) -> Config {
+    let mut config = Config::default();
+    config.auth.jwt_secret = "database-engines-test-secret".to_string();
+    config.storage.root = upload_dir.display().to_string();
+    config
+}
+
+fn png_bytes() -> Vec<u8> {
+    let img = image::RgbImage::from_fn(64, 48, |x, y| image::Rgb([x as u8 * 4, y as u8 * 5, 128]));
+    let mut out = Cursor::new(Vec::new());
+    img.write_to(&mut out, image::ImageFormat::Png).unwrap();
+    out.into_inner()
+}
+
+fn multipart_body(filename: &str, data: &[u8]) -> (String, Vec<u8>) {
+    let boundary = "voxium-test-boundary";
+    let mut body = format!(
+        "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\nContent-Type: image/png\r\n\r\n"
+    )
+    .into_bytes();
+    body.extend_from_slice(data);
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+    (format!("multipart/form-data; boundary={boundary}"), body)
+}
+
+macro_rules! call {
+    ($app:expr, $req:expr) => {{
+        let resp = test::call_service(&$app, $req.to_request()).await;
+        let status = resp.status();
+        let body = test::read_body(resp).await;
+        let json: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        assert!(status.is_success(), "{} -> {}", status, String::from_utf8_lossy(&body));
+        json
+    }};
+}
+
+fn bearer(token: &str) -> (header::HeaderName, String) {
+    (header::AUTHORIZATION, format!("Bearer {}", token))
+}
+
+async fn exercise_api(pool: DbPool, upload_dir: &Path) {
+    db::run_migrations(&pool).await.expect("migrations");
+    assert_eq!(db::schema_version(&pool).await, 22);
+    // Migrating twice is a no-op
+    db::run_migrations(&pool).await.expect("second migration run");
+
+    let state = backend::AppState::new(pool.clone(), test_config(upload_dir));
+    let app = test::init_service(App::new().configure(|cfg| backend::configure(cfg, &state))).await;
+
+    // Readiness: database, storage and schema all fine
+    let ready = call!(app, test::TestRequest::get().uri("/health/ready"));
+    assert_eq!(ready["status"], "ready");
+    assert_eq!(ready["checks"]["schema"]["ok"], true);
+    assert!(ready["checks"]["database"].get("error").is_none());
+
+    // Accounts
+    let credentials = |name: &str| json!({ "username": name, "password": "correct horse" });
+    let alice = call!(app, test::TestRequest::post().uri("/api/register").set_json(credentials("alice")));
+    let bob = call!(app, test::TestRequest::post().uri("/api/register").set_json(credentials("bob")));
+    let bob_token = bob["token"].as_str().unwrap().to_string();
+    let bob_id = bob["user_id"].as_str().unwrap().to_string();
+
+    sqlx::query("UPDATE users SET role = 'admin' WHERE id = $1")
+        .bind(alice["user_id"].as_str().unwrap())
+        .execute(&pool)
+        .await
+        .unwrap();
+    let alice = call!(app, test::TestRequest::post().uri("/api/login").set_json(credentials("alice")));
+    assert_eq!(alice["role"], "admin");
+    let admin_token = alice["token"].as_str().unwrap().to_string();
+
+    // Profile
+    call!(app, test::TestRequest::patch()
+        .uri("/api/users/me")
+        .insert_header(bearer(&bob_token))
+        .set_json(json!({ "about": "hi there", "avatar_color": 3 })));
+    let me = call!(app, test::TestRequest::get().uri("/api/users/me").insert_header(bearer(&bob_token)));
+    assert_eq!(me["about"], "hi there");
+    assert_eq!(me["avatar_color"], 3);
+
+    // Rooms
+    let room = call!(app, test::TestRequest::post()
+        .uri("/api/rooms")
+        .insert_header(bearer(&admin_token))
+        .set_json(json!({ "name": "engine-room", "user_limit": 5 })));
+    let room_id = room["id"].as_str().unwrap().to_string();
+    let rooms = call!(app, test::TestRequest::get().uri("/api/rooms").insert_header(bearer(&bob_token)));
+    let listed = rooms.as_array().unwrap().iter().find(|r| r["id"] == room_id.as_str()).expect("room listed");
+    assert_eq!(listed["user_limit"], 5);
+
+    // Messages are written by the WebSocket handler; insert one the same way
+    let message_id = uuid::Uuid::new_v4().to_string();
+    sqlx::query(
+        "INSERT INTO messages (id, room_id, user_id, username, content, created_at, image_url, reply_to_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"
+    )
+    .bind(&message_id)
+    .bind("general")
+    .bind(&bob_id)
+    .bind("bob")
+    .bind("Hello from Every Engine")
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(None::<String>)
+    .bind(None::<String>)
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    for _ in 0..2 {
+        call!(app, test::TestRequest::post()
+            .uri(&format!("/api/messages/{}/reactions", message_id))
+            .insert_header(bearer(&bob_token))
+            .set_json(json!({ "emoji": "👍" })));
+    }
+    call!(app, test::TestRequest::post()
+        .uri(&format!("/api/messages/{}/pin", message_id))
+        .insert_header(bearer(&admin_token)));
+
+    let history = call!(app, test::TestRequest::get().uri("/api/rooms/general/messages").insert_header(bearer(&bob_token)));
+    let history = history.as_array().unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0]["reactions"][0]["count"], 1);
+    assert!(history[0]["pinned_at"].is_string());
+
+    let pins = call!(app, test::TestRequest::get().uri("/api/rooms/general/pins").insert_header(bearer(&bob_token)));
+    assert_eq!(pins.as_array().unwrap().len(), 1);
+
+    // Search is case-insensitive on both engines
+    let found = call!(app, test::TestRequest::get()
+        .uri("/api/messages/search?q=every%20engine&room_id=general")
+        .insert_header(bearer(&bob_token)));
+    assert_eq!(found.as_array().unwrap().len(), 1);
+    let found = call!(app, test::TestRequest::get()
+        .uri("/api/messages/search?author=BOB")
+        .insert_header(bearer(&admin_token)));
+    assert_eq!(found.as_array().unwrap().len(), 1);
+
+    // Roles
+    call!(app, test::TestRequest::post()
+        .uri("/api/server/roles")
+        .insert_header(bearer(&admin_token))
+        .set_json(json!({ "name": "mods", "move_members": true, "storage_quota": 1_000_000 })));
+    call!(app, test::TestRequest::patch()
+        .uri("/api/server/roles/mods")
+        .insert_header(bearer(&admin_token))
+        .set_json(json!({ "animated_avatars": true, "storage_quota": -1 })));
+    let roles = call!(app, test::TestRequest::get().uri("/api/server/roles").insert_header(bearer(&admin_token)));
+    let mods = roles.as_array().unwrap().iter().find(|r| r["name"] == "mods").expect("role listed");
+    assert_eq!(mods["move_members"], true);
+    assert_eq!(mods["animated_avatars"], true);
+    assert!(mods["storage_quota"].is_null());
+
+    // Uploads are deduplicated with a reference count
+    let png = png_bytes();
+    let mut urls = Vec::new();
+    for name in ["one.png", "two.png"] {
+        let (content_type, body) = multipart_body(name, &png);
+        let uploaded = call!(app, test::TestRequest::post()
+            .uri("/api/upload")
+            .insert_header(bearer(&bob_token))
+            .insert_header((header::CONTENT_TYPE, content_type))
+            .set_payload(body));
+        urls.push(uploaded["url"].as_str().unwrap().split('?').next().unwrap().to_string());
+    }
+    assert_eq!(urls[0], urls[1]);
+    let key = urls[0].trim_start_matches("/uploads/");
+    let ref_count: i64 = sqlx::query_scalar("SELECT ref_count FROM upload_blobs WHERE key = $1")
+        .bind(key)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(ref_count, 2);
+
+    // Profile images count toward usage as well
+    let (content_type, body) = multipart_body("avatar.png", &png);
+    call!(app, test::TestRequest::post()
+        .uri("/api/users/me/avatar")
+        .insert_header(bearer(&bob_token))
+        .insert_header((header::CONTENT_TYPE, content_type))
+        .set_payload(body));
+
+    let usage = call!(app, test::TestRequest::get().uri("/api/users/me/storage").insert_header(bearer(&bob_token)));
+    assert_eq!(usage["files"], 3);
+    assert!(usage["used"].as_i64().unwrap() > 0);
+    let consumers = call!(app, test::TestRequest::get()
+        .uri("/api/server/storage/users")
+        .insert_header(bearer(&admin_token)));
+    assert_eq!(consumers[0]["username"], "bob");
+    assert_eq!(consumers[0]["used"], usage["used"]);
+
+    // Profile images can only be cleared through PATCH, even to another own upload
+    let repointed = test::call_service(&app, test::TestRequest::patch()
+        .uri("/api/users/me")
+        .insert_header(bearer(&bob_token))
+        .set_json(json!({ "avatar_url": urls[0] }))
+        .to_request()).await;
+    assert_eq!(repointed.status(), 400);
+    call!(app, test::TestRequest::patch()
+        .uri("/api/users/me")
+        .insert_header(bearer(&bob_token))
+        .set_json(json!({ "avatar_url": "" })));
+    let usage = call!(app, test::TestRequest::get().uri("/api/users/me/storage").insert_header(bearer(&bob_token)));
+    assert_eq!(usage["files"], 2);
+
+    // Cleanup paths
+    call!(app, test::TestRequest::delete()
+        .uri(&format!("/api/rooms/{}", room_id))
+        .insert_header(bearer(&admin_token)));
+    call!(app, test::TestRequest::delete()
+        .uri(&format!("/api/users/{}/messages", bob_id))
+        .insert_header(bearer(&admin_token)));
+    let history = call!(app, test::TestRequest::get().uri("/api/rooms/general/messages").insert_header(bearer(&bob_token)));
+    assert!(history.as_array().unwrap().is_empty());
+    call!(app, test::TestRequest::delete()
+        .uri(&format!("/api/users/{}", bob_id))
+        .insert_header(bearer(&admin_token)));
+
+    // With no grace period, the sweeper drops the now unreferenced upload
+    backend::upload_gc::sweep(&pool, &state.upload_storage, 0).await.expect("sweep");
+    let blobs: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM upload_blobs").fetch_one(&pool).await.unwrap();
+    assert_eq!(blobs, 0);
+    assert!(state.upload_storage.list().await.unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn sqlite_engine() {
+    let dir = std::env::temp_dir().join(format!("voxium-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let pool = db::connect_to(&format!("sqlite:{}", dir.join("voxium.db").display()), 4).await;
+    assert_eq!(db::Backend::of(&pool), db::Backend::Sqlite);
+
+    exercise_api(pool.clone(), &dir.join("uploads")).await;
+
+    pool.close().await;
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[actix_web::test]
+async fn postgres_engine() {
+    let Ok(base_url) = std::env::var("VOXIUM_TEST_POSTGRES_URL") else {
+        eprintln!("VOXIUM_TEST_POSTGRES_URL not set, skipping the Postgres run");
+        return;
+    };
+
+    // Each run gets its own schema
+    let schema = format!("voxium_test_{}", uuid::Uuid::new_v4().simple());
+    let admin = db::connect_to(&base_url, 1).await;
+    sqlx::query(&format!("CREATE SCHEMA {}", schema)).execute(&admin).await.unwrap();
+
+    let separator = if base_url.contains('?') { '&' } else { '?' };
+    let pool = db::connect_to(&format!("{}{}options[search_path]={}", base_url, separator, schema), 4).await;
+    assert_eq!(db::Backend::of(&pool), db::Backend::Postgres);
+
+    let dir = std::env::temp_dir().join(&schema);
+    exercise_api(pool.clone(), &dir.join("uploads")).await;
+
+    pool.close().await;
+    sqlx::query(&format!("DROP SCHEMA {} CASCADE", schema)).execute(&admin).await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,23 @@
+// Messages sent over the WebSocket.
+
+mod common;
+
+use common::{event_type, TestServer};
+use serde_json::json;
+
+#[actix_web::test]
+async fn message_author_comes_from_the_token() {
+    let server = TestServer::start().await;
+    let (bob_id, bob_token) = server.register("bob").await;
+    server.register("alice").await;
+
+    let mut bob = server.connect(&bob_token).await;
+    bob.send(json!({ "type": "message", "room_id": "general", "user_id": bob_id, "username": "alice", "content": "hi" }))
+        .await;
+    let posted = bob.expect(|e| event_type(e) == "message").await;
+    assert_eq!(posted["username"], "bob");
+
+    let history = server.ok(server.get("/api/rooms/general/messages"), Some(&bob_token)).await;
+    assert_eq!(history[0]["username"], "bob");
+    assert_eq!(history[0]["user_id"], bob_id.as_str());
+}
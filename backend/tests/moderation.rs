@@ -0,0 +1,70 @@
+// Changes made with the admin CLI reaching a running server.
+
+mod common;
+
+use common::{event_type, TestServer};
+use serde_json::json;
+use std::process::Command;
+use std::time::Duration;
+
+/// Longer than the server's poll interval.
+const POLL_WAIT: Duration = Duration::from_secs(12);
+
+/// Run `voxium-admin` on the test server's database.
+fn admin_cli(server: &TestServer, args: &[&str]) {
+    let output = Command::new(env!("CARGO_BIN_EXE_voxium-admin"))
+        .args(args)
+        .current_dir(&server.dir)
+        .env("DATABASE_URL", format!("sqlite:{}", server.dir.join("voxium.db").display()))
+        .env("JWT_SECRET", "feature-test-secret")
+        .env("UPLOAD_DIR", server.dir.join("uploads"))
+        .output()
+        .expect("voxium-admin");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[actix_web::test]
+async fn cli_role_change_reaches_signed_in_sessions() {
+    let server = TestServer::start().await;
+    let (alice_id, alice_token) = server.register_admin("alice").await;
+    let (_, bob_token) = server.register_admin("bob").await;
+    let state = &server.state;
+    backend::moderation::refresh_access(&state.pool, &state.current_access, &state.access_cache, &state.broadcaster)
+        .await;
+    backend::moderation::spawn_access_watcher(
+        state.pool.clone(),
+        state.current_access.clone(),
+        state.access_cache.clone(),
+        state.broadcaster.clone(),
+    );
+    let staff = server
+        .ok(server.post("/api/rooms").json(&json!({ "name": "staff", "required_role": "admin" })), Some(&alice_token))
+        .await;
+    let staff_id = staff["id"].as_str().unwrap().to_string();
+
+    let mut alice = server.connect(&alice_token).await;
+    let mut bob = server.connect(&bob_token).await;
+    let say = |content: &str| {
+        json!({ "type": "message", "room_id": staff_id, "user_id": alice_id, "username": "alice", "content": content })
+    };
+    alice.send(say("before")).await;
+    bob.expect(|e| event_type(e) == "message" && e["content"] == "before").await;
+    let (status, _) = server.send(server.get("/api/server/storage"), Some(&bob_token)).await;
+    assert_eq!(status, 200);
+
+    admin_cli(&server, &["user", "set-role", "bob", "user"]);
+    assert!(bob.receives(|e| event_type(e) == "access_changed", POLL_WAIT).await);
+
+    // The token still says admin; the server goes by the current role
+    let (status, _) = server.send(server.get("/api/server/storage"), Some(&bob_token)).await;
+    assert_eq!(status, 403);
+    alice.send(say("after")).await;
+    alice.expect(|e| event_type(e) == "message" && e["content"] == "after").await;
+    assert!(!bob.receives(|e| event_type(e) == "message", Duration::from_secs(1)).await);
+
+    // Room requirements changed with the CLI apply the same way
+    admin_cli(&server, &["room", "set-required-role", "staff", "user"]);
+    assert!(bob.receives(|e| event_type(e) == "access_changed", POLL_WAIT).await);
+    alice.send(say("reopened")).await;
+    bob.expect(|e| event_type(e) == "message" && e["content"] == "reopened").await;
+}
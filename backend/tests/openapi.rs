@@ -0,0 +1,140 @@
+// Keeps the OpenAPI document in step with the routes the server registers.
+//
+// Routes are read from the app `configure` builds: actix only exposes its
+// resource patterns through the `ResourceMap` debug output, and the methods
+// of each pattern are found by sending a request per method and checking
+// whether a resource took it, rather than the default service.
+
+use actix_web::http::{Method, StatusCode};
+use actix_web::{test, web, App, HttpResponse};
+use backend::config::Config;
+use backend::openapi::ApiDoc;
+use std::collections::BTreeSet;
+use utoipa::openapi::path::{Operation, PathItem};
+use utoipa::OpenApi;
+
+const METHODS: [&str; 5] = ["get", "post", "put", "patch", "delete"];
+/// Answer of the test's default service, for requests no route took
+const UNROUTED: StatusCode = StatusCode::IM_A_TEAPOT;
+
+fn operation<'a>(item: &'a PathItem, method: &str) -> Option<&'a Operation> {
+    match method {
+        "get" => item.get.as_ref(),
+        "post" => item.post.as_ref(),
+        "put" => item.put.as_ref(),
+        "patch" => item.patch.as_ref(),
+        "delete" => item.delete.as_ref(),
+        other => panic!("unexpected method {other}"),
+    }
+}
+
+/// Resource patterns in the debug form of the app's `ResourceMap`.
+fn resource_patterns(resource_map: &str) -> BTreeSet<String> {
+    let mut patterns = BTreeSet::new();
+    for node in resource_map.split("ResourceDef {").skip(1) {
+        let field = |name: &str| {
+            let start = node.find(name).unwrap_or_else(|| panic!("no {name} in {node}")) + name.len();
+            node[start..].split([',', ' ']).next().unwrap().to_string()
+        };
+        let pattern = field("patterns: Single(\"").trim_end_matches(['"', ')']).to_string();
+        if field("is_prefix: ") == "true" {
+            // The app root; routes nested in a scope would need its prefix
+            assert!(pattern.is_empty(), "scope {pattern:?}: teach this test about nested resources");
+            continue;
+        }
+        patterns.insert(pattern);
+    }
+    patterns
+}
+
+/// A concrete path matching `pattern`.
+fn sample_path(pattern: &str) -> String {
+    pattern
+        .split('/')
+        .map(|segment| if segment.starts_with('{') { "sample" } else { segment })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Names of the `{…}` segments in `pattern`.
+fn pattern_vars(pattern: &str) -> BTreeSet<String> {
+    pattern
+        .split('/')
+        .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// `(method, path)` of every route the app serves.
+async fn registered_routes() -> BTreeSet<(String, String)> {
+    let dir = std::env::temp_dir().join(format!("voxium-openapi-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let pool = backend::db::connect_to(&format!("sqlite:{}", dir.join("voxium.db").display()), 4).await;
+    backend::db::run_migrations(&pool).await.expect("migrations");
+    let mut config = Config::default();
+    config.auth.jwt_secret = "openapi-test-secret".to_string();
+    config.storage.root = dir.join("uploads").display().to_string();
+    config.server.api_docs = true;
+    let state = backend::AppState::new(pool, config);
+    let app = test::init_service(
+        App::new()
+            .configure(|cfg| backend::configure(cfg, &state))
+            .default_service(web::to(|| async { HttpResponse::new(UNROUTED) })),
+    )
+    .await;
+
+    let probe = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+    let patterns = resource_patterns(&format!("{:?}", probe.request().resource_map()));
+    assert!(!patterns.is_empty(), "no resources found in the app");
+
+    // Unauthenticated requests without a body: handlers refuse them early
+    let mut routes = BTreeSet::new();
+    for pattern in patterns {
+        for method in METHODS {
+            let req = test::TestRequest::default()
+                .method(Method::from_bytes(method.to_uppercase().as_bytes()).unwrap())
+                .uri(&sample_path(&pattern))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            if resp.status() == UNROUTED || resp.status() == StatusCode::METHOD_NOT_ALLOWED {
+                continue;
+            }
+            // `/api/messages/search` can also be taken by `/api/messages/{id}`
+            let captured: BTreeSet<String> = resp.request().match_info().iter().map(|(name, _)| name.to_string()).collect();
+            if captured == pattern_vars(&pattern) {
+                routes.insert((method.to_string(), pattern.clone()));
+            }
+        }
+    }
+    std::fs::remove_dir_all(&dir).ok();
+    routes
+}
+
+#[actix_web::test]
+async fn every_route_is_documented() {
+    let spec = ApiDoc::openapi();
+    let missing: Vec<String> = registered_routes()
+        .await
+        .into_iter()
+        .filter(|(method, path)| spec.paths.paths.get(path).is_none_or(|item| operation(item, method).is_none()))
+        .map(|(method, path)| format!("{} {}", method.to_uppercase(), path))
+        .collect();
+    assert!(
+        missing.is_empty(),
+        "routes missing from the OpenAPI document (annotate the handler with #[utoipa::path] and list it in openapi::ApiDoc): {missing:?}"
+    );
+}
+
+#[actix_web::test]
+async fn every_documented_route_exists() {
+    let registered = registered_routes().await;
+    let mut stale = Vec::new();
+    for (path, item) in &ApiDoc::openapi().paths.paths {
+        for method in METHODS {
+            if operation(item, method).is_some() && !registered.contains(&(method.to_string(), path.clone())) {
+                stale.push(format!("{} {}", method.to_uppercase(), path));
+            }
+        }
+    }
+    assert!(stale.is_empty(), "documented routes that are not registered: {stale:?}");
+}
@@ -0,0 +1,96 @@
+// Uploads once posted: ownership, thumbnails, quotas and reference counts
+// across sweeps.
+
+mod common;
+
+use common::{bare_url, event_type, png_bytes, TestServer};
+use serde_json::{json, Value};
+
+/// Post a message in `general` and return the broadcast event.
+async fn post_message(server: &TestServer, token: &str, user_id: &str, extra: Value) -> Value {
+    let mut client = server.connect(token).await;
+    let mut frame = json!({ "type": "message", "room_id": "general", "user_id": user_id, "content": "" });
+    frame.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+    client.send(frame).await;
+    client.expect(|e| event_type(e) == "message").await
+}
+
+#[actix_web::test]
+async fn posted_images_keep_thumbnails_after_sweep() {
+    let server = TestServer::start().await;
+    let (bob_id, bob_token) = server.register("bob").await;
+
+    let image = server.upload(&bob_token, "image.png", png_bytes(1)).await;
+    let posted = post_message(&server, &bob_token, &bob_id, json!({ "image_url": image["url"] })).await;
+    assert!(posted["thumbnail_url"].is_string());
+    let attached = server.upload(&bob_token, "attached.png", png_bytes(2)).await;
+    let posted = post_message(&server, &bob_token, &bob_id, json!({ "attachment_ids": [attached["id"]] })).await;
+    assert_eq!(posted["attachments"][0]["id"], attached["id"]);
+
+    // A grace period of zero expires every upload not attached to a message
+    backend::upload_gc::sweep(server.pool(), &server.state.upload_storage, 0).await.expect("sweep");
+
+    let history = server.ok(server.get("/api/rooms/general/messages"), Some(&bob_token)).await;
+    let history = history.as_array().unwrap();
+    let by_image = history.iter().find(|m| m["image_url"].is_string()).expect("image message");
+    assert!(by_image["thumbnail_url"].is_string(), "{by_image}");
+    assert!(by_image["attachments"].as_array().unwrap().is_empty(), "image listed twice: {by_image}");
+    let by_attachment = history.iter().find(|m| m["image_url"].is_null()).expect("attachment message");
+    assert!(by_attachment["attachments"][0]["thumbnail_url"].is_string(), "{by_attachment}");
+
+    // The image is still the sender's own, so it can be posted again
+    let reposted = post_message(&server, &bob_token, &bob_id, json!({ "image_url": bare_url(&image) })).await;
+    assert!(reposted["image_url"].is_string());
+    assert!(reposted["thumbnail_url"].is_string());
+}
+
+#[actix_web::test]
+async fn posted_images_count_toward_usage_after_sweep() {
+    let server = TestServer::start().await;
+    let (bob_id, bob_token) = server.register("bob").await;
+    let usage = || async { server.ok(server.get("/api/users/me/storage"), Some(&bob_token)).await };
+
+    let image = server.upload(&bob_token, "image.png", png_bytes(1)).await;
+    post_message(&server, &bob_token, &bob_id, json!({ "image_url": image["url"] })).await;
+    let posted = usage().await;
+    assert_eq!(posted["files"], 1);
+    assert_eq!(posted["used"], image["size"]);
+
+    backend::upload_gc::sweep(server.pool(), &server.state.upload_storage, 0).await.expect("sweep");
+    let swept = usage().await;
+    assert_eq!(swept["files"], 1);
+    assert_eq!(swept["used"], image["size"]);
+
+    // Messages posted before uploads were claimed still count through `image_url`
+    sqlx::query("UPDATE message_attachments SET message_id = NULL").execute(server.pool()).await.unwrap();
+    backend::upload_gc::sweep(server.pool(), &server.state.upload_storage, 0).await.expect("sweep");
+    let legacy = usage().await;
+    assert_eq!(legacy["files"], 1);
+    assert_eq!(legacy["used"], image["size"]);
+}
+
+#[actix_web::test]
+async fn resync_keeps_references_taken_during_an_upload() {
+    let server = TestServer::start().await;
+    let (bob_id, bob_token) = server.register("bob").await;
+    let image = server.upload(&bob_token, "image.png", png_bytes(1)).await;
+    post_message(&server, &bob_token, &bob_id, json!({ "image_url": image["url"] })).await;
+    let ref_count = || async {
+        sqlx::query_scalar::<_, i64>("SELECT ref_count FROM upload_blobs").fetch_one(server.pool()).await.unwrap()
+    };
+
+    let posted = ref_count().await;
+
+    // A second upload of the same file has its reference but no row yet
+    backend::uploads::retain_upload(server.pool(), &bare_url(&image)).await;
+    backend::upload_gc::sweep(server.pool(), &server.state.upload_storage, 3600).await.expect("sweep");
+    assert_eq!(ref_count().await, posted + 1);
+
+    // Past the grace period the count follows the rows again
+    sqlx::query("UPDATE upload_blobs SET created_at = '2000-01-01 00:00:00', retained_at = '2000-01-01 00:00:00'")
+        .execute(server.pool())
+        .await
+        .unwrap();
+    backend::upload_gc::sweep(server.pool(), &server.state.upload_storage, 3600).await.expect("sweep");
+    assert_eq!(ref_count().await, posted);
+}
@@ -0,0 +1,50 @@
+// Voice signalling between members of a voice room.
+
+mod common;
+
+use common::{event_type, TestServer};
+use serde_json::json;
+
+#[actix_web::test]
+async fn server_muted_member_still_negotiates() {
+    let server = TestServer::start().await;
+    let (alice_id, alice_token) = server.register_admin("alice").await;
+    let (bob_id, bob_token) = server.register("bob").await;
+    let room = server
+        .ok(server.post("/api/rooms").json(&json!({ "name": "lounge", "kind": "voice" })), Some(&alice_token))
+        .await;
+    let room_id = room["id"].as_str().unwrap();
+
+    let mut alice = server.connect(&alice_token).await;
+    let mut bob = server.connect(&bob_token).await;
+    for (client, user_id) in [(&mut alice, &alice_id), (&mut bob, &bob_id)] {
+        client.send(json!({ "type": "voice_join", "room_id": room_id })).await;
+        client.expect(|e| event_type(e) == "voice_join" && e["user_id"] == user_id.as_str()).await;
+    }
+
+    server
+        .ok(
+            server.patch(&format!("/api/voice/members/{bob_id}")).json(&json!({ "server_muted": true })),
+            Some(&alice_token),
+        )
+        .await;
+    bob.expect(|e| event_type(e) == "voice_state" && e["server_muted"] == true).await;
+
+    // Offer to the muted member, and its answer back
+    alice
+        .send(json!({ "type": "voice_signal", "target_user_id": bob_id, "sdp": { "type": "offer", "sdp": "v=0" } }))
+        .await;
+    let offer = bob.expect(|e| event_type(e) == "voice_signal").await;
+    assert_eq!(offer["user_id"], alice_id.as_str());
+    assert_eq!(offer["sdp"]["type"], "offer");
+
+    bob.send(json!({ "type": "voice_signal", "target_user_id": alice_id, "sdp": { "type": "answer", "sdp": "v=0" } }))
+        .await;
+    bob.send(json!({ "type": "voice_signal", "target_user_id": alice_id, "candidate": { "candidate": "c" } }))
+        .await;
+    let answer = alice.expect(|e| event_type(e) == "voice_signal").await;
+    assert_eq!(answer["user_id"], bob_id.as_str());
+    assert_eq!(answer["sdp"]["type"], "answer");
+    let candidate = alice.expect(|e| event_type(e) == "voice_signal").await;
+    assert_eq!(candidate["candidate"]["candidate"], "c");
+}
@@ -7,8 +7,10 @@ fn main() {
         backend::run_server();
     });
 
-    // Give the backend a moment to bind the port
-    std::thread::sleep(std::time::Duration::from_millis(500));
+    // Open the window once the backend can serve requests
+    if !backend::health::wait_until_ready(std::time::Duration::from_secs(30)) {
+        eprintln!("Backend not ready after 30s, starting anyway");
+    }
 
     tauri::Builder::default()
         .run(tauri::generate_context!())
@@ -65,7 +65,7 @@ window.VoxiumContext = (() => {
                 });
                 const data = await res.json().catch(() => []);
                 if (!res.ok) {
-                    setRoomSettingsFeedback(data.error || "Impossible de charger les rôles", true);
+                    setRoomSettingsFeedback(data.message || data.error || "Impossible de charger les rôles", true);
                     return;
                 }
 
@@ -145,7 +145,7 @@ window.VoxiumContext = (() => {
                     });
                     if (!res.ok) {
                         const data = await res.json();
-                        alert(data.error || "Erreur");
+                        alert(data.message || data.error || "Erreur");
                     }
                 } catch (e) { alert("Erreur réseau"); }
             });
@@ -215,7 +215,7 @@ window.VoxiumContext = (() => {
 
                         const data = await res.json().catch(() => ({}));
                         if (!res.ok) {
-                            setRoomSettingsFeedback(data.error || "Erreur", true);
+                            setRoomSettingsFeedback(data.message || data.error || "Erreur", true);
                             return;
                         }
 
@@ -266,7 +266,7 @@ window.VoxiumContext = (() => {
                         alert(`${ctxTarget.name} est maintenant Admin !`);
                     } else {
                         const data = await res.json();
-                        alert(data.error || "Erreur");
+                        alert(data.message || data.error || "Erreur");
                     }
                 } catch (e) { alert("Erreur réseau"); }
             });
@@ -283,7 +283,7 @@ window.VoxiumContext = (() => {
                     });
                     if (!res.ok) {
                         const data = await res.json();
-                        alert(data.error || "Erreur");
+                        alert(data.message || data.error || "Erreur");
                     }
                 } catch (e) { alert("Erreur réseau"); }
             });
@@ -300,7 +300,7 @@ window.VoxiumContext = (() => {
                     });
                     if (!res.ok) {
                         const data = await res.json();
-                        alert(data.error || "Erreur");
+                        alert(data.message || data.error || "Erreur");
                     }
                 } catch (e) {
                     alert("Erreur réseau");
@@ -74,7 +74,7 @@ window.VoxiumDiscordVoice = (() => {
 
             if (!resp.ok) {
                 const err = await resp.json().catch(() => ({}));
-                throw new Error(err.error || `HTTP ${resp.status}`);
+                throw new Error(err.message || err.error || `HTTP ${resp.status}`);
             }
 
             voiceInfo = await resp.json();
@@ -317,9 +317,7 @@ window.VoxiumDiscordVoice = (() => {
         // readyData contains: ssrc, ip, port, modes, experiments
         // We use WebRTC mode (protocol: "webrtc")
 
-        const iceServers = (runtime.iceServers || []).concat([
-            { urls: "stun:stun.l.google.com:19302" },
-        ]);
+        const iceServers = await window.VoxiumIceServers.get();
 
         pc = new RTCPeerConnection({ iceServers, bundlePolicy: "max-bundle" });
 
@@ -0,0 +1,92 @@
+// ═══════════════════════════════════════════════════════
+//  Voxium — ICE servers
+// ═══════════════════════════════════════════════════════
+//
+// STUN/TURN servers come from GET /api/voice/ice-servers: TURN credentials
+// are short-lived and issued per user, so none ship with the client. The
+// list is fetched again once most of its `ttl` has passed; open peer
+// connections pick up the new credentials through `onRefresh`.
+//
+window.VoxiumIceServers = (() => {
+    const runtime = window.VOXIUM_RUNTIME_CONFIG || {};
+    const apiBase = (runtime.apiBaseUrl || "http://127.0.0.1:8080").replace(/\/$/, "");
+
+    // Used until the server answers, or when it cannot be reached: STUN only
+    const FALLBACK_ICE_SERVERS = [{ urls: "stun:stun.l.google.com:19302" }];
+    // Refresh after this share of the credential lifetime
+    const REFRESH_AT = 0.8;
+    const MIN_TTL_SECS = 60;
+
+    let iceServers = null;
+    let refreshAt = 0;
+    let refreshTimer = null;
+    let pending = null;
+    const listeners = [];
+
+    async function fetchIceServers() {
+        const token = localStorage.getItem("token") || "";
+        const res = await fetch(`${apiBase}/api/voice/ice-servers`, {
+            headers: { Authorization: `Bearer ${token}` },
+        });
+        if (!res.ok) {
+            throw new Error(`ICE servers request failed (${res.status})`);
+        }
+
+        const data = await res.json();
+        const ttl = Math.max(MIN_TTL_SECS, Number(data.ttl) || 0);
+        iceServers = Array.isArray(data.ice_servers) && data.ice_servers.length > 0
+            ? data.ice_servers
+            : FALLBACK_ICE_SERVERS;
+        refreshAt = Date.now() + ttl * 1000 * REFRESH_AT;
+
+        clearTimeout(refreshTimer);
+        refreshTimer = setTimeout(() => {
+            refreshAt = 0;
+            get().then((servers) => listeners.forEach((listener) => listener(servers)));
+        }, refreshAt - Date.now());
+
+        return iceServers;
+    }
+
+    /**
+     * ICE servers for a new RTCPeerConnection, fetched when missing or due
+     * for a refresh. Falls back to public STUN when the server is unreachable.
+     * @returns {Promise<RTCIceServer[]>}
+     */
+    function get() {
+        if (iceServers && Date.now() < refreshAt) {
+            return Promise.resolve(iceServers);
+        }
+        if (!pending) {
+            pending = fetchIceServers()
+                .catch((err) => {
+                    console.error("Failed to load ICE servers", err);
+                    return current();
+                })
+                .finally(() => {
+                    pending = null;
+                });
+        }
+        return pending;
+    }
+
+    /** Last fetched list, without waiting. */
+    function current() {
+        return iceServers || FALLBACK_ICE_SERVERS;
+    }
+
+    /** Called with the new list after each scheduled refresh. */
+    function onRefresh(listener) {
+        listeners.push(listener);
+    }
+
+    /** Forget the credentials, on logout. */
+    function clear() {
+        clearTimeout(refreshTimer);
+        refreshTimer = null;
+        iceServers = null;
+        refreshAt = 0;
+    }
+
+    return { get, current, onRefresh, clear };
+})();
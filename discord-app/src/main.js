@@ -5,11 +5,8 @@
 const RUNTIME_CONFIG = window.VOXIUM_RUNTIME_CONFIG || {};
 const API = RUNTIME_CONFIG.apiBaseUrl || "http://127.0.0.1:8080";
 const WS_URL = RUNTIME_CONFIG.wsUrl || "ws://127.0.0.1:8080/ws";
-const WEBRTC_CONFIG = {
-    iceServers: Array.isArray(RUNTIME_CONFIG.iceServers) && RUNTIME_CONFIG.iceServers.length > 0
-        ? RUNTIME_CONFIG.iceServers
-        : [{ urls: "stun:stun.l.google.com:19302" }]
-};
+// WebSocket error frames about our own voice session
+const VOICE_ERROR_CODES = ["server_muted", "server_deafened", "not_voice_room", "voice_full"];
 const QUICK_REACTION_EMOJIS = ["👍", "❤️", "😂", "😮", "😢", "🔥"];
 const MESSAGE_REACTION_PICKER_ID = "message-reaction-picker";
 
@@ -111,6 +108,8 @@ const app = $("#app");
 const authForm = $("#auth-form");
 const authUsername = $("#auth-username");
 const authPassword = $("#auth-password");
+const authInviteGroup = $("#auth-invite-group");
+const authInvite = $("#auth-invite");
 const authSubmit = $("#auth-submit");
 const authDiscordBtn = $("#auth-discord-btn");
 const authDiscordQrWrap = $("#auth-discord-qr");
@@ -386,6 +385,7 @@ tabLogin.addEventListener("click", () => {
     authMode = "login";
     tabLogin.classList.add("active");
     tabRegister.classList.remove("active");
+    authInviteGroup.classList.add("hidden");
     authSubmit.textContent = "Se connecter";
 });
 
@@ -393,6 +393,7 @@ tabRegister.addEventListener("click", () => {
     authMode = "register";
     tabRegister.classList.add("active");
     tabLogin.classList.remove("active");
+    authInviteGroup.classList.remove("hidden");
     authSubmit.textContent = "S'inscrire";
 });
 
@@ -460,7 +461,7 @@ async function startDiscordQrAuth() {
         const res = await fetch(`${API}/api/auth/discord/qr/start`, { method: "POST" });
         const data = await res.json();
         if (!res.ok || !data.session_id) {
-            throw new Error(data.error || "Impossible de démarrer la session QR.");
+            throw new Error(data.message || data.error || "Impossible de démarrer la session QR.");
         }
         discordQrSessionId = data.session_id;
     } catch (err) {
@@ -474,7 +475,7 @@ async function startDiscordQrAuth() {
         try {
             const res = await fetch(`${API}/api/auth/discord/qr/status?session_id=${encodeURIComponent(discordQrSessionId)}`);
             const status = await res.json();
-            if (!res.ok) { throw new Error(status.error || "Session expirée."); }
+            if (!res.ok) { throw new Error(status.message || status.error || "Session expirée."); }
 
             switch (status.status) {
                 case "connecting":
@@ -544,16 +545,19 @@ authForm.addEventListener("submit", async (e) => {
     const username = authUsername.value.trim();
     const password = authPassword.value;
     if (!username || !password) return;
+    const payload = { username, password };
+    const inviteCode = authInvite.value.trim();
+    if (authMode === "register" && inviteCode) payload.invite_code = inviteCode;
 
     try {
         const res = await fetch(`${API}/api/${authMode}`, {
             method: "POST",
             headers: { "Content-Type": "application/json" },
-            body: JSON.stringify({ username, password }),
+            body: JSON.stringify(payload),
         });
         const data = await res.json();
         if (!res.ok) {
-            authError.textContent = data.error || "Erreur d'authentification";
+            authError.textContent = data.message || data.error || "Erreur d'authentification";
             return;
         }
         saveSession(data);
@@ -669,6 +673,7 @@ function logout() {
     stopMicMeter();
     if (state.ws) state.ws.close();
     localStorage.removeItem("token");
+    window.VoxiumIceServers.clear();
     localStorage.removeItem("userId");
     localStorage.removeItem("username");
     state = {
@@ -683,6 +688,7 @@ function logout() {
     authModal.classList.remove("hidden");
     authUsername.value = "";
     authPassword.value = "";
+    authInvite.value = "";
     authError.textContent = "";
     updateVoiceQuickStatus();
 }
@@ -1016,16 +1022,23 @@ function connectWebSocket() {
             role: state.role || "user"
         }));
 
+        // The server forgot our place in line with the old socket
+        voiceController.sendQueuedJoin();
+
         if (state.voice.joinedRoomId) {
-            state.ws.send(JSON.stringify({
-                type: "voice_join",
-                room_id: state.voice.joinedRoomId,
-                user_id: state.userId,
-                username: state.username,
-                muted: state.voice.muted,
-                deafened: state.voice.deafened,
-                screen_sharing: state.voice.screenSharing,
-            }));
+            // Credentials may have expired while we were offline
+            window.VoxiumIceServers.get().then(() => {
+                if (!state.voice.joinedRoomId) return;
+                wsSend({
+                    type: "voice_join",
+                    room_id: state.voice.joinedRoomId,
+                    user_id: state.userId,
+                    username: state.username,
+                    muted: state.voice.muted,
+                    deafened: state.voice.deafened,
+                    screen_sharing: state.voice.screenSharing,
+                });
+            });
         }
     };
 
@@ -1234,9 +1247,27 @@ function connectWebSocket() {
                     showTypingIndicator(msg.username);
                 }
             }
-            else if (msg.type === "voice_join" || msg.type === "voice_leave" || msg.type === "voice_state" || msg.type === "voice_signal") {
+            else if (msg.type === "voice_join" || msg.type === "voice_leave" || msg.type === "voice_state" || msg.type === "voice_signal"
+                || msg.type === "voice_move" || msg.type === "voice_disconnect" || msg.type === "voice_queue_ready") {
                 handleVoiceWsEvent(msg);
             }
+            else if (msg.type === "error" && VOICE_ERROR_CODES.includes(msg.code)) {
+                handleVoiceWsEvent(msg);
+            }
+            else if (msg.type === "error" && msg.code === "message_not_saved") {
+                showToast("Le message n'a pas pu être envoyé", "error");
+            }
+            else if (msg.type === "access_changed") {
+                // Our role or the rooms we may see may have changed
+                fetchMyProfile();
+                loadRooms();
+            }
+            else if (msg.type === "banned") {
+                // The server closes the socket next; don't reconnect
+                state.ws.onclose = null;
+                logout();
+                authError.textContent = msg.message || "Ce compte est banni";
+            }
         } catch (err) {
             console.error("WS error:", err);
         }
@@ -1256,8 +1287,9 @@ function wsSend(payload) {
 voiceController = window.VoxiumVoice.createVoiceController({
     getState: () => state,
     API,
-    WEBRTC_CONFIG,
+    iceServers: window.VoxiumIceServers,
     wsSend,
+    showToast,
     escapeHtml,
     hashString,
     dom: {
@@ -1392,8 +1424,8 @@ function handleVoiceWsEvent(msg) {
     return voiceController.handleVoiceWsEvent(msg);
 }
 
-async function joinVoiceRoom() {
-    return voiceController.joinVoiceRoom();
+async function joinVoiceRoom(roomId = null) {
+    return voiceController.joinVoiceRoom(roomId);
 }
 
 function leaveVoiceRoom() {
@@ -1647,7 +1679,7 @@ async function toggleMessageReaction(messageId, emoji) {
         }
 
         if (!res.ok) {
-            alert(data?.error || "Erreur");
+            alert(data?.message || data?.error || "Erreur");
             return;
         }
 
@@ -1702,7 +1734,7 @@ function renderThreadPanel() {
     threadPanel.classList.remove("hidden");
     chatArea?.classList.add("thread-open");
 
-    const rootText = (rootMsg.content && rootMsg.content.trim()) || (rootMsg.image_url ? "[Image]" : "Message");
+    const rootText = messagePreviewText(rootMsg);
     threadRoot.innerHTML = `
         <div class="thread-item">
             <div class="thread-item-user">${escapeHtml(rootMsg.username || "Utilisateur")}</div>
@@ -1721,7 +1753,7 @@ function renderThreadPanel() {
 
     threadReplies.innerHTML = "";
     replies.forEach((reply) => {
-        const content = (reply.content && reply.content.trim()) || (reply.image_url ? "[Image]" : "Message");
+        const content = messagePreviewText(reply);
         const row = document.createElement("div");
         row.className = "thread-item";
         row.innerHTML = `
@@ -1768,6 +1800,7 @@ function appendMessage(msg, isFirstInGroup = true, parent = messagesContainer) {
         user_id: msg.user_id,
         content: msg.content || "",
         image_url: msg.image_url || null,
+        attachments: msg.attachments || [],
         created_at: msg.created_at || null,
         reply_to_id: msg.reply_to_id || null,
         avatar_url: msg.avatar_url || null,
@@ -1821,6 +1854,19 @@ function appendMessage(msg, isFirstInGroup = true, parent = messagesContainer) {
             <img class="message-image" src="${API}${msg.image_url}" alt="image" data-lightbox="${escapeHtml(API + msg.image_url)}" />
         </div>
     ` : '';
+    const attachmentsHtml = (msg.attachments || []).map((a) => {
+        if ((a.mime_type || "").startsWith("image/")) {
+            return `
+                <div class="message-image-wrapper">
+                    <img class="message-image" src="${API}${a.thumbnail_url || a.url}" alt="${escapeHtml(a.filename)}" data-lightbox="${escapeHtml(API + a.url)}" />
+                </div>`;
+        }
+        return `
+            <div class="message-file">
+                <a href="${escapeHtml(API + a.url)}" target="_blank" rel="noopener">${escapeHtml(a.filename)}</a>
+                <span class="message-file-size">(${formatFileSize(a.size)})</span>
+            </div>`;
+    }).join("");
 
     // Detect emoji-only messages for jumbo display
     const emojiClass = msg.content ? getEmojiClass(msg.content) : '';
@@ -1840,7 +1886,7 @@ function appendMessage(msg, isFirstInGroup = true, parent = messagesContainer) {
         const parent = state.messageMetaById[msg.reply_to_id];
         const parentName = parent?.username || "Message";
         const parentSnippet = parent
-            ? ((parent.content && parent.content.trim()) || (parent.image_url ? "[Image]" : "Message"))
+            ? (messagePreviewText(parent))
             : "Message introuvable";
 
         replyRefHtml = `
@@ -1870,6 +1916,7 @@ function appendMessage(msg, isFirstInGroup = true, parent = messagesContainer) {
                 ${replyRefHtml}
                 ${contentHtml}
                 ${imageHtml}
+                ${attachmentsHtml}
                 ${reactionsHtml}
             </div>
         `;
@@ -1882,6 +1929,7 @@ function appendMessage(msg, isFirstInGroup = true, parent = messagesContainer) {
                 ${replyRefHtml}
                 ${contentHtml}
                 ${imageHtml}
+                ${attachmentsHtml}
                 ${reactionsHtml}
             </div>
         `;
@@ -1914,7 +1962,7 @@ function appendMessage(msg, isFirstInGroup = true, parent = messagesContainer) {
         replyBtn.addEventListener("click", (event) => {
             event.preventDefault();
             event.stopPropagation();
-            const previewSnippet = (msg.content && msg.content.trim()) || (msg.image_url ? "[Image]" : "Message");
+            const previewSnippet = messagePreviewText(msg);
             setReplyTarget({
                 id: msg.id,
                 username: msg.username,
@@ -1947,7 +1995,7 @@ function appendMessage(msg, isFirstInGroup = true, parent = messagesContainer) {
                 });
                 if (!res.ok) {
                     const data = await res.json();
-                    alert(data.error || "Erreur");
+                    alert(data.message || data.error || "Erreur");
                 }
             } catch (e) {
                 alert("Erreur réseau");
@@ -1983,7 +2031,7 @@ window.deleteMessageFromBtn = async function (msgId) {
         });
         if (!res.ok) {
             const data = await res.json();
-            alert(data.error || "Erreur");
+            alert(data.message || data.error || "Erreur");
         }
     } catch (e) { alert("Erreur réseau"); }
 };
@@ -2043,7 +2091,7 @@ messageForm.addEventListener("submit", async (e) => {
     if (state.currentRoomKind !== "text") return;
     if (!state.currentRoomId || !state.ws) return;
 
-    let imageUrl = null;
+    let attachmentId = null;
 
     // Upload image first if there is one
     if (file) {
@@ -2057,10 +2105,10 @@ messageForm.addEventListener("submit", async (e) => {
             });
             if (res.ok) {
                 const data = await res.json();
-                imageUrl = data.url;
+                attachmentId = data.id;
             } else {
                 const data = await res.json();
-                alert(data.error || "Erreur d'upload");
+                alert(data.message || data.error || "Erreur d'upload");
                 return;
             }
         } catch (err) {
@@ -2080,7 +2128,7 @@ messageForm.addEventListener("submit", async (e) => {
     if (state.replyingTo?.id) {
         msg.reply_to_id = state.replyingTo.id;
     }
-    if (imageUrl) msg.image_url = imageUrl;
+    if (attachmentId) msg.attachment_ids = [attachmentId];
 
     state.ws.send(JSON.stringify(msg));
     messageInput.value = "";
@@ -2121,7 +2169,7 @@ async function loadPinnedMessages() {
                     <span class="pinned-item-user">${escapeHtml(item.username || "Utilisateur")}</span>
                     <span class="pinned-item-time">${escapeHtml(formatTime(item.created_at))}</span>
                 </div>
-                <div class="pinned-item-content">${escapeHtml((item.content && item.content.trim()) || (item.image_url ? "[Image]" : "Message"))}</div>
+                <div class="pinned-item-content">${escapeHtml(messagePreviewText(item))}</div>
             `;
             row.addEventListener("click", () => {
                 pinnedModal.classList.add("hidden");
@@ -2230,7 +2278,7 @@ deleteRoomBtn.addEventListener("click", async () => {
         });
         if (!res.ok) {
             const data = await res.json();
-            alert(data.error || "Erreur lors de la suppression");
+            alert(data.message || data.error || "Erreur lors de la suppression");
         }
     } catch (err) {
         alert("Erreur réseau");
@@ -2391,7 +2439,7 @@ function renderServerRoles() {
                         });
                         const data = await res.json().catch(() => ({}));
                         if (!res.ok) {
-                            setServerSettingsFeedback(data.error || "Erreur", true);
+                            setServerSettingsFeedback(data.message || data.error || "Erreur", true);
                             return;
                         }
                         await loadServerSettingsData();
@@ -2447,10 +2495,10 @@ async function loadServerSettingsData() {
     const usersData = await usersRes.json().catch(() => []);
 
     if (!rolesRes.ok) {
-        throw new Error(rolesData.error || "Impossible de charger les rôles");
+        throw new Error(rolesData.message || rolesData.error || "Impossible de charger les rôles");
     }
     if (!usersRes.ok) {
-        throw new Error(usersData.error || "Impossible de charger les membres");
+        throw new Error(usersData.message || usersData.error || "Impossible de charger les membres");
     }
 
     state.serverRoles = Array.isArray(rolesData) ? rolesData : [];
@@ -2529,7 +2577,7 @@ if (serverRoleForm) {
             });
             const data = await res.json().catch(() => ({}));
             if (!res.ok) {
-                setServerSettingsFeedback(data.error || "Erreur", true);
+                setServerSettingsFeedback(data.message || data.error || "Erreur", true);
                 return;
             }
 
@@ -2559,7 +2607,7 @@ if (serverAssignBtn) {
             });
             const data = await res.json().catch(() => ({}));
             if (!res.ok) {
-                setServerSettingsFeedback(data.error || "Erreur", true);
+                setServerSettingsFeedback(data.message || data.error || "Erreur", true);
                 return;
             }
 
@@ -2957,7 +3005,7 @@ updateProfileForm.addEventListener("submit", async (e) => {
             connectWebSocket();
         } else {
             const data = await res.json();
-            alert(data.error || "Erreur de mise à jour");
+            alert(data.message || data.error || "Erreur de mise à jour");
         }
     } catch (err) {
         alert("Erreur réseau");
@@ -2993,7 +3041,7 @@ saveProfileBtn.addEventListener("click", async () => {
             connectWebSocket();
         } else {
             const data = await res.json();
-            alert(data.error || "Erreur de mise \u00e0 jour");
+            alert(data.message || data.error || "Erreur de mise \u00e0 jour");
         }
     } catch (err) {
         alert("Erreur r\u00e9seau");
@@ -3015,6 +3063,7 @@ const bannerCropApply = $("#banner-crop-apply");
 
 let bannerCropImage = null;
 let bannerCropImageUrl = "";
+let bannerCropFile = null;
 let bannerCropState = { zoom: 1, x: 0, y: 0 };
 let bannerCropDragging = false;
 let bannerCropDragStart = { mouseX: 0, mouseY: 0, x: 0, y: 0 };
@@ -3068,6 +3117,7 @@ function closeBannerCropModal() {
         bannerCropImageUrl = "";
     }
     bannerCropImage = null;
+    bannerCropFile = null;
     if (bannerCropPreview) {
         bannerCropPreview.style.backgroundImage = "";
         bannerCropPreview.style.backgroundSize = "cover";
@@ -3094,6 +3144,7 @@ function refreshBannerCropPreview() {
 async function openBannerCropModal(file) {
     if (!bannerCropModal || !bannerCropPreview) return;
 
+    bannerCropFile = file;
     bannerCropImageUrl = URL.createObjectURL(file);
     bannerCropImage = await new Promise((resolve, reject) => {
         const img = new Image();
@@ -3106,32 +3157,31 @@ async function openBannerCropModal(file) {
     bannerCropModal.classList.remove("hidden");
 }
 
-async function exportBannerCroppedBlob() {
+// Crop rectangle in source pixels for the current zoom/offset, at the
+// server's 3:1 banner ratio
+function computeBannerCropRect() {
     if (!bannerCropImage) throw new Error("No crop image");
 
     const outW = 1200;
     const outH = 400;
-    const canvas = document.createElement("canvas");
-    canvas.width = outW;
-    canvas.height = outH;
-    const ctx = canvas.getContext("2d");
-
-    const { drawX, drawY, drawW, drawH } = computeBannerCropTransform(outW, outH);
+    const { drawX, drawY, drawW } = computeBannerCropTransform(outW, outH);
+    const scale = drawW / bannerCropImage.width;
 
-    ctx.drawImage(bannerCropImage, drawX, drawY, drawW, drawH);
-
-    return await new Promise((resolve) => {
-        canvas.toBlob((blob) => resolve(blob), "image/webp", 0.92);
-    });
+    const width = Math.max(1, Math.min(bannerCropImage.width, Math.round(outW / scale)));
+    const height = Math.max(1, Math.min(bannerCropImage.height, Math.round(outH / scale)));
+    const x = clampValue(Math.round(-drawX / scale), 0, bannerCropImage.width - width);
+    const y = clampValue(Math.round(-drawY / scale), 0, bannerCropImage.height - height);
+    return { x, y, width, height };
 }
 
-async function uploadAndSaveBanner(blob) {
-    if (!blob) throw new Error("Invalid banner blob");
+async function uploadAndSaveBanner(file, rect) {
+    if (!file) throw new Error("Invalid banner file");
 
     const formData = new FormData();
-    formData.append("file", new File([blob], "banner.webp", { type: "image/webp" }));
+    formData.append("file", file);
+    const params = new URLSearchParams(rect);
 
-    const uploadRes = await fetch(`${API}/api/upload`, {
+    const uploadRes = await fetch(`${API}/api/users/me/banner?${params}`, {
         method: "POST",
         headers: { Authorization: `Bearer ${state.token}` },
         body: formData
@@ -3139,26 +3189,11 @@ async function uploadAndSaveBanner(blob) {
 
     if (!uploadRes.ok) {
         const data = await uploadRes.json();
-        throw new Error(data.error || "Erreur d'upload");
+        throw new Error(data.message || data.error || "Erreur d'upload");
     }
 
     const uploadData = await uploadRes.json();
-    const bannerUrl = uploadData.url;
-
-    const saveRes = await fetch(`${API}/api/users/me`, {
-        method: "PATCH",
-        headers: {
-            "Content-Type": "application/json",
-            Authorization: `Bearer ${state.token}`
-        },
-        body: JSON.stringify({ banner_url: bannerUrl })
-    });
-
-    if (!saveRes.ok) {
-        throw new Error("Erreur lors de la sauvegarde");
-    }
-
-    state.bannerUrl = bannerUrl;
+    state.bannerUrl = uploadData.url;
     if (bannerRemoveBtn) bannerRemoveBtn.style.display = "inline-flex";
     if (bannerUploadStatus) bannerUploadStatus.textContent = "✓ Bannière mise à jour !";
     populateSettingsUI();
@@ -3259,8 +3294,7 @@ if (bannerCropApply) {
     bannerCropApply.addEventListener("click", async () => {
         if (bannerUploadStatus) bannerUploadStatus.textContent = "Upload de la bannière...";
         try {
-            const blob = await exportBannerCroppedBlob();
-            await uploadAndSaveBanner(blob);
+            await uploadAndSaveBanner(bannerCropFile, computeBannerCropRect());
             closeBannerCropModal();
         } catch (err) {
             if (bannerUploadStatus) {
@@ -3318,40 +3352,23 @@ avatarFileInput.addEventListener("change", async () => {
     try {
         const formData = new FormData();
         formData.append("file", file);
-        const uploadRes = await fetch(`${API}/api/upload`, {
+        const uploadRes = await fetch(`${API}/api/users/me/avatar`, {
             method: "POST",
             headers: { Authorization: `Bearer ${state.token}` },
             body: formData
         });
 
-        if (!uploadRes.ok) {
-            const data = await uploadRes.json();
-            avatarUploadStatus.textContent = data.error || "Erreur d'upload";
-            return;
-        }
-
-        const uploadData = await uploadRes.json();
-        const avatarUrl = uploadData.url;
-
-        // Save to profile
-        const saveRes = await fetch(`${API}/api/users/me`, {
-            method: "PATCH",
-            headers: {
-                "Content-Type": "application/json",
-                Authorization: `Bearer ${state.token}`
-            },
-            body: JSON.stringify({ avatar_url: avatarUrl })
-        });
-
-        if (saveRes.ok) {
-            state.avatarUrl = avatarUrl;
+        if (uploadRes.ok) {
+            const uploadData = await uploadRes.json();
+            state.avatarUrl = uploadData.url;
             avatarUploadStatus.textContent = "\u2713 Avatar mis \u00e0 jour !";
             avatarRemoveBtn.style.display = "inline-flex";
             updateUserPanel();
             populateSettingsUI();
             connectWebSocket();
         } else {
-            avatarUploadStatus.textContent = "Erreur lors de la sauvegarde";
+            const data = await uploadRes.json().catch(() => ({}));
+            avatarUploadStatus.textContent = data.message || data.error || "Erreur d'upload";
         }
     } catch (err) {
         avatarUploadStatus.textContent = "Erreur r\u00e9seau";
@@ -3456,7 +3473,7 @@ createRoomForm.addEventListener("submit", async (e) => {
             await loadRooms();
         } else {
             const data = await res.json();
-            alert(data.error || "Erreur");
+            alert(data.message || data.error || "Erreur");
         }
     } catch (err) { alert("Erreur réseau"); }
 });
@@ -3556,6 +3573,14 @@ function escapeHtml(s) {
         .replace(/'/g, "&#039;");
 }
 
+// Short text for a message in replies, pins and search results
+function messagePreviewText(msg) {
+    if (msg.content && msg.content.trim()) return msg.content.trim();
+    const attachments = msg.attachments || [];
+    if (msg.image_url || attachments.some((a) => (a.mime_type || "").startsWith("image/"))) return "[Image]";
+    return attachments.length ? "[Fichier]" : "Message";
+}
+
 function hashString(str) {
     let hash = 0;
     for (let i = 0; i < str.length; i++) {
@@ -3661,7 +3686,7 @@ function renderSearchResults(items) {
         row.className = "search-result-item";
         const room = state.rooms.find((r) => r.id === item.room_id);
         const roomLabel = room ? `#${room.name}` : "salon";
-        const content = (item.content && item.content.trim()) || (item.image_url ? "[Image]" : "Message");
+        const content = messagePreviewText(item);
 
         row.innerHTML = `
             <div class="search-result-head">
@@ -3721,7 +3746,7 @@ async function runAdvancedSearch() {
         });
         if (!res.ok) {
             const data = await res.json().catch(() => ({}));
-            const message = data.error || "Erreur de recherche";
+            const message = data.message || data.error || "Erreur de recherche";
             if (searchResults) {
                 searchResults.innerHTML = `<div class="search-result-item">${escapeHtml(message)}</div>`;
             }
@@ -1,7 +1,4 @@
 window.VOXIUM_RUNTIME_CONFIG = {
     apiBaseUrl: "http://127.0.0.1:8080",
-    wsUrl: "ws://127.0.0.1:8080/ws",
-    iceServers: [
-        { urls: "stun:stun.l.google.com:19302" }
-    ]
+    wsUrl: "ws://127.0.0.1:8080/ws"
 };
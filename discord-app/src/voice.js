@@ -16,6 +16,11 @@ window.VoxiumVoice = (() => {
             members: {},
             muted: false,
             deafened: false,
+            // Imposed by a moderator, the server refuses to lift them
+            serverMuted: false,
+            serverDeafened: false,
+            // { roomId, position } while waiting for a slot in a full room
+            queue: null,
         };
     }
 
@@ -29,6 +34,17 @@ window.VoxiumVoice = (() => {
         let micMeterAnim = null;
 
         const getState = deps.getState;
+
+        // New TURN credentials for connections already open
+        deps.iceServers.onRefresh((iceServers) => {
+            Object.values(getState().voice.peers).forEach((peer) => {
+                try {
+                    peer.setConfiguration({ iceServers });
+                } catch (err) {
+                    console.error("Failed to update ICE servers", err);
+                }
+            });
+        });
         const videoController = window.VoxiumVideo.createVideoShareController({
             getState,
             dom,
@@ -74,8 +90,9 @@ window.VoxiumVoice = (() => {
         function updateVoiceButtons() {
             const state = getState();
             const inVoice = !!state.voice.joinedRoomId;
-            dom.joinVoiceBtn.classList.toggle("hidden", inVoice || state.currentRoomKind !== "voice");
-            dom.leaveVoiceBtn.classList.toggle("hidden", !inVoice);
+            const queued = !!state.voice.queue;
+            dom.joinVoiceBtn.classList.toggle("hidden", inVoice || queued || state.currentRoomKind !== "voice");
+            dom.leaveVoiceBtn.classList.toggle("hidden", !inVoice && !queued);
             dom.voiceMuteBtn.classList.toggle("hidden", !inVoice);
             dom.voiceDeafenBtn.classList.toggle("hidden", !inVoice);
             dom.voiceScreenBtn.classList.toggle("hidden", !inVoice);
@@ -139,6 +156,18 @@ window.VoxiumVoice = (() => {
                 if (dom.voiceRoomSubtitle) {
                     dom.voiceRoomSubtitle.textContent = `Discussion active dans ${room ? room.name : "ce salon"}.`;
                 }
+            } else if (state.voice.queue) {
+                const room = state.rooms.find((r) => r.id === state.voice.queue.roomId);
+                const position = state.voice.queue.position ? ` (n°${state.voice.queue.position})` : "";
+                dom.voiceQuickStatus.classList.add("is-selected");
+                dom.voiceStatusText.textContent = `En file d'attente : ${room ? room.name : "salon vocal"}${position}`;
+                if (dom.voiceRoomChip) {
+                    dom.voiceRoomChip.textContent = "En attente";
+                    dom.voiceRoomChip.classList.add("is-selected");
+                }
+                if (dom.voiceRoomSubtitle) {
+                    dom.voiceRoomSubtitle.textContent = "Le salon est plein. Vous le rejoindrez dès qu'une place se libère.";
+                }
             } else if (state.currentRoomKind === "voice" && state.currentRoomName) {
                 dom.voiceQuickStatus.classList.add("is-selected");
                 dom.voiceStatusText.textContent = `Sélectionné : ${state.currentRoomName}`;
@@ -257,8 +286,10 @@ window.VoxiumVoice = (() => {
                     : deps.escapeHtml((member.username || "U")[0].toUpperCase());
 
                 const badges = [];
-                if (member.muted) badges.push('<span class="voice-badge is-danger">Muet</span>');
-                if (member.deafened) badges.push('<span class="voice-badge is-danger">Casque</span>');
+                if (member.serverMuted) badges.push('<span class="voice-badge is-danger">Muet (modération)</span>');
+                else if (member.muted) badges.push('<span class="voice-badge is-danger">Muet</span>');
+                if (member.serverDeafened) badges.push('<span class="voice-badge is-danger">Casque (modération)</span>');
+                else if (member.deafened) badges.push('<span class="voice-badge is-danger">Casque</span>');
                 if (member.screenSharing) badges.push('<span class="voice-badge is-good">Écran</span>');
                 if (badges.length === 0) badges.push('<span class="voice-badge">En ligne</span>');
 
@@ -302,11 +333,55 @@ window.VoxiumVoice = (() => {
                     username: username || state.users[userId]?.username || "Utilisateur",
                     muted: false,
                     deafened: false,
+                    serverMuted: false,
+                    serverDeafened: false,
                     screenSharing: false,
                 };
             }
         }
 
+        function applyMemberVoiceState(msg) {
+            const state = getState();
+            ensureVoiceMember(msg.user_id, msg.username);
+            const member = state.voice.members[msg.user_id];
+            member.muted = !!msg.muted;
+            member.deafened = !!msg.deafened;
+            member.serverMuted = !!msg.server_muted;
+            member.serverDeafened = !!msg.server_deafened;
+            member.screenSharing = !!msg.screen_sharing;
+
+            if (msg.user_id === state.userId) {
+                applyServerFlags(!!msg.server_muted, !!msg.server_deafened);
+            }
+        }
+
+        // Moderator flags win over the local mute/deafen toggles
+        function applyServerFlags(serverMuted, serverDeafened) {
+            const state = getState();
+            const wasMuted = state.voice.serverMuted;
+            const wasDeafened = state.voice.serverDeafened;
+            state.voice.serverMuted = serverMuted;
+            state.voice.serverDeafened = serverDeafened;
+            if (serverMuted) state.voice.muted = true;
+            if (serverDeafened) state.voice.deafened = true;
+
+            applyLocalTrackState();
+            Object.values(state.voice.audioEls).forEach((audioEl) => {
+                audioEl.muted = state.voice.deafened;
+            });
+            if (state.voice.members[state.userId]) {
+                state.voice.members[state.userId].muted = state.voice.muted;
+                state.voice.members[state.userId].deafened = state.voice.deafened;
+            }
+            updateVoiceButtons();
+            updateVoiceQuickStatus();
+
+            if (serverMuted && !wasMuted) deps.showToast("Un modérateur a coupé votre micro", "error");
+            if (serverDeafened && !wasDeafened) deps.showToast("Un modérateur a coupé votre son", "error");
+            if (!serverMuted && wasMuted) deps.showToast("Un modérateur a réactivé votre micro", "success");
+            if (!serverDeafened && wasDeafened) deps.showToast("Un modérateur a réactivé votre son", "success");
+        }
+
         function cleanupRemotePeer(userId) {
             const state = getState();
             const peer = state.voice.peers[userId];
@@ -347,7 +422,7 @@ window.VoxiumVoice = (() => {
                 return state.voice.peers[remoteUserId];
             }
 
-            const peer = new RTCPeerConnection(deps.WEBRTC_CONFIG);
+            const peer = new RTCPeerConnection({ iceServers: deps.iceServers.current() });
             state.voice.peers[remoteUserId] = peer;
 
             if (state.voice.localStream) {
@@ -493,10 +568,13 @@ window.VoxiumVoice = (() => {
 
             if (msg.type === "voice_join") {
                 if (!msg.user_id || !msg.username) return;
-                ensureVoiceMember(msg.user_id, msg.username);
-                state.voice.members[msg.user_id].muted = !!msg.muted;
-                state.voice.members[msg.user_id].deafened = !!msg.deafened;
-                state.voice.members[msg.user_id].screenSharing = !!msg.screen_sharing;
+                // Our own join went through: any waiting list entry is gone
+                if (msg.user_id === state.userId && state.voice.queue) {
+                    state.voice.queue = null;
+                    updateVoiceButtons();
+                    updateVoiceQuickStatus();
+                }
+                applyMemberVoiceState(msg);
                 renderVoiceMembers();
 
                 if (
@@ -519,10 +597,11 @@ window.VoxiumVoice = (() => {
 
             if (msg.type === "voice_state") {
                 if (!msg.user_id) return;
-                ensureVoiceMember(msg.user_id, msg.username);
-                state.voice.members[msg.user_id].muted = !!msg.muted;
-                state.voice.members[msg.user_id].deafened = !!msg.deafened;
-                state.voice.members[msg.user_id].screenSharing = !!msg.screen_sharing;
+                // Moderation updates carry no screen share flag, keep ours
+                if (msg.screen_sharing === undefined && state.voice.members[msg.user_id]) {
+                    msg.screen_sharing = state.voice.members[msg.user_id].screenSharing;
+                }
+                applyMemberVoiceState(msg);
                 if (!msg.screen_sharing) {
                     removeRemoteScreenTile(msg.user_id);
                 } else if (state.voice.remoteStreams[msg.user_id]) {
@@ -534,13 +613,122 @@ window.VoxiumVoice = (() => {
 
             if (msg.type === "voice_signal") {
                 handleVoiceSignal(msg).catch((err) => console.error("Voice signal error", err));
+                return;
+            }
+
+            if (msg.type === "voice_move") {
+                if (msg.user_id !== state.userId || !state.voice.joinedRoomId) return;
+                moveToVoiceRoom(msg);
+                return;
+            }
+
+            if (msg.type === "voice_disconnect") {
+                if (msg.user_id !== state.userId || !state.voice.joinedRoomId) return;
+                // The server already removed us, no voice_leave to send
+                teardownVoice();
+                deps.showToast("Un modérateur vous a déconnecté du salon vocal", "error");
+                return;
+            }
+
+            if (msg.type === "error" && msg.code === "voice_full") {
+                handleVoiceFull(msg);
+                return;
+            }
+
+            if (msg.type === "voice_queue_ready") {
+                if (msg.user_id !== state.userId || state.voice.queue?.roomId !== msg.room_id) return;
+                state.voice.queue = null;
+                deps.showToast("Une place s'est libérée, connexion au salon vocal…", "success");
+                joinVoiceRoom(msg.room_id);
+                return;
+            }
+
+            if (msg.type === "error" && msg.code === "not_voice_room") {
+                if (state.voice.joinedRoomId === msg.room_id) teardownVoice();
+                deps.showToast(msg.message || "Ce salon n'est pas un salon vocal", "error");
+                return;
+            }
+
+            if (msg.type === "error" && (msg.code === "server_muted" || msg.code === "server_deafened")) {
+                // Our unmute/undeafen was refused: put the flag back
+                applyServerFlags(
+                    state.voice.serverMuted || msg.code === "server_muted",
+                    state.voice.serverDeafened || msg.code === "server_deafened",
+                );
+                renderVoiceMembers();
+                deps.showToast(msg.message || "Action refusée par la modération", "error");
             }
         }
 
-        async function joinVoiceRoom() {
+        function handleVoiceFull(msg) {
             const state = getState();
-            if (state.currentRoomKind !== "voice" || !state.currentRoomId) return;
-            if (state.voice.joinedRoomId === state.currentRoomId) return;
+            // Our join was refused: release the microphone taken for it
+            if (state.voice.joinedRoomId === msg.room_id) {
+                teardownVoice();
+            }
+
+            if (msg.queued) {
+                state.voice.queue = { roomId: msg.room_id, position: msg.position || null };
+                updateVoiceButtons();
+                updateVoiceQuickStatus();
+                deps.showToast(`Salon plein : vous êtes n°${msg.position} dans la file d'attente`, "success");
+                return;
+            }
+
+            const limit = msg.user_limit ? ` (${msg.user_limit} places)` : "";
+            if (!confirm(`Ce salon vocal est plein${limit}. Rejoindre la file d'attente ?`)) return;
+            state.voice.queue = { roomId: msg.room_id, position: null };
+            updateVoiceButtons();
+            updateVoiceQuickStatus();
+            sendQueuedJoin();
+        }
+
+        function sendQueuedJoin() {
+            const state = getState();
+            if (!state.voice.queue) return;
+            deps.wsSend({
+                type: "voice_join",
+                room_id: state.voice.queue.roomId,
+                user_id: state.userId,
+                username: state.username,
+                muted: state.voice.muted,
+                deafened: state.voice.deafened,
+                queue: true,
+            });
+        }
+
+        // A moderator moved us: drop the old peers and join the new room
+        function moveToVoiceRoom(msg) {
+            const state = getState();
+            resetVoiceConnections();
+            state.voice.joinedRoomId = msg.room_id;
+            state.voice.members = {};
+            ensureVoiceMember(state.userId, state.username);
+            applyMemberVoiceState({ ...msg, user_id: state.userId, screen_sharing: state.voice.screenSharing });
+            renderVoiceMembers();
+            updateVoiceButtons();
+            updateVoiceQuickStatus();
+
+            deps.wsSend({
+                type: "voice_join",
+                room_id: msg.room_id,
+                user_id: state.userId,
+                username: state.username,
+                muted: state.voice.muted,
+                deafened: state.voice.deafened,
+                screen_sharing: state.voice.screenSharing,
+            });
+
+            const room = state.rooms.find((r) => r.id === msg.room_id);
+            deps.showToast(`Un modérateur vous a déplacé vers ${room ? room.name : "un autre salon vocal"}`, "success");
+        }
+
+        // Joins the selected room, or `roomId` when a queued slot is ready
+        async function joinVoiceRoom(roomId = null) {
+            const state = getState();
+            if (!roomId && state.currentRoomKind !== "voice") return;
+            const targetRoomId = roomId || state.currentRoomId;
+            if (!targetRoomId || state.voice.joinedRoomId === targetRoomId) return;
 
             if (!navigator.mediaDevices || !navigator.mediaDevices.getUserMedia) {
                 alert("Votre navigateur ne supporte pas l'audio WebRTC.");
@@ -548,9 +736,11 @@ window.VoxiumVoice = (() => {
             }
 
             try {
+                // Peers are created as soon as the join is announced
+                await deps.iceServers.get();
                 const stream = await navigator.mediaDevices.getUserMedia({ audio: true, video: false });
                 state.voice.localStream = stream;
-                state.voice.joinedRoomId = state.currentRoomId;
+                state.voice.joinedRoomId = targetRoomId;
                 state.voice.members = {};
                 ensureVoiceMember(state.userId, state.username);
                 state.voice.members[state.userId].muted = state.voice.muted;
@@ -565,7 +755,7 @@ window.VoxiumVoice = (() => {
 
                 deps.wsSend({
                     type: "voice_join",
-                    room_id: state.currentRoomId,
+                    room_id: targetRoomId,
                     user_id: state.userId,
                     username: state.username,
                     muted: state.voice.muted,
@@ -580,10 +770,15 @@ window.VoxiumVoice = (() => {
 
         function leaveVoiceRoom() {
             const state = getState();
+            if (state.voice.queue) {
+                // Also takes us off the waiting list
+                deps.wsSend({ type: "voice_leave", room_id: state.voice.queue.roomId });
+                state.voice.queue = null;
+                updateVoiceButtons();
+                updateVoiceQuickStatus();
+            }
             if (!state.voice.joinedRoomId) return;
 
-            stopScreenShare(false, false).catch((err) => console.error("Screen stop error", err));
-
             deps.wsSend({
                 type: "voice_leave",
                 room_id: state.voice.joinedRoomId,
@@ -591,6 +786,12 @@ window.VoxiumVoice = (() => {
                 username: state.username,
             });
 
+            teardownVoice();
+        }
+
+        function teardownVoice() {
+            const state = getState();
+            stopScreenShare(false, false).catch((err) => console.error("Screen stop error", err));
             resetVoiceConnections();
             if (state.voice.localStream) {
                 state.voice.localStream.getTracks().forEach((track) => track.stop());
@@ -608,6 +809,10 @@ window.VoxiumVoice = (() => {
         function toggleVoiceMute() {
             const state = getState();
             if (!state.voice.joinedRoomId) return;
+            if (state.voice.serverMuted) {
+                deps.showToast("Votre micro a été coupé par un modérateur", "error");
+                return;
+            }
             state.voice.muted = !state.voice.muted;
             applyLocalTrackState();
             ensureVoiceMember(state.userId, state.username);
@@ -622,6 +827,10 @@ window.VoxiumVoice = (() => {
         function toggleVoiceDeafen() {
             const state = getState();
             if (!state.voice.joinedRoomId) return;
+            if (state.voice.serverDeafened) {
+                deps.showToast("Votre son a été coupé par un modérateur", "error");
+                return;
+            }
             state.voice.deafened = !state.voice.deafened;
             applyLocalTrackState();
 
@@ -672,6 +881,7 @@ window.VoxiumVoice = (() => {
             stopScreenShare,
             handleVoiceWsEvent,
             joinVoiceRoom,
+            sendQueuedJoin,
             leaveVoiceRoom,
             toggleVoiceMute,
             toggleVoiceDeafen,
@@ -1,3 +1,5 @@
+@echo off
+set /p TARGET_USER=Username to promote to admin: 
 cd backend
-cargo run --bin make_admin
+cargo run --bin voxium-admin -- user set-role "%TARGET_USER%" admin
 pause
@@ -0,0 +1,2 @@
+ALTER TABLE rooms ADD COLUMN user_limit INTEGER NOT NULL DEFAULT 0;   -- 0 = unlimited
+ALTER TABLE roles ADD COLUMN move_members INTEGER NOT NULL DEFAULT 0; -- may move users / bypass voice limits
@@ -0,0 +1,3 @@
+ALTER TABLE users ADD COLUMN presence TEXT NOT NULL DEFAULT 'online';  -- online, idle, dnd, invisible
+ALTER TABLE users ADD COLUMN custom_status TEXT DEFAULT NULL;
+ALTER TABLE users ADD COLUMN custom_status_expires_at TEXT DEFAULT NULL; -- RFC 3339, NULL = never
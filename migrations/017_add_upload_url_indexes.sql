@@ -0,0 +1,11 @@
+CREATE INDEX IF NOT EXISTS idx_messages_image_url
+    ON messages(image_url);
+
+CREATE INDEX IF NOT EXISTS idx_message_attachments_url
+    ON message_attachments(url);
+
+CREATE INDEX IF NOT EXISTS idx_users_avatar_url
+    ON users(avatar_url);
+
+CREATE INDEX IF NOT EXISTS idx_users_banner_url
+    ON users(banner_url);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS upload_blobs (
+    key TEXT PRIMARY KEY,
+    sha256 TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    mime_type TEXT NOT NULL,
+    ref_count INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_message_attachments_uploader_id
+    ON message_attachments(uploader_id);
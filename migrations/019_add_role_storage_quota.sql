@@ -0,0 +1,2 @@
+-- Per-role upload quota in bytes (NULL falls back to UPLOAD_USER_QUOTA)
+ALTER TABLE roles ADD COLUMN storage_quota INTEGER;
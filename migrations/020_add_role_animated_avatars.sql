@@ -0,0 +1,3 @@
+-- Roles allowed to keep animated GIF avatars
+ALTER TABLE roles ADD COLUMN animated_avatars INTEGER NOT NULL DEFAULT 0;
+UPDATE roles SET animated_avatars = 1 WHERE name = 'admin';
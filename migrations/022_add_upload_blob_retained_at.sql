@@ -0,0 +1,3 @@
+-- When a reference was last taken on a blob, so the upload sweeper leaves
+-- recent uploads out of its reference count resync
+ALTER TABLE upload_blobs ADD COLUMN retained_at TEXT DEFAULT NULL;
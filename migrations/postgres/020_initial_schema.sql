@@ -0,0 +1,132 @@
+-- PostgreSQL schema, equivalent to the SQLite migrations 001 to 020.
+-- Later migrations are added to both sets under the same version.
+--
+-- Timestamps stay in TEXT columns in the format of SQLite's datetime('now')
+-- and URLs use the "C" collation, so range scans on upload URLs compare
+-- bytes as they do in SQLite.
+
+CREATE TABLE users (
+    id TEXT PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS')),
+    role TEXT NOT NULL DEFAULT 'user',
+    avatar_color INTEGER DEFAULT 0,
+    about TEXT DEFAULT '',
+    avatar_url TEXT COLLATE "C" DEFAULT NULL,
+    banner_url TEXT COLLATE "C",
+    discord_id TEXT,
+    discord_access_token TEXT,
+    discord_refresh_token TEXT,
+    discord_token_expires_at BIGINT,
+    presence TEXT NOT NULL DEFAULT 'online',
+    custom_status TEXT DEFAULT NULL,
+    custom_status_expires_at TEXT DEFAULT NULL
+);
+
+CREATE UNIQUE INDEX idx_users_discord_id
+    ON users(discord_id)
+    WHERE discord_id IS NOT NULL;
+
+CREATE INDEX idx_users_avatar_url
+    ON users(avatar_url);
+
+CREATE INDEX idx_users_banner_url
+    ON users(banner_url);
+
+CREATE TABLE rooms (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    created_at TEXT NOT NULL DEFAULT (to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS')),
+    kind TEXT NOT NULL DEFAULT 'text',
+    required_role TEXT NOT NULL DEFAULT 'user',
+    user_limit INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX idx_rooms_required_role
+    ON rooms(required_role);
+
+INSERT INTO rooms (id, name) VALUES ('general', 'général') ON CONFLICT DO NOTHING;
+
+CREATE TABLE messages (
+    id TEXT PRIMARY KEY,
+    room_id TEXT NOT NULL REFERENCES rooms(id),
+    user_id TEXT NOT NULL REFERENCES users(id),
+    username TEXT NOT NULL,
+    content TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS')),
+    image_url TEXT COLLATE "C" DEFAULT NULL,
+    reply_to_id TEXT DEFAULT NULL,
+    pinned_at TEXT DEFAULT NULL,
+    pinned_by TEXT DEFAULT NULL
+);
+
+CREATE INDEX idx_messages_room_created_at
+    ON messages(room_id, created_at);
+
+CREATE INDEX idx_messages_user_created_at
+    ON messages(user_id, created_at);
+
+CREATE INDEX idx_messages_pinned_at
+    ON messages(pinned_at);
+
+CREATE INDEX idx_messages_reply_to_id
+    ON messages(reply_to_id);
+
+CREATE INDEX idx_messages_image_url
+    ON messages(image_url);
+
+CREATE TABLE roles (
+    name TEXT PRIMARY KEY,
+    color TEXT NOT NULL DEFAULT '#99aab5',
+    created_at TEXT NOT NULL DEFAULT (to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS')),
+    move_members INTEGER NOT NULL DEFAULT 0,
+    storage_quota BIGINT,
+    animated_avatars INTEGER NOT NULL DEFAULT 0
+);
+
+INSERT INTO roles (name, color) VALUES ('user', '#99aab5') ON CONFLICT DO NOTHING;
+INSERT INTO roles (name, color, animated_avatars) VALUES ('admin', '#ed4245', 1) ON CONFLICT DO NOTHING;
+
+CREATE TABLE message_reactions (
+    message_id TEXT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    emoji TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS')),
+    PRIMARY KEY (message_id, user_id, emoji)
+);
+
+CREATE INDEX idx_message_reactions_message_id
+    ON message_reactions(message_id);
+
+CREATE TABLE message_attachments (
+    id TEXT PRIMARY KEY,
+    message_id TEXT REFERENCES messages(id) ON DELETE CASCADE,
+    uploader_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    filename TEXT NOT NULL,
+    url TEXT COLLATE "C" NOT NULL,
+    mime_type TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    sha256 TEXT NOT NULL,
+    width INTEGER,
+    height INTEGER,
+    created_at TEXT NOT NULL DEFAULT (to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'))
+);
+
+CREATE INDEX idx_message_attachments_message_id
+    ON message_attachments(message_id);
+
+CREATE INDEX idx_message_attachments_url
+    ON message_attachments(url);
+
+CREATE INDEX idx_message_attachments_uploader_id
+    ON message_attachments(uploader_id);
+
+CREATE TABLE upload_blobs (
+    key TEXT COLLATE "C" PRIMARY KEY,
+    sha256 TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    mime_type TEXT NOT NULL,
+    ref_count BIGINT NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL DEFAULT (to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'))
+);